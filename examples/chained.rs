@@ -0,0 +1,252 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::Cursor;
+
+use stb_vorbis::{
+    stb_vorbis_get_info, stb_vorbis_get_comment, stb_vorbis_open_pushdata,
+    stb_vorbis_seek, stb_vorbis_get_sample_offset, stb_vorbis_link_changed,
+    stb_vorbis_stream_length_in_samples, stb_vorbis_stream_length_in_seconds, stb_vorbis_seek_start,
+    stb_vorbis_get_samples_float_interleaved, stb_vorbis_decode_frame_pushdata,
+};
+use stb_vorbis::{Vorbis, VorbisDecoder, VorbisComments, AudioBufferSlice};
+
+mod common;
+use common::{fail, same, opened, decode_linear, seek_targets, seek_to, check_after_seek, set_crc};
+
+// chained stream checks: the given files (or the one file twice) are glued
+// into one chained stream, each link with its own serial number, and every
+// way of decoding it is compared against decoding the files one by one.
+// exits with 1 on mismatch.
+
+struct Reference {
+    channels: usize,
+    sample_rate: u32,
+    comments: VorbisComments,
+    samples: Vec<f32>,
+    start: usize, // samples in the links before this one
+}
+
+impl Reference {
+    fn len(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+}
+
+// copy of an ogg stream with every page moved to another serial number
+fn relabel(data: &[u8], serial: u32) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut p = 0;
+    while p + 27 <= data.len() {
+        let segments = data[p + 26] as usize;
+        let len = 27 + segments + data[p + 27 .. p + 27 + segments].iter().map(|&x| x as usize).sum::<usize>();
+        let mut page = data[p .. p + len].to_vec();
+        page[14 .. 18].copy_from_slice(&[serial as u8, (serial >> 8) as u8, (serial >> 16) as u8, (serial >> 24) as u8]);
+        set_crc(&mut page);
+        result.extend_from_slice(&page);
+        p += len;
+    }
+    return result;
+}
+
+fn decode_reference(data: Vec<u8>, start: usize) -> Reference {
+    let mut v = opened(VorbisDecoder::from_vec(data));
+    let info = v.get_info();
+    Reference {
+        channels: info.channels as usize,
+        sample_rate: info.sample_rate,
+        comments: v.get_comment().clone(),
+        samples: decode_linear(&mut v),
+        start: start,
+    }
+}
+
+fn check_link_info(v: &Vorbis, link: &Reference, k: usize, name: &str) {
+    let info = stb_vorbis_get_info(v);
+    if info.channels as usize != link.channels || info.sample_rate != link.sample_rate {
+        fail(format!("{}: link {} reports {} channels at {} Hz", name, k, info.channels, info.sample_rate));
+    }
+    if *stb_vorbis_get_comment(v) != link.comments {
+        fail(format!("{}: link {} has the wrong comments", name, k));
+    }
+}
+
+// the whole chain front to back, watching for the link changes
+fn check_linear(v: &mut Vorbis, links: &[Reference], name: &str) {
+    let mut k = 0;
+    let mut position = 0;
+    check_link_info(v, &links[0], 0, name);
+    loop {
+        if stb_vorbis_get_sample_offset(v) != (links[k].start + position) as i64 {
+            fail(format!("{}: sample offset {} at link {} sample {}", name, stb_vorbis_get_sample_offset(v), k, position));
+        }
+        let channels = stb_vorbis_get_info(v).channels as usize;
+        let mut sbuffer = vec![0.0; 1000 * channels];
+        let n = stb_vorbis_get_samples_float_interleaved(v, channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        let link = &links[k];
+        if position + n > link.len() || !same(&sbuffer[.. n * channels], &link.samples[position * channels .. (position + n) * channels]) {
+            fail(format!("{}: samples at link {} sample {} differ", name, k, position));
+        }
+        position += n;
+
+        // the next call starts on the new link
+        if stb_vorbis_link_changed(v) {
+            if position != links[k].len() {
+                fail(format!("{}: link {} ended after {} of {} samples", name, k, position, links[k].len()));
+            }
+            k += 1;
+            position = 0;
+            if k == links.len() {
+                fail(format!("{}: more links than there are", name));
+            }
+            check_link_info(v, &links[k], k, name);
+        }
+    }
+    if k != links.len() - 1 || position != links[k].len() {
+        fail(format!("{}: decoding stopped at link {} sample {}", name, k, position));
+    }
+}
+
+fn check_seek(v: &mut Vorbis, links: &[Reference], name: &str) {
+    let last = links.last().unwrap();
+    let total = last.start + last.len();
+    if stb_vorbis_stream_length_in_samples(v) as usize != total {
+        fail(format!("{}: stream length {}, expected {}", name, stb_vorbis_stream_length_in_samples(v), total));
+    }
+    // each link plays at its own rate
+    let seconds: f64 = links.iter().map(|link| link.len() as f64 / link.sample_rate as f64).sum();
+    if (stb_vorbis_stream_length_in_seconds(v) as f64 - seconds).abs() > 1e-3 {
+        fail(format!("{}: stream length {} seconds, expected {}", name, stb_vorbis_stream_length_in_seconds(v), seconds));
+    }
+
+    let mut fixed = vec![0, total - 1];
+    for link in links {
+        fixed.extend_from_slice(&[link.start, link.start + 1, link.start + link.len() / 2, (link.start + link.len()).saturating_sub(1)]);
+    }
+    let targets = seek_targets(total, &fixed);
+
+    for &target in targets.iter() {
+        let k = links.iter().rposition(|link| link.start <= target).unwrap();
+        let link = &links[k];
+        let position = target - link.start;
+        seek_to(v, target, name);
+        check_link_info(v, link, k, name);
+        check_after_seek(v, target, &link.samples[position * link.channels ..], name);
+    }
+    if stb_vorbis_seek(v, total as u64 + 1) {
+        fail(format!("{}: seek past the end succeeded", name));
+    }
+
+    stb_vorbis_seek_start(v);
+    stb_vorbis_link_changed(v);
+    check_linear(v, links, &format!("{} after seek_start", name));
+    println!("  {}: {} seeks OK", name, targets.len());
+}
+
+fn check_pushdata(buffer: &[u8], links: &[Reference]) {
+    let mut used = 0;
+    let mut length = std::cmp::min(buffer.len(), 4096);
+    let mut v = loop {
+        match stb_vorbis_open_pushdata(&buffer[.. length], &mut used) {
+            Ok(v) => break v,
+            Err(_) if length < buffer.len() => length = std::cmp::min(buffer.len(), length * 2),
+            Err(why) => fail(format!("pushdata: {}", why)),
+        }
+    };
+
+    let mut p = used as usize;
+    let mut k = 0;
+    let mut decoded = 0;
+    check_link_info(&v, &links[0], 0, "pushdata");
+    while p < buffer.len() {
+        let mut q = 4096;
+        let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
+        let mut num_c = 0;
+        let mut n = 0;
+        let used = loop {
+            let end = std::cmp::min(p + q, buffer.len());
+            let used = unsafe {
+                stb_vorbis_decode_frame_pushdata(&mut v, &buffer[p .. end], &mut num_c, &mut outputs, &mut n)
+            };
+            if used == 0 && end < buffer.len() {
+                q *= 2;
+                continue;
+            }
+            break used as usize;
+        };
+        if used == 0 {
+            break;
+        }
+        p += used;
+
+        if stb_vorbis_link_changed(&mut v) {
+            if decoded != links[k].len() {
+                fail(format!("pushdata: link {} ended after {} of {} samples", k, decoded, links[k].len()));
+            }
+            k += 1;
+            decoded = 0;
+            check_link_info(&v, &links[k], k, "pushdata");
+        }
+        if n == 0 {
+            continue;
+        }
+        let link = &links[k];
+        let offset = stb_vorbis_get_sample_offset(&mut v);
+        if offset < 0 || num_c as usize != link.channels {
+            fail(format!("pushdata: frame at link {} sample {} has no offset", k, decoded));
+        }
+        let start = offset as usize - n as usize - link.start;
+        if start != decoded || start + n as usize > link.len() {
+            fail(format!("pushdata: frame at link {} sample {} claims to be at {}", k, decoded, start));
+        }
+        for c in 0 .. link.channels {
+            let expected: Vec<f32> = (start .. start + n as usize).map(|i| link.samples[i * link.channels + c]).collect();
+            if !same(&outputs[c][.. n as usize], &expected) {
+                fail(format!("pushdata: frame at link {} sample {} differs", k, start));
+            }
+        }
+        decoded += n as usize;
+    }
+    if k != links.len() - 1 || decoded != links[k].len() {
+        fail(format!("pushdata: decoding stopped at link {} sample {}", k, decoded));
+    }
+    println!("  pushdata: {} links OK", links.len());
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut files = args[1 ..].to_vec();
+    if files.len() == 1 {
+        files.push(files[0].clone());
+    }
+
+    let mut chain = Vec::new();
+    let mut links: Vec<Reference> = Vec::new();
+    for (k, filename) in files.iter().enumerate() {
+        let mut data = Vec::new();
+        File::open(filename).unwrap().read_to_end(&mut data).unwrap();
+        let data = relabel(&data, 1000 + k as u32);
+        let start = links.last().map_or(0, |link| link.start + link.len());
+        links.push(decode_reference(data.clone(), start));
+        chain.extend_from_slice(&data);
+    }
+    println!("{} links, {} bytes", links.len(), chain.len());
+
+    let mut decoder = VorbisDecoder::from_vec(chain.clone()).unwrap();
+    check_linear(&mut decoder, &links, "memory");
+    check_seek(&mut decoder, &links, "memory");
+
+    let mut reader = Vorbis::from_reader(Cursor::new(chain.clone())).unwrap();
+    check_linear(&mut reader, &links, "reader");
+    check_seek(&mut reader, &links, "reader");
+
+    // length and seeking before anything was decoded
+    let mut reader = Vorbis::from_reader(Cursor::new(chain.clone())).unwrap();
+    check_seek(&mut reader, &links, "fresh reader");
+
+    check_pushdata(&chain, &links);
+}
@@ -0,0 +1,104 @@
+extern crate stb_vorbis;
+
+use std::io::Cursor;
+
+use stb_vorbis::{stb_vorbis_open_reader, stb_vorbis_open_pushdata, stb_vorbis_get_comment};
+use stb_vorbis::{VorbisDecoder, VorbisComments};
+
+mod common;
+use common::{fail, opened, BitWriter, id_header, setup_header, audio_packet, channel_packet, write_page, SAMPLE_RATE};
+
+// comment header checks: a stream is put together with a comment header of
+// keys in mixed case, repeated keys, a value holding '=', a comment with no
+// '=' at all and one long enough to take several lacing values. opened from
+// memory, through a reader and in push mode, get() has to find the first
+// comment of a key whatever its case and get_all() every one of them, in
+// stream order. takes no arguments, exits with 1 on mismatch.
+
+const VENDOR: &str = "comments example";
+
+fn comments() -> Vec<String> {
+    vec![
+        "TITLE=First".to_string(),
+        "Artist=One".to_string(),
+        "ARTIST=Two".to_string(),
+        "album=Ünïcödé".to_string(),
+        "artist=Three".to_string(),
+        "DESCRIPTION=a=b".to_string(),
+        "NOEQUALS".to_string(),
+        format!("LYRICS={}", "la".repeat(600)),
+        "TITLE=Second".to_string(),
+    ]
+}
+
+fn comment_header() -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.write(3, 8);
+    w.write_bytes(b"vorbis");
+    w.write(VENDOR.len() as u32, 32);
+    w.write_bytes(VENDOR.as_bytes());
+    w.write(comments().len() as u32, 32);
+    for comment in comments() {
+        w.write(comment.len() as u32, 32);
+        w.write_bytes(comment.as_bytes());
+    }
+    w.write(1, 1);
+    w.into_data()
+}
+
+fn build_stream() -> Vec<u8> {
+    let mut out = Vec::new();
+    write_page(&mut out, 1, &[id_header(1, SAMPLE_RATE)], 2, 0, 0);
+    write_page(&mut out, 1, &[comment_header(), setup_header()], 0, 0, 1);
+    write_page(&mut out, 1, &[audio_packet(&[channel_packet(0, 0)])], 4, 0, 2);
+    return out;
+}
+
+fn check(c: &VorbisComments, name: &str) {
+    if c.vendor() != VENDOR || c.len() != comments().len() {
+        fail(format!("{}: vendor {:?} and {} comments", name, c.vendor(), c.len()));
+    }
+    for key in ["TITLE", "title", "Title"].iter() {
+        if c.get(key) != Some("First") {
+            fail(format!("{}: get({:?}) is {:?}", name, key, c.get(key)));
+        }
+    }
+    for key in ["ARTIST", "artist", "aRtIsT"].iter() {
+        let all: Vec<&str> = c.get_all(key).collect();
+        if all != ["One", "Two", "Three"] {
+            fail(format!("{}: get_all({:?}) is {:?}", name, key, all));
+        }
+    }
+    let titles: Vec<&str> = c.get_all("Title").collect();
+    if titles != ["First", "Second"] {
+        fail(format!("{}: get_all(\"Title\") is {:?}", name, titles));
+    }
+    if c.get("ALBUM") != Some("Ünïcödé") || c.get("description") != Some("a=b") || c.get("noequals") != Some("") {
+        fail(format!("{}: album {:?}, description {:?}, noequals {:?}", name, c.get("ALBUM"), c.get("description"), c.get("noequals")));
+    }
+    if c.get("lyrics").map(|lyrics| lyrics.len()) != Some(1200) {
+        fail(format!("{}: the long comment didn't come through", name));
+    }
+    if c.get("missing").is_some() || c.get_all("missing").next().is_some() || c.get("ARTIST=One").is_some() {
+        fail(format!("{}: found a key that isn't there", name));
+    }
+    let keys: Vec<&str> = c.comments().iter().map(|&(ref key, _)| key.as_str()).collect();
+    if keys != ["TITLE", "Artist", "ARTIST", "album", "artist", "DESCRIPTION", "NOEQUALS", "LYRICS", "TITLE"] {
+        fail(format!("{}: keys {:?}", name, keys));
+    }
+    println!("  {}: OK", name);
+}
+
+fn main() {
+    let data = build_stream();
+
+    let v = opened(VorbisDecoder::new(&data));
+    check(v.get_comment(), "memory");
+
+    let v = opened(stb_vorbis_open_reader(Cursor::new(data.clone())));
+    check(stb_vorbis_get_comment(&v), "reader");
+
+    let mut used = 0;
+    let v = opened(stb_vorbis_open_pushdata(&data, &mut used));
+    check(stb_vorbis_get_comment(&v), "pushdata");
+}
@@ -0,0 +1,345 @@
+// what the self-checking examples share: how they report a mismatch,
+// compare and decode samples and check seeks, and a builder for small
+// vorbis streams put together bit by bit, for examples that need channel
+// counts no test file has. every channel of those carries its own signal,
+// the same whichever other channels it is put together with.
+#![allow(dead_code)]
+
+use std::process;
+
+use stb_vorbis::{
+    stb_vorbis_seek, stb_vorbis_get_sample_offset, stb_vorbis_get_samples_float_interleaved,
+};
+use stb_vorbis::{Vorbis, VorbisError};
+
+// at most this many samples are compared after each seek
+pub const CHECK_LEN: usize = 64;
+
+pub fn fail(message: String) -> ! {
+    println!("  [FAILED] {}", message);
+    process::exit(1);
+}
+
+// a file that doesn't open exits with its error kind, as the decode
+// examples do
+pub fn opened<T>(result: Result<T, VorbisError>) -> T {
+    match result {
+        Ok(v) => v,
+        Err(why) => {
+            println!("Couldn't open the stream. Error: {}", why);
+            process::exit(why.kind() as i32);
+        }
+    }
+}
+
+pub fn same(a: &[f32], b: &[f32]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-4)
+}
+
+// everything left in the stream, interleaved
+pub fn decode_linear(v: &mut Vorbis) -> Vec<f32> {
+    let channels = v.channels as usize;
+    let mut result = Vec::with_capacity(4096);
+    let mut sbuffer = vec![0.0; 333 * channels];
+    loop {
+        let n = stb_vorbis_get_samples_float_interleaved(v, channels as i32, &mut sbuffer);
+        if n == 0 {
+            break;
+        }
+        result.extend_from_slice(&sbuffer[.. n as usize * channels]);
+    }
+    return result;
+}
+
+// the given positions plus some pseudo-random ones below 'total', the
+// same ones every run
+pub fn seek_targets(total: usize, fixed: &[usize]) -> Vec<usize> {
+    let mut targets = fixed.to_vec();
+    let mut seed: u32 = 12345;
+    for _ in 0 .. 32 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        targets.push((seed >> 8) as usize % total.max(1));
+    }
+    return targets;
+}
+
+// seek to 'target', which has to land there
+pub fn seek_to(v: &mut Vorbis, target: usize, name: &str) {
+    if stb_vorbis_seek(v, target as u64) == false {
+        fail(format!("{}: seek to {} failed", name, target));
+    }
+    if stb_vorbis_get_sample_offset(v) != target as i64 {
+        fail(format!("{}: sample offset after seek to {} is {}", name, target, stb_vorbis_get_sample_offset(v)));
+    }
+}
+
+// seek to 'target' and check that what follows is what 'expected' starts
+// with, up to CHECK_LEN samples of it
+pub fn check_seek_to(v: &mut Vorbis, target: usize, expected: &[f32], name: &str) {
+    seek_to(v, target, name);
+    check_after_seek(v, target, expected, name);
+}
+
+pub fn check_after_seek(v: &mut Vorbis, target: usize, expected: &[f32], name: &str) {
+    let channels = v.channels as usize;
+    let mut sbuffer = vec![0.0; CHECK_LEN * channels];
+    let n = stb_vorbis_get_samples_float_interleaved(v, channels as i32, &mut sbuffer) as usize;
+    let len = std::cmp::min(CHECK_LEN, expected.len() / channels);
+    if n != len || !same(&sbuffer[.. n * channels], &expected[.. n * channels]) {
+        fail(format!("{}: samples after seek to {} differ", name, target));
+    }
+}
+
+// for pages edited in place
+pub fn set_crc(page: &mut [u8]) {
+    page[22 .. 26].copy_from_slice(&[0; 4]);
+    let crc = ogg_crc(page);
+    page[22 .. 26].copy_from_slice(&[crc as u8, (crc >> 8) as u8, (crc >> 16) as u8, (crc >> 24) as u8]);
+}
+
+pub const PACKETS: usize = 40;
+pub const BLOCKSIZE: usize = 256;
+pub const SAMPLE_RATE: u32 = 48000;
+
+// vorbis packs its bitstream from the least significant bit up
+pub struct BitWriter {
+    data: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter { data: Vec::new(), bits: 0 }
+    }
+
+    pub fn write(&mut self, value: u32, count: usize) {
+        for i in 0 .. count {
+            if self.bits % 8 == 0 {
+                self.data.push(0);
+            }
+            if (value >> i) & 1 != 0 {
+                *self.data.last_mut().unwrap() |= 1 << (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write(byte as u32, 8);
+        }
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+// what one channel carries in one packet
+#[derive(Clone, Copy)]
+pub struct ChannelPacket {
+    pub floor: [u32; 2],     // the two floor1 values, 7 bits each
+    pub residue: [u64; 2],   // one bit per coefficient
+}
+
+pub fn channel_packet(channel: usize, packet: usize) -> ChannelPacket {
+    let mut seed = (channel as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15) ^ (packet as u64).wrapping_mul(0xc2b2ae3d27d4eb4f);
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    // sparse enough that the samples stay well inside [-1, 1]
+    let residue = [next() & next() & next(), next() & next() & next()];
+    let floor = [60 + (next() % 40) as u32, 60 + (next() % 40) as u32];
+    ChannelPacket { floor: floor, residue: residue }
+}
+
+pub fn id_header(channels: usize, rate: u32) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.write(1, 8);
+    w.write_bytes(b"vorbis");
+    w.write(0, 32);                   // version
+    w.write(channels as u32, 8);
+    w.write(rate, 32);
+    w.write(0, 32);                   // bitrates
+    w.write(0, 32);
+    w.write(0, 32);
+    w.write(8, 4);                    // both block sizes 256
+    w.write(8, 4);
+    w.write(1, 1);                    // framing
+    w.data
+}
+
+pub fn comment_header() -> Vec<u8> {
+    let vendor = b"many_channels";
+    let mut w = BitWriter::new();
+    w.write(3, 8);
+    w.write_bytes(b"vorbis");
+    w.write(vendor.len() as u32, 32);
+    w.write_bytes(vendor);
+    w.write(0, 32);                   // no comments
+    w.write(1, 1);
+    w.data
+}
+
+pub fn write_codebook(w: &mut BitWriter, lookup: bool) {
+    w.write(0x564342, 24);
+    w.write(1, 16);                   // dimensions
+    w.write(2, 24);                   // entries, with codewords 0 and 1
+    w.write(0, 1);                    // not ordered
+    w.write(0, 1);                    // not sparse
+    w.write(0, 5);                    // both one bit long
+    w.write(0, 5);
+    if lookup {
+        w.write(1, 4);
+        w.write(0, 32);               // minimum 0.0
+        w.write((788 << 21) | 1, 32); // delta 1.0
+        w.write(0, 4);                // one bit per value
+        w.write(0, 1);                // no sequence
+        w.write(0, 1);                // entry 0 is 0.0
+        w.write(1, 1);                // entry 1 is 1.0
+    } else {
+        w.write(0, 4);
+    }
+}
+
+// one floor1 with just its two end points, one residue 1 covering the whole
+// spectrum as a single partition, one mapping and one mode
+pub fn setup_header() -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.write(5, 8);
+    w.write_bytes(b"vorbis");
+
+    w.write(1, 8);                    // two codebooks
+    write_codebook(&mut w, false);    // 0: residue classes
+    write_codebook(&mut w, true);     // 1: residue values
+
+    w.write(0, 6);                    // one time domain transform
+    w.write(0, 16);
+
+    w.write(0, 6);                    // one floor
+    w.write(1, 16);                   // type 1
+    w.write(0, 5);                    // no partitions
+    w.write(1, 2);                    // multiplier 2
+    w.write(7, 4);                    // range bits, end point at 128
+
+    w.write(0, 6);                    // one residue
+    w.write(1, 16);                   // type 1
+    w.write(0, 24);                   // begin
+    w.write(BLOCKSIZE as u32 / 2, 24);// end
+    w.write(BLOCKSIZE as u32 / 2 - 1, 24); // partition size
+    w.write(0, 6);                    // one class
+    w.write(0, 8);                    // class book
+    w.write(1, 3);                    // values book on the first pass only
+    w.write(0, 1);
+    w.write(1, 8);
+
+    w.write(0, 6);                    // one mapping
+    w.write(0, 16);
+    w.write(0, 1);                    // one submap
+    w.write(0, 1);                    // no coupling
+    w.write(0, 2);
+    w.write(0, 8);
+    w.write(0, 8);                    // floor 0
+    w.write(0, 8);                    // residue 0
+
+    w.write(0, 6);                    // one mode
+    w.write(0, 1);                    // short blocks
+    w.write(0, 16);
+    w.write(0, 16);
+    w.write(0, 8);                    // mapping 0
+
+    w.write(1, 1);
+    w.data
+}
+
+pub fn audio_packet(channels: &[ChannelPacket]) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.write(0, 1);                    // audio, no mode bits with one mode
+    for c in channels {
+        w.write(1, 1);                // floor in use
+        w.write(c.floor[0], 7);
+        w.write(c.floor[1], 7);
+    }
+    for _ in channels {
+        w.write(0, 1);                // class 0
+    }
+    for c in channels {
+        for k in 0 .. BLOCKSIZE / 2 {
+            w.write((c.residue[k / 64] >> (k % 64)) as u32 & 1, 1);
+        }
+    }
+    w.data
+}
+
+pub fn ogg_crc(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0 .. 8 {
+            crc = if crc & 0x80000000 != 0 { (crc << 1) ^ 0x04c11db7 } else { crc << 1 };
+        }
+    }
+    return crc;
+}
+
+pub fn write_page(out: &mut Vec<u8>, serial: u32, packets: &[Vec<u8>], flags: u8, granule: u64, sequence: u32) {
+    let mut lacing = Vec::new();
+    for packet in packets {
+        lacing.extend(std::iter::repeat(255).take(packet.len() / 255));
+        lacing.push((packet.len() % 255) as u8);
+    }
+    let mut page = vec![b'O', b'g', b'g', b'S', 0, flags];
+    for i in 0 .. 8 {
+        page.push((granule >> (8 * i)) as u8);
+    }
+    for i in 0 .. 4 {
+        page.push((serial >> (8 * i)) as u8);
+    }
+    for i in 0 .. 4 {
+        page.push((sequence >> (8 * i)) as u8);
+    }
+    page.extend_from_slice(&[0; 4]);
+    page.push(lacing.len() as u8);
+    page.extend_from_slice(&lacing);
+    for packet in packets {
+        page.extend_from_slice(packet);
+    }
+    set_crc(&mut page);
+    out.extend_from_slice(&page);
+}
+
+// a stream of the given channels of the full set
+pub fn build_stream(channels: &[usize]) -> Vec<u8> {
+    build_link(channels, SAMPLE_RATE, 1337)
+}
+
+// the same at any sample rate, with a serial number of its own so that
+// links can be chained
+pub fn build_link(channels: &[usize], rate: u32, serial: u32) -> Vec<u8> {
+    build_pages(channels, rate, serial, 1)
+}
+
+// the same with 'per_page' audio packets on every page
+pub fn build_pages(channels: &[usize], rate: u32, serial: u32, per_page: usize) -> Vec<u8> {
+    let packets: Vec<Vec<u8>> = (0 .. PACKETS).map(|packet| {
+        let data: Vec<ChannelPacket> = channels.iter().map(|&c| channel_packet(c, packet)).collect();
+        audio_packet(&data)
+    }).collect();
+    return build_packets(channels.len(), rate, serial, &packets, per_page);
+}
+
+// the headers and then the PACKETS audio packets given, 'per_page' to a page
+pub fn build_packets(channels: usize, rate: u32, serial: u32, packets: &[Vec<u8>], per_page: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_page(&mut out, serial, &[id_header(channels, rate)], 2, 0, 0);
+    write_page(&mut out, serial, &[comment_header(), setup_header()], 0, 0, 1);
+    for (page, packets) in packets.chunks(per_page).enumerate() {
+        let last = page * per_page + packets.len() - 1;
+        let flags = if last == PACKETS - 1 { 4 } else { 0 };
+        write_page(&mut out, serial, packets, flags, (last * BLOCKSIZE / 2) as u64, page as u32 + 2);
+    }
+    return out;
+}
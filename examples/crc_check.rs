@@ -0,0 +1,156 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use stb_vorbis::{
+    stb_vorbis_open_reader, stb_vorbis_get_samples_float_interleaved, stb_vorbis_set_crc_check,
+    stb_vorbis_get_corrupt_pages, stb_vorbis_set_corrupt_page_callback, stb_vorbis_get_last_error,
+};
+use stb_vorbis::{Vorbis, OwnedVorbisDecoder, OggPageReader, OggPage, CrcCheck, CorruptPage, VorbisErrorKind};
+
+mod common;
+use common::{fail, opened};
+
+// page CRC checks: the file has to decode the same with checks off, set to
+// skip and set to fail. then audio pages are damaged, in the body, in the
+// segment table and two in a row, and decoded from memory and through a
+// reader: skipping has to count and report the pages and leave a gap, with
+// everything before and after it as in the clean decode; failing has to
+// stop at the page with an InvalidPageCrc error giving its offset, after
+// what came before it. exits with 1 on mismatch.
+
+struct Decoded {
+    samples: Vec<f32>,
+    corrupt_pages: u64,
+    reported: Vec<CorruptPage>,
+    error: Option<(VorbisErrorKind, Option<u64>)>,
+}
+
+fn decode(data: &[u8], check: CrcCheck, pull: bool) -> Decoded {
+    if pull {
+        return decode_all(&mut opened(stb_vorbis_open_reader(Cursor::new(data.to_vec()))), check);
+    }
+    let mut v = opened(OwnedVorbisDecoder::from_vec(data.to_vec()));
+    return decode_all(&mut v, check);
+}
+
+fn decode_all(v: &mut Vorbis, check: CrcCheck) -> Decoded {
+    stb_vorbis_set_crc_check(v, check);
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let pages = reported.clone();
+    stb_vorbis_set_corrupt_page_callback(v, Some(Box::new(move |page: &CorruptPage| pages.lock().unwrap().push(page.clone()))));
+
+    let channels = v.channels as usize;
+    let mut samples = Vec::new();
+    let mut sbuffer = vec![0.0; 1000 * channels];
+    loop {
+        let n = stb_vorbis_get_samples_float_interleaved(v, channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+    let error = stb_vorbis_get_last_error(v).map(|e| (e.kind(), e.offset()));
+    let corrupt_pages = stb_vorbis_get_corrupt_pages(v);
+    let reported = reported.lock().unwrap().clone();
+    return Decoded { samples: samples, corrupt_pages: corrupt_pages, reported: reported, error: error };
+}
+
+fn common_prefix(a: &[f32], b: &[f32]) -> usize {
+    a.iter().zip(b).take_while(|&(x, y)| x == y).count()
+}
+
+// damages 'bad' pages with 'damage', and checks both modes both ways
+fn check(data: &[u8], clean: &[f32], channels: usize, pages: &[OggPage], bad: &[usize], damage: &dyn Fn(&mut Vec<u8>, &OggPage), name: &str) {
+    let mut damaged = data.to_vec();
+    for &k in bad {
+        damage(&mut damaged, &pages[k]);
+    }
+    let first = &pages[bad[0]];
+    let last = bad[bad.len() - 1];
+    // the samples the pages around the damage end at, counting the links
+    // of a chained file before
+    let link_start: u64 = pages[.. bad[0]].iter().filter(|p| p.is_last()).map(|p| p.granule_position).sum();
+    let before = link_start + pages[bad[0] - 2].granule_position;
+    let after = link_start + pages[last + 2].granule_position;
+
+    for &pull in [false, true].iter() {
+        let name = format!("{}, {}", name, if pull { "reader" } else { "memory" });
+
+        let skipped = decode(&damaged, CrcCheck::Skip, pull);
+        let expected: Vec<(u64, u32, u32)> = bad.iter().map(|&k| (pages[k].offset, pages[k].serial, pages[k].sequence)).collect();
+        let reported: Vec<(u64, u32, u32)> = skipped.reported.iter().map(|p| (p.offset, p.serial, p.sequence)).collect();
+        if skipped.corrupt_pages != bad.len() as u64 || reported != expected || skipped.error.is_some() {
+            fail(format!("{}: skip counted {} pages, reported {:?}, error {:?}", name, skipped.corrupt_pages, reported, skipped.error));
+        }
+        // the clean decode with a gap in it
+        let head = common_prefix(&skipped.samples, clean);
+        let tail = skipped.samples.len() - head;
+        if skipped.samples.len() >= clean.len() || skipped.samples[head ..] != clean[clean.len() - tail ..] {
+            fail(format!("{}: skip doesn't leave a gap in the clean decode", name));
+        }
+        let (gap_start, gap_end) = ((head / channels) as u64, ((clean.len() - tail) / channels) as u64);
+        if gap_start < before || gap_end > after {
+            fail(format!("{}: samples {} to {} lost, not within {} to {}", name, gap_start, gap_end, before, after));
+        }
+
+        let failed = decode(&damaged, CrcCheck::Fail, pull);
+        if failed.error != Some((VorbisErrorKind::InvalidPageCrc, Some(first.offset))) || failed.corrupt_pages != 0 || failed.reported.is_empty() == false {
+            fail(format!("{}: fail gave {:?}, expected a CRC error at {}", name, failed.error, first.offset));
+        }
+        if (failed.samples.len() / channels) as u64 <= before || failed.samples[..] != clean[.. failed.samples.len()] {
+            fail(format!("{}: fail stopped after {} samples, the clean decode isn't", name, failed.samples.len() / channels));
+        }
+        println!("  {}: OK, samples {} to {} lost", name, gap_start, gap_end);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+
+    let clean = decode(&data, CrcCheck::Off, false);
+    let channels = opened(OwnedVorbisDecoder::from_vec(data.clone())).get_info().channels as usize;
+    for &check in [CrcCheck::Skip, CrcCheck::Fail].iter() {
+        for &pull in [false, true].iter() {
+            let d = decode(&data, check, pull);
+            if d.samples != clean.samples || d.corrupt_pages != 0 || d.error.is_some() {
+                fail(format!("{:?}: the clean file decodes differently", check));
+            }
+        }
+    }
+    println!("  clean: OK");
+
+    // audio pages with two more of the same stream either side
+    let pages: Vec<OggPage> = OggPageReader::new(Cursor::new(&data)).map(|p| p.unwrap()).collect();
+    let usable: Vec<usize> = (3 .. pages.len().saturating_sub(3)).filter(|&k| {
+        pages[k - 2 ..= k + 3].iter().all(|p| p.serial == pages[k].serial && p.granule_position != !0 && p.is_first() == false && p.is_last() == false)
+            && pages[k - 2].granule_position > 0
+    }).collect();
+    if usable.is_empty() {
+        println!("  too short to damage");
+        return;
+    }
+
+    let body = |data: &mut Vec<u8>, page: &OggPage| {
+        let p = page.offset as usize + 27 + page.lacing.len() + page.data.len() / 2;
+        data[p] ^= 0x10;
+    };
+    let lacing = |data: &mut Vec<u8>, page: &OggPage| {
+        data[page.offset as usize + 27] ^= 0x81;
+    };
+    let k = usable[usable.len() / 2];
+    check(&data, &clean.samples, channels, &pages, &[k], &body, "body");
+    check(&data, &clean.samples, channels, &pages, &[k], &lacing, "segment table");
+    if usable.contains(&(k + 1)) {
+        check(&data, &clean.samples, channels, &pages, &[k, k + 1], &body, "two pages");
+    }
+    // a page whose last packet goes on to the next page
+    if let Some(&k) = usable.iter().find(|&&k| pages[k + 1].is_continued()) {
+        check(&data, &clean.samples, channels, &pages, &[k], &body, "packet cut");
+    }
+}
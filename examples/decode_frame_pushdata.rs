@@ -8,7 +8,7 @@ use std::mem;
 use std::process;
 
 use stb_vorbis::{stb_vorbis_get_info, stb_vorbis_open_pushdata, stb_vorbis_decode_frame_pushdata};
-use stb_vorbis::{Vorbis, VorbisError, AudioBufferSlice};
+use stb_vorbis::{Vorbis, VorbisErrorKind, AudioBufferSlice};
 
 
 fn show_info(v: &mut Vorbis) {
@@ -64,13 +64,13 @@ unsafe fn test_decode_frame_pushdata(mut out_file: File, filename: &str) {
     let mut v;
     'retry: loop {
         v = match stb_vorbis_open_pushdata(&buffer[0 .. length], &mut used){
-            Err(why) if why == VorbisError::NeedMoreData => {
+            Err(why) if why == VorbisErrorKind::NeedMoreData => {
                 length += 1;
                 continue; //goto retry;
             },
             Err(why) => {
-                println!("Error {:?}", why);
-                process::exit(why as i32);
+                println!("Error: {}", why);
+                process::exit(why.kind() as i32);
             },
             Ok(v) => v,
         };
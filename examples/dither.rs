@@ -0,0 +1,250 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::process;
+
+use stb_vorbis::{
+    stb_vorbis_get_samples_short_interleaved, stb_vorbis_get_samples_float_interleaved,
+    stb_vorbis_get_samples_interleaved, stb_vorbis_get_samples_short, stb_vorbis_seek, stb_vorbis_seek_start,
+};
+use stb_vorbis::{OwnedVorbisDecoder, AudioBufferSlice, Dither, Sample, I24};
+
+mod common;
+use common::fail;
+
+// dithering checks: the file is decoded to 16 bits without dither, with
+// TPDF and with noise shaped dither, and the rounding errors are measured
+// against the float output. TPDF errors have to be within 1.5 steps, with the
+// same spread whatever the fraction being rounded; shaped ones have to be
+// louder overall and quieter at low frequencies. the same seed has to give
+// the same samples, planar, interleaved, in any chunks, short or generic,
+// and after a seek whatever was decoded before it. exits with 1 on mismatch.
+
+fn open(data: &[u8], dither: Dither, seed: u32) -> OwnedVorbisDecoder {
+    let mut v = match OwnedVorbisDecoder::from_vec(data.to_vec()) {
+        Ok(v) => v,
+        Err(why) => {
+            println!("Couldn't open the stream. Error: {}", why);
+            process::exit(why.kind() as i32);
+        }
+    };
+    if v.get_dither() != Dither::Off {
+        fail(format!("dither is on by default"));
+    }
+    v.set_dither(dither, seed);
+    if v.get_dither() != dither {
+        fail(format!("{:?} set, {:?} got", dither, v.get_dither()));
+    }
+    return v;
+}
+
+fn decode_short(data: &[u8], channels: usize, dither: Dither, seed: u32, chunk: usize) -> Vec<i16> {
+    let mut v = open(data, dither, seed);
+    let mut samples = Vec::new();
+    let mut sbuffer = vec![0; chunk * channels];
+    loop {
+        let n = stb_vorbis_get_samples_short_interleaved(&mut v, channels as u32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+    return samples;
+}
+
+fn decode_short_planar(data: &[u8], channels: usize, dither: Dither, seed: u32, chunk: usize) -> Vec<i16> {
+    let mut v = open(data, dither, seed);
+    let mut samples = Vec::new();
+    let mut buffers = vec![vec![0; chunk]; channels];
+    loop {
+        let mut buffer = unsafe { AudioBufferSlice::from(&mut buffers) };
+        let n = unsafe { stb_vorbis_get_samples_short(&mut v, channels as i32, &mut buffer) } as usize;
+        if n == 0 {
+            break;
+        }
+        for i in 0 .. n {
+            for c in 0 .. channels {
+                samples.push(buffers[c][i]);
+            }
+        }
+    }
+    return samples;
+}
+
+fn decode<T: Sample + Default>(data: &[u8], channels: usize, dither: Dither, seed: u32, chunk: usize) -> Vec<T> {
+    let mut v = open(data, dither, seed);
+    let mut samples = Vec::new();
+    let mut sbuffer = vec![T::default(); chunk * channels];
+    loop {
+        let n = stb_vorbis_get_samples_interleaved(&mut v, channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+    return samples;
+}
+
+// the samples from 'target' on, after decoding 'before' samples first
+fn decode_after_seek(data: &[u8], channels: usize, dither: Dither, before: usize, target: usize) -> Vec<i16> {
+    let mut v = open(data, dither, 1);
+    let mut sbuffer = vec![0; 333 * channels];
+    let mut decoded = 0;
+    while decoded < before {
+        let n = stb_vorbis_get_samples_short_interleaved(&mut v, channels as u32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        decoded += n;
+    }
+    if target == 0 {
+        stb_vorbis_seek_start(&mut v);
+    } else if stb_vorbis_seek(&mut v, target as u64) == false {
+        fail(format!("{:?}: seek to {} failed", dither, target));
+    }
+    let mut samples = Vec::new();
+    loop {
+        let n = stb_vorbis_get_samples_short_interleaved(&mut v, channels as u32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+    return samples;
+}
+
+// the rounding errors in steps, per channel, leaving out samples near
+// full scale that may have been clipped
+fn errors(samples: &[f64], reference: &[f32], channels: usize, scale: f64) -> Vec<Vec<(f64, f64)>> {
+    let mut errors = vec![Vec::new(); channels];
+    for (i, (&s, &x)) in samples.iter().zip(reference).enumerate() {
+        let exact = x as f64 * scale;
+        if exact.abs() < scale * 0.95 {
+            errors[i % channels].push((exact, s - exact));
+        }
+    }
+    return errors;
+}
+
+fn max_error(errors: &[Vec<(f64, f64)>]) -> f64 {
+    errors.iter().flat_map(|e| e.iter()).fold(0.0, |m, &(_, e)| m.max(e.abs()))
+}
+
+fn power(errors: &[Vec<(f64, f64)>]) -> f64 {
+    let n: usize = errors.iter().map(|e| e.len()).sum();
+    errors.iter().flat_map(|e| e.iter()).map(|&(_, e)| e * e).sum::<f64>() / n as f64
+}
+
+// the power of the error summed over blocks of 16 samples: the low end of
+// its spectrum
+fn low_power(errors: &[Vec<(f64, f64)>]) -> f64 {
+    let mut sum = 0.0;
+    let mut n = 0;
+    for e in errors {
+        for block in e.chunks_exact(16) {
+            let s: f64 = block.iter().map(|&(_, e)| e).sum();
+            sum += s * s;
+            n += 1;
+        }
+    }
+    return sum / n as f64;
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+
+    let channels = open(&data, Dither::Off, 0).get_info().channels as usize;
+    let mut v = open(&data, Dither::Off, 0);
+    let mut reference = Vec::new();
+    let mut sbuffer = vec![0.0; 4096 * channels];
+    loop {
+        let n = stb_vorbis_get_samples_float_interleaved(&mut v, channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        reference.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+
+    let mut low = Vec::new();
+    let mut total = Vec::new();
+    for &dither in [Dither::Off, Dither::Tpdf, Dither::Shaped].iter() {
+        let samples = decode_short(&data, channels, dither, 1, 333);
+        if samples.len() != reference.len() {
+            fail(format!("{:?}: {} samples, expected {}", dither, samples.len(), reference.len()));
+        }
+
+        // the same samples, however they're asked for
+        if decode_short(&data, channels, dither, 1, 4096) != samples
+            || decode_short_planar(&data, channels, dither, 1, 1000) != samples
+            || decode::<i16>(&data, channels, dither, 1, 777) != samples
+        {
+            fail(format!("{:?}: differs between calls", dither));
+        }
+        if dither != Dither::Off && decode_short(&data, channels, dither, 2, 333) == samples {
+            fail(format!("{:?}: seeds 1 and 2 give the same samples", dither));
+        }
+        // the noise starts over on a seek
+        let middle = samples.len() / channels / 2;
+        if decode_after_seek(&data, channels, dither, 5000, 0) != samples
+            || decode_after_seek(&data, channels, dither, 0, middle) != decode_after_seek(&data, channels, dither, 3000, middle)
+        {
+            fail(format!("{:?}: differs after a seek", dither));
+        }
+
+        let wide: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+        let e = errors(&wide, &reference, channels, 32768.0);
+        let worst = max_error(&e);
+        let limit = match dither {
+            Dither::Off => 0.5,
+            Dither::Tpdf => 1.5,
+            Dither::Shaped => 8.0,
+        };
+        if worst > limit + 1e-3 {
+            fail(format!("{:?}: error of {} steps", dither, worst));
+        }
+
+        if dither == Dither::Tpdf {
+            // the error's spread mustn't depend on what's being rounded
+            for bin in 0 .. 8 {
+                let part: Vec<Vec<(f64, f64)>> = e.iter().map(|e| e.iter().cloned().filter(|&(x, _)| {
+                    ((x - x.floor()) * 8.0) as usize == bin
+                }).collect()).collect();
+                let p = power(&part);
+                if !(p > 0.22 && p < 0.28) {
+                    fail(format!("{:?}: error power {} for fractions {}/8", dither, p, bin));
+                }
+            }
+        }
+        low.push(low_power(&e));
+        total.push(power(&e));
+        println!("  {:?}: OK, error power {:.3}, low {:.3}", dither, total.last().unwrap(), low.last().unwrap());
+    }
+    if !(total[2] > total[1] && low[2] < low[1] * 0.5) {
+        fail(format!("shaped noise isn't moved up: power {} and {} low, TPDF {} and {}", total[2], low[2], total[1], low[1]));
+    }
+
+    // the other dithered types
+    for &dither in [Dither::Tpdf, Dither::Shaped].iter() {
+        let samples = decode::<u8>(&data, channels, dither, 7, 500);
+        let wide: Vec<f64> = samples.iter().map(|&s| s as f64 - 128.0).collect();
+        let worst = max_error(&errors(&wide, &reference, channels, 128.0));
+        if samples != decode::<u8>(&data, channels, dither, 7, 4096) || worst > 8.0 {
+            fail(format!("{:?}: u8 samples differ between calls, or are {} steps off", dither, worst));
+        }
+        let samples = decode::<I24>(&data, channels, dither, 7, 500);
+        let wide: Vec<f64> = samples.iter().map(|s| s.get() as f64).collect();
+        let worst = max_error(&errors(&wide, &reference, channels, 8388608.0));
+        if samples != decode::<I24>(&data, channels, dither, 7, 4096) || worst > 8.0 {
+            fail(format!("{:?}: I24 samples differ between calls, or are {} steps off", dither, worst));
+        }
+    }
+    // i32 is never dithered
+    let rounded: Vec<i32> = reference.iter().map(|&x| i32::from_f32(x)).collect();
+    if decode::<i32>(&data, channels, Dither::Tpdf, 7, 500) != rounded {
+        fail(format!("i32 samples are dithered"));
+    }
+    println!("  u8, I24, i32: OK");
+}
@@ -0,0 +1,291 @@
+extern crate stb_vorbis;
+
+use stb_vorbis::{
+    stb_vorbis_get_samples_float, stb_vorbis_get_samples_short, stb_vorbis_get_samples_short_interleaved,
+    stb_vorbis_get_frame_short_interleaved, stb_vorbis_set_channel_mix,
+};
+use stb_vorbis::{OwnedVorbisDecoder, AudioBufferSlice, ChannelMix, ChannelLayout, Speaker, Vorbis};
+
+mod common;
+use common::{fail, same, build_stream};
+
+// channel layout and mixing checks: the layouts of streams of 1 to 8
+// channels are checked against the Vorbis I spec, then they are decoded to every
+// channel count from 1 to 8 through the float and 16-bit functions, and
+// compared against mixing the decoded channels by hand. a few of the
+// standard mixes are spelled out, all of them must keep within the level
+// of the loudest input; then user matrices and the original stb_vorbis
+// rules. takes no arguments, exits with 1 on mismatch.
+
+const M3: f32 = std::f32::consts::FRAC_1_SQRT_2; // -3dB
+
+fn same_short(a: &[i16], b: &[f32]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(&s, &x)| (s as f32 - (x * 32768.0).max(-32768.0).min(32767.0)).abs() <= 1.0)
+}
+
+fn open(stream: &[u8], mix: &ChannelMix) -> OwnedVorbisDecoder {
+    let mut v = OwnedVorbisDecoder::from_vec(stream.to_vec()).unwrap();
+    if v.set_channel_mix(mix.clone()) == false {
+        fail(format!("{:?} refused", mix));
+    }
+    return v;
+}
+
+// the decoded channels, one Vec each
+fn decode_planar(v: &mut Vorbis, channels: usize) -> Vec<Vec<f32>> {
+    let mut planar = vec![vec![0.0; 100]; channels];
+    let mut decoded = vec![Vec::new(); channels];
+    loop {
+        let mut buffer = unsafe { AudioBufferSlice::from(&mut planar) };
+        let n = stb_vorbis_get_samples_float(v, channels as i32, &mut buffer) as usize;
+        if n == 0 {
+            break;
+        }
+        for c in 0 .. channels {
+            decoded[c].extend_from_slice(&planar[c][.. n]);
+        }
+    }
+    return decoded;
+}
+
+// 'gains' is output major, as in ChannelMix::Matrix
+fn mix(gains: &[f32], input: &[Vec<f32>]) -> Vec<f32> {
+    let from = input.len();
+    let to = gains.len() / from;
+    let mut output = Vec::new();
+    for i in 0 .. input[0].len() {
+        for c in 0 .. to {
+            output.push((0 .. from).map(|j| gains[c * from + j] * input[j][i]).sum());
+        }
+    }
+    return output;
+}
+
+fn interleave(planar: &[Vec<f32>]) -> Vec<f32> {
+    mix(&identity(planar.len()), planar)
+}
+
+fn identity(n: usize) -> Vec<f32> {
+    (0 .. n * n).map(|i| if i / n == i % n { 1.0 } else { 0.0 }).collect()
+}
+
+// every way of getting 'to' channels out of the stream has to give 'expected'
+fn check_outputs(stream: &[u8], mix: &ChannelMix, to: usize, expected: &[f32], name: &str) {
+    let mut v = open(stream, mix);
+    let mut floats = Vec::new();
+    let mut sbuffer = vec![0.0; 77 * to];
+    loop {
+        let n = v.get_samples_float_interleaved(to as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        floats.extend_from_slice(&sbuffer[.. n * to]);
+    }
+    if !same(&floats, expected) {
+        fail(format!("{}: get_samples_float_interleaved differs", name));
+    }
+
+    let mut v = open(stream, mix);
+    if !same(&interleave(&decode_planar(&mut v, to)), expected) {
+        fail(format!("{}: get_samples_float differs", name));
+    }
+
+    let mut v = open(stream, mix);
+    let mut shorts = Vec::new();
+    let mut sbuffer = vec![0; 77 * to];
+    loop {
+        let n = stb_vorbis_get_samples_short_interleaved(&mut v, to as u32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        shorts.extend_from_slice(&sbuffer[.. n * to]);
+    }
+    if !same_short(&shorts, expected) {
+        fail(format!("{}: get_samples_short_interleaved differs", name));
+    }
+
+    let mut v = open(stream, mix);
+    let mut planar = vec![vec![0; 77]; to];
+    let mut decoded = vec![Vec::new(); to];
+    loop {
+        let mut buffer = unsafe { AudioBufferSlice::from(&mut planar) };
+        let n = unsafe { stb_vorbis_get_samples_short(&mut v, to as i32, &mut buffer) } as usize;
+        if n == 0 {
+            break;
+        }
+        for c in 0 .. to {
+            decoded[c].extend_from_slice(&planar[c][.. n]);
+        }
+    }
+    let shorts: Vec<i16> = (0 .. decoded[0].len()).flat_map(|i| decoded.iter().map(move |channel| channel[i])).collect();
+    if !same_short(&shorts, expected) {
+        fail(format!("{}: get_samples_short differs", name));
+    }
+
+    let mut v = open(stream, mix);
+    let mut shorts = Vec::new();
+    let mut sbuffer = vec![0; 4096 * to];
+    loop {
+        let n = stb_vorbis_get_frame_short_interleaved(&mut v, to as u32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        shorts.extend_from_slice(&sbuffer[.. n * to]);
+    }
+    if !same_short(&shorts, expected) {
+        fail(format!("{}: get_frame_short_interleaved differs", name));
+    }
+}
+
+fn normalized(gains: &[f32], from: usize) -> Vec<f32> {
+    let loudest = gains.chunks(from).map(|row| row.iter().sum::<f32>()).fold(1.0, f32::max);
+    gains.iter().map(|&gain| gain / loudest).collect()
+}
+
+fn main() {
+    let streams: Vec<Vec<u8>> = (0 ..= 8).map(|n| build_stream(&(0 .. n).collect::<Vec<usize>>())).collect();
+    let decoded: Vec<Vec<Vec<f32>>> = (0 ..= 8).map(|n| {
+        if n == 0 {
+            return Vec::new();
+        }
+        let mut v = open(&streams[n], &ChannelMix::Standard);
+        decode_planar(&mut v, n)
+    }).collect();
+
+    // the speakers of each channel count, and their channel masks
+    let layouts = [
+        (ChannelLayout::Mono, 0x4),
+        (ChannelLayout::Stereo, 0x3),
+        (ChannelLayout::Surround30, 0x7),
+        (ChannelLayout::Quad, 0x33),
+        (ChannelLayout::Surround50, 0x37),
+        (ChannelLayout::Surround51, 0x3f),
+        (ChannelLayout::Surround61, 0x70f),
+        (ChannelLayout::Surround71, 0x63f),
+    ];
+    for (k, &(layout, mask)) in layouts.iter().enumerate() {
+        let v = open(&streams[k + 1], &ChannelMix::Standard);
+        let info = v.get_info();
+        if info.layout != layout || layout.channels() != k + 1 || layout.speakers().len() != k + 1 || layout.channel_mask() != mask {
+            fail(format!("{} channels: layout {:?}, mask {:x}", k + 1, info.layout, info.layout.channel_mask()));
+        }
+    }
+    if ChannelLayout::Surround71.speakers()[3] != Speaker::SideLeft || ChannelLayout::Surround51.speakers()[5] != Speaker::Lfe {
+        fail(format!("speakers out of the Vorbis I order"));
+    }
+    println!("  layouts of 1 to 8 channels: OK");
+
+    // some of the standard mixes, in the Vorbis I channel order
+    let known: Vec<(usize, usize, Vec<f32>)> = vec![
+        (1, 2, vec![M3, M3]),
+        (2, 1, vec![0.5, 0.5]),
+        (3, 2, normalized(&[1.0, M3, 0.0,
+                            0.0, M3, 1.0], 3)),
+        (6, 2, normalized(&[1.0, M3, 0.0, M3, 0.0, 0.0,
+                            0.0, M3, 1.0, 0.0, M3, 0.0], 6)),
+        (6, 1, normalized(&[M3, 1.0, M3, 0.5, 0.5, 0.0], 6)),
+        (8, 2, normalized(&[1.0, M3, 0.0, M3, 0.0, M3, 0.0, 0.0,
+                            0.0, M3, 1.0, 0.0, M3, 0.0, M3, 0.0], 8)),
+        (2, 6, vec![1.0, 0.0,
+                    0.0, 0.0,
+                    0.0, 1.0,
+                    0.0, 0.0,
+                    0.0, 0.0,
+                    0.0, 0.0]),
+        (7, 8, vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                    0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+                    0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0,
+                    0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0,
+                    0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0,
+                    0.0, 0.0, 0.0, 0.0, 0.0, M3,  0.0,
+                    0.0, 0.0, 0.0, 0.0, 0.0, M3,  0.0,
+                    0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]),
+    ];
+    for &(from, to, ref gains) in known.iter() {
+        let name = format!("{} to {} channels", from, to);
+        check_outputs(&streams[from], &ChannelMix::Standard, to, &mix(gains, &decoded[from]), &name);
+        println!("  {}: OK", name);
+    }
+
+    // all of them: the same through every function, never louder than the
+    // loudest input, and untouched if the channel count is the same
+    for from in 1 ..= 8 {
+        let loudest = decoded[from].iter().flat_map(|channel| channel.iter()).fold(0.0f32, |m, x| m.max(x.abs()));
+        for to in 1 ..= 8 {
+            let name = format!("{} to {} channels", from, to);
+            let mut v = open(&streams[from], &ChannelMix::Standard);
+            let mut floats = Vec::new();
+            let mut sbuffer = vec![0.0; 100 * to];
+            loop {
+                let n = v.get_samples_float_interleaved(to as i32, &mut sbuffer) as usize;
+                if n == 0 {
+                    break;
+                }
+                floats.extend_from_slice(&sbuffer[.. n * to]);
+            }
+            if floats.iter().any(|x| x.abs() > loudest + 1e-4) {
+                fail(format!("{}: louder than the input", name));
+            }
+            if from == to && floats != interleave(&decoded[from]) {
+                fail(format!("{}: channels changed", name));
+            }
+            check_outputs(&streams[from], &ChannelMix::Standard, to, &floats, &name);
+        }
+    }
+    println!("  standard mixes between 1 and 8 channels: OK");
+
+    // a matrix of our own for 6 channels to 3, and the standard mix for
+    // everything it doesn't cover
+    let gains = vec![0.1, 0.2, 0.3, 0.0, 0.0, 0.4,
+                     0.0, 0.0, 0.0, 0.5, 0.5, 0.0,
+                     -0.5, 0.0, 0.5, 0.0, 0.0, 0.0];
+    let user = ChannelMix::Matrix { inputs: 6, outputs: 3, gains: gains.clone() };
+    check_outputs(&streams[6], &user, 3, &mix(&gains, &decoded[6]), "6 to 3 channels by matrix");
+    check_outputs(&streams[6], &user, 2, &mix(&known[3].2, &decoded[6]), "6 to 2 channels, matrix for 3");
+    let swap = ChannelMix::Matrix { inputs: 2, outputs: 2, gains: vec![0.0, 1.0, 1.0, 0.0] };
+    check_outputs(&streams[2], &swap, 2, &mix(&[0.0, 1.0, 1.0, 0.0], &decoded[2]), "2 channels swapped by matrix");
+    let mut v = open(&streams[6], &ChannelMix::Standard);
+    if v.set_channel_mix(ChannelMix::Matrix { inputs: 6, outputs: 2, gains: vec![1.0; 6] }) {
+        fail(format!("a 6 by 2 matrix with 6 gains was taken"));
+    }
+    if *v.get_channel_mix() != ChannelMix::Standard {
+        fail(format!("a refused matrix changed the mix"));
+    }
+    println!("  user matrices: OK");
+
+    // the original rules: left and center into left, right and center into
+    // right for 16-bit output, the first channels for float output
+    let original = [1.0, 1.0, 0.0, 1.0, 0.0, 1.0,
+                    0.0, 1.0, 1.0, 0.0, 1.0, 1.0];
+    let mut v = open(&streams[6], &ChannelMix::Original);
+    let mut shorts = Vec::new();
+    let mut sbuffer = vec![0; 200];
+    loop {
+        let n = stb_vorbis_get_samples_short_interleaved(&mut v, 2, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        shorts.extend_from_slice(&sbuffer[.. n * 2]);
+    }
+    if !same_short(&shorts, &mix(&original, &decoded[6])) {
+        fail(format!("original rules: 16-bit stereo differs"));
+    }
+    let mut v = open(&streams[6], &ChannelMix::Original);
+    if !same(&interleave(&decode_planar(&mut v, 2)), &interleave(&decoded[6][.. 2])) {
+        fail(format!("original rules: float stereo differs"));
+    }
+    let mut v = open(&streams[2], &ChannelMix::Standard);
+    stb_vorbis_set_channel_mix(&mut v, ChannelMix::Original);
+    let mut shorts = Vec::new();
+    loop {
+        let n = stb_vorbis_get_samples_short_interleaved(&mut v, 1, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        shorts.extend_from_slice(&sbuffer[.. n]);
+    }
+    if !same_short(&shorts, &mix(&[1.0, 1.0], &decoded[2])) {
+        fail(format!("original rules: 16-bit mono differs"));
+    }
+    println!("  original rules: OK");
+}
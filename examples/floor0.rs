@@ -0,0 +1,269 @@
+extern crate stb_vorbis;
+
+use std::f64::consts::PI;
+
+use stb_vorbis::VorbisDecoder;
+
+mod common;
+use common::{fail, BitWriter, id_header, comment_header, write_codebook, write_page, BLOCKSIZE, SAMPLE_RATE};
+
+// floor 0 checks: no test file with a floor 0 has a reference decode, so
+// two streams are put together bit by bit, one with a floor 0 and one with
+// a floor 1 held flat at 1.0, and every other packet of both has a residue
+// of a single 1.0 at one bin. the floor 0 output of such a packet is the
+// floor 1 output scaled by the floor 0 curve at that bin, which has to be
+// what the spec's formula gives for that packet's amplitude and LSP
+// coefficients, for an odd and an even order. takes no arguments, exits
+// with 1 on mismatch.
+
+const BINS: usize = BLOCKSIZE / 2;
+const RATE: u32 = 44100;
+const BARK_MAP_SIZE: u32 = 256;
+const AMPLITUDE_BITS: u32 = 6;
+const AMPLITUDE_OFFSET: u32 = 20;
+
+// what one packet carries; amplitude 0 is an unused floor and no residue
+struct Packet {
+    amplitude: u32,
+    lsp: Vec<u32>,      // one codeword per coefficient, 0 is 0.25 and 1 is 0.5
+    bin: usize,
+}
+
+// every odd packet puts its 1.0 in the next bin, the even ones are silent
+fn packets(order: usize) -> Vec<Packet> {
+    let mut seed: u32 = 0x2545f491 ^ order as u32;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    let mut out = Vec::new();
+    for p in 0 .. 2 * BINS + 1 {
+        if p % 2 == 0 {
+            out.push(Packet { amplitude: 0, lsp: Vec::new(), bin: 0 });
+        } else {
+            let amplitude = 1 + next() % ((1 << AMPLITUDE_BITS) - 1);
+            let lsp = (0 .. order).map(|_| next() & 1).collect();
+            out.push(Packet { amplitude: amplitude, lsp: lsp, bin: p / 2 });
+        }
+    }
+    return out;
+}
+
+// 0.25 + 0.25 * entry, a coefficient each
+fn write_lsp_codebook(w: &mut BitWriter) {
+    w.write(0x564342, 24);
+    w.write(1, 16);                   // dimensions
+    w.write(2, 24);                   // entries
+    w.write(0, 1);                    // not ordered
+    w.write(0, 1);                    // not sparse
+    w.write(0, 5);                    // both one bit long
+    w.write(0, 5);
+    w.write(1, 4);
+    w.write((786 << 21) | 1, 32);     // minimum 0.25
+    w.write((786 << 21) | 1, 32);     // delta 0.25
+    w.write(0, 4);                    // one bit per value
+    w.write(0, 1);
+    w.write(0, 1);
+    w.write(1, 1);
+}
+
+fn setup_header(order: Option<usize>) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.write(5, 8);
+    w.write_bytes(b"vorbis");
+
+    w.write(2, 8);                    // three codebooks
+    write_codebook(&mut w, false);    // 0: residue classes
+    write_codebook(&mut w, true);     // 1: residue values
+    write_lsp_codebook(&mut w);       // 2: floor 0 coefficients
+
+    w.write(0, 6);                    // one time domain transform
+    w.write(0, 16);
+
+    w.write(0, 6);                    // one floor
+    match order {
+        Some(order) => {
+            w.write(0, 16);           // type 0
+            w.write(order as u32, 8);
+            w.write(RATE, 16);
+            w.write(BARK_MAP_SIZE, 16);
+            w.write(AMPLITUDE_BITS, 6);
+            w.write(AMPLITUDE_OFFSET, 8);
+            w.write(0, 4);            // one book
+            w.write(2, 8);
+        }
+        None => {
+            w.write(1, 16);           // type 1
+            w.write(0, 5);            // no partitions
+            w.write(0, 2);            // multiplier 1
+            w.write(7, 4);            // range bits, end point at 128
+        }
+    }
+
+    w.write(0, 6);                    // one residue
+    w.write(1, 16);                   // type 1
+    w.write(0, 24);                   // begin
+    w.write(BINS as u32, 24);         // end
+    w.write(BINS as u32 - 1, 24);     // partition size
+    w.write(0, 6);                    // one class
+    w.write(0, 8);                    // class book
+    w.write(1, 3);                    // values book on the first pass only
+    w.write(0, 1);
+    w.write(1, 8);
+
+    w.write(0, 6);                    // one mapping
+    w.write(0, 16);
+    w.write(0, 1);                    // one submap
+    w.write(0, 1);                    // no coupling
+    w.write(0, 2);
+    w.write(0, 8);
+    w.write(0, 8);                    // floor 0
+    w.write(0, 8);                    // residue 0
+
+    w.write(0, 6);                    // one mode
+    w.write(0, 1);                    // short blocks
+    w.write(0, 16);
+    w.write(0, 16);
+    w.write(0, 8);                    // mapping 0
+
+    w.write(1, 1);
+    w.into_data()
+}
+
+fn audio_packet(packet: &Packet, floor0: bool) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.write(0, 1);                    // audio, no mode bits with one mode
+    if floor0 {
+        w.write(packet.amplitude, AMPLITUDE_BITS as usize);
+        if packet.amplitude != 0 {
+            w.write(0, 1);            // book 0
+            for &codeword in &packet.lsp {
+                w.write(codeword, 1);
+            }
+        }
+    } else if packet.amplitude != 0 {
+        w.write(1, 1);                // flat at the top of the range, 1.0
+        w.write(255, 8);
+        w.write(255, 8);
+    } else {
+        w.write(0, 1);
+    }
+    if packet.amplitude != 0 {
+        w.write(0, 1);                // class 0
+        for k in 0 .. BINS {
+            w.write((k == packet.bin) as u32, 1);
+        }
+    }
+    w.into_data()
+}
+
+fn build_stream(packets: &[Packet], order: Option<usize>) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_page(&mut out, 1, &[id_header(1, SAMPLE_RATE)], 2, 0, 0);
+    write_page(&mut out, 1, &[comment_header(), setup_header(order)], 0, 0, 1);
+    for (p, packet) in packets.iter().enumerate() {
+        let flags = if p == packets.len() - 1 { 4 } else { 0 };
+        let data = audio_packet(packet, order.is_some());
+        write_page(&mut out, 1, &[data], flags, (p * BINS) as u64, p as u32 + 2);
+    }
+    return out;
+}
+
+fn decode(data: &[u8], name: &str) -> Vec<f32> {
+    let mut v = match VorbisDecoder::new(data) {
+        Ok(v) => v,
+        Err(why) => fail(format!("{}: {}", name, why)),
+    };
+    let mut samples = Vec::new();
+    let mut buffer = vec![0.0; 1024];
+    loop {
+        let n = v.get_samples_float_interleaved(1, &mut buffer) as usize;
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&buffer[.. n]);
+    }
+    return samples;
+}
+
+fn bark(x: f64) -> f64 {
+    13.1 * (0.00074 * x).atan() + 2.24 * (0.0000000185 * x * x).atan() + 0.0001 * x
+}
+
+// the floor 0 curve at one bin, straight from the spec
+fn reference(packet: &Packet, order: usize) -> f64 {
+    let rate = RATE as f64;
+    let size = BARK_MAP_SIZE as f64;
+    let i = packet.bin as f64;
+    let map = (size - 1.0).min((bark(rate * i / (2.0 * BINS as f64)) * size / bark(0.5 * rate)).floor());
+    let cos_omega = (PI * map / size).cos();
+
+    let mut coefficients = Vec::new();
+    let mut last = 0.0;
+    for &codeword in &packet.lsp {
+        last += 0.25 + 0.25 * codeword as f64;
+        coefficients.push(last);
+    }
+    let term = |j: usize| 4.0 * (coefficients[j].cos() - cos_omega).powi(2);
+    let (mut p, mut q);
+    if order % 2 == 1 {
+        p = 1.0 - cos_omega * cos_omega;
+        q = 0.25;
+    } else {
+        p = (1.0 - cos_omega) / 2.0;
+        q = (1.0 + cos_omega) / 2.0;
+    }
+    for j in 0 .. order {
+        if j % 2 == 1 {
+            p *= term(j);
+        } else {
+            q *= term(j);
+        }
+    }
+    let amplitude = packet.amplitude as f64;
+    let offset = AMPLITUDE_OFFSET as f64;
+    let bits = ((1u32 << AMPLITUDE_BITS) - 1) as f64;
+    (0.11512925 * (amplitude * offset / (bits * (p + q).sqrt()) - offset)).exp()
+}
+
+fn check(order: usize) {
+    let name = format!("order {}", order);
+    let packets = packets(order);
+    let floor0 = decode(&build_stream(&packets, Some(order)), &name);
+    let flat = decode(&build_stream(&packets, None), &format!("{}, floor 1", name));
+    if floor0.len() != (packets.len() - 1) * BINS || flat.len() != floor0.len() {
+        fail(format!("{}: {} and {} samples", name, floor0.len(), flat.len()));
+    }
+
+    let mut worst: f64 = 0.0;
+    for (p, packet) in packets.iter().enumerate().filter(|&(_, packet)| packet.amplitude != 0) {
+        // the packet overlaps the silent ones on either side of it
+        let range = (p - 1) * BINS .. (p + 1) * BINS;
+        let (a, b) = (&floor0[range.clone()], &flat[range]);
+        let ab: f64 = a.iter().zip(b).map(|(&x, &y)| x as f64 * y as f64).sum();
+        let bb: f64 = b.iter().map(|&y| y as f64 * y as f64).sum();
+        let aa: f64 = a.iter().map(|&x| x as f64 * x as f64).sum();
+        if bb == 0.0 {
+            fail(format!("{}: bin {} decoded to silence with a flat floor", name, packet.bin));
+        }
+        let scale = ab / bb;
+        let residual: f64 = a.iter().zip(b).map(|(&x, &y)| (x as f64 - scale * y as f64).powi(2)).sum();
+        if residual > 1e-8 * aa {
+            fail(format!("{}: bin {} isn't the flat floor output scaled", name, packet.bin));
+        }
+        let expected = reference(packet, order);
+        let error = (scale - expected).abs() / expected;
+        if error > 1e-3 {
+            fail(format!("{}: bin {} has a floor of {}, expected {}", name, packet.bin, scale, expected));
+        }
+        worst = worst.max(error);
+    }
+    println!("  {}: OK, {} bins within {:.1e} of the spec", name, BINS, worst);
+}
+
+fn main() {
+    check(7);
+    check(8);
+}
@@ -10,10 +10,10 @@ use std::process;
 
 use stb_vorbis::{
     stb_vorbis_get_info, stb_vorbis_get_error, 
-    stb_vorbis_open_filename, stb_vorbis_seek,
+    stb_vorbis_open_filename, stb_vorbis_seek, stb_vorbis_set_channel_mix,
     stb_vorbis_get_samples_short_interleaved, stb_vorbis_get_samples_short
 };
-use stb_vorbis::{Vorbis, VorbisError, AudioBufferSlice};
+use stb_vorbis::{Vorbis, VorbisErrorKind, AudioBufferSlice, ChannelMix};
 
 
 fn show_info(v: &mut Vorbis) {
@@ -90,8 +90,8 @@ fn main() {
     let v = stb_vorbis_open_filename(&filename);
     let mut v = match v {
         Err(why) => {
-            println!("Couldn't open {}. Error: {:?}'", filename.display(), why);
-            process::exit(why as i32);
+            println!("Couldn't open {}. Error: {}'", filename.display(), why);
+            process::exit(why.kind() as i32);
         },
         Ok(v) => v,
     };
@@ -99,12 +99,15 @@ fn main() {
     println!("test_stb_vorbis_get_info()");
     show_info(&mut v);
 
+    // the output is compared against the original stb_vorbis
+    stb_vorbis_set_channel_mix(&mut v, ChannelMix::Original);
+
     println!("test_get_samples_short_interleaved(). {}", filename.display());
     let bytes = test_get_samples_short_interleaved(&mut v);
 
     // test stb_vorbis_get_error
     let error = stb_vorbis_get_error(&mut v);
-    if error != VorbisError::NoError {
+    if error != VorbisErrorKind::NoError {
         println!("Error: {:?}'", error);
         process::exit(error as i32);
     }
@@ -0,0 +1,249 @@
+extern crate stb_vorbis;
+
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+use std::process;
+
+use stb_vorbis::{
+    stb_vorbis_open_file_section, stb_vorbis_open_reader, stb_vorbis_seek,
+    stb_vorbis_get_sample_offset, stb_vorbis_get_file_offset,
+    stb_vorbis_stream_length_in_samples, stb_vorbis_get_samples_float_interleaved,
+};
+use stb_vorbis::{Vorbis, VorbisDecoder};
+
+mod common;
+use common::{fail, same, opened, decode_linear, check_seek_to, set_crc};
+
+// 64-bit offset checks: the stream is moved to granule positions past 2^33
+// (a couple of days in at 48kHz) and then
+//   - written after a 5 GiB hole of a sparse file, opened as a file section
+//   - spread across a 4.5 GiB section of a virtual file, interleaved with
+//     pages of another logical stream
+// seeking, length and offsets are compared against decoding the file as is.
+// exits with 1 on mismatch.
+
+const GRANULE_SHIFT: u64 = 1 << 33;
+const HOLE: u64 = 5 << 30;
+const SPREAD: u64 = 9 << 29; // 4.5 GiB
+
+fn granule(page: &[u8]) -> u64 {
+    (6 .. 14).rev().fold(0, |x, i| (x << 8) | page[i] as u64)
+}
+
+// the pages of the stream, audio pages moved GRANULE_SHIFT samples on
+fn shifted_pages(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut pages = Vec::new();
+    let mut p = 0;
+    while p + 27 <= data.len() {
+        let segments = data[p + 26] as usize;
+        let len = 27 + segments + data[p + 27 .. p + 27 + segments].iter().map(|&x| x as usize).sum::<usize>();
+        let mut page = data[p .. p + len].to_vec();
+        let position = granule(&page);
+        if position != !0 && position != 0 {
+            let position = position + GRANULE_SHIFT;
+            for i in 0 .. 8 {
+                page[6 + i] = (position >> (8 * i)) as u8;
+            }
+            set_crc(&mut page);
+        }
+        pages.push(page);
+        p += len;
+    }
+    return pages;
+}
+
+// one full page (255 segments of 255 bytes) of some other logical stream;
+// every one of them is the same, so only one checksum is needed
+fn junk_page() -> Vec<u8> {
+    let mut page = vec![b'O', b'g', b'g', b'S', 0, 0];
+    page.extend_from_slice(&[0xff; 8]); // no packet ends here
+    page.extend_from_slice(&[0xb8, 0x0b, 0, 0]); // serial 3000
+    page.extend_from_slice(&[0; 8]);
+    page.push(255);
+    page.extend_from_slice(&[255; 255]);
+    page.extend((0 .. 255 * 255).map(|i| (i * 7) as u8));
+    set_crc(&mut page);
+    return page;
+}
+
+enum Part {
+    Data(Vec<u8>),
+    Junk(u64), // that many junk pages
+}
+
+// a huge file that only exists as a list of parts
+struct VirtualFile {
+    parts: Vec<(u64, Part)>, // with where each starts
+    junk: Vec<u8>,
+    len: u64,
+    pos: u64,
+}
+
+impl VirtualFile {
+    fn new(parts: Vec<Part>) -> VirtualFile {
+        let junk = junk_page();
+        let mut start = 0;
+        let mut list = Vec::new();
+        for part in parts {
+            let len = match part {
+                Part::Data(ref data) => data.len() as u64,
+                Part::Junk(pages) => pages * junk.len() as u64,
+            };
+            list.push((start, part));
+            start += len;
+        }
+        VirtualFile { parts: list, junk: junk, len: start, pos: 0 }
+    }
+}
+
+impl Read for VirtualFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let k = match self.parts.binary_search_by(|&(start, _)| start.cmp(&self.pos)) {
+            Ok(k) => k,
+            Err(k) => k - 1,
+        };
+        let at = self.pos - self.parts[k].0;
+        let n = match self.parts[k].1 {
+            Part::Data(ref data) => {
+                let n = std::cmp::min(buf.len(), data.len() - at as usize);
+                buf[.. n].copy_from_slice(&data[at as usize .. at as usize + n]);
+                n
+            }
+            Part::Junk(_) => {
+                let at = (at % self.junk.len() as u64) as usize;
+                let n = std::cmp::min(buf.len(), self.junk.len() - at);
+                buf[.. n].copy_from_slice(&self.junk[at .. at + n]);
+                n
+            }
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for VirtualFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => self.len as i64 + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+// 'first' is the first sample that can be seeked to: in a stream that
+// doesn't start at 0, positions are only known from the end of the first
+// audio page on, give or take a block
+fn check(v: &mut Vorbis, channels: usize, reference: &[f32], first: u64, name: &str) {
+    let total = (reference.len() / channels) as u64;
+    let length = stb_vorbis_stream_length_in_samples(v);
+    if length != GRANULE_SHIFT + total {
+        fail(format!("{}: stream length {}, expected {}", name, length, GRANULE_SHIFT + total));
+    }
+
+    let mut targets = vec![];
+    if first < total {
+        let mut seed: u32 = 12345;
+        for _ in 0 .. 16 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            targets.push(first + (seed >> 8) as u64 % (total - first));
+        }
+        targets.push(first);
+        targets.push(total - 1);
+    }
+
+    for &target in targets.iter() {
+        let expected = &reference[target as usize * channels ..];
+        check_seek_to(v, (GRANULE_SHIFT + target) as usize, expected, name);
+    }
+
+    // and on to the end from the first of them
+    if first < total && stb_vorbis_seek(v, GRANULE_SHIFT + first) {
+        let mut sbuffer = vec![0.0; 1000 * channels];
+        let mut position = first as usize;
+        loop {
+            let n = stb_vorbis_get_samples_float_interleaved(v, channels as i32, &mut sbuffer) as usize;
+            if n == 0 {
+                break;
+            }
+            if position + n > total as usize || !same(&sbuffer[.. n * channels], &reference[position * channels .. (position + n) * channels]) {
+                fail(format!("{}: samples at {} differ", name, GRANULE_SHIFT + position as u64));
+            }
+            position += n;
+        }
+        if stb_vorbis_get_sample_offset(v) != (GRANULE_SHIFT + total) as i64 || position != total as usize {
+            fail(format!("{}: decoding stopped at {}", name, stb_vorbis_get_sample_offset(v)));
+        }
+    }
+
+    if stb_vorbis_seek(v, GRANULE_SHIFT + total + 1) {
+        fail(format!("{}: seek past the end succeeded", name));
+    }
+    println!("  {}: {} seeks OK, {} bytes in at the end", name, targets.len(), stb_vorbis_get_file_offset(v));
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+    let mut v = opened(VorbisDecoder::from_vec(data.clone()));
+    let channels = v.channels as usize;
+    let reference = decode_linear(&mut v);
+
+    let pages = shifted_pages(&data);
+    let first = match pages.iter().map(|page| granule(page)).find(|&x| x != 0 && x != !0) {
+        Some(x) => x - GRANULE_SHIFT + 4096,
+        None => {
+            println!("no audio pages, nothing to check");
+            return;
+        }
+    };
+    let stream = pages.concat();
+
+    // a sparse file with the stream after the hole
+    let path = std::env::temp_dir().join(format!("stb_vorbis_large_file_{}.ogg", process::id()));
+    {
+        let mut file = File::create(&path).unwrap();
+        file.seek(SeekFrom::Start(HOLE)).unwrap();
+        file.write_all(&stream).unwrap();
+    }
+    let mut file = File::open(&path).unwrap();
+    file.seek(SeekFrom::Start(HOLE)).unwrap();
+    let result = stb_vorbis_open_file_section(file, stream.len() as u64);
+    let _ = fs::remove_file(&path);
+    let mut v = match result {
+        Ok(v) => v,
+        Err(why) => fail(format!("sparse file: {}", why)),
+    };
+    check(&mut v, channels, &reference, first, "sparse file");
+
+    // the same stream spread over more than 4 GiB. the header pages stay
+    // together, each audio page gets its share of junk after it
+    let audio = pages.iter().position(|page| granule(page) != 0).unwrap_or(pages.len());
+    let junk = junk_page().len() as u64;
+    let per_page = SPREAD / junk / std::cmp::max(pages.len() - audio, 1) as u64 + 1;
+    let mut parts = vec![Part::Data(pages[.. audio].concat())];
+    for page in &pages[audio ..] {
+        parts.push(Part::Data(page.clone()));
+        parts.push(Part::Junk(per_page));
+    }
+    let file = VirtualFile::new(parts);
+    let len = file.len;
+    let mut v = match stb_vorbis_open_reader(file) {
+        Ok(v) => v,
+        Err(why) => fail(format!("virtual file: {}", why)),
+    };
+    check(&mut v, channels, &reference, first, &format!("{} byte file", len));
+    if stb_vorbis_get_file_offset(&mut v) <= 1u64 << 32 {
+        fail(format!("virtual file: offset {} after seeking to the end", stb_vorbis_get_file_offset(&mut v)));
+    }
+}
@@ -0,0 +1,186 @@
+extern crate stb_vorbis;
+
+use std::io::Cursor;
+
+use stb_vorbis::{
+    stb_vorbis_open_pushdata, stb_vorbis_decode_frame_pushdata, stb_vorbis_get_samples_float,
+    stb_vorbis_get_samples_short_interleaved, stb_vorbis_open_reader_with_limits,
+    stb_vorbis_open_pushdata_with_limits,
+};
+use stb_vorbis::{OwnedVorbisDecoder, VorbisDecoder, AudioBufferSlice, ChannelLayout, SetupLimits, VorbisErrorKind};
+
+mod common;
+use common::{fail, same, build_stream, PACKETS, BLOCKSIZE};
+
+// channel count checks: streams with more channels than any test file has
+// (up to the 255 the ID header can hold) are put together bit by bit, with
+// every channel carrying its own signal. each channel has to decode the same
+// as a mono stream made of that channel's data alone, through the float,
+// short, frame and push APIs. a channel cap one under the stream's has to
+// turn it down with TooManyChannels in every mode, and one at it let it
+// open. takes no arguments, exits with 1 on mismatch.
+
+const CHANNEL_COUNTS: [usize; 5] = [1, 16, 17, 64, 255];
+
+fn decode_interleaved(data: &[u8], channels: usize, name: &str) -> Vec<f32> {
+    let mut v = match VorbisDecoder::new(data) {
+        Ok(v) => v,
+        Err(why) => fail(format!("{}: {}", name, why)),
+    };
+    if v.get_info().channels as usize != channels || v.get_info().layout.channels() != channels {
+        fail(format!("{}: {} channels, expected {}", name, v.get_info().channels, channels));
+    }
+    if channels > 8 && (v.get_info().layout != ChannelLayout::Discrete(channels) || v.get_info().layout.channel_mask() != 0) {
+        fail(format!("{}: layout {:?}", name, v.get_info().layout));
+    }
+    let mut samples = Vec::new();
+    let mut sbuffer = vec![0.0; 100 * channels];
+    loop {
+        let n = v.get_samples_float_interleaved(channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+    if samples.len() != (PACKETS - 1) * BLOCKSIZE / 2 * channels {
+        fail(format!("{}: {} samples per channel", name, samples.len() / channels));
+    }
+    return samples;
+}
+
+fn column(samples: &[f32], channels: usize, c: usize) -> Vec<f32> {
+    samples.iter().skip(c).step_by(channels).cloned().collect()
+}
+
+fn check(channels: usize) {
+    let all: Vec<usize> = (0 .. channels).collect();
+    let stream = build_stream(&all);
+    let name = format!("{} channels", channels);
+    let interleaved = decode_interleaved(&stream, channels, &name);
+    if interleaved.iter().all(|x| x.abs() < 1e-3) || interleaved.iter().any(|x| x.abs() > 1.0) {
+        fail(format!("{}: decoded to silence or out of range", name));
+    }
+
+    // every channel against its own mono stream
+    let columns: Vec<Vec<f32>> = (0 .. channels).map(|c| column(&interleaved, channels, c)).collect();
+    for c in 0 .. channels {
+        let mono = decode_interleaved(&build_stream(&[c]), 1, &format!("{}, channel {} alone", name, c));
+        if !same(&columns[c], &mono) {
+            fail(format!("{}: channel {} differs from its mono stream", name, c));
+        }
+    }
+
+    // non-interleaved, into a buffer per channel
+    let mut v = OwnedVorbisDecoder::from_vec(stream.clone()).unwrap();
+    let mut planar = vec![vec![0.0; 100]; channels];
+    let mut decoded = vec![Vec::new(); channels];
+    loop {
+        let mut buffer = unsafe { AudioBufferSlice::from(&mut planar) };
+        let n = stb_vorbis_get_samples_float(&mut v, channels as i32, &mut buffer) as usize;
+        if n == 0 {
+            break;
+        }
+        for c in 0 .. channels {
+            decoded[c].extend_from_slice(&planar[c][.. n]);
+        }
+    }
+    if decoded != columns {
+        fail(format!("{}: get_samples_float differs", name));
+    }
+
+    // one frame at a time
+    let mut v = OwnedVorbisDecoder::from_vec(stream.clone()).unwrap();
+    let mut decoded = vec![Vec::new(); channels];
+    loop {
+        let mut num_c = 0;
+        let mut outputs = AudioBufferSlice::new(0);
+        let n = v.get_frame_float(Some(&mut num_c), Some(&mut outputs)) as usize;
+        if n == 0 {
+            break;
+        }
+        if num_c as usize != channels || outputs.channel_count() != channels {
+            fail(format!("{}: frame with {} channels", name, num_c));
+        }
+        for c in 0 .. channels {
+            decoded[c].extend_from_slice(&outputs[c][.. n]);
+        }
+    }
+    if decoded != columns {
+        fail(format!("{}: get_frame_float differs", name));
+    }
+
+    // 16-bit
+    let mut v = OwnedVorbisDecoder::from_vec(stream.clone()).unwrap();
+    let mut shorts = Vec::new();
+    let mut sbuffer = vec![0; 100 * channels];
+    loop {
+        let n = stb_vorbis_get_samples_short_interleaved(&mut v, channels as u32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        shorts.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+    if shorts.len() != interleaved.len() || shorts.iter().zip(&interleaved).any(|(&s, &x)| (s as f32 - (x * 32768.0).max(-32768.0).min(32767.0)).abs() > 1.0) {
+        fail(format!("{}: get_samples_short_interleaved differs", name));
+    }
+
+    // push mode, with the whole stream available
+    let mut used = 0;
+    let mut v = match stb_vorbis_open_pushdata(&stream, &mut used) {
+        Ok(v) => v,
+        Err(why) => fail(format!("{}: pushdata: {}", name, why)),
+    };
+    let mut p = used as usize;
+    let mut decoded = vec![Vec::new(); channels];
+    while p < stream.len() {
+        let mut outputs = AudioBufferSlice::new(0);
+        let mut num_c = 0;
+        let mut n = 0;
+        let used = unsafe {
+            stb_vorbis_decode_frame_pushdata(&mut v, &stream[p ..], &mut num_c, &mut outputs, &mut n)
+        };
+        if used == 0 {
+            break;
+        }
+        p += used as usize;
+        for c in 0 .. num_c as usize {
+            decoded[c].extend_from_slice(&outputs[c][.. n as usize]);
+        }
+    }
+    if decoded != columns {
+        fail(format!("{}: pushdata differs", name));
+    }
+
+    println!("  {}: OK", name);
+}
+
+// what opening with at most 'max_channels' channels comes to in each mode
+fn open_all(data: &[u8], max_channels: usize) -> [Result<(), VorbisErrorKind>; 3] {
+    let limits = SetupLimits { max_channels: max_channels, ..SetupLimits::default() };
+    let mut used = 0;
+    [
+        VorbisDecoder::with_limits(data, limits).map(|_| ()).map_err(|e| e.kind()),
+        stb_vorbis_open_reader_with_limits(Cursor::new(data.to_vec()), limits).map(|_| ()).map_err(|e| e.kind()),
+        stb_vorbis_open_pushdata_with_limits(data, limits, &mut used).map(|_| ()).map_err(|e| e.kind()),
+    ]
+}
+
+fn check_limit(channels: usize) {
+    let all: Vec<usize> = (0 .. channels).collect();
+    let stream = build_stream(&all);
+    if open_all(&stream, channels).iter().any(|r| r.is_err()) {
+        fail(format!("{} channels: doesn't open with a cap of {}", channels, channels));
+    }
+    if open_all(&stream, channels - 1).iter().any(|r| *r != Err(VorbisErrorKind::TooManyChannels)) {
+        fail(format!("{} channels: opens with a cap of {}", channels, channels - 1));
+    }
+    println!("  {} channels, capped: OK", channels);
+}
+
+fn main() {
+    for &channels in CHANNEL_COUNTS.iter() {
+        check(channels);
+    }
+    check_limit(2);
+    check_limit(255);
+}
@@ -0,0 +1,306 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::Cursor;
+
+use stb_vorbis::{
+    stb_vorbis_get_info, stb_vorbis_open_pushdata, stb_vorbis_open_pushdata_stream,
+    stb_vorbis_get_sample_offset, stb_vorbis_get_streams,
+    stb_vorbis_get_serial, stb_vorbis_select_stream, stb_vorbis_get_error,
+    stb_vorbis_stream_length_in_samples, stb_vorbis_seek_start,
+    stb_vorbis_get_samples_float_interleaved, stb_vorbis_decode_frame_pushdata,
+};
+use stb_vorbis::{Vorbis, VorbisDecoder, VorbisErrorKind, OggStream, AudioBufferSlice};
+
+mod common;
+use common::{fail, same, opened, decode_linear, seek_targets, check_seek_to, set_crc};
+
+// multiplexed stream checks: the given files (or the one file twice) become
+// two vorbis streams of one ogg file, interleaved page by page with two
+// streams of junk standing in for video and skeleton. both vorbis streams
+// are decoded every way there is and compared against decoding the files
+// on their own. exits with 1 on mismatch.
+
+const JUNK_SERIALS: [u32; 2] = [3000, 3001];
+const VORBIS_SERIALS: [u32; 2] = [2000, 2001];
+
+struct Reference {
+    channels: usize,
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+impl Reference {
+    fn len(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+}
+
+fn put32(page: &mut [u8], at: usize, x: u32) {
+    page[at .. at + 4].copy_from_slice(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]);
+}
+
+// the pages of an ogg stream, each moved to another serial number
+fn relabel(data: &[u8], serial: u32) -> Vec<Vec<u8>> {
+    let mut pages = Vec::new();
+    let mut p = 0;
+    while p + 27 <= data.len() {
+        let segments = data[p + 26] as usize;
+        let len = 27 + segments + data[p + 27 .. p + 27 + segments].iter().map(|&x| x as usize).sum::<usize>();
+        let mut page = data[p .. p + len].to_vec();
+        put32(&mut page, 14, serial);
+        set_crc(&mut page);
+        pages.push(page);
+        p += len;
+    }
+    return pages;
+}
+
+// a page of some stream that isn't vorbis, holding one packet
+fn junk_page(serial: u32, flags: u8, sequence: u32, payload: &[u8]) -> Vec<u8> {
+    let mut lacing = vec![255u8; payload.len() / 255];
+    lacing.push((payload.len() % 255) as u8);
+    let mut page = vec![b'O', b'g', b'g', b'S', 0, flags];
+    page.extend_from_slice(&[0; 20]);
+    page.push(lacing.len() as u8);
+    let granule = sequence as u64 * 1000;
+    put32(&mut page, 6, granule as u32);
+    put32(&mut page, 10, (granule >> 32) as u32);
+    put32(&mut page, 14, serial);
+    put32(&mut page, 18, sequence);
+    page.extend_from_slice(&lacing);
+    page.extend_from_slice(payload);
+    set_crc(&mut page);
+    return page;
+}
+
+struct Junk {
+    serial: u32,
+    sequence: u32,
+    seed: u32,
+}
+
+impl Junk {
+    fn next_page(&mut self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        self.seed = self.seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let len = 200 + (self.seed >> 8) as usize % 4000;
+        for _ in 0 .. len {
+            self.seed = self.seed.wrapping_mul(1103515245).wrapping_add(12345);
+            payload.push((self.seed >> 16) as u8);
+        }
+        self.sequence += 1;
+        return junk_page(self.serial, 0, self.sequence, &payload);
+    }
+}
+
+// all first pages up front, then everything interleaved, then a long tail of
+// junk so the last vorbis pages aren't anywhere near the end of the file
+fn multiplex(a: &[Vec<u8>], b: &[Vec<u8>]) -> Vec<u8> {
+    let mut junk = [
+        Junk { serial: JUNK_SERIALS[0], sequence: 0, seed: 1 },
+        Junk { serial: JUNK_SERIALS[1], sequence: 0, seed: 2 },
+    ];
+    let mut result = Vec::new();
+    result.extend_from_slice(&junk_page(JUNK_SERIALS[0], 2, 0, b"fishead\0 not really a skeleton"));
+    result.extend_from_slice(&a[0]);
+    result.extend_from_slice(&b[0]);
+    result.extend_from_slice(&junk_page(JUNK_SERIALS[1], 2, 0, b"\x80theora not really a video"));
+    for i in 1 .. std::cmp::max(a.len(), b.len()) {
+        if i < a.len() {
+            result.extend_from_slice(&a[i]);
+        }
+        if i % 2 == 0 {
+            result.extend_from_slice(&junk[i / 2 % 2].next_page());
+        }
+        if i < b.len() {
+            result.extend_from_slice(&b[i]);
+        }
+    }
+    for i in 0 .. 40 {
+        result.extend_from_slice(&junk[i % 2].next_page());
+    }
+    return result;
+}
+
+fn decode_reference(data: Vec<u8>) -> Reference {
+    let mut v = opened(VorbisDecoder::from_vec(data));
+    let info = v.get_info();
+    Reference {
+        channels: info.channels as usize,
+        sample_rate: info.sample_rate,
+        samples: decode_linear(&mut v),
+    }
+}
+
+fn check_streams(v: &Vorbis, serial: u32, reference: &Reference, name: &str) {
+    let expected = [
+        OggStream { serial: JUNK_SERIALS[0], vorbis: false },
+        OggStream { serial: VORBIS_SERIALS[0], vorbis: true },
+        OggStream { serial: VORBIS_SERIALS[1], vorbis: true },
+        OggStream { serial: JUNK_SERIALS[1], vorbis: false },
+    ];
+    if stb_vorbis_get_streams(v) != &expected[..] {
+        fail(format!("{}: streams {:?}", name, stb_vorbis_get_streams(v)));
+    }
+    if stb_vorbis_get_serial(v) != serial {
+        fail(format!("{}: decoding stream {} instead of {}", name, stb_vorbis_get_serial(v), serial));
+    }
+    let info = stb_vorbis_get_info(v);
+    if info.channels as usize != reference.channels || info.sample_rate != reference.sample_rate {
+        fail(format!("{}: {} channels at {} Hz", name, info.channels, info.sample_rate));
+    }
+}
+
+fn check_linear(v: &mut Vorbis, reference: &Reference, name: &str) {
+    let channels = reference.channels;
+    let mut sbuffer = vec![0.0; 1000 * channels];
+    let mut position = 0;
+    loop {
+        if stb_vorbis_get_sample_offset(v) != position as i64 {
+            fail(format!("{}: sample offset {} at sample {}", name, stb_vorbis_get_sample_offset(v), position));
+        }
+        let n = stb_vorbis_get_samples_float_interleaved(v, channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        if position + n > reference.len() || !same(&sbuffer[.. n * channels], &reference.samples[position * channels .. (position + n) * channels]) {
+            fail(format!("{}: samples at {} differ", name, position));
+        }
+        position += n;
+    }
+    if position != reference.len() {
+        fail(format!("{}: decoding stopped at sample {} of {}", name, position, reference.len()));
+    }
+}
+
+fn check_seek(v: &mut Vorbis, reference: &Reference, name: &str) {
+    let total = reference.len();
+    if stb_vorbis_stream_length_in_samples(v) as usize != total {
+        fail(format!("{}: stream length {}, expected {}", name, stb_vorbis_stream_length_in_samples(v), total));
+    }
+
+    let targets = seek_targets(total, &[0, 1, total / 2, total - 1]);
+    for &target in targets.iter() {
+        check_seek_to(v, target, &reference.samples[target * reference.channels ..], name);
+    }
+
+    stb_vorbis_seek_start(v);
+    check_linear(v, reference, &format!("{} after seek_start", name));
+    println!("  {}: {} seeks OK", name, targets.len());
+}
+
+fn check_pull(v: &mut Vorbis, references: &[Reference], name: &str) {
+    check_streams(v, VORBIS_SERIALS[0], &references[0], name);
+    check_linear(v, &references[0], name);
+    check_seek(v, &references[0], name);
+
+    // neither a junk stream nor one that isn't there can be picked
+    if stb_vorbis_select_stream(v, JUNK_SERIALS[0]) || stb_vorbis_get_error(v) != VorbisErrorKind::IncorrectStreamSerialNumber {
+        fail(format!("{}: selected a stream that isn't vorbis", name));
+    }
+
+    let name = format!("{} second stream", name);
+    if stb_vorbis_select_stream(v, VORBIS_SERIALS[1]) == false {
+        fail(format!("{}: couldn't select it", name));
+    }
+    check_streams(v, VORBIS_SERIALS[1], &references[1], &name);
+    check_linear(v, &references[1], &name);
+    check_seek(v, &references[1], &name);
+}
+
+fn check_pushdata(buffer: &[u8], serial: Option<u32>, reference: &Reference, name: &str) {
+    let mut used = 0;
+    let mut length = std::cmp::min(buffer.len(), 4096);
+    let mut v = loop {
+        let result = match serial {
+            Some(serial) => stb_vorbis_open_pushdata_stream(&buffer[.. length], serial, &mut used),
+            None => stb_vorbis_open_pushdata(&buffer[.. length], &mut used),
+        };
+        match result {
+            Ok(v) => break v,
+            Err(_) if length < buffer.len() => length = std::cmp::min(buffer.len(), length * 2),
+            Err(why) => fail(format!("{}: {}", name, why)),
+        }
+    };
+    check_streams(&v, serial.unwrap_or(VORBIS_SERIALS[0]), reference, name);
+
+    let mut p = used as usize;
+    let mut decoded = 0;
+    let channels = reference.channels;
+    while p < buffer.len() {
+        let mut q = 4096;
+        let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
+        let mut num_c = 0;
+        let mut n = 0;
+        let used = loop {
+            let end = std::cmp::min(p + q, buffer.len());
+            let used = unsafe {
+                stb_vorbis_decode_frame_pushdata(&mut v, &buffer[p .. end], &mut num_c, &mut outputs, &mut n)
+            };
+            if used == 0 && end < buffer.len() {
+                q *= 2;
+                continue;
+            }
+            break used as usize;
+        };
+        if used == 0 {
+            break;
+        }
+        p += used;
+        if n == 0 {
+            continue;
+        }
+
+        let offset = stb_vorbis_get_sample_offset(&mut v);
+        if offset < 0 || offset as usize - n as usize != decoded || decoded + n as usize > reference.len() {
+            fail(format!("{}: frame at sample {} claims to end at {}", name, decoded, offset));
+        }
+        for c in 0 .. channels {
+            let expected: Vec<f32> = (decoded .. decoded + n as usize).map(|i| reference.samples[i * channels + c]).collect();
+            if !same(&outputs[c][.. n as usize], &expected) {
+                fail(format!("{}: frame at sample {} differs", name, decoded));
+            }
+        }
+        decoded += n as usize;
+    }
+    if decoded != reference.len() {
+        fail(format!("{}: decoding stopped at sample {} of {}", name, decoded, reference.len()));
+    }
+    println!("  {}: {} samples OK", name, decoded);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut files = args[1 ..].to_vec();
+    if files.len() == 1 {
+        files.push(files[0].clone());
+    }
+
+    let mut streams = Vec::new();
+    let mut references = Vec::new();
+    for k in 0 .. 2 {
+        let mut data = Vec::new();
+        File::open(&files[k]).unwrap().read_to_end(&mut data).unwrap();
+        let pages = relabel(&data, VORBIS_SERIALS[k]);
+        references.push(decode_reference(pages.concat()));
+        streams.push(pages);
+    }
+    let file = multiplex(&streams[0], &streams[1]);
+    println!("{} bytes multiplexed", file.len());
+
+    let mut decoder = VorbisDecoder::from_vec(file.clone()).unwrap();
+    check_pull(&mut decoder, &references, "memory");
+
+    let mut reader = Vorbis::from_reader(Cursor::new(file.clone())).unwrap();
+    check_pull(&mut reader, &references, "reader");
+
+    check_pushdata(&file, None, &references[0], "pushdata");
+    check_pushdata(&file, Some(VORBIS_SERIALS[1]), &references[1], "pushdata second stream");
+    let mut used = 0;
+    match stb_vorbis_open_pushdata_stream(&file, JUNK_SERIALS[1], &mut used) {
+        Err(ref why) if why.kind() == VorbisErrorKind::IncorrectStreamSerialNumber => {},
+        _ => fail(format!("pushdata: opened a stream that isn't vorbis")),
+    }
+}
@@ -0,0 +1,190 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+
+use stb_vorbis::{OggPageReader, OggPage, OggPacketSpan, OwnedVorbisDecoder};
+
+mod common;
+use common::{fail, ogg_crc};
+
+// page reader checks: the pages of the file are read back to back, whole
+// and a byte at a time, and every field checked against the bytes with a
+// CRC computed here. packets put together from the spans have to start
+// each logical stream with its ID header. then the file is read after
+// some junk, with a byte of one page changed and cut off in its last page,
+// and a packet spread over two pages is split up. exits with 1 on mismatch.
+
+// hands out a byte per read
+struct Trickle<'a>(&'a [u8]);
+
+impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() || buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = self.0[0];
+        self.0 = &self.0[1 ..];
+        Ok(1)
+    }
+}
+
+fn read_pages<R: Read>(r: R) -> (Vec<OggPage>, Option<io::Error>) {
+    let mut pages = Vec::new();
+    for page in OggPageReader::new(r) {
+        match page {
+            Ok(page) => pages.push(page),
+            Err(e) => return (pages, Some(e)),
+        }
+    }
+    return (pages, None);
+}
+
+fn u32_at(data: &[u8], p: usize) -> u32 {
+    data[p] as u32 | (data[p + 1] as u32) << 8 | (data[p + 2] as u32) << 16 | (data[p + 3] as u32) << 24
+}
+
+// the page against the bytes it came from
+fn check_page(data: &[u8], page: &OggPage, name: &str) {
+    let p = page.offset as usize;
+    let bytes = &data[p .. p + page.size()];
+    let mut zeroed = bytes.to_vec();
+    zeroed[22 .. 26].copy_from_slice(&[0; 4]);
+    let mut granule = 0;
+    for i in (6 .. 14).rev() {
+        granule = granule << 8 | bytes[i] as u64;
+    }
+    if &bytes[.. 4] != b"OggS" || page.version != bytes[4] || page.flags != bytes[5]
+        || page.granule_position != granule || page.serial != u32_at(bytes, 14)
+        || page.sequence != u32_at(bytes, 18) || page.crc != u32_at(bytes, 22)
+        || page.crc_ok != (ogg_crc(&zeroed) == page.crc)
+        || page.lacing[..] != bytes[27 .. 27 + bytes[26] as usize]
+        || page.data[..] != bytes[27 + page.lacing.len() ..]
+    {
+        fail(format!("{}: page at {} doesn't match its bytes", name, p));
+    }
+}
+
+// the packets of every stream, put together from the spans of its pages
+fn packets(pages: &[OggPage], serial: u32) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    let mut partial: Option<Vec<u8>> = None;
+    for page in pages.iter().filter(|p| p.serial == serial) {
+        for span in page.packets() {
+            let mut packet = if span.continued { partial.take().unwrap_or_default() } else { Vec::new() };
+            packet.extend_from_slice(&page.data[span.range.clone()]);
+            if span.complete {
+                packets.push(packet);
+            } else {
+                partial = Some(packet);
+            }
+        }
+    }
+    return packets;
+}
+
+fn raw_page(flags: u8, sequence: u32, lacing: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut page = vec![b'O', b'g', b'g', b'S', 0, flags, 0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0];
+    page.extend_from_slice(&[sequence as u8, 0, 0, 0, 0, 0, 0, 0, lacing.len() as u8]);
+    page.extend_from_slice(lacing);
+    page.extend_from_slice(body);
+    let crc = ogg_crc(&page);
+    page[22 .. 26].copy_from_slice(&[crc as u8, (crc >> 8) as u8, (crc >> 16) as u8, (crc >> 24) as u8]);
+    return page;
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+
+    // the file as it is
+    let (pages, error) = read_pages(Cursor::new(&data));
+    if let Some(e) = error {
+        fail(format!("file: {}", e));
+    }
+    let mut end = 0;
+    for page in &pages {
+        if page.offset != end + page.skipped {
+            fail(format!("file: page at {} after one ending at {} and {} skipped", page.offset, end, page.skipped));
+        }
+        check_page(&data, page, "file");
+        end = page.offset + page.size() as u64;
+    }
+    if read_pages(Trickle(&data)).0 != pages {
+        fail(format!("file: different pages a byte at a time"));
+    }
+
+    // every stream starts with its ID header
+    let mut serials: Vec<u32> = pages.iter().filter(|p| p.is_first()).map(|p| p.serial).collect();
+    serials.dedup();
+    for &serial in &serials {
+        let packets = packets(&pages, serial);
+        if packets.is_empty() || packets[0].is_empty() {
+            fail(format!("file: no packets in stream {:08x}", serial));
+        }
+    }
+    if let Ok(v) = OwnedVorbisDecoder::from_vec(data.clone()) {
+        let packets = packets(&pages, v.get_serial());
+        if packets.len() < 3 || packets[0].starts_with(b"\x01vorbis") == false || packets[2].starts_with(b"\x05vorbis") == false {
+            fail(format!("file: the headers of stream {:08x} aren't its first packets", v.get_serial()));
+        }
+    }
+    println!("  file: OK, {} pages", pages.len());
+    if pages.len() < 2 {
+        return;
+    }
+
+    // after some junk
+    let mut junk = b"OggOgOggjunk".to_vec();
+    junk.extend_from_slice(&data);
+    let (moved, _) = read_pages(Cursor::new(&junk));
+    if moved.len() != pages.len() || moved[0].skipped != pages[0].skipped + 12
+        || moved.iter().zip(&pages).any(|(m, p)| m.offset != p.offset + 12 || m.data != p.data)
+    {
+        fail(format!("junk: pages differ"));
+    }
+
+    // a byte of one page's granule position changed
+    let k = pages.len() / 2;
+    let mut changed = data.clone();
+    changed[pages[k].offset as usize + 6] ^= 0x40;
+    let (bad, _) = read_pages(Cursor::new(&changed));
+    for (i, page) in bad.iter().enumerate() {
+        if page.crc_ok != (pages[i].crc_ok && i != k) {
+            fail(format!("changed: page {} has crc_ok {}", i, page.crc_ok));
+        }
+    }
+
+    // cut off
+    let last = pages.last().unwrap();
+    let cut = &data[.. last.offset as usize + last.size() - 1];
+    let (some, error) = read_pages(Cursor::new(cut));
+    if some.len() != pages.len() - 1 || error.map(|e| e.kind()) != Some(io::ErrorKind::UnexpectedEof) {
+        fail(format!("cut: {} pages and no error", some.len()));
+    }
+    println!("  junk, changed, cut: OK");
+
+    // a packet of 600 bytes over two pages, then one of 255 that needs a
+    // 0 to end it
+    let body: Vec<u8> = (0 .. 855).map(|i| i as u8).collect();
+    let mut stream = raw_page(2, 0, &[255, 255], &body[.. 510]);
+    stream.extend_from_slice(&raw_page(1, 1, &[90, 255, 0], &body[510 ..]));
+    let (two, _) = read_pages(Cursor::new(&stream));
+    let spans: Vec<Vec<OggPacketSpan>> = two.iter().map(|p| p.packets()).collect();
+    let expected = vec![
+        vec![OggPacketSpan { range: 0 .. 510, continued: false, complete: false }],
+        vec![OggPacketSpan { range: 0 .. 90, continued: true, complete: true },
+             OggPacketSpan { range: 90 .. 345, continued: false, complete: true }],
+    ];
+    if two.len() != 2 || two.iter().any(|p| p.crc_ok == false) || spans != expected {
+        fail(format!("split: spans {:?}", spans));
+    }
+    let joined = packets(&two, 7);
+    if joined.len() != 2 || joined[0][..] != body[.. 600] || joined[1][..] != body[600 ..] {
+        fail(format!("split: packets put together wrong"));
+    }
+    println!("  split packets: OK");
+}
@@ -0,0 +1,90 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+
+use stb_vorbis::{stb_vorbis_open_reader_section, stb_vorbis_stream_length_in_samples};
+use stb_vorbis::{Vorbis, OwnedVorbisDecoder};
+
+mod common;
+use common::{fail, same, opened, decode_linear, seek_targets, check_seek_to};
+
+// generic reader checks: the file is opened through a Cursor, through a
+// reader of its own that hands out a few bytes per read the way an archive
+// or VFS stream may, and as a section of a Cursor with junk on both sides.
+// each has to give the stream length, decode and seek the same as the file
+// opened from memory. exits with 1 on mismatch.
+
+// a seekable reader that never returns more than 'step' bytes at once
+struct Trickle {
+    data: Vec<u8>,
+    position: u64,
+    step: usize,
+}
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = std::cmp::min(self.position, self.data.len() as u64) as usize;
+        let n = std::cmp::min(std::cmp::min(buf.len(), self.step), self.data.len() - start);
+        buf[.. n].copy_from_slice(&self.data[start .. start + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Trickle {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let position = match to {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::Current(n) => self.position as i64 + n,
+            SeekFrom::End(n) => self.data.len() as i64 + n,
+        };
+        if position < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+fn check(v: &mut Vorbis, linear: &[f32], name: &str) {
+    let channels = v.channels as usize;
+    let total = linear.len() / channels;
+    if stb_vorbis_stream_length_in_samples(v) as usize != total {
+        fail(format!("{}: stream length {}, expected {}", name, stb_vorbis_stream_length_in_samples(v), total));
+    }
+    if same(&decode_linear(v), linear) == false {
+        fail(format!("{}: decodes differently", name));
+    }
+    let targets = seek_targets(total, &[0, 1, total / 2, total.saturating_sub(1)]);
+    for &target in targets.iter().filter(|&&t| t < total) {
+        check_seek_to(v, target, &linear[target * channels ..], name);
+    }
+    println!("  {}: OK, {} seeks", name, targets.len());
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+
+    let mut v = opened(OwnedVorbisDecoder::from_vec(data.clone()));
+    let linear = decode_linear(&mut v);
+
+    let mut v = opened(Vorbis::from_reader(Cursor::new(data.clone())));
+    check(&mut v, &linear, "cursor");
+
+    let mut v = opened(Vorbis::from_reader(Trickle { data: data.clone(), position: 0, step: 7 }));
+    check(&mut v, &linear, "short reads");
+
+    let mut padded = b"junk before the stream".to_vec();
+    let start = padded.len() as u64;
+    padded.extend_from_slice(&data);
+    padded.extend_from_slice(b"junk after it");
+    let mut cursor = Cursor::new(padded);
+    cursor.seek(SeekFrom::Start(start)).unwrap();
+    let mut v = opened(stb_vorbis_open_reader_section(cursor, data.len() as u64));
+    check(&mut v, &linear, "section");
+}
@@ -0,0 +1,194 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use stb_vorbis::{
+    stb_vorbis_open_reader, stb_vorbis_get_samples_float_interleaved, stb_vorbis_set_error_recovery,
+    stb_vorbis_get_concealed_samples, stb_vorbis_set_concealment_callback, stb_vorbis_get_last_error,
+};
+use stb_vorbis::{Vorbis, OwnedVorbisDecoder, OggPageReader, OggPage, Concealment, VorbisErrorKind};
+
+mod common;
+use common::{fail, opened};
+
+// error recovery: the file has to decode the same with recovery on. then
+// audio pages are broken, their capture pattern or their version, one and
+// two in a row, and decoded from memory and through a reader. without
+// recovery decoding has to stop there, after what came before; with it,
+// it has to go on, with silence for what was lost in a stretch that's
+// reported, and everything before and after as in the clean decode, the
+// same length. the last page of a link of a chained file is broken too:
+// recovery has to go on with the next link. exits with 1 on mismatch.
+
+struct Decoded {
+    samples: Vec<f32>,
+    concealed: u64,
+    reported: Vec<Concealment>,
+    error: Option<VorbisErrorKind>,
+}
+
+fn decode(data: &[u8], recover: bool, pull: bool) -> Decoded {
+    if pull {
+        return decode_all(&mut opened(stb_vorbis_open_reader(Cursor::new(data.to_vec()))), recover);
+    }
+    let mut v = opened(OwnedVorbisDecoder::from_vec(data.to_vec()));
+    return decode_all(&mut v, recover);
+}
+
+fn decode_all(v: &mut Vorbis, recover: bool) -> Decoded {
+    stb_vorbis_set_error_recovery(v, recover);
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let stretches = reported.clone();
+    stb_vorbis_set_concealment_callback(v, Some(Box::new(move |c: &Concealment| stretches.lock().unwrap().push(c.clone()))));
+
+    let channels = v.channels as usize;
+    let mut samples = Vec::new();
+    let mut sbuffer = vec![0.0; 1000 * channels];
+    loop {
+        let n = stb_vorbis_get_samples_float_interleaved(v, channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+    let error = stb_vorbis_get_last_error(v).map(|e| e.kind());
+    let concealed = stb_vorbis_get_concealed_samples(v);
+    let reported = reported.lock().unwrap().clone();
+    return Decoded { samples: samples, concealed: concealed, reported: reported, error: error };
+}
+
+fn common_prefix(a: &[f32], b: &[f32]) -> usize {
+    a.iter().zip(b).take_while(|&(x, y)| x == y).count()
+}
+
+// the samples the links of a chained file before page 'k' hold
+fn link_start(pages: &[OggPage], k: usize) -> u64 {
+    pages[.. k].iter().filter(|p| p.is_last()).map(|p| p.granule_position).sum()
+}
+
+// breaks 'bad' pages with 'damage', and checks both modes with recovery
+// and without
+fn check(data: &[u8], clean: &[f32], channels: usize, pages: &[OggPage], bad: &[usize], damage: &dyn Fn(&mut Vec<u8>, &OggPage), name: &str) {
+    let mut damaged = data.to_vec();
+    for &k in bad {
+        damage(&mut damaged, &pages[k]);
+    }
+    let last = bad[bad.len() - 1];
+    let before = link_start(pages, bad[0]) + pages[bad[0] - 2].granule_position;
+    let after = link_start(pages, bad[0]) + pages[last + 2].granule_position;
+
+    for &pull in [false, true].iter() {
+        let name = format!("{}, {}", name, if pull { "reader" } else { "memory" });
+
+        let stopped = decode(&damaged, false, pull);
+        let stop = stopped.samples.len() / channels;
+        if stopped.error.is_none() || (stop as u64) < before || (stop as u64) > after
+            || stopped.samples[.. before as usize * channels] != clean[.. before as usize * channels]
+        {
+            fail(format!("{}: without recovery, {} of {} samples and error {:?}", name, stopped.samples.len() / channels, clean.len() / channels, stopped.error));
+        }
+
+        let recovered = decode(&damaged, true, pull);
+        if recovered.error.is_some() || recovered.samples.len() != clean.len() {
+            fail(format!("{}: recovery gave {} of {} samples and error {:?}", name, recovered.samples.len() / channels, clean.len() / channels, recovered.error));
+        }
+        // the clean decode with a stretch of silence in it
+        let head = common_prefix(&recovered.samples, clean) / channels * channels;
+        let tail = common_prefix_rev(&recovered.samples, clean) / channels * channels;
+        let (start, end) = (head / channels, (clean.len() - tail) / channels);
+        if start >= end || recovered.samples[head .. clean.len() - tail].iter().any(|&x| x != 0.0) {
+            fail(format!("{}: no silence where the damage was", name));
+        }
+        if (start as u64) < before || (end as u64) > after {
+            fail(format!("{}: samples {} to {} concealed, not within {} to {}", name, start, end, before, after));
+        }
+        // the reported stretch covers what differs from the clean decode,
+        // and can only start earlier where the clean samples were 0 too
+        let reported: Vec<(Option<u64>, u64)> = recovered.reported.iter().map(|c| (c.sample, c.samples)).collect();
+        let total: u64 = reported.iter().map(|r| r.1).sum();
+        match reported[..] {
+            [(Some(sample), samples)] if sample <= start as u64 && sample + samples >= end as u64 && total == recovered.concealed => {}
+            _ => fail(format!("{}: {:?} reported, {} concealed, samples {} to {} differ", name, reported, recovered.concealed, start, end)),
+        }
+        let c = &recovered.reported[0];
+        let (first, last_end) = (pages[bad[0] - 1].offset, pages[last].offset + pages[last].size() as u64);
+        if c.offset < first || c.offset > last_end {
+            fail(format!("{}: damage reported at {}, not within {} to {}", name, c.offset, first, last_end));
+        }
+        println!("  {}: OK, samples {} to {} concealed", name, c.sample.unwrap(), c.sample.unwrap() + c.samples);
+    }
+}
+
+fn common_prefix_rev(a: &[f32], b: &[f32]) -> usize {
+    a.iter().rev().zip(b.iter().rev()).take_while(|&(x, y)| x == y).count()
+}
+
+// the last page of a link broken: decoding goes on with the next one
+fn check_link_end(data: &[u8], clean: &[f32], channels: usize, pages: &[OggPage], k: usize, damage: &dyn Fn(&mut Vec<u8>, &OggPage)) {
+    let mut damaged = data.to_vec();
+    damage(&mut damaged, &pages[k]);
+    let link_end = ((link_start(pages, k) + pages[k].granule_position) as usize) * channels;
+    for &pull in [false, true].iter() {
+        let name = format!("link end, {}", if pull { "reader" } else { "memory" });
+        let recovered = decode(&damaged, true, pull);
+        let head = common_prefix(&recovered.samples, clean);
+        let tail = recovered.samples.len() - head;
+        if recovered.error.is_some() || head > link_end || tail < clean.len() - link_end
+            || recovered.samples[head ..] != clean[clean.len() - tail ..]
+        {
+            fail(format!("{}: the next link isn't decoded as it is", name));
+        }
+        if recovered.reported.len() != 1 || recovered.reported[0].samples != 0 || recovered.concealed != 0 {
+            fail(format!("{}: {:?} reported", name, recovered.reported));
+        }
+        println!("  {}: OK", name);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+
+    let clean = decode(&data, false, false);
+    let channels = opened(OwnedVorbisDecoder::from_vec(data.clone())).get_info().channels as usize;
+    for &pull in [false, true].iter() {
+        let d = decode(&data, true, pull);
+        if d.samples != clean.samples || d.concealed != 0 || d.reported.is_empty() == false || d.error.is_some() {
+            fail(format!("the clean file decodes differently with recovery"));
+        }
+    }
+    println!("  clean: OK");
+
+    let pages: Vec<OggPage> = OggPageReader::new(Cursor::new(&data)).map(|p| p.unwrap()).collect();
+    let capture = |data: &mut Vec<u8>, page: &OggPage| {
+        data[page.offset as usize + 3] = b'X';
+    };
+    let version = |data: &mut Vec<u8>, page: &OggPage| {
+        data[page.offset as usize + 4] = 1;
+    };
+
+    // the last page of a link that has another after it
+    if let Some(k) = (3 .. pages.len().saturating_sub(1)).find(|&k| pages[k].is_last() && pages[k + 1].is_first() && pages[k - 1].serial == pages[k].serial) {
+        check_link_end(&data, &clean.samples, channels, &pages, k, &capture);
+    }
+
+    // audio pages with two more of the same stream either side
+    let usable: Vec<usize> = (3 .. pages.len().saturating_sub(3)).filter(|&k| {
+        pages[k - 2 ..= k + 3].iter().all(|p| p.serial == pages[k].serial && p.granule_position != !0 && p.is_first() == false && p.is_last() == false)
+            && pages[k - 2].granule_position > 0
+    }).collect();
+    if usable.is_empty() {
+        println!("  too short to damage");
+        return;
+    }
+    let k = usable[usable.len() / 2];
+    check(&data, &clean.samples, channels, &pages, &[k], &capture, "capture pattern");
+    check(&data, &clean.samples, channels, &pages, &[k], &version, "version");
+    if usable.contains(&(k + 1)) {
+        check(&data, &clean.samples, channels, &pages, &[k, k + 1], &capture, "two pages");
+    }
+}
@@ -0,0 +1,410 @@
+extern crate stb_vorbis;
+
+use std::io::Cursor;
+use std::panic;
+
+use stb_vorbis::{
+    stb_vorbis_seek, stb_vorbis_get_sample_offset, stb_vorbis_stream_length_in_samples,
+    stb_vorbis_get_samples_float, stb_vorbis_get_samples_float_interleaved,
+    stb_vorbis_get_samples_short_interleaved, stb_vorbis_set_crc_check, stb_vorbis_set_dither,
+};
+use stb_vorbis::{Vorbis, OwnedVorbisDecoder, AudioBufferSlice, ResampleQuality, OggPageReader, CrcCheck, Dither};
+
+mod common;
+use common::{fail, same, build_link, build_pages, build_packets, audio_packet, channel_packet, PACKETS, BLOCKSIZE};
+
+// output rate checks: streams put together at a handful of rates are
+// decoded at others through both resamplers. the output has to be as long
+// as the stream at the new rate, the same whichever function and buffer
+// size it comes through, and the same after seeking as when decoding from
+// the start. linear output is checked against interpolating the decoded
+// samples by hand, sinc output by its spectrum: the power of the stream
+// below both Nyquist frequencies and next to nothing above. a chained
+// stream of links at different rates has to come out at one rate all
+// through, and with pages of it damaged and skipped for their CRC, up to
+// the damage and from a page or so after it as without. dithered, what
+// comes after a skipped page mustn't depend on what came before it. takes
+// no arguments, exits with 1 on mismatch.
+
+const CHANNELS: usize = 2;
+const CHECK_LEN: usize = 64;
+
+// (stream rate, output rate)
+const CONVERSIONS: [(u32, u32); 6] = [
+    (44100, 48000), (22050, 48000), (8000, 48000),
+    (48000, 44100), (48000, 16000), (32000, 22050),
+];
+
+fn open(data: &[u8], rate: u32, quality: ResampleQuality) -> OwnedVorbisDecoder {
+    let mut v = match OwnedVorbisDecoder::from_vec(data.to_vec()) {
+        Ok(v) => v,
+        Err(why) => fail(format!("open: {}", why)),
+    };
+    v.set_output_rate(rate, quality);
+    return v;
+}
+
+// everything left, interleaved, 'chunk' samples per channel at a time
+fn decode(v: &mut Vorbis, chunk: usize) -> Vec<f32> {
+    let mut samples = Vec::new();
+    let mut sbuffer = vec![0.0; chunk * CHANNELS];
+    loop {
+        let n = stb_vorbis_get_samples_float_interleaved(v, CHANNELS as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&sbuffer[.. n * CHANNELS]);
+    }
+    return samples;
+}
+
+fn column(samples: &[f32], c: usize) -> Vec<f32> {
+    samples.iter().skip(c).step_by(CHANNELS).cloned().collect()
+}
+
+// power spectral density at 'freq', through a Hann window over all of 'samples'
+fn density(samples: &[f32], rate: f64, freq: f64) -> f64 {
+    let len = samples.len() as f64;
+    let step = 2.0 * std::f64::consts::PI * freq / rate;
+    let (mut re, mut im, mut energy) = (0.0, 0.0, 0.0);
+    for (n, &x) in samples.iter().enumerate() {
+        let w = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * n as f64 / len).cos();
+        re += w * x as f64 * (step * n as f64).cos();
+        im -= w * x as f64 * (step * n as f64).sin();
+        energy += w * w;
+    }
+    (re * re + im * im) / (energy * rate)
+}
+
+fn band(samples: &[f32], rate: u32, from: f64, to: f64) -> f64 {
+    let points = 80;
+    (0 .. points).map(|k| density(samples, rate as f64, from + (to - from) * (k as f64 + 0.5) / points as f64)).sum::<f64>() / points as f64
+}
+
+// linear interpolation of 'native', done the slow way
+fn interpolate(native: &[f32], from: u32, to: u32, len: usize) -> Vec<f32> {
+    let frames = native.len() / CHANNELS;
+    let at = |i: usize, c: usize| if i < frames { native[i * CHANNELS + c] as f64 } else { 0.0 };
+    let mut samples = Vec::with_capacity(len * CHANNELS);
+    for t in 0 .. len {
+        let position = t as u64 * from as u64;
+        let (i, frac) = ((position / to as u64) as usize, (position % to as u64) as f64 / to as f64);
+        for c in 0 .. CHANNELS {
+            samples.push((at(i, c) * (1.0 - frac) + at(i + 1, c) * frac) as f32);
+        }
+    }
+    return samples;
+}
+
+fn check_seeks(v: &mut Vorbis, reference: &[f32], name: &str) {
+    let total = reference.len() / CHANNELS;
+    let mut targets = vec![0, 1, total / 2, total - 1];
+    let mut seed: u32 = 12345;
+    for _ in 0 .. 16 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        targets.push((seed >> 8) as usize % total);
+    }
+    let mut sbuffer = vec![0.0; CHECK_LEN * CHANNELS];
+    for &target in targets.iter() {
+        if stb_vorbis_seek(v, target as u64) == false {
+            fail(format!("{}: seek to {} failed", name, target));
+        }
+        if stb_vorbis_get_sample_offset(v) != target as i64 {
+            fail(format!("{}: sample offset after seek to {} is {}", name, target, stb_vorbis_get_sample_offset(v)));
+        }
+        let n = stb_vorbis_get_samples_float_interleaved(v, CHANNELS as i32, &mut sbuffer) as usize;
+        let expected = std::cmp::min(CHECK_LEN, total - target);
+        if n != expected || !same(&sbuffer[.. n * CHANNELS], &reference[target * CHANNELS .. (target + n) * CHANNELS]) {
+            fail(format!("{}: samples after seek to {} differ", name, target));
+        }
+    }
+}
+
+fn check(from: u32, to: u32, quality: ResampleQuality) {
+    let stream = build_link(&[0, 1], from, 1);
+    let name = format!("{:?} {} to {}", quality, from, to);
+    let native = decode(&mut open(&stream, 0, quality), 333);
+    let frames = native.len() / CHANNELS;
+    if frames != (PACKETS - 1) * BLOCKSIZE / 2 {
+        fail(format!("{}: {} samples at the stream rate", name, frames));
+    }
+    let expected_len = (frames as u64 * to as u64 + from as u64 - 1) / from as u64;
+
+    // length, in every way it can be asked for
+    let mut v = open(&stream, to, quality);
+    if v.get_output_rate() != to || v.get_info().sample_rate != from {
+        fail(format!("{}: output rate {}, stream rate {}", name, v.get_output_rate(), v.get_info().sample_rate));
+    }
+    if stb_vorbis_stream_length_in_samples(&mut v) != expected_len {
+        fail(format!("{}: stream length {}, expected {}", name, stb_vorbis_stream_length_in_samples(&mut v), expected_len));
+    }
+    let reference = decode(&mut v, 333);
+    if reference.len() / CHANNELS != expected_len as usize {
+        fail(format!("{}: {} samples decoded, expected {}", name, reference.len() / CHANNELS, expected_len));
+    }
+    if stb_vorbis_get_sample_offset(&mut v) != expected_len as i64 {
+        fail(format!("{}: sample offset {} at the end", name, stb_vorbis_get_sample_offset(&mut v)));
+    }
+
+    // through other buffer sizes and functions
+    for &chunk in [1, 7, 4096].iter() {
+        if !same(&decode(&mut open(&stream, to, quality), chunk), &reference) {
+            fail(format!("{}: {} samples at a time differ", name, chunk));
+        }
+    }
+    let mut v = open(&stream, to, quality);
+    let mut planar = vec![vec![0.0; 100]; CHANNELS];
+    let mut decoded = vec![Vec::new(); CHANNELS];
+    loop {
+        let mut buffer = unsafe { AudioBufferSlice::from(&mut planar) };
+        let n = stb_vorbis_get_samples_float(&mut v, CHANNELS as i32, &mut buffer) as usize;
+        if n == 0 {
+            break;
+        }
+        for c in 0 .. CHANNELS {
+            decoded[c].extend_from_slice(&planar[c][.. n]);
+        }
+    }
+    if (0 .. CHANNELS).any(|c| !same(&decoded[c], &column(&reference, c))) {
+        fail(format!("{}: get_samples_float differs", name));
+    }
+    let mut v = open(&stream, to, quality);
+    let mut shorts = Vec::new();
+    let mut sbuffer = vec![0; 100 * CHANNELS];
+    loop {
+        let n = stb_vorbis_get_samples_short_interleaved(&mut v, CHANNELS as u32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        shorts.extend_from_slice(&sbuffer[.. n * CHANNELS]);
+    }
+    if shorts.len() != reference.len() || shorts.iter().zip(&reference).any(|(&s, &x)| (s as f32 - (x * 32768.0).max(-32768.0).min(32767.0)).abs() > 1.0) {
+        fail(format!("{}: get_samples_short_interleaved differs", name));
+    }
+
+    // downmixed after resampling
+    let mut v = open(&stream, to, quality);
+    let mut mono = Vec::new();
+    let mut sbuffer = vec![0.0; 100];
+    loop {
+        let n = v.get_samples_float_interleaved(1, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        mono.extend_from_slice(&sbuffer[.. n]);
+    }
+    let mixed: Vec<f32> = reference.chunks(CHANNELS).map(|x| 0.5 * (x[0] + x[1])).collect();
+    if !same(&mono, &mixed) {
+        fail(format!("{}: mono downmix differs", name));
+    }
+
+    check_seeks(&mut open(&stream, to, quality), &reference, &name);
+
+    match quality {
+        ResampleQuality::Linear => {
+            if !same(&reference, &interpolate(&native, from, to, expected_len as usize)) {
+                fail(format!("{}: differs from interpolating by hand", name));
+            }
+        }
+        ResampleQuality::WindowedSinc => {
+            let (input, output) = (column(&native, 0), column(&reference, 0));
+            let nyquist = std::cmp::min(from, to) as f64 / 2.0;
+            let mut k = 0.0;
+            while k < 0.7 {
+                let (lo, hi) = (nyquist * k, nyquist * (k + 0.1));
+                let ratio = band(&output, to, lo, hi) / band(&input, from, lo, hi);
+                if ratio < 0.95 || ratio > 1.05 {
+                    fail(format!("{}: power from {} to {} Hz changed by {}", name, lo, hi, ratio));
+                }
+                k += 0.1;
+            }
+            if to as f64 / 2.0 > nyquist * 1.5 {
+                // no images of the stream's spectrum above its Nyquist frequency
+                let passband = band(&output, to, 0.0, nyquist * 0.7);
+                let images = band(&output, to, nyquist * 1.2, to as f64 / 2.0);
+                if images > passband * 1e-4 {
+                    fail(format!("{}: images at {} times the passband power", name, images / passband));
+                }
+            }
+        }
+    }
+
+    // at its own rate, the stream is left as it is
+    if decode(&mut open(&stream, from, quality), 333) != native {
+        fail(format!("{}: output at the stream rate differs", name));
+    }
+    println!("  {}: OK", name);
+}
+
+// links at different rates, and one at the output rate, chained together;
+// it has to come out the same as the links resampled one by one
+fn check_chained(quality: ResampleQuality) {
+    let rates = [22050, 48000, 44100];
+    let links: Vec<Vec<u8>> = rates.iter().enumerate().map(|(k, &rate)| build_link(&[0, 1], rate, k as u32 + 1)).collect();
+    let stream = links.concat();
+    let name = format!("{:?} chained", quality);
+
+    let parts: Vec<Vec<f32>> = links.iter().map(|link| decode(&mut open(link, 48000, quality), 333)).collect();
+    let reference = parts.concat();
+
+    let mut v = open(&stream, 48000, quality);
+    let mut sbuffer = vec![0.0; 333 * CHANNELS];
+    for (k, part) in parts.iter().enumerate() {
+        let mut samples = Vec::new();
+        loop {
+            let n = v.get_samples_float_interleaved(CHANNELS as i32, &mut sbuffer) as usize;
+            samples.extend_from_slice(&sbuffer[.. n * CHANNELS]);
+            if n == 0 || v.link_changed() {
+                break;
+            }
+        }
+        if !same(&samples, part) {
+            fail(format!("{}: link {} gave {} samples, expected {}, or they differ", name, k, samples.len() / CHANNELS, part.len() / CHANNELS));
+        }
+        let position = parts[.. k + 1].iter().map(|part| part.len() / CHANNELS).sum::<usize>();
+        if v.get_sample_offset() != position as i64 {
+            fail(format!("{}: sample offset {} after link {}, expected {}", name, v.get_sample_offset(), k, position));
+        }
+    }
+    if v.stream_length_in_samples() != (reference.len() / CHANNELS) as u64 {
+        fail(format!("{}: stream length {}, expected {}", name, v.stream_length_in_samples(), reference.len() / CHANNELS));
+    }
+
+    check_seeks(&mut open(&stream, 48000, quality), &reference, &name);
+    println!("  {}: OK", name);
+}
+
+// a few packets to a page, so that a skipped one leaves the decoder not
+// knowing where it is for the packets after it on the next
+const PER_PAGE: usize = 4;
+
+// everything left with pages skipped for their CRC; None if that panics
+fn decode_skipping(data: &[u8], quality: ResampleQuality) -> Option<Vec<f32>> {
+    let result = panic::catch_unwind(|| {
+        OwnedVorbisDecoder::from_vec(data.to_vec()).ok().map(|mut v| {
+            stb_vorbis_set_crc_check(&mut v, CrcCheck::Skip);
+            v.set_output_rate(48000, quality);
+            decode(&mut v, 333)
+        })
+    });
+    match result {
+        Ok(samples) => Some(samples.unwrap_or_default()),
+        Err(_) => None,
+    }
+}
+
+fn check_damaged(quality: ResampleQuality) {
+    let rates = [22050, 48000, 44100];
+    let links: Vec<Vec<u8>> = rates.iter().enumerate().map(|(k, &rate)| build_pages(&[0, 1], rate, k as u32 + 1, PER_PAGE)).collect();
+    let stream = links.concat();
+    let name = format!("{:?} damaged", quality);
+    let clean = decode(&mut open(&stream, 48000, quality), 333);
+
+    // the middle audio page of each link, a byte of its body flipped
+    let mut damaged = stream.clone();
+    let pages: Vec<_> = OggPageReader::new(Cursor::new(&stream)).map(|p| p.unwrap()).collect();
+    for link in 0 .. rates.len() {
+        let page = &pages[link * (PACKETS / PER_PAGE + 2) + 2 + PACKETS / PER_PAGE / 2];
+        damaged[page.offset as usize + 27 + page.lacing.len() + page.data.len() / 2] ^= 0x10;
+    }
+    let samples = match decode_skipping(&damaged, quality) {
+        Some(samples) => samples,
+        None => fail(format!("{}: panicked", name)),
+    };
+    // up to the first damaged page, and the end of the last link
+    let (head, tail) = (PACKETS / 4 * BLOCKSIZE / 2 * CHANNELS, PACKETS / 4 * BLOCKSIZE / 2 * CHANNELS);
+    if samples.len() >= clean.len() || samples.len() < head + tail
+        || same(&samples[.. head], &clean[.. head]) == false
+        || same(&samples[samples.len() - tail ..], &clean[clean.len() - tail ..]) == false
+    {
+        fail(format!("{}: {} samples, {} clean, or they differ", name, samples.len() / CHANNELS, clean.len() / CHANNELS));
+    }
+
+    // bytes flipped anywhere, as many copies as it takes to hit every kind
+    // of page; none may panic or put out more than a block too many per link
+    let most = clean.len() / CHANNELS + rates.len() * BLOCKSIZE * 48000 / 22050;
+    let mut seed: u32 = 0x2545f491;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    panic::set_hook(Box::new(|info| println!("  {}", info)));
+    for round in 0 .. 100 {
+        let mut damaged = stream.clone();
+        for _ in 0 .. 8 {
+            let at = next() as usize % damaged.len();
+            damaged[at] ^= 1 << (next() % 8);
+        }
+        match decode_skipping(&damaged, quality) {
+            Some(ref samples) if samples.len() / CHANNELS <= most => {}
+            Some(samples) => fail(format!("{}: {} samples from damaged copy {}", name, samples.len() / CHANNELS, round)),
+            None => fail(format!("{}: panicked on damaged copy {}", name, round)),
+        }
+    }
+    let _ = panic::take_hook();
+    println!("  {}: OK", name);
+}
+
+// a stream at 44100 with the middle audio page damaged, the packets before
+// it taken from channels 'first' and 'first' + 1
+fn damaged_from(first: usize) -> Vec<u8> {
+    let packets: Vec<Vec<u8>> = (0 .. PACKETS).map(|packet| {
+        let first = if packet < PACKETS / 2 { first } else { 0 };
+        audio_packet(&[channel_packet(first, packet), channel_packet(first + 1, packet)])
+    }).collect();
+    let mut data = build_packets(CHANNELS, 44100, 1, &packets, PER_PAGE);
+    let pages: Vec<_> = OggPageReader::new(Cursor::new(data.clone())).map(|p| p.unwrap()).collect();
+    let page = &pages[2 + PACKETS / PER_PAGE / 2];
+    data[page.offset as usize + 27 + page.lacing.len() + page.data.len() / 2] ^= 0x10;
+    return data;
+}
+
+fn decode_dithered(data: &[u8], quality: ResampleQuality) -> Vec<i16> {
+    let mut v = open(data, 48000, quality);
+    stb_vorbis_set_crc_check(&mut v, CrcCheck::Skip);
+    stb_vorbis_set_dither(&mut v, Dither::Shaped, 1);
+    let mut shorts = Vec::new();
+    let mut sbuffer = vec![0; 100 * CHANNELS];
+    loop {
+        let n = stb_vorbis_get_samples_short_interleaved(&mut v, CHANNELS as u32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        shorts.extend_from_slice(&sbuffer[.. n * CHANNELS]);
+    }
+    return shorts;
+}
+
+// after a page skipped for its CRC the resampler and the dither start over,
+// so two streams that only differ before the damage come out the same after
+// it; the noise shaping would carry the difference on otherwise
+fn check_dithered(quality: ResampleQuality) {
+    let name = format!("{:?} dithered", quality);
+    let (a, b) = (decode_dithered(&damaged_from(0), quality), decode_dithered(&damaged_from(2), quality));
+    if a != decode_dithered(&damaged_from(0), quality) {
+        fail(format!("{}: decodes differently the second time", name));
+    }
+    // from the damaged page on, until the end
+    let tail = PACKETS / 2 * BLOCKSIZE / 2 * CHANNELS;
+    if a.len() != b.len() || a.len() < tail || a[.. a.len() - tail] == b[.. b.len() - tail] {
+        fail(format!("{}: {} and {} samples, or the same before the damage", name, a.len() / CHANNELS, b.len() / CHANNELS));
+    }
+    let tail = PACKETS / 4 * BLOCKSIZE / 2 * CHANNELS;
+    if a[a.len() - tail ..] != b[b.len() - tail ..] {
+        fail(format!("{}: the samples after the damage depend on those before it", name));
+    }
+    println!("  {}: OK", name);
+}
+
+fn main() {
+    for &quality in [ResampleQuality::Linear, ResampleQuality::WindowedSinc].iter() {
+        for &(from, to) in CONVERSIONS.iter() {
+            check(from, to, quality);
+        }
+        check_chained(quality);
+        check_damaged(quality);
+        check_dithered(quality);
+    }
+}
@@ -0,0 +1,162 @@
+extern crate stb_vorbis;
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::prelude::*;
+use std::process;
+
+use stb_vorbis::{
+    stb_vorbis_get_samples, stb_vorbis_get_samples_interleaved, stb_vorbis_get_frame,
+    stb_vorbis_get_frame_interleaved, stb_vorbis_get_samples_short_interleaved,
+};
+use stb_vorbis::{OwnedVorbisDecoder, AudioBufferSlice, Sample, I24};
+
+mod common;
+use common::fail;
+
+// output format checks: the file is decoded as f64, i32, packed 24-bit,
+// i16 and u8 samples, planar and interleaved, by sample count and by frame,
+// with the file's channel count and downmixed to mono. every sample has to
+// be the float one scaled to the type, rounded and clipped; i16 has to be
+// what the short functions put out. frames stop at the end of the first
+// link, like get_frame_float. exits with 1 on mismatch.
+
+const CHUNK: usize = 333;
+
+fn open(data: &[u8]) -> OwnedVorbisDecoder {
+    match OwnedVorbisDecoder::from_vec(data.to_vec()) {
+        Ok(v) => v,
+        Err(why) => {
+            println!("Couldn't open the stream. Error: {}", why);
+            process::exit(why.kind() as i32);
+        }
+    }
+}
+
+fn scaled(x: f32, scale: f64, min: f64, max: f64) -> f64 {
+    (x as f64 * scale).round().max(min).min(max)
+}
+
+// all of the file, interleaved, through each of the four generic functions
+fn decode_all<T: Sample + Default>(data: &[u8], channels: usize) -> [Vec<T>; 4] {
+    let mut interleaved = Vec::new();
+    let mut v = open(data);
+    let mut sbuffer = vec![T::default(); CHUNK * channels];
+    loop {
+        let n = stb_vorbis_get_samples_interleaved(&mut v, channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        interleaved.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+
+    let mut planar = Vec::new();
+    let mut v = open(data);
+    let mut buffers = vec![vec![T::default(); CHUNK]; channels];
+    loop {
+        let mut buffer = unsafe { AudioBufferSlice::from(&mut buffers) };
+        let n = stb_vorbis_get_samples(&mut v, channels as i32, &mut buffer) as usize;
+        if n == 0 {
+            break;
+        }
+        for i in 0 .. n {
+            for c in 0 .. channels {
+                planar.push(buffers[c][i]);
+            }
+        }
+    }
+
+    // some frames come out longer than max_frame_size
+    let max_frame = v.get_info().max_frame_size * 2;
+    let mut frames = Vec::new();
+    let mut v = open(data);
+    let mut buffers = vec![vec![T::default(); max_frame]; channels];
+    loop {
+        let mut buffer = unsafe { AudioBufferSlice::from(&mut buffers) };
+        let n = stb_vorbis_get_frame(&mut v, channels as i32, &mut buffer) as usize;
+        if n == 0 {
+            break;
+        }
+        for i in 0 .. n {
+            for c in 0 .. channels {
+                frames.push(buffers[c][i]);
+            }
+        }
+    }
+
+    let mut interleaved_frames = Vec::new();
+    let mut v = open(data);
+    let mut sbuffer = vec![T::default(); max_frame * channels];
+    loop {
+        let n = stb_vorbis_get_frame_interleaved(&mut v, channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        interleaved_frames.extend_from_slice(&sbuffer[.. n * channels]);
+    }
+    [interleaved, planar, frames, interleaved_frames]
+}
+
+fn check<T, F>(data: &[u8], channels: usize, reference: &[f32], frame_samples: usize, mut expect: F, name: &str)
+    where T: Sample + Default + PartialEq + Debug, F: FnMut(f32) -> T
+{
+    let expected: Vec<T> = reference.iter().map(|&x| expect(x)).collect();
+    let ways = ["get_samples_interleaved", "get_samples", "get_frame", "get_frame_interleaved"];
+    let lengths = [expected.len(), expected.len(), frame_samples * channels, frame_samples * channels];
+    for ((samples, way), &length) in decode_all::<T>(data, channels).iter().zip(ways.iter()).zip(lengths.iter()) {
+        if samples.len() != length {
+            fail(format!("{}, {} channels: {} gave {} samples, expected {}", name, channels, way, samples.len(), length));
+        }
+        if let Some(i) = (0 .. samples.len()).find(|&i| samples[i] != expected[i]) {
+            fail(format!("{}, {} channels: {} gave {:?} at {}, expected {:?} for {}", name, channels, way, samples[i], i, expected[i], reference[i]));
+        }
+    }
+    println!("  {}, {} channels: OK", name, channels);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+
+    if std::mem::size_of::<I24>() != 3 || I24::as_bytes(&[I24::new(-2), I24::new(0x123456)]) != [0xfe, 0xff, 0xff, 0x56, 0x34, 0x12] {
+        fail(format!("I24 isn't packed"));
+    }
+    if I24::new(-8388608).get() != -8388608 || I24::new(8388607).get() != 8388607 {
+        fail(format!("I24 doesn't round trip"));
+    }
+
+    let file_channels = open(&data).get_info().channels as usize;
+    let mut v = open(&data);
+    let mut frame_samples = 0;
+    loop {
+        let mut outputs = AudioBufferSlice::new(0);
+        let n = v.get_frame_float(None, Some(&mut outputs)) as usize;
+        if n == 0 {
+            break;
+        }
+        frame_samples += n;
+    }
+    for &channels in [file_channels, 1].iter() {
+        let reference = decode_all::<f32>(&data, channels)[0].clone();
+
+        check(&data, channels, &reference, frame_samples, |x| x as f64, "f64");
+        check(&data, channels, &reference, frame_samples, |x| scaled(x, 2147483648.0, -2147483648.0, 2147483647.0) as i32, "i32");
+        check(&data, channels, &reference, frame_samples, |x| I24::new(scaled(x, 8388608.0, -8388608.0, 8388607.0) as i32), "I24");
+        check(&data, channels, &reference, frame_samples, |x| (scaled(x, 128.0, -128.0, 127.0) + 128.0) as u8, "u8");
+
+        // 16-bit, against the short functions
+        let mut shorts = Vec::new();
+        let mut v = open(&data);
+        let mut sbuffer = vec![0; CHUNK * channels];
+        loop {
+            let n = stb_vorbis_get_samples_short_interleaved(&mut v, channels as u32, &mut sbuffer) as usize;
+            if n == 0 {
+                break;
+            }
+            shorts.extend_from_slice(&sbuffer[.. n * channels]);
+        }
+        let mut k = 0;
+        check(&data, channels, &reference, frame_samples, |_| { k += 1; shorts[k - 1] }, "i16");
+    }
+}
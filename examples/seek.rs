@@ -0,0 +1,193 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use std::path::Path;
+use std::process;
+
+use stb_vorbis::{
+    stb_vorbis_get_info, stb_vorbis_open_filename, stb_vorbis_open_pushdata,
+    stb_vorbis_open_reader_section,
+    stb_vorbis_seek, stb_vorbis_seek_frame, stb_vorbis_get_sample_offset,
+    stb_vorbis_stream_length_in_samples, stb_vorbis_stream_length_in_seconds,
+    stb_vorbis_get_frame_float,
+    stb_vorbis_decode_frame_pushdata, stb_vorbis_flush_pushdata,
+};
+use stb_vorbis::{Vorbis, VorbisDecoder, AudioBufferSlice};
+
+mod common;
+use common::{fail, same, decode_linear, seek_targets, check_seek_to};
+
+// seek, stream length and pushdata resync checks. every position is compared
+// against a plain linear decode of the same file; exits with 1 on mismatch.
+
+fn check_seek(v: &mut Vorbis, linear: &[f32], name: &str) {
+    let channels = v.channels as usize;
+    let total = linear.len() / channels;
+    let targets = seek_targets(total, &[0, 1, 1000, 4095, total / 3, total / 2, total.saturating_sub(5000), total.saturating_sub(1)]);
+
+    for &target in targets.iter().filter(|&&t| t < total) {
+        check_seek_to(v, target, &linear[target * channels ..], name);
+
+        // the frame after seek_frame() must contain the target sample
+        if stb_vorbis_seek_frame(v, target as u64) == false {
+            fail(format!("{}: seek_frame to {} failed", name, target));
+        }
+        let start = stb_vorbis_get_sample_offset(v) as usize;
+        let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
+        let n = stb_vorbis_get_frame_float(v, None, Some(&mut outputs)) as usize;
+        if target < start || target >= start + n {
+            fail(format!("{}: frame after seek_frame to {} covers {}..{}", name, target, start, start + n));
+        }
+        let expected: Vec<f32> = (start .. start + n).map(|i| linear[i * channels]).collect();
+        if !same(&outputs[0][.. n], &expected) {
+            fail(format!("{}: frame after seek_frame to {} differs from linear decode", name, target));
+        }
+    }
+    if stb_vorbis_seek(v, total as u64 + 1) {
+        fail(format!("{}: seek past the end succeeded", name));
+    }
+    println!("  {}: {} seeks OK", name, targets.len());
+}
+
+// feed 'buffer' from 'p' on until 'frames' frames with a known sample offset
+// have been checked against the linear decode. returns where it stopped.
+fn check_pushdata_frames(v: &mut Vorbis, buffer: &[u8], mut p: usize, linear: &[f32], frames: usize, name: &str) -> usize {
+    let channels = v.channels as usize;
+    let mut checked = 0;
+    while p < buffer.len() && checked < frames {
+        let mut q = 4096;
+        let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
+        let mut num_c = 0;
+        let mut n = 0;
+        let used = loop {
+            let end = std::cmp::min(p + q, buffer.len());
+            let used = unsafe {
+                stb_vorbis_decode_frame_pushdata(v, &buffer[p .. end], &mut num_c, &mut outputs, &mut n)
+            };
+            if used == 0 && end < buffer.len() {
+                q *= 2;
+                continue;
+            }
+            break used as usize;
+        };
+        if used == 0 {
+            break;
+        }
+        p += used;
+
+        let offset = stb_vorbis_get_sample_offset(v);
+        if n == 0 || offset < 0 {
+            continue;
+        }
+        let start = offset as usize - n as usize;
+        for c in 0 .. channels {
+            let expected: Vec<f32> = (start .. start + n as usize).map(|i| linear[i * channels + c]).collect();
+            if !same(&outputs[c][.. n as usize], &expected) {
+                fail(format!("{}: frame at {} differs from linear decode", name, start));
+            }
+        }
+        checked += 1;
+    }
+    if checked == 0 && buffer.len() - p > 65536 {
+        fail(format!("{}: no frames decoded", name));
+    }
+    return p;
+}
+
+// jump around with flush_pushdata() and check the frames after each resync
+// land where get_sample_offset() says they do; then drop a chunk without
+// telling the decoder and check it finds its way back
+fn check_pushdata_resync(buffer: &[u8], linear: &[f32]) {
+    let mut used = 0;
+    let mut length = std::cmp::min(buffer.len(), 4096);
+    let mut v = loop {
+        match stb_vorbis_open_pushdata(&buffer[.. length], &mut used) {
+            Ok(v) => break v,
+            Err(_) if length < buffer.len() => length = std::cmp::min(buffer.len(), length * 2),
+            Err(why) => fail(format!("pushdata: {}", why)),
+        }
+    };
+
+    let jumps = [buffer.len() / 2 + 1234, buffer.len() / 5, buffer.len() * 4 / 5, used as usize + 100];
+    for &jump in jumps.iter() {
+        stb_vorbis_flush_pushdata(&mut v);
+        if stb_vorbis_get_sample_offset(&mut v) != -1 {
+            fail(format!("pushdata: sample offset still known after flush"));
+        }
+        check_pushdata_frames(&mut v, buffer, jump, linear, 8, "pushdata flush");
+    }
+
+    // frames decoded across the gap are garbage until the decoder runs into
+    // the next page header and notices; everything after that must be right
+    stb_vorbis_flush_pushdata(&mut v);
+    let mut p = check_pushdata_frames(&mut v, buffer, buffer.len() / 3, linear, 4, "pushdata gap") + 3000;
+    while stb_vorbis_get_sample_offset(&mut v) >= 0 {
+        let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
+        let mut num_c = 0;
+        let mut n = 0;
+        let end = std::cmp::min(p + 65536, buffer.len());
+        let used = unsafe {
+            stb_vorbis_decode_frame_pushdata(&mut v, &buffer[p .. end], &mut num_c, &mut outputs, &mut n)
+        };
+        if used == 0 {
+            fail(format!("pushdata gap: decoder never noticed the missing data"));
+        }
+        p += used as usize;
+    }
+    check_pushdata_frames(&mut v, buffer, p, linear, 1000000, "pushdata gap");
+    println!("  pushdata: {} resyncs OK", jumps.len() + 1);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let filename = Path::new(&args[1]);
+
+    let mut v = match stb_vorbis_open_filename(&filename) {
+        Err(why) => {
+            println!("Couldn't open {}. Error: {}'", filename.display(), why);
+            process::exit(why.kind() as i32);
+        },
+        Ok(v) => v,
+    };
+    let info = stb_vorbis_get_info(&v);
+    println!("{} channels, {} samples/sec", info.channels, info.sample_rate);
+
+    let channels = info.channels as usize;
+    let length = stb_vorbis_stream_length_in_samples(&mut v);
+    let seconds = stb_vorbis_stream_length_in_seconds(&mut v);
+    println!("length: {} samples, {:.3} seconds", length, seconds);
+    if stb_vorbis_get_sample_offset(&mut v) != 0 {
+        fail(format!("sample offset of a fresh decoder is not 0"));
+    }
+
+    let linear = decode_linear(&mut v);
+    if linear.len() / channels != length as usize {
+        fail(format!("stream length {} but decoded {} samples", length, linear.len() / channels));
+    }
+    if (seconds - length as f32 / info.sample_rate as f32).abs() > 1e-3 {
+        fail(format!("stream length in seconds doesn't match the sample count"));
+    }
+
+    check_seek(&mut v, &linear, "file");
+
+    let mut buffer = Vec::new();
+    File::open(filename).unwrap().read_to_end(&mut buffer).unwrap();
+    let mut decoder = VorbisDecoder::from_vec(buffer.clone()).unwrap();
+    check_seek(&mut decoder, &linear, "memory");
+
+    let mut reader = Vorbis::from_reader(Cursor::new(buffer.clone())).unwrap();
+    check_seek(&mut reader, &linear, "reader");
+
+    // the stream embedded in a larger file, between junk that looks like pages
+    let mut embedded = vec![b'O'; 70000];
+    embedded.extend_from_slice(&buffer);
+    embedded.extend_from_slice(b"OggS junk");
+    let mut cursor = Cursor::new(embedded);
+    cursor.seek(SeekFrom::Start(70000)).unwrap();
+    let mut section = stb_vorbis_open_reader_section(cursor, buffer.len() as u64).unwrap();
+    check_seek(&mut section, &linear, "section");
+
+    check_pushdata_resync(&buffer, &linear);
+}
@@ -0,0 +1,231 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::panic;
+
+use stb_vorbis::{
+    stb_vorbis_open_reader_with_limits, stb_vorbis_open_pushdata_with_limits, stb_vorbis_decode_frame_pushdata,
+    stb_vorbis_get_samples_float_interleaved,
+};
+use stb_vorbis::{Vorbis, VorbisDecoder, OwnedVorbisDecoder, AudioBufferSlice, SetupLimits, VorbisErrorKind};
+
+mod common;
+use common::{fail, opened, BitWriter, id_header, comment_header, write_page};
+
+// header allocation caps: the file has to open from memory, through a
+// reader and in push mode with the same smallest setup cap, fail with
+// OutOfMem in all three one byte under it, and decode the same with that
+// cap and the comments cut down to nothing. setup headers asking for far
+// more than the default cap have to be turned down with OutOfMem in every
+// mode. then the file is truncated and has bytes of its headers and first
+// pages overwritten, over and over, and every copy opened and decoded in
+// all three modes without a panic. exits with 1 on mismatch.
+
+fn setup_limit(bytes: usize) -> SetupLimits {
+    SetupLimits { max_setup_bytes: bytes, ..SetupLimits::default() }
+}
+
+fn open_push(data: &[u8], limits: SetupLimits) -> Result<Vorbis, VorbisErrorKind> {
+    let mut used = 0;
+    let mut length = std::cmp::min(data.len(), 4096);
+    loop {
+        match stb_vorbis_open_pushdata_with_limits(&data[.. length], limits, &mut used) {
+            Ok(v) => return Ok(v),
+            Err(ref why) if why.kind() == VorbisErrorKind::NeedMoreData && length < data.len() => {
+                length = std::cmp::min(data.len(), length * 2);
+            }
+            Err(why) => return Err(why.kind()),
+        }
+    }
+}
+
+// what opening with these caps comes to in each mode
+fn open_all(data: &[u8], limits: SetupLimits) -> [Result<(), VorbisErrorKind>; 3] {
+    [
+        VorbisDecoder::with_limits(data, limits).map(|_| ()).map_err(|e| e.kind()),
+        stb_vorbis_open_reader_with_limits(Cursor::new(data.to_vec()), limits).map(|_| ()).map_err(|e| e.kind()),
+        open_push(data, limits).map(|_| ()),
+    ]
+}
+
+fn decode(v: &mut Vorbis, frames: usize) -> Vec<f32> {
+    let channels = v.channels as usize;
+    let mut samples = Vec::new();
+    let mut buffer = vec![0.0; 1024 * channels];
+    for _ in 0 .. frames {
+        let n = stb_vorbis_get_samples_float_interleaved(v, channels as i32, &mut buffer) as usize;
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&buffer[.. n * channels]);
+    }
+    return samples;
+}
+
+// the smallest setup cap the file opens with, the same in every mode
+fn check_setup_cap(data: &[u8]) -> usize {
+    let (mut low, mut high) = (0, SetupLimits::default().max_setup_bytes);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match VorbisDecoder::with_limits(data, setup_limit(middle)) {
+            Ok(_) => high = middle,
+            Err(ref why) if why.kind() == VorbisErrorKind::OutOfMem => low = middle + 1,
+            Err(why) => fail(format!("opening with a {} byte cap failed with {}", middle, why)),
+        }
+    }
+    if open_all(data, setup_limit(low)).iter().any(|r| r.is_err()) {
+        fail(format!("the modes don't all open with a {} byte cap", low));
+    }
+    if low > 0 && open_all(data, setup_limit(low - 1)).iter().any(|r| *r != Err(VorbisErrorKind::OutOfMem)) {
+        fail(format!("the modes don't all fail with OutOfMem under a {} byte cap", low));
+    }
+    println!("  setup cap: OK, the tables take {} bytes", low);
+    return low;
+}
+
+fn check_comment_cap(data: &[u8]) {
+    let clean = opened(VorbisDecoder::new(data));
+    let vendor = clean.get_comment().vendor().len();
+
+    let none = SetupLimits { max_comment_bytes: 0, ..SetupLimits::default() };
+    let v = opened(VorbisDecoder::with_limits(data, none));
+    if v.get_comment().is_empty() == false || (vendor > 0 && v.get_comment().vendor().is_empty() == false) {
+        fail(format!("comments kept with no room for them"));
+    }
+    let just_vendor = SetupLimits { max_comment_bytes: vendor, ..SetupLimits::default() };
+    let v = opened(VorbisDecoder::with_limits(data, just_vendor));
+    if v.get_comment().vendor() != clean.get_comment().vendor() || v.get_comment().is_empty() == false {
+        fail(format!("room for the vendor string alone keeps {} comments", v.get_comment().len()));
+    }
+    println!("  comment cap: OK, {} comments dropped", clean.get_comment().len());
+}
+
+// both caps as tight as they go change nothing about the audio
+fn check_capped_decode(data: &[u8], setup_bytes: usize) {
+    let limits = SetupLimits { max_setup_bytes: setup_bytes, max_comment_bytes: 0, ..SetupLimits::default() };
+    let mut capped = opened(OwnedVorbisDecoder::from_vec_with_limits(data.to_vec(), limits));
+    let mut clean = opened(OwnedVorbisDecoder::from_vec(data.to_vec()));
+    if decode(&mut capped, std::usize::MAX) != decode(&mut clean, std::usize::MAX) {
+        fail(format!("the file decodes differently with the tightest caps"));
+    }
+    println!("  capped decode: OK");
+}
+
+// a setup header with one codebook of 'entries' entries of 'dimensions'
+// values each, all of them one length, and every value in its own slot
+fn hostile_stream(entries: u32, dimensions: u32) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.write(5, 8);
+    w.write_bytes(b"vorbis");
+    w.write(0, 8);                    // one codebook
+    w.write(0x564342, 24);
+    w.write(dimensions, 16);
+    w.write(entries, 24);
+    w.write(1, 1);                    // ordered
+    w.write(23, 5);                   // 24 bits long
+    w.write(entries, 32 - entries.leading_zeros() as usize);
+    w.write(2, 4);                    // one value per entry and dimension
+    w.write(0, 32);
+    w.write(0, 32);
+    w.write(0, 4);
+    w.write(0, 1);
+    w.write(0, 32);                   // the start of the values, then nothing
+    w.write(0, 32);
+
+    let mut out = Vec::new();
+    write_page(&mut out, 1, &[id_header(1, 48000)], 2, 0, 0);
+    write_page(&mut out, 1, &[comment_header(), w.into_data()], 0, 0, 1);
+    return out;
+}
+
+fn check_hostile() {
+    // 80 MB of codeword tables, and 13 MB of them with 4 G values (16 GB
+    // of multiplicands) behind
+    for &(entries, dimensions) in [(0xffffff, 1), (1 << 20, 4000)].iter() {
+        let data = hostile_stream(entries, dimensions);
+        let results = open_all(&data, SetupLimits::default());
+        if results.iter().any(|r| *r != Err(VorbisErrorKind::OutOfMem)) {
+            fail(format!("{} entries of {} values: {:?}, not OutOfMem", entries, dimensions, results));
+        }
+    }
+    println!("  hostile setups: OK");
+}
+
+// decode a few frames of a damaged copy in each mode; none may panic
+fn survive(data: &[u8]) -> bool {
+    let result = panic::catch_unwind(|| {
+        if let Ok(mut v) = OwnedVorbisDecoder::from_vec(data.to_vec()) {
+            decode(&mut v, 8);
+        }
+        if let Ok(mut v) = stb_vorbis_open_reader_with_limits(Cursor::new(data.to_vec()), SetupLimits::default()) {
+            decode(&mut v, 8);
+        }
+        if let Ok(mut v) = open_push(data, SetupLimits::default()) {
+            let mut p = 0;
+            for _ in 0 .. 8 {
+                let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
+                let (mut channels, mut samples) = (0, 0);
+                let end = std::cmp::min(p + 8192, data.len());
+                let used = unsafe { stb_vorbis_decode_frame_pushdata(&mut v, &data[p .. end], &mut channels, &mut outputs, &mut samples) };
+                if used == 0 {
+                    break;
+                }
+                p += used as usize;
+            }
+        }
+    });
+    return result.is_ok();
+}
+
+fn check_damaged(data: &[u8]) {
+    panic::set_hook(Box::new(|info| println!("  {}", info)));
+    let mut seed: u32 = 0x9e3779b9;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    // the headers are where a stream says how much to allocate
+    let head = std::cmp::min(data.len(), 8192);
+    let mut copies = 0;
+    for round in 0 .. 300 {
+        let mut damaged = data[.. std::cmp::min(data.len(), 64 * 1024)].to_vec();
+        if round % 3 == 0 {
+            let at = next() as usize % head;
+            damaged.truncate(at);
+        }
+        for _ in 0 .. 1 + round % 4 {
+            if damaged.is_empty() {
+                break;
+            }
+            let at = next() as usize % std::cmp::min(damaged.len(), head);
+            damaged[at] = match next() % 4 {
+                0 => 0,
+                1 => 0xff,
+                2 => damaged[at] ^ (1 << (next() % 8)),
+                _ => next() as u8,
+            };
+        }
+        if survive(&damaged) == false {
+            fail(format!("panicked on damaged copy {}", round));
+        }
+        copies += 1;
+    }
+    println!("  damaged copies: OK, {} decoded without a panic", copies);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+    opened(VorbisDecoder::new(&data));
+
+    let setup_bytes = check_setup_cap(&data);
+    check_comment_cap(&data);
+    check_capped_decode(&data, setup_bytes);
+    check_hostile();
+    check_damaged(&data);
+}
@@ -0,0 +1,207 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use stb_vorbis::{
+    stb_vorbis_open_reader, stb_vorbis_open_pushdata, stb_vorbis_decode_frame_pushdata,
+    stb_vorbis_get_samples_float_interleaved,
+};
+use stb_vorbis::{Vorbis, VorbisDecoder, OwnedVorbisDecoder, AudioBufferSlice};
+
+mod common;
+use common::{fail, same};
+
+// threading checks: the file is decoded by several decoders at once, and by
+// decoders that move to a new thread every few hundred samples (or frames
+// in push mode). everything is compared against a plain linear decode.
+// exits with 1 on mismatch.
+
+const THREADS: usize = 8;
+const CHUNK: usize = 777;
+
+fn assert_send<T: Send>() {}
+
+// up to 'limit' samples per channel, interleaved, from whatever 'get'
+// fills the buffer from
+fn decode_with<F: FnMut(&mut [f32]) -> i32>(mut get: F, channels: usize, limit: usize) -> Vec<f32> {
+    let mut samples = Vec::new();
+    let mut sbuffer = vec![0.0; 333 * channels];
+    while samples.len() / channels < limit {
+        let len = std::cmp::min(333, limit - samples.len() / channels);
+        let n = get(&mut sbuffer[.. len * channels]);
+        if n == 0 {
+            break;
+        }
+        samples.extend_from_slice(&sbuffer[.. n as usize * channels]);
+    }
+    return samples;
+}
+
+fn decode(v: &mut Vorbis, channels: usize, limit: usize) -> Vec<f32> {
+    decode_with(|buffer| stb_vorbis_get_samples_float_interleaved(v, channels as i32, buffer), channels, limit)
+}
+
+fn decode_borrowed(v: &mut VorbisDecoder, channels: usize, limit: usize) -> Vec<f32> {
+    decode_with(|buffer| v.get_samples_float_interleaved(channels as i32, buffer), channels, limit)
+}
+
+fn check(samples: &[f32], reference: &[f32], name: &str) {
+    if !same(samples, reference) {
+        fail(format!("{}: {} samples decoded, {} expected, or they differ", name, samples.len(), reference.len()));
+    }
+    println!("  {}: OK", name);
+}
+
+// hand the decoder to a new thread for every chunk
+fn relay(mut v: Vorbis, channels: usize) -> Vec<f32> {
+    let mut samples = Vec::new();
+    loop {
+        let (next, chunk) = thread::spawn(move || {
+            let chunk = decode(&mut v, channels, CHUNK);
+            (v, chunk)
+        }).join().unwrap();
+        if chunk.is_empty() {
+            return samples;
+        }
+        samples.extend_from_slice(&chunk);
+        v = next;
+    }
+}
+
+fn relay_pushdata(data: Arc<[u8]>) -> Vec<f32> {
+    let mut used = 0;
+    let mut length = std::cmp::min(data.len(), 4096);
+    let mut v = loop {
+        match stb_vorbis_open_pushdata(&data[.. length], &mut used) {
+            Ok(v) => break v,
+            Err(_) if length < data.len() => length = std::cmp::min(data.len(), length * 2),
+            Err(why) => fail(format!("pushdata: {}", why)),
+        }
+    };
+
+    let mut p = used as usize;
+    let mut samples = Vec::new();
+    while p < data.len() {
+        let data = data.clone();
+        let (next, used, frames) = thread::spawn(move || {
+            let mut frames = Vec::new();
+            let mut q = 4096;
+            while p < data.len() && frames.len() < 8 {
+                let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
+                let mut num_c = 0;
+                let mut n = 0;
+                let end = std::cmp::min(p + q, data.len());
+                let used = unsafe {
+                    stb_vorbis_decode_frame_pushdata(&mut v, &data[p .. end], &mut num_c, &mut outputs, &mut n)
+                };
+                if used == 0 && end < data.len() {
+                    q *= 2;
+                    continue;
+                }
+                if used == 0 {
+                    break;
+                }
+                p += used as usize;
+                let mut frame = Vec::with_capacity(n as usize * num_c as usize);
+                for i in 0 .. n as usize {
+                    for c in 0 .. num_c as usize {
+                        frame.push(outputs[c][i]);
+                    }
+                }
+                frames.push(frame);
+            }
+            (v, p, frames)
+        }).join().unwrap();
+        if used == p {
+            break;
+        }
+        for frame in frames {
+            samples.extend_from_slice(&frame);
+        }
+        v = next;
+        p = used;
+    }
+    return samples;
+}
+
+fn main() {
+    assert_send::<Vorbis>();
+    assert_send::<OwnedVorbisDecoder>();
+    assert_send::<VorbisDecoder>();
+
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+
+    let mut v = match VorbisDecoder::new(&data) {
+        Ok(v) => v,
+        Err(why) => {
+            println!("Couldn't open the stream. Error: {}", why);
+            process::exit(why.kind() as i32);
+        }
+    };
+    let channels = v.get_info().channels as usize;
+    let reference = decode_borrowed(&mut v, channels, std::usize::MAX);
+    println!("{} channels, {} samples", channels, reference.len() / channels);
+
+    // owned decoders sharing one buffer, all at the same time
+    let shared: Arc<[u8]> = Arc::from(data.clone());
+    let workers: Vec<_> = (0 .. THREADS).map(|_| {
+        let shared = shared.clone();
+        thread::spawn(move || {
+            let mut v = match OwnedVorbisDecoder::from_arc(shared) {
+                Ok(v) => v,
+                Err(why) => fail(format!("shared: {}", why)),
+            };
+            decode(&mut v, channels, std::usize::MAX)
+        })
+    }).collect();
+    for (k, worker) in workers.into_iter().enumerate() {
+        check(&worker.join().unwrap(), &reference, &format!("shared buffer, thread {}", k));
+    }
+
+    // decoders borrowing the buffer, in scoped threads. each one decodes
+    // the first half on one thread and the rest on another
+    let half = reference.len() / channels / 2;
+    let started: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0 .. THREADS).map(|_| {
+            let data = &data[..];
+            scope.spawn(move || {
+                let mut v = match VorbisDecoder::new(data) {
+                    Ok(v) => v,
+                    Err(why) => fail(format!("borrowed: {}", why)),
+                };
+                let samples = decode_borrowed(&mut v, channels, half);
+                (v, samples)
+            })
+        }).collect();
+        workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+    });
+    thread::scope(|scope| {
+        let workers: Vec<_> = started.into_iter().map(|(mut v, mut samples)| {
+            scope.spawn(move || {
+                samples.extend(decode_borrowed(&mut v, channels, std::usize::MAX));
+                samples
+            })
+        }).collect();
+        for (k, worker) in workers.into_iter().enumerate() {
+            check(&worker.join().unwrap(), &reference, &format!("borrowed buffer, thread {}", k));
+        }
+    });
+
+    // one decoder of each kind, moving from thread to thread
+    match OwnedVorbisDecoder::from_vec(data.clone()) {
+        Ok(v) => check(&relay(v.into_vorbis(), channels), &reference, "owned decoder relay"),
+        Err(why) => fail(format!("owned: {}", why)),
+    }
+    match stb_vorbis_open_reader(Cursor::new(data.clone())) {
+        Ok(v) => check(&relay(v, channels), &reference, "reader relay"),
+        Err(why) => fail(format!("reader: {}", why)),
+    }
+    check(&relay_pushdata(shared), &reference, "pushdata relay");
+}
@@ -0,0 +1,200 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use std::process;
+
+use stb_vorbis::{stb_vorbis_write_wav, stb_vorbis_write_wav_channels, stb_vorbis_get_samples_interleaved};
+use stb_vorbis::{OwnedVorbisDecoder, ResampleQuality, Sample, WavFormat, I24, VorbisErrorKind};
+
+mod common;
+use common::{fail, build_stream};
+
+// WAV export checks: the file, and made up streams of 1 to 8 and 11
+// channels, are written as 16-bit, 24-bit and float WAV files after a few
+// bytes of something else, from the start and after a seek. the headers
+// are read back field by field, the channels have to be in WAV order with
+// the right mask, and the samples the same as get_samples puts out. output
+// rates too high for the header's 32-bit byte rate have to fail before
+// anything is written. exits with 1 on mismatch.
+
+// the Vorbis channel each WAV channel comes from, and the channel masks
+const WAV_ORDER: [&[usize]; 9] = [
+    &[],
+    &[0],
+    &[0, 1],
+    &[0, 2, 1],
+    &[0, 1, 2, 3],
+    &[0, 2, 1, 3, 4],
+    &[0, 2, 1, 5, 3, 4],
+    &[0, 2, 1, 6, 5, 3, 4],
+    &[0, 2, 1, 7, 5, 6, 3, 4],
+];
+const MASKS: [u32; 9] = [0, 0x4, 0x3, 0x7, 0x33, 0x37, 0x3f, 0x70f, 0x63f];
+
+const PREFIX: &[u8] = b"junk!";
+
+fn open(data: &[u8], rate: u32, start: u64) -> OwnedVorbisDecoder {
+    let mut v = match OwnedVorbisDecoder::from_vec(data.to_vec()) {
+        Ok(v) => v,
+        Err(why) => {
+            println!("Couldn't open the stream. Error: {}", why);
+            process::exit(why.kind() as i32);
+        }
+    };
+    if rate != 0 {
+        v.set_output_rate(rate, ResampleQuality::Linear);
+    }
+    if start != 0 && v.seek(start) == false {
+        fail(format!("can't seek to {}", start));
+    }
+    return v;
+}
+
+fn u16_at(data: &[u8], p: usize) -> u32 {
+    data[p] as u32 | (data[p + 1] as u32) << 8
+}
+
+fn u32_at(data: &[u8], p: usize) -> u32 {
+    u16_at(data, p) | u16_at(data, p + 2) << 16
+}
+
+// all of the stream as get_samples puts it out, in WAV channel order, as bytes
+fn expected_data<T: Sample + Default, F: Fn(&[T], &mut Vec<u8>)>(data: &[u8], rate: u32, start: u64, channels: usize, put: F) -> Vec<u8> {
+    let mut v = open(data, rate, start);
+    let mut sbuffer = vec![T::default(); 1000 * channels];
+    let mut samples = Vec::new();
+    loop {
+        let n = stb_vorbis_get_samples_interleaved(&mut v, channels as i32, &mut sbuffer) as usize;
+        if n == 0 {
+            break;
+        }
+        for frame in sbuffer[.. n * channels].chunks(channels) {
+            for c in 0 .. channels {
+                samples.push(frame[if channels <= 8 { WAV_ORDER[channels][c] } else { c }]);
+            }
+        }
+    }
+    let mut bytes = Vec::new();
+    put(&samples, &mut bytes);
+    return bytes;
+}
+
+// writes the stream from sample 'start' on
+fn check(data: &[u8], rate: u32, start: u64, format: WavFormat, name: &str) {
+    let mut v = open(data, rate, start);
+    let channels = v.get_info().channels as usize;
+    let mut out = Cursor::new(PREFIX.to_vec());
+    out.seek(SeekFrom::End(0)).unwrap();
+    let samples = match stb_vorbis_write_wav(&mut v, &mut out, format) {
+        Ok(n) => n,
+        Err(why) => fail(format!("{}, {:?}: {}", name, format, why)),
+    };
+    if out.position() != out.get_ref().len() as u64 {
+        fail(format!("{}, {:?}: not left at the end", name, format));
+    }
+    let file = out.into_inner();
+    if &file[.. PREFIX.len()] != PREFIX {
+        fail(format!("{}, {:?}: wrote over what came before", name, format));
+    }
+    let wav = &file[PREFIX.len() ..];
+    let name = format!("{}, {:?}", name, format);
+
+    // the chunks
+    if &wav[0 .. 4] != b"RIFF" || &wav[8 .. 12] != b"WAVE" || u32_at(wav, 4) as usize != wav.len() - 8 {
+        fail(format!("{}: bad RIFF header", name));
+    }
+    let mut p = 12;
+    let mut fmt = &wav[0 .. 0];
+    let mut fact = None;
+    let mut sample_data = None;
+    while p + 8 <= wav.len() {
+        let size = u32_at(wav, p + 4) as usize;
+        let chunk = &wav[p + 8 .. std::cmp::min(p + 8 + size, wav.len())];
+        match &wav[p .. p + 4] {
+            b"fmt " => fmt = chunk,
+            b"fact" => fact = Some(u32_at(chunk, 0)),
+            b"data" => sample_data = Some(chunk),
+            id => fail(format!("{}: unknown chunk {:?}", name, id)),
+        }
+        p += 8 + size + (size & 1);
+    }
+    if p != wav.len() {
+        fail(format!("{}: chunks end at {} of {}", name, p, wav.len()));
+    }
+
+    // the format
+    let rate = v.get_output_rate();
+    let bits = format.bits_per_sample();
+    let float = format == WavFormat::Float32;
+    let extensible = channels > 2 || bits > 16;
+    let tag = if extensible { 0xfffe } else if float { 3 } else { 1 };
+    if fmt.len() != if extensible { 40 } else if float { 18 } else { 16 }
+        || u16_at(fmt, 0) != tag || u16_at(fmt, 2) as usize != channels || u32_at(fmt, 4) != rate
+        || u32_at(fmt, 8) as usize != rate as usize * channels * bits / 8
+        || u16_at(fmt, 12) as usize != channels * bits / 8 || u16_at(fmt, 14) as usize != bits
+    {
+        fail(format!("{}: bad fmt chunk {:?}", name, fmt));
+    }
+    if extensible {
+        let subformat = if float { 3 } else { 1 };
+        let mask = if channels <= 8 { MASKS[channels] } else { 0 };
+        if u16_at(fmt, 16) != 22 || u16_at(fmt, 18) as usize != bits || u32_at(fmt, 20) != mask
+            || u16_at(fmt, 24) != subformat || fmt[26 ..] != [0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xaa, 0, 0x38, 0x9b, 0x71]
+        {
+            fail(format!("{}: bad extensible format {:?}", name, &fmt[16 ..]));
+        }
+    } else if float && u16_at(fmt, 16) != 0 {
+        fail(format!("{}: cbSize isn't 0", name));
+    }
+    if fact != if extensible || float { Some(samples as u32) } else { None } {
+        fail(format!("{}: fact chunk {:?} for {} samples", name, fact, samples));
+    }
+
+    // the samples
+    let sample_data = sample_data.unwrap_or_else(|| fail(format!("{}: no data chunk", name)));
+    let expected = match format {
+        WavFormat::Pcm16 => expected_data(data, rate, start, channels, |s: &[i16], b| for x in s { b.extend_from_slice(&x.to_le_bytes()) }),
+        WavFormat::Pcm24 => expected_data(data, rate, start, channels, |s: &[I24], b| b.extend_from_slice(I24::as_bytes(s))),
+        WavFormat::Float32 => expected_data(data, rate, start, channels, |s: &[f32], b| for x in s { b.extend_from_slice(&x.to_le_bytes()) }),
+    };
+    if sample_data.len() as u64 != samples * (channels * bits / 8) as u64 || sample_data != &expected[..] {
+        fail(format!("{}: {} bytes of samples for {} samples, or they differ", name, sample_data.len(), samples));
+    }
+    println!("  {}: OK", name);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut data = Vec::new();
+    File::open(&args[1]).unwrap().read_to_end(&mut data).unwrap();
+
+    for &format in [WavFormat::Pcm16, WavFormat::Pcm24, WavFormat::Float32].iter() {
+        check(&data, 0, 0, format, "file");
+    }
+    check(&data, 48000, 0, WavFormat::Pcm16, "file at 48 kHz");
+    // an odd sample count, for the padding of mono 24-bit data
+    check(&data, 0, 1, WavFormat::Pcm24, "file from sample 1");
+
+    for &channels in [1, 2, 3, 4, 5, 6, 7, 8, 11].iter() {
+        let all: Vec<usize> = (0 .. channels).collect();
+        let stream = build_stream(&all);
+        for &format in [WavFormat::Pcm16, WavFormat::Pcm24, WavFormat::Float32].iter() {
+            check(&stream, 0, 0, format, &format!("{} channels", channels));
+        }
+    }
+
+    // 255 channels of floats at 4210752 Hz are the most bytes per second
+    // that fit; one more and there's no header to write
+    for &rate in [4210753, u32::MAX].iter() {
+        let mut v = open(&data, rate, 0);
+        let mut out = Cursor::new(Vec::new());
+        let name = format!("255 channels at {} Hz", rate);
+        match stb_vorbis_write_wav_channels(&mut v, &mut out, WavFormat::Float32, 255) {
+            Err(ref why) if why.kind() == VorbisErrorKind::FeatureNotSupported && out.get_ref().is_empty() => {}
+            result => fail(format!("{}: {:?}, {} bytes written", name, result, out.get_ref().len())),
+        }
+        println!("  {}: OK", name);
+    }
+}
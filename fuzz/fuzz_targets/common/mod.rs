@@ -0,0 +1,39 @@
+// what the fuzz targets share: the first SETTINGS bytes of an input pick the
+// output rate, CRC checking, error recovery, dither and channel mix the rest
+// is decoded with, so that each of them gets fuzzed along with the stream.
+// push mode has none of them but the mix; setting the others has to be
+// harmless there too.
+
+use stb_vorbis::{stb_vorbis_set_output_rate, stb_vorbis_set_crc_check, stb_vorbis_set_error_recovery};
+use stb_vorbis::{stb_vorbis_set_dither, stb_vorbis_set_channel_mix};
+use stb_vorbis::{Vorbis, ResampleQuality, CrcCheck, Dither, ChannelMix};
+
+pub const SETTINGS: usize = 2;
+
+// sets 'v' up as 'settings' say, and returns the channel count to ask the
+// functions that take one for
+pub fn configure(v: &mut Vorbis, settings: &[u8]) -> i32 {
+    let (a, b) = (settings[0], settings[1]);
+
+    let rate = [0, 8000, 44100, 96000][(a & 3) as usize];
+    let quality = if a & 4 != 0 { ResampleQuality::WindowedSinc } else { ResampleQuality::Linear };
+    stb_vorbis_set_output_rate(v, rate, quality);
+    let check = [CrcCheck::Off, CrcCheck::Skip, CrcCheck::Fail, CrcCheck::Skip][(a >> 3 & 3) as usize];
+    stb_vorbis_set_crc_check(v, check);
+    stb_vorbis_set_error_recovery(v, a & 0x20 != 0);
+    let dither = [Dither::Off, Dither::Tpdf, Dither::Shaped, Dither::Off][(a >> 6) as usize];
+    stb_vorbis_set_dither(v, dither, b as u32);
+
+    // from 1 to 8 output channels, mixed by one of the rules
+    let outputs = (b & 7) as usize + 1;
+    let inputs = v.channels as usize;
+    let mix = match b >> 3 & 3 {
+        0 => ChannelMix::Standard,
+        1 => ChannelMix::Original,
+        2 => ChannelMix::Matrix { inputs: inputs, outputs: outputs, gains: vec![0.5; inputs * outputs] },
+        // one that doesn't fit the stream, mixed as Standard
+        _ => ChannelMix::Matrix { inputs: inputs + 1, outputs: outputs, gains: vec![-1.0; (inputs + 1) * outputs] },
+    };
+    stb_vorbis_set_channel_mix(v, mix);
+    return outputs as i32;
+}
@@ -0,0 +1,43 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate stb_vorbis;
+
+use stb_vorbis::{VorbisDecoder, SetupLimits};
+
+mod common;
+use common::{configure, SETTINGS};
+
+// after the settings, opens the input from memory and decodes all of it,
+// frame by frame as floats and then again from the start as interleaved
+// 16-bit and float samples. first it goes through the setup with caps small
+// enough to trip halfway
+fuzz_target!(|data: &[u8]| {
+    if data.len() < SETTINGS {
+        return;
+    }
+    let (settings, data) = data.split_at(SETTINGS);
+
+    let tight = SetupLimits { max_setup_bytes: 4096, max_comment_bytes: 64, ..SetupLimits::default() };
+    if let Ok(mut v) = VorbisDecoder::with_limits(data, tight) {
+        v.get_frame_float(None, None);
+    }
+
+    let mut v = match VorbisDecoder::from_vec(data.to_vec()) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    let channels = configure(&mut v, settings);
+    let _ = v.get_comment().get("TITLE");
+
+    while v.get_frame_float(None, None) != 0 {}
+    v.get_last_error();
+
+    v.seek_start();
+    let mut buffer = vec![0i16; 1024 * channels as usize];
+    while v.get_samples_short_interleaved(channels as u32, &mut buffer) != 0 {}
+
+    v.seek_start();
+    let mut buffer = vec![0.0; 1024 * channels as usize];
+    while v.get_samples_float_interleaved(channels, &mut buffer) != 0 {}
+});
@@ -0,0 +1,65 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate stb_vorbis;
+
+use stb_vorbis::{stb_vorbis_open_pushdata, stb_vorbis_decode_frame_pushdata, stb_vorbis_flush_pushdata};
+use stb_vorbis::{AudioBufferSlice, VorbisErrorKind};
+
+mod common;
+use common::{configure, SETTINGS};
+
+// after the settings, a byte picks how much data is handed over at a time;
+// the rest is opened and decoded in push mode the way a streaming player
+// would, more data for every call that needs it, with a flush halfway
+// through
+fuzz_target!(|data: &[u8]| {
+    if data.len() < SETTINGS + 1 {
+        return;
+    }
+    let settings = &data[.. SETTINGS];
+    let step = data[SETTINGS] as usize + 1;
+    let data = &data[SETTINGS + 1 ..];
+
+    let mut used = 0;
+    let mut length = std::cmp::min(step, data.len());
+    let mut v = loop {
+        match stb_vorbis_open_pushdata(&data[.. length], &mut used) {
+            Ok(v) => break v,
+            Err(ref why) if why.kind() == VorbisErrorKind::NeedMoreData && length < data.len() => {
+                length = std::cmp::min(length + step, data.len());
+            }
+            Err(_) => return,
+        }
+    };
+    configure(&mut v, settings);
+
+    let mut p = used as usize;
+    let mut flushed = false;
+    while p < data.len() {
+        if flushed == false && p > data.len() / 2 {
+            stb_vorbis_flush_pushdata(&mut v);
+            flushed = true;
+        }
+        let mut outputs = AudioBufferSlice::new(0);
+        let mut channels = 0;
+        let mut samples = 0;
+        let mut q = step;
+        let used = loop {
+            let end = std::cmp::min(p + q, data.len());
+            let used = unsafe { stb_vorbis_decode_frame_pushdata(&mut v, &data[p .. end], &mut channels, &mut outputs, &mut samples) };
+            if used == 0 && end < data.len() {
+                q += step;
+                continue;
+            }
+            break used as usize;
+        };
+        if used == 0 {
+            break;
+        }
+        p += used;
+        for c in 0 .. channels as usize {
+            let _ = outputs[c][.. samples as usize].iter().sum::<f32>();
+        }
+    }
+});
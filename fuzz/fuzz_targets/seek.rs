@@ -0,0 +1,51 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate stb_vorbis;
+
+use std::io::Cursor;
+
+use stb_vorbis::{stb_vorbis_open_reader, VorbisDecoder, Vorbis};
+use stb_vorbis::{stb_vorbis_stream_length_in_samples, stb_vorbis_seek, stb_vorbis_seek_frame, stb_vorbis_seek_start};
+use stb_vorbis::{stb_vorbis_get_frame_float, stb_vorbis_get_samples_float_interleaved, stb_vorbis_get_sample_offset};
+use stb_vorbis::stb_vorbis_get_samples_short_interleaved;
+
+mod common;
+use common::{configure, SETTINGS};
+
+// after the settings, 8 bytes are two places to seek to; the rest is opened
+// from memory and through a reader, measured, and seeked around in,
+// decoding a little after each seek
+fuzz_target!(|data: &[u8]| {
+    if data.len() < SETTINGS + 8 {
+        return;
+    }
+    let (settings, data) = data.split_at(SETTINGS);
+    let first = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as u64;
+    let second = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as u64;
+    let data = &data[8 ..];
+
+    if let Ok(mut v) = VorbisDecoder::from_vec(data.to_vec()) {
+        seek_around(&mut v, settings, first, second);
+    }
+    if let Ok(mut v) = stb_vorbis_open_reader(Cursor::new(data.to_vec())) {
+        seek_around(&mut v, settings, first, second);
+    }
+});
+
+fn seek_around(v: &mut Vorbis, settings: &[u8], first: u64, second: u64) {
+    let channels = configure(v, settings);
+    let mut shorts = vec![0; 256 * channels as usize];
+    let mut buffer = vec![0.0; 256 * channels as usize];
+    let length = stb_vorbis_stream_length_in_samples(v);
+    for &target in [first, second, first % (length + 1), second % (length + 1)].iter() {
+        stb_vorbis_seek(v, target);
+        stb_vorbis_get_samples_float_interleaved(v, channels, &mut buffer);
+        stb_vorbis_get_samples_short_interleaved(v, channels as u32, &mut shorts);
+        stb_vorbis_get_sample_offset(v);
+        stb_vorbis_seek_frame(v, target);
+        stb_vorbis_get_frame_float(v, None, None);
+    }
+    stb_vorbis_seek_start(v);
+    stb_vorbis_get_samples_float_interleaved(v, channels, &mut buffer);
+}
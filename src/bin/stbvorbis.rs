@@ -0,0 +1,425 @@
+extern crate stb_vorbis;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::process;
+
+use stb_vorbis::{
+    stb_vorbis_open_filename, stb_vorbis_get_info, stb_vorbis_get_comment, stb_vorbis_get_streams,
+    stb_vorbis_get_serial, stb_vorbis_select_stream, stb_vorbis_stream_length_in_samples,
+    stb_vorbis_get_output_rate, stb_vorbis_set_output_rate, stb_vorbis_set_dither, stb_vorbis_get_error,
+    stb_vorbis_get_samples_interleaved, stb_vorbis_write_wav_channels, stb_vorbis_set_channel_mix,
+};
+use stb_vorbis::{Vorbis, VorbisError, VorbisErrorKind, OggPageReader, ChannelMix, ResampleQuality, Dither, Sample, WavFormat, I24};
+
+// stbvorbis: decodes, inspects and verifies Ogg Vorbis files through the
+// library's own API. exits with 0 on success, with the code exit_code()
+// gives a decoder error, or with 1 for anything else (bad arguments, i/o
+// errors, CRC failures).
+
+const USAGE: &str = "\
+usage: stbvorbis info FILE
+       stbvorbis decode [OPTIONS] FILE OUTPUT
+       stbvorbis verify FILE...
+       stbvorbis pages FILE
+
+  info     channels, sample rate, length, bitrates and comments
+  decode   decode to a WAV file, or to raw interleaved samples in the
+           Vorbis channel order with --raw ('-' writes them to stdout)
+  verify   decode everything and check the CRC of every page
+  pages    list the Ogg pages; flags are c(ontinued packet), b(eginning
+           of stream) and e(nd of stream)
+
+decode options:
+  --format s16|s24|f32    sample format (default s16)
+  --raw                   no WAV header
+  --channels N            mix to N channels (default: the stream's)
+  --mix standard|original by speaker position (default), or the way the
+                          original stb_vorbis does
+  --rate HZ               resample to HZ samples per second
+  --dither tpdf|shaped    dither integer samples
+  --seed N                dither noise seed (default 0)
+  --stream SERIAL         the logical stream of a multiplexed file";
+
+// how many bad pages verify lists before it just counts them
+const LISTED_PAGES: usize = 10;
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("stbvorbis: {}", message);
+    process::exit(1);
+}
+
+// the exit status for a decoder error: the numbers the VorbisErrorKind
+// values have today, spelled out so reordering the enum can't move them,
+// and never 0
+fn exit_code(kind: VorbisErrorKind) -> i32 {
+    use VorbisErrorKind::*;
+    match kind {
+        NoError | NeedMoreData => 1,
+        InvalidApiMixing => 2,
+        OutOfMem => 3,
+        FeatureNotSupported => 4,
+        TooManyChannels => 5,
+        FileOpenFailure => 6,
+        SeekWithoutLength => 7,
+        WriteFailure => 8,
+        UnexpectedEof => 10,
+        SeekInvalid => 11,
+        InvalidSetup => 20,
+        InvalidStream => 21,
+        MissingCapturePattern => 30,
+        InvalidStreamStructureVersion => 31,
+        ContinuedPacketFlagInvalid => 32,
+        IncorrectStreamSerialNumber => 33,
+        InvalidFirstPage => 34,
+        BadPacketType => 35,
+        CantFindLastPage => 36,
+        SeekFailed => 37,
+        InvalidPageCrc => 38,
+    }
+}
+
+fn decoder_failure(name: &str, why: VorbisError) -> ! {
+    eprintln!("stbvorbis: {}: {}", name, why);
+    process::exit(exit_code(why.kind()));
+}
+
+fn open(name: &str) -> Vorbis {
+    match stb_vorbis_open_filename(Path::new(name)) {
+        Ok(v) => v,
+        Err(why) => decoder_failure(name, why),
+    }
+}
+
+// the decoder's pending error, if any
+fn decode_error(v: &mut Vorbis) -> Option<VorbisErrorKind> {
+    match stb_vorbis_get_error(v) {
+        VorbisErrorKind::NoError => None,
+        e => Some(e),
+    }
+}
+
+fn kbps(bitrate: i32) -> String {
+    if bitrate > 0 {
+        format!("{} kbps", (bitrate + 500) / 1000)
+    } else {
+        format!("-")
+    }
+}
+
+fn info(name: &str) {
+    let mut v = open(name);
+    let info = stb_vorbis_get_info(&v);
+    let serial = stb_vorbis_get_serial(&v);
+    let streams: Vec<String> = stb_vorbis_get_streams(&v).iter().map(|s| {
+        let kind = if s.serial == serial { " (decoded)" } else if s.vorbis { " (vorbis)" } else { "" };
+        format!("{:08x}{}", s.serial, kind)
+    }).collect();
+    let samples = stb_vorbis_stream_length_in_samples(&mut v);
+    let seconds = samples as f64 / info.sample_rate as f64;
+    let file_size = match std::fs::metadata(name) {
+        Ok(m) => m.len(),
+        Err(why) => fail(format!("{}: {}", name, why)),
+    };
+
+    println!("file:            {}", name);
+    println!("streams:         {}", streams.join(", "));
+    println!("channels:        {} ({:?})", info.channels, info.layout);
+    println!("sample rate:     {} Hz", info.sample_rate);
+    println!("length:          {} samples, {:.3} s", samples, seconds);
+    println!("bitrate:         {} nominal, {} minimum, {} maximum", kbps(info.bitrate_nominal), kbps(info.bitrate_minimum), kbps(info.bitrate_maximum));
+    if seconds > 0.0 {
+        println!("average bitrate: {}", kbps((file_size as f64 * 8.0 / seconds) as i32));
+    }
+    println!("max frame size:  {}", info.max_frame_size);
+    let comments = stb_vorbis_get_comment(&v);
+    println!("vendor:          {}", comments.vendor());
+    for &(ref key, ref value) in comments.comments() {
+        println!("  {}={}", key, value);
+    }
+}
+
+// the rest of the stream as T samples, interleaved, 'put' turning them into
+// bytes. returns the samples per channel written
+fn write_raw<T, W, F>(v: &mut Vorbis, out: &mut W, channels: usize, mut put: F) -> io::Result<u64>
+    where T: Sample + Default, W: Write, F: FnMut(&[T], &mut Vec<u8>)
+{
+    let mut samples = vec![T::default(); 4096 * channels];
+    let mut bytes = Vec::new();
+    let mut total = 0;
+    loop {
+        let n = stb_vorbis_get_samples_interleaved(v, channels as i32, &mut samples) as usize;
+        if n == 0 {
+            return Ok(total);
+        }
+        bytes.clear();
+        put(&samples[.. n * channels], &mut bytes);
+        out.write_all(&bytes)?;
+        total += n as u64;
+    }
+}
+
+fn decode(args: &[String]) {
+    let mut format = WavFormat::Pcm16;
+    let mut raw = false;
+    let mut channels = None;
+    let mut mix = ChannelMix::Standard;
+    let mut rate = None;
+    let mut dither = Dither::Off;
+    let mut seed = 0;
+    let mut serial = None;
+    let mut files = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i][..];
+        if arg.starts_with("--") && arg != "--raw" && i + 1 == args.len() {
+            fail(format!("{} needs a value", arg));
+        }
+        let value = args.get(i + 1).map(|s| &s[..]).unwrap_or("");
+        let number = || value.parse::<u32>().unwrap_or_else(|_| fail(format!("{} {}: not a number", arg, value)));
+        match arg {
+            "--format" => format = match value {
+                "s16" => WavFormat::Pcm16,
+                "s24" => WavFormat::Pcm24,
+                "f32" => WavFormat::Float32,
+                _ => fail(format!("unknown format {}", value)),
+            },
+            "--raw" => {
+                raw = true;
+                i += 1;
+                continue;
+            }
+            "--channels" => channels = Some(number()),
+            "--mix" => mix = match value {
+                "standard" => ChannelMix::Standard,
+                "original" => ChannelMix::Original,
+                _ => fail(format!("unknown mix {}", value)),
+            },
+            "--rate" => rate = Some(number()),
+            "--dither" => dither = match value {
+                "tpdf" => Dither::Tpdf,
+                "shaped" => Dither::Shaped,
+                _ => fail(format!("unknown dither {}", value)),
+            },
+            "--seed" => seed = number(),
+            "--stream" => serial = Some(u32::from_str_radix(value, 16).unwrap_or_else(|_| fail(format!("bad serial {}", value)))),
+            _ if arg.starts_with("--") => fail(format!("unknown option {}", arg)),
+            _ => {
+                files.push(arg);
+                i += 1;
+                continue;
+            }
+        }
+        i += 2;
+    }
+    if files.len() != 2 {
+        usage();
+    }
+    let (name, output) = (files[0], files[1]);
+
+    let mut v = open(name);
+    if let Some(serial) = serial {
+        if stb_vorbis_select_stream(&mut v, serial) == false {
+            fail(format!("{}: no vorbis stream {:08x}", name, serial));
+        }
+    }
+    stb_vorbis_set_channel_mix(&mut v, mix);
+    if let Some(rate) = rate {
+        stb_vorbis_set_output_rate(&mut v, rate, ResampleQuality::WindowedSinc);
+    }
+    stb_vorbis_set_dither(&mut v, dither, seed);
+    let channels = channels.unwrap_or(v.channels as u32) as usize;
+    if channels < 1 || channels > 255 {
+        fail(format!("can't decode to {} channels", channels));
+    }
+
+    let samples = if raw {
+        let mut out: Box<dyn Write> = if output == "-" {
+            Box::new(io::stdout())
+        } else {
+            match File::create(output) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(why) => fail(format!("{}: {}", output, why)),
+            }
+        };
+        let written = match format {
+            WavFormat::Pcm16 => write_raw(&mut v, &mut out, channels, |s: &[i16], b| for x in s { b.extend_from_slice(&x.to_le_bytes()) }),
+            WavFormat::Pcm24 => write_raw(&mut v, &mut out, channels, |s: &[I24], b| b.extend_from_slice(I24::as_bytes(s))),
+            WavFormat::Float32 => write_raw(&mut v, &mut out, channels, |s: &[f32], b| for x in s { b.extend_from_slice(&x.to_le_bytes()) }),
+        };
+        match written.and_then(|n| out.flush().map(|_| n)) {
+            Ok(n) => n,
+            Err(why) => fail(format!("{}: {}", output, why)),
+        }
+    } else {
+        let file = match File::create(output) {
+            Ok(file) => file,
+            Err(why) => fail(format!("{}: {}", output, why)),
+        };
+        match stb_vorbis_write_wav_channels(&mut v, BufWriter::new(file), format, channels as i32) {
+            Ok(n) => n,
+            Err(why) => decoder_failure(name, why),
+        }
+    };
+    if let Some(e) = decode_error(&mut v) {
+        eprintln!("stbvorbis: {}: {}", name, e);
+        process::exit(exit_code(e));
+    }
+    eprintln!("{}: {} samples, {} channels at {} Hz", output, samples, channels, stb_vorbis_get_output_rate(&v));
+}
+
+fn open_pages(name: &str) -> OggPageReader<BufReader<File>> {
+    match File::open(name) {
+        Ok(file) => OggPageReader::new(BufReader::new(file)),
+        Err(why) => fail(format!("{}: {}", name, why)),
+    }
+}
+
+fn pages(name: &str) {
+    let stdout = io::stdout();
+    match list_pages(name, &mut stdout.lock()) {
+        Ok(()) => {}
+        // piped into something that has seen enough
+        Err(ref why) if why.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(why) => fail(format!("{}", why)),
+    }
+}
+
+fn list_pages<W: Write>(name: &str, out: &mut W) -> io::Result<()> {
+    let mut r = open_pages(name);
+    writeln!(out, "{:>10} {:>8} {:>8} {:>20} {:>5} {:>4} {:>6} {:>7}  crc", "offset", "serial", "sequence", "granule", "flags", "segs", "bytes", "packets")?;
+    loop {
+        let page = match r.next_page() {
+            Ok(Some(page)) => page,
+            Ok(None) => break,
+            Err(ref why) if why.kind() == io::ErrorKind::UnexpectedEof => {
+                writeln!(out, "{:>10} {}", "", why)?;
+                break;
+            }
+            Err(why) => fail(format!("{}: {}", name, why)),
+        };
+        if page.skipped != 0 {
+            writeln!(out, "{:>10} {} bytes of junk", page.offset - page.skipped, page.skipped)?;
+        }
+        let flags = format!("{}{}{}",
+            if page.is_continued() { "c" } else { "-" },
+            if page.is_first() { "b" } else { "-" },
+            if page.is_last() { "e" } else { "-" });
+        let granule = if page.granule_position == !0 { format!("-") } else { format!("{}", page.granule_position) };
+        // packets that end on the page
+        let packets = page.packets().iter().filter(|p| p.complete).count();
+        writeln!(out, "{:>10} {:08x} {:>8} {:>20} {:>5} {:>4} {:>6} {:>7}  {}{}",
+            page.offset, page.serial, page.sequence, granule, flags, page.lacing.len(),
+            page.size(), packets,
+            if page.crc_ok { "ok" } else { "BAD" },
+            if page.version != 0 { ", bad version" } else { "" })?;
+    }
+    Ok(())
+}
+
+// true if the file is sound
+fn verify(name: &str) -> Result<bool, VorbisErrorKind> {
+    let mut ok = true;
+
+    // every page's CRC, and anything between pages
+    let mut r = open_pages(name);
+    let mut count = 0;
+    let mut bad = 0;
+    let mut end = 0;
+    loop {
+        let page = match r.next_page() {
+            Ok(Some(page)) => page,
+            Ok(None) => {
+                if r.offset() > end {
+                    println!("{}: {} bytes of junk at {}", name, r.offset() - end, end);
+                    ok = false;
+                }
+                break;
+            }
+            Err(ref why) if why.kind() == io::ErrorKind::UnexpectedEof => {
+                println!("{}: {}", name, why);
+                ok = false;
+                break;
+            }
+            Err(why) => fail(format!("{}: {}", name, why)),
+        };
+        count += 1;
+        end = page.offset + page.size() as u64;
+        if page.skipped != 0 {
+            println!("{}: {} bytes of junk at {}", name, page.skipped, page.offset - page.skipped);
+            ok = false;
+        }
+        if page.crc_ok == false || page.version != 0 {
+            bad += 1;
+            if bad <= LISTED_PAGES {
+                println!("{}: bad page at {}, stream {:08x}, sequence {}", name, page.offset, page.serial, page.sequence);
+            }
+        }
+    }
+    if bad > LISTED_PAGES {
+        println!("{}: {} more bad pages", name, bad - LISTED_PAGES);
+    }
+    if bad != 0 {
+        ok = false;
+    }
+
+    // everything decoded
+    let mut v = match stb_vorbis_open_filename(Path::new(name)) {
+        Ok(v) => v,
+        Err(why) => {
+            println!("{}: {}", name, why);
+            return Err(why.kind());
+        }
+    };
+    let channels = v.channels as usize;
+    let mut samples = vec![0.0f32; 4096 * channels];
+    let mut decoded = 0u64;
+    loop {
+        let n = stb_vorbis_get_samples_interleaved(&mut v, channels as i32, &mut samples);
+        if n == 0 {
+            break;
+        }
+        decoded += n as u64;
+    }
+    if let Some(e) = decode_error(&mut v) {
+        println!("{}: {} after {} samples", name, e, decoded);
+        return Err(e);
+    }
+    println!("{}: {} pages{}, {} samples decoded{}", name, count,
+        if bad != 0 { format!(", {} bad", bad) } else { format!("") }, decoded,
+        if ok { ", ok" } else { "" });
+    return Ok(ok);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        usage();
+    }
+    match &args[1][..] {
+        "info" if args.len() == 3 => info(&args[2]),
+        "decode" => decode(&args[2 ..]),
+        "pages" if args.len() == 3 => pages(&args[2]),
+        "verify" => {
+            let mut status = 0;
+            for name in &args[2 ..] {
+                match verify(name) {
+                    Ok(true) => {}
+                    Ok(false) => if status == 0 { status = 1 },
+                    Err(e) => status = exit_code(e),
+                }
+            }
+            process::exit(status);
+        }
+        _ => usage(),
+    }
+}
@@ -7,18 +7,40 @@ use ::std::slice;
 /// this basically slice but for vorbis audio buffer and not owned.
 /// just work around so I don't need to add lifetime annotation to ALL function that use
 /// Vorbis struct.
-#[derive(Copy, Clone)]
+///
+/// the channel list is sized at runtime, so any channel count the stream
+/// header allows (up to 255) fits.
 pub struct AudioBufferSlice<T> {
     channel_count: usize,
-    buffers: [*mut T; 16],
-    sizes: [usize; 16],
+    buffers: Vec<*mut T>,
+    sizes: Vec<usize>,
     offset: isize,
 }
 
+impl<T> Clone for AudioBufferSlice<T> {
+    fn clone(&self) -> Self {
+        AudioBufferSlice {
+            channel_count: self.channel_count,
+            buffers: self.buffers.clone(),
+            sizes: self.sizes.clone(),
+            offset: self.offset
+        }
+    }
+
+    // reuses the channel lists, so handing out the decoder's outputs every
+    // frame doesn't allocate
+    fn clone_from(&mut self, source: &Self) {
+        self.channel_count = source.channel_count;
+        self.buffers.clone_from(&source.buffers);
+        self.sizes.clone_from(&source.sizes);
+        self.offset = source.offset;
+    }
+}
+
 impl<T> AudioBufferSlice<T> {
     pub fn new(channel_count: usize) -> Self {
-        let buffers = [ptr::null_mut::<T>(); 16];
-        let sizes = [0usize; 16];
+        let buffers = vec![ptr::null_mut::<T>(); channel_count];
+        let sizes = vec![0usize; channel_count];
         
         AudioBufferSlice {
             channel_count: channel_count,
@@ -29,13 +51,13 @@ impl<T> AudioBufferSlice<T> {
     }
 
     pub unsafe fn from(value: &mut Vec<Vec<T>>) -> Self {
-        let mut buffers = [ptr::null_mut::<T>(); 16];
-        let mut sizes: [usize; 16] = [0usize; 16];
+        let mut buffers = Vec::with_capacity(value.len());
+        let mut sizes = Vec::with_capacity(value.len());
         
         let channel_count = value.len();
-        for i in 0 .. value.len() {
-            buffers[i] = value[i].as_mut_ptr();
-            sizes[i] = value[i].len();
+        for channel in value.iter_mut() {
+            buffers.push(channel.as_mut_ptr());
+            sizes.push(channel.len());
         }
         
         AudioBufferSlice {
@@ -47,12 +69,9 @@ impl<T> AudioBufferSlice<T> {
     }
 
     pub unsafe fn from_single_channel(value: &mut [T]) -> Self {
-        let mut buffers = [ptr::null_mut::<T>(); 16];
-        let mut sizes: [usize; 16] = [0usize; 16];
-        
         let channel_count = 1;
-        buffers[0] = value.as_mut_ptr();
-        sizes[0] = value.len();
+        let buffers = vec![value.as_mut_ptr()];
+        let sizes = vec![value.len()];
         
         AudioBufferSlice {
             channel_count: channel_count,
@@ -76,6 +95,10 @@ impl<T> AudioBufferSlice<T> {
     pub unsafe fn push_channel(&mut self, values: &mut [T]){
         let channel_index = self.channel_count;
         self.channel_count += 1;
+        if self.buffers.len() < self.channel_count {
+            self.buffers.resize(self.channel_count, ptr::null_mut());
+            self.sizes.resize(self.channel_count, 0);
+        }
 
         self.set(channel_index, values);
     }
@@ -87,15 +110,18 @@ impl<T> AudioBufferSlice<T> {
     pub fn range_from(&self, start: usize) -> Self {
         AudioBufferSlice {
             channel_count: self.channel_count,
-            buffers: self.buffers,
-            sizes: self.sizes,
+            buffers: self.buffers.clone(),
+            sizes: self.sizes.clone(),
             offset: start as isize
         }
     }
 
     /// get length of first channel data, use channel_count if you need to count the number of channel
     pub fn len(&self) -> usize {
-        self.sizes[0]
+        match self.sizes.first() {
+            Some(&size) => size,
+            None => 0,
+        }
     }
 
     pub fn channel_count(&self) -> usize {
@@ -146,4 +172,84 @@ impl<T> IndexMut<usize> for AudioBufferSlice<T> {
             )
         }
     }    
-}
\ No newline at end of file
+}
+
+/// a type the generic sample-access functions (`stb_vorbis_get_samples()`,
+/// `stb_vorbis_get_frame()` and their interleaved versions) put out,
+/// converted straight from the decoder's float buffers
+pub trait Sample: Copy {
+    /// the sample for 'value', where -1.0 to 1.0 is full scale. integer
+    /// types round and clip
+    fn from_f32(value: f32) -> Self;
+
+    /// one step of the type, where -1.0 to 1.0 is full scale. 0 for types
+    /// that aren't dithered
+    const STEP: f32 = 0.0;
+}
+
+impl Sample for f32 {
+    fn from_f32(value: f32) -> f32 {
+        value
+    }
+}
+
+impl Sample for f64 {
+    fn from_f32(value: f32) -> f64 {
+        value as f64
+    }
+}
+
+impl Sample for i16 {
+    /// the same as the `*_short` functions put out
+    fn from_f32(value: f32) -> i16 {
+        ::convert_to_i16(value)
+    }
+
+    const STEP: f32 = 1.0 / 32768.0;
+}
+
+impl Sample for i32 {
+    fn from_f32(value: f32) -> i32 {
+        (value as f64 * 2147483648.0).round().clamp(-2147483648.0, 2147483647.0) as i32
+    }
+}
+
+impl Sample for I24 {
+    fn from_f32(value: f32) -> I24 {
+        I24::new((value as f64 * 8388608.0).round().clamp(-8388608.0, 8388607.0) as i32)
+    }
+
+    const STEP: f32 = 1.0 / 8388608.0;
+}
+
+/// unsigned, with silence at 128, as in 8-bit WAV data
+impl Sample for u8 {
+    fn from_f32(value: f32) -> u8 {
+        ((value as f64 * 128.0).round().clamp(-128.0, 127.0) + 128.0) as u8
+    }
+
+    const STEP: f32 = 1.0 / 128.0;
+}
+
+/// a packed 24-bit sample: three bytes, little endian, as in 24-bit WAV
+/// data. a slice of them is three bytes per sample
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct I24(pub [u8; 3]);
+
+impl I24 {
+    /// the low 24 bits of 'value'
+    pub fn new(value: i32) -> I24 {
+        I24([value as u8, (value >> 8) as u8, (value >> 16) as u8])
+    }
+
+    /// the sample, sign extended
+    pub fn get(self) -> i32 {
+        ((self.0[0] as i32) | (self.0[1] as i32) << 8 | (self.0[2] as i32) << 16) << 8 >> 8
+    }
+
+    /// the bytes of packed samples, ready to be written out
+    pub fn as_bytes(samples: &[I24]) -> &[u8] {
+        unsafe { slice::from_raw_parts(samples.as_ptr() as *const u8, samples.len() * 3) }
+    }
+}
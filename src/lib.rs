@@ -13,26 +13,27 @@
 extern crate smallvec;
 
 use std::mem;
+use std::fmt;
+use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::SeekFrom;
 use std::fs::File;
 use std::path::Path;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 use smallvec::SmallVec;
 
 mod helper;
 pub use helper::*;
 
-// STB_VORBIS_MAX_CHANNELS [number]
-//     globally define this to the maximum number of channels you need.
-//     The spec does not put a restriction on channels except that
-//     the count is stored in a byte, so 255 is the hard limit.
-//     Reducing this saves about 16 bytes per value, so using 16 saves
-//     (255-16)*16 or around 4KB. Plus anything other memory usage
-//     I forgot to account for. Can probably go as low as 8 (7.1 audio),
-//     6 (5.1 audio), or 2 (stereo only).
-const STB_VORBIS_MAX_CHANNELS : i32 = 16;  // enough for anyone?
+mod wav;
+pub use wav::*;
+
+mod ogg;
+pub use ogg::*;
 
 // STB_PUSHDATA_CRC_COUNT [number]
 //     after a flush_pushdata(), stb_vorbis begins scanning for the
@@ -55,8 +56,15 @@ const STB_PUSHDATA_CRC_COUNT : i32 = 4;
 //     to probe (and try multiple ogg vorbis files) to find the sweet spot.
 const STB_FAST_HUFFMAN_LENGTH : i32 = 10;
 
+// STB_VORBIS_MAX_JUNK [number]
+//     how many bytes that aren't a page are skipped looking for the first
+//     page of a stream. a file with more junk than that in front doesn't
+//     open; without the limit anything that isn't Ogg would be read to the
+//     end first.
+const STB_VORBIS_MAX_JUNK : usize = 65536;
+
 const PACKET_ID : u8 = 1;
-// const packet_comment : u8 = 3;
+const PACKET_COMMENT : u8 = 3;
 const PACKET_SETUP : u8 = 5;
 
 const PAGEFLAG_CONTINUED_PACKET : u8 =   1;
@@ -64,7 +72,8 @@ const PAGEFLAG_FIRST_PAGE       : u8 =   2;
 const PAGEFLAG_LAST_PAGE        : u8 =   4;
 
 
-static mut CRC_TABLE: [u32; 256] = [0; 256];
+// built at compile time, so decoders on different threads never write to it
+static CRC_TABLE: [u32; 256] = crc32_table();
 
 
 // the following table is block-copied from the specification
@@ -154,39 +163,133 @@ static CHANNEL_POSITION: [[i8; 6]; 7] = [
    [ CP_L, CP_C, CP_R, CP_L, CP_R, CP_C ],
 ];
 
-static OGG_PAGE_HEADER: [u8; 4] = [ b'O', b'g', b'g', b'S' ];
+/// a speaker position of the Vorbis I channel order
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Speaker
+{
+   FrontLeft, FrontCenter, FrontRight,
+   RearLeft, RearRight, SideLeft, SideRight, RearCenter,
+   Lfe,
+}
 
+impl Speaker {
+    /// the speaker's bit in a `WAVEFORMATEXTENSIBLE` channel mask
+    pub fn mask(self) -> u32 {
+        use Speaker::*;
+        match self {
+            FrontLeft   => 0x1,
+            FrontRight  => 0x2,
+            FrontCenter => 0x4,
+            Lfe         => 0x8,
+            RearLeft    => 0x10,
+            RearRight   => 0x20,
+            RearCenter  => 0x100,
+            SideLeft    => 0x200,
+            SideRight   => 0x400,
+        }
+    }
+}
 
-/// macro to force rust to borrow 
-macro_rules! FORCE_BORROW_MUT {
-    ($e: expr) => {{
-        // satify borrow checker
-        let p = $e;
-        let p = p as *mut _;
-        mem::transmute(p)
-    }}
+/// the speakers a stream's channels belong to, which the Vorbis I spec
+/// fixes for 1 to 8 channels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelLayout
+{
+   Mono,
+   Stereo,
+   Surround30,     // L C R
+   Quad,           // FL FR RL RR
+   Surround50,     // FL C FR RL RR
+   Surround51,     // FL C FR RL RR LFE
+   Surround61,     // FL C FR SL SR RC LFE
+   Surround71,     // FL C FR SL SR RL RR LFE
+   Discrete(usize), // any other channel count, with the meaning up to the application
 }
 
-macro_rules! FORCE_BORROW {
-    ($e: expr) => {{
-        // satify borrow checker
-        let p = $e;
-        let p = p as *const _;
-        mem::transmute(p)
-    }}
+impl ChannelLayout {
+    pub fn from_channels(channels: usize) -> ChannelLayout {
+        use ChannelLayout::*;
+        match channels {
+            1 => Mono,
+            2 => Stereo,
+            3 => Surround30,
+            4 => Quad,
+            5 => Surround50,
+            6 => Surround51,
+            7 => Surround61,
+            8 => Surround71,
+            n => Discrete(n),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match *self {
+            ChannelLayout::Discrete(n) => n,
+            _ => self.speakers().len(),
+        }
+    }
+
+    /// the speaker of each channel, in channel order. empty for `Discrete`
+    pub fn speakers(&self) -> &'static [Speaker] {
+        use ChannelLayout::*;
+        let channels = match *self {
+            Mono => 1,
+            Stereo => 2,
+            Surround30 => 3,
+            Quad => 4,
+            Surround50 => 5,
+            Surround51 => 6,
+            Surround61 => 7,
+            Surround71 => 8,
+            Discrete(_) => 0,
+        };
+        VORBIS_CHANNEL_ORDER[channels]
+    }
+
+    /// the `WAVEFORMATEXTENSIBLE` channel mask of the speakers, 0 for `Discrete`
+    pub fn channel_mask(&self) -> u32 {
+        self.speakers().iter().fold(0, |mask, speaker| mask | speaker.mask())
+    }
 }
 
+static VORBIS_CHANNEL_ORDER: [&[Speaker]; 9] = {
+   use Speaker::*;
+   [
+      &[],
+      &[ FrontCenter ],
+      &[ FrontLeft, FrontRight ],
+      &[ FrontLeft, FrontCenter, FrontRight ],
+      &[ FrontLeft, FrontRight, RearLeft, RearRight ],
+      &[ FrontLeft, FrontCenter, FrontRight, RearLeft, RearRight ],
+      &[ FrontLeft, FrontCenter, FrontRight, RearLeft, RearRight, Lfe ],
+      &[ FrontLeft, FrontCenter, FrontRight, SideLeft, SideRight, RearCenter, Lfe ],
+      &[ FrontLeft, FrontCenter, FrontRight, SideLeft, SideRight, RearLeft, RearRight, Lfe ],
+   ]
+};
+
+const MIX_3DB : f32 = std::f32::consts::FRAC_1_SQRT_2; // -3dB
+
+// the windowed sinc resampler's kernel reaches this many zero crossings each
+// side of an output sample, and is tabulated in this many steps per crossing
+const SINC_ZERO_CROSSINGS : usize = 16;
+const SINC_OVERSAMPLE : usize = 256;
+const SINC_CUTOFF : f64 = 0.95; // of the lower Nyquist frequency, for the transition band
+
+static OGG_PAGE_HEADER: [u8; 4] = [ b'O', b'g', b'g', b'S' ];
+
+
 macro_rules! FAST_SCALED_FLOAT_TO_INT {
     ($x: expr, $s: expr) => {{
         let temp = $x + (1.5f32 * (1 << (23-$s)) as f32 + 0.5f32/(1 << $s) as f32);
         let temp : i32 = $crate::std::mem::transmute(temp);
-        temp - (((150-$s) << 23) + (1 << 22) )        
+        // the bit pattern of a huge (or NaN) sample wraps, as in the C
+        temp.wrapping_sub(((150-$s) << 23) + (1 << 22))
     }}
 }
 
 fn convert_to_i16(value: f32) -> i16 {
     let mut v : i32 = unsafe { FAST_SCALED_FLOAT_TO_INT!(value, 15) };
-    if (v + 32768) as u32 > 65535 {
+    if v.wrapping_add(32768) as u32 > 65535 {
         v = if v < 0 { -32768 } else { 32767 };
     }
 
@@ -266,27 +369,44 @@ impl Clone for Codebook {
 
 impl Default for Codebook {
     fn default() -> Self {
-        let mut instance : Codebook = unsafe { mem::zeroed() };
-        instance.multiplicands = Vec::new();
-        instance.codewords = Vec::new();
-        instance.codeword_lengths = Vec::new();
-        instance.sorted_codewords = Vec::new();
-        instance.sorted_values = Vec::new();
-        instance
+        Codebook {
+            dimensions: 0, entries: 0,
+            codeword_lengths: Vec::new(),
+            minimum_value: 0.0,
+            delta_value: 0.0,
+            value_bits: 0,
+            lookup_type: 0,
+            sequence_p: 0,
+            sparse: false,
+            lookup_values: 0,
+            multiplicands: Vec::new(),
+            codewords: Vec::new(),
+            fast_huffman: [0; FAST_HUFFMAN_TABLE_SIZE as usize],
+            sorted_codewords: Vec::new(),
+            sorted_values: Vec::new(),
+            sorted_entries: 0
+        }
     } 
 }
 
-#[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Floor0
 {
    order: u8,
-   rate: u16,
    bark_map_size: u16,
    amplitude_bits: u8,
    amplitude_offset: u8,
    number_of_books: u8,
    book_list: [u8; 16], // varies
+   bark_map: [Vec<i32>; 2], // precomputed for blocksize_0/2 and blocksize_1/2
+}
+
+// floor 0 values decoded from the current packet, one per channel
+#[derive(Clone, Default)]
+struct Floor0Data
+{
+   amplitude: u64,
+   coefficients: Vec<f32>, // 2*cos() of each lsp coefficient
 }
 
 #[repr(C)]
@@ -313,7 +433,7 @@ impl Clone for Floor1 {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum Floor
 {
     Type0(Floor0),
@@ -333,9 +453,14 @@ pub struct Residue
 
 impl Default for Residue {
     fn default() -> Self {
-        let mut instance : Residue = unsafe { mem::zeroed() };
-        instance.residue_books = Vec::new();
-        instance
+        Residue {
+            begin: 0, end: 0,
+            part_size: 0,
+            classifications: 0,
+            classbook: 0,
+            classdata: Vec::new(),
+            residue_books: Vec::new(),
+        }
     }
 }
 
@@ -355,15 +480,15 @@ pub struct Mapping
    coupling_steps: u16,
    chan: Vec<MappingChannel>,
    submaps: u8,
-   submap_floor: [u8; 15], // varies
-   submap_residue: [u8; 15], // varies
+   submap_floor: [u8; 16], // varies
+   submap_residue: [u8; 16], // varies
 }
 
 impl Default for Mapping {
     fn default() -> Self {
         Mapping { 
             coupling_steps: 0, 
-            submaps: 0, submap_floor: [0; 15], submap_residue: [0; 15],
+            submaps: 0, submap_floor: [0; 16], submap_residue: [0; 16],
             chan: Vec::new()
         }
     }
@@ -379,6 +504,16 @@ pub struct Mode
    transformtype: u16,
 }
 
+// the setup tables, lent out of the decoder while a packet is decoded so
+// they can be read alongside the bits it reads
+struct SetupTables<'a>
+{
+   codebooks: &'a [Codebook],
+   floors: &'a [Floor],
+   residues: &'a [Residue],
+   mappings: &'a [Mapping],
+}
+
 #[derive(Copy, Clone, Default)]
 pub struct CRCscan
 {
@@ -386,44 +521,273 @@ pub struct CRCscan
    bytes_left: i32,  // bytes left in packet
    crc_so_far: u32,  // running crc
    bytes_done: i32,  // bytes processed in _current_ chunk
-   sample_loc: u32,  // granule pos encoded in page
+   sample_loc: u64,  // granule pos encoded in page
+   last_packet_left: i32, // bytes_left when the first byte of the page's last packet is next, 0 if unknown
+   last_packet_byte: i32, // that byte once scanned, -1 until then
 } 
 
 #[derive(Copy, Clone, Default)]
 pub struct ProbedPage
 {
-   page_start: u32, page_end: u32,
-   last_decoded_sample: u32
+   page_start: u64, page_end: u64,
+   last_decoded_sample: u64
+}
+
+// one logical bitstream of a chained file: where it starts, how many samples
+// come before it and where its last page is
+#[derive(Copy, Clone, Default)]
+struct Link
+{
+   serial: u32,
+   page_start: u64,
+   sample_start: u64,
+   p_last: ProbedPage,
+   sample_rate: u32,
+}
+
+// converts the decoded samples to another rate for the get_samples_*
+// functions. frames go into 'history' as they are decoded, and the output
+// is handed out of the channel buffers, in place of the decoded frame
+struct Resampler
+{
+   rate: u32, // output sample rate
+   quality: ResampleQuality,
+   sinc: Vec<f32>, // one side of the kernel, SINC_OVERSAMPLE steps per zero crossing
+   weights: Vec<f32>, // of the input samples the next output sample is made of
+   link: Option<u64>, // link_page_start of the link in 'history', None to start over
+   input_rate: u32,
+   history: Vec<Vec<f32>>, // per channel, from in-link sample history_start on
+   history_start: u64,
+   next_out: u64, // in-link output-rate position of the next sample to put out
+   link_start: u64, // output-rate position of the start of the link
+}
+
+impl Resampler {
+   fn new(rate: u32, quality: ResampleQuality) -> Resampler
+   {
+      let mut sinc = Vec::new();
+      if quality == ResampleQuality::WindowedSinc {
+         use std::f64::consts::PI;
+         let steps = SINC_ZERO_CROSSINGS * SINC_OVERSAMPLE;
+         for i in 0 .. steps {
+            let x = i as f64 / SINC_OVERSAMPLE as f64;
+            let u = i as f64 / steps as f64;
+            let window = 0.42 + 0.5 * (PI * u).cos() + 0.08 * (2.0 * PI * u).cos();
+            let value = if i == 0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            sinc.push((value * window) as f32);
+         }
+         sinc.push(0.0);
+      }
+      Resampler {
+         rate: rate,
+         quality: quality,
+         sinc: sinc,
+         weights: Vec::new(),
+         link: None,
+         input_rate: 0,
+         history: Vec::new(),
+         history_start: 0,
+         next_out: 0,
+         link_start: 0,
+      }
+   }
+
+   // start over at in-link sample 'position' of a link
+   fn start(&mut self, link: u64, input_rate: u32, channels: usize, position: u64, link_start: u64)
+   {
+      self.link = Some(link);
+      self.input_rate = input_rate;
+      self.history.resize(channels, Vec::new());
+      for history in self.history.iter_mut() {
+         history.clear();
+      }
+      self.history_start = position;
+      self.next_out = rescale(position, self.rate, input_rate);
+      self.link_start = link_start;
+   }
+
+   fn sinc_scale(&self, input_rate: u32) -> f64
+   {
+      return (self.rate as f64 / input_rate as f64).min(1.0) * SINC_CUTOFF;
+   }
+
+   // how many input samples before and after its position an output sample
+   // is made of
+   fn reach(&self, input_rate: u32) -> (u64, u64)
+   {
+      match self.quality {
+         ResampleQuality::Linear => (0, 1),
+         ResampleQuality::WindowedSinc => {
+            let taps = (SINC_ZERO_CROSSINGS as f64 / self.sinc_scale(input_rate)).ceil() as u64;
+            (taps - 1, taps)
+         }
+      }
+   }
+
+   // the weights of the input samples for an output sample 'frac' of the way
+   // from one input sample to the next, from the first sample it reaches on
+   fn set_weights(&mut self, frac: f64, input_rate: u32)
+   {
+      self.weights.clear();
+      match self.quality {
+         ResampleQuality::Linear => {
+            self.weights.push(1.0 - frac as f32);
+            self.weights.push(frac as f32);
+         }
+         ResampleQuality::WindowedSinc => {
+            let (before, after) = self.reach(input_rate);
+            let scale = self.sinc_scale(input_rate) * SINC_OVERSAMPLE as f64;
+            let mut sum = 0.0;
+            for k in 0 .. before + after + 1 {
+               let x = (frac + before as f64 - k as f64).abs() * scale;
+               let i = x as usize;
+               let weight = if i < SINC_ZERO_CROSSINGS * SINC_OVERSAMPLE {
+                  self.sinc[i] + (self.sinc[i + 1] - self.sinc[i]) * (x - i as f64) as f32
+               } else {
+                  0.0
+               };
+               self.weights.push(weight);
+               sum += weight;
+            }
+            // no change in level, whatever the phase
+            for weight in self.weights.iter_mut() {
+               *weight /= sum;
+            }
+         }
+      }
+   }
+}
+
+// how much of each of the last rounding errors Dither::Shaped takes off the
+// next sample, most recent first. puts the noise up high, where it's least
+// audible at 44.1 and 48 kHz
+const NOISE_SHAPING: [f32; 3] = [1.623, -0.982, 0.109];
+
+// dithers the integer output. every output channel has its own noise and
+// error feedback, so the samples of a channel don't depend on how they're
+// asked for: planar or interleaved, in any number of calls
+struct Ditherer
+{
+   dither: Dither,
+   seed: u32,
+   channels: Vec<DitherChannel>,
+}
+
+#[derive(Copy, Clone)]
+struct DitherChannel
+{
+   random: u32, // noise generator state
+   error: [f32; 3], // the last rounding errors in steps, most recent first
+}
+
+impl Ditherer {
+   fn new(dither: Dither, seed: u32) -> Ditherer
+   {
+      Ditherer {
+         dither: dither,
+         seed: seed,
+         channels: Vec::new(),
+      }
+   }
+
+   // 'value' with dither, rounded to a multiple of 'step' and clipped to
+   // the range of a type with steps that size
+   fn apply(&mut self, channel: usize, value: f32, step: f32) -> f32
+   {
+      while self.channels.len() <= channel {
+         let k = self.channels.len() as u32;
+         self.channels.push(DitherChannel {
+            random: self.seed ^ k.wrapping_mul(0x6a09e667),
+            error: [0.0; 3],
+         });
+      }
+      let shaped = self.dither == Dither::Shaped;
+      let ch = &mut self.channels[channel];
+      let mut target = value / step;
+      if shaped == true {
+         for (&h, &e) in NOISE_SHAPING.iter().zip(ch.error.iter()) {
+            target -= h * e;
+         }
+      }
+      // triangular, from -1 to 1 step
+      let noise = ch.uniform() - ch.uniform();
+      let limit = 1.0 / step;
+      let q = (target + noise).round().clamp(-limit, limit - 1.0);
+      if shaped == true {
+         // clipping is no error to feed back, or the filter would run away
+         ch.error = [(q - target).clamp(-1.5, 1.5), ch.error[0], ch.error[1]];
+      }
+      return q * step;
+   }
+}
+
+impl DitherChannel {
+   // uniform from 0 to 1
+   fn uniform(&mut self) -> f32
+   {
+      self.random = self.random.wrapping_add(0x9e3779b9);
+      let mut z = self.random;
+      z = (z ^ (z >> 16)).wrapping_mul(0x85ebca6b);
+      z = (z ^ (z >> 13)).wrapping_mul(0xc2b2ae35);
+      z ^= z >> 16;
+      return (z >> 8) as f32 / 16777216.0;
+   }
 }
- 
+
+
+/// any seekable byte source the pull decoder can read from
+/// (`File`, `Cursor<Vec<u8>>`, archive entries, custom VFS readers...).
+/// it has to be `Send` so the decoder can move between threads, and the
+/// open functions also want it `'static`, since the decoder keeps it boxed.
+/// a reader that is neither (one holding an `Rc`, or borrowing its archive)
+/// can be read into a `Vec<u8>` and opened with `VorbisDecoder::from_vec()`.
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
 
 pub struct Vorbis
 {
   // user-accessible info
    pub sample_rate: u32,
    pub channels: i32,
+   bitrate_maximum: i32,
+   bitrate_nominal: i32,
+   bitrate_minimum: i32,
 
   // input config
-   f: Option<BufReader<File>>,
-   f_start: u32,
+   f: Option<BufReader<Box<dyn ReadSeek>>>,
+   f_start: u64,
 
    stream: *const u8,
    stream_start: *const u8,
    stream_end: *const u8,
-   stream_len: u32,
+   stream_len: u64,
+   stream_data: Option<StreamData>, // owned memory block for VorbisDecoder
 
    push_mode: bool,
 
-   first_audio_page_offset: u32,
+   first_audio_page_offset: u64,
 
    p_first: ProbedPage, p_last: ProbedPage,
 
+  // chained streams
+   links: Vec<Link>,       // every link in the file, once the length is known (if chained)
+   link_page_start: u64,   // offset of the first page of the current link
+   link_sample_start: u64, // samples in the links before the current one, if known
+   link_changed: bool,     // a new link started since the user last asked
+
+  // multiplexed streams
+   streams: Vec<OggStream>,   // logical streams in the current link's first pages
+   wanted_serial: Option<u32>, // vorbis stream picked by the user, if any
 
   // run-time results
    pub eof: bool,
-   pub error: VorbisError,
+   pub error: VorbisErrorKind,
+   io_error: Option<io::Error>, // first read error from 'f', if any
+   packet_type: Option<u8>, // type of the last packet started
 
   // user-useful data
+   comments: VorbisComments,
 
   // header info
    blocksize: [usize; 2],
@@ -435,7 +799,7 @@ pub struct Vorbis
    mapping: Vec<Mapping>,
    mode_config: SmallVec<[Mode; 64]>,  // varies
 
-   total_samples: u32,
+   total_samples: u64,
 
   // decode buffer
    channel_buffers: Vec<Vec<f32>>,
@@ -445,8 +809,9 @@ pub struct Vorbis
    previous_length: i32,
 
    final_y: Vec<Vec<i16>>,
+   floor0_data: Vec<Floor0Data>,
 
-   current_loc: u32, // sample location of next frame to decode
+   current_loc: u64, // sample location of next frame to decode
    current_loc_valid: bool,
 
   // per-blocksize precomputed data
@@ -457,8 +822,8 @@ pub struct Vorbis
    bit_reverse: [Vec<u16>; 2],
 
   // current page/packet/segment streaming info
-//    serial: u32, // stream serial number for verification. NOTE(bungcip): not used?
-   last_page: i32,
+   serial: u32, // stream serial number of the current link
+   last_page: Option<u32>, // sequence number of the last page read
    segment_count: i32,
    segments: [u8; 255],
    page_flag: u8,
@@ -471,39 +836,94 @@ pub struct Vorbis
    valid_bits: i32,
    packet_bytes: i32,
    end_seg_with_known_loc: i32,
-   known_loc_for_packet: u32,
+   known_loc_for_packet: u64,
    discard_samples_deferred: i32,
-   samples_output: u32,
+   samples_output: u64,
+   start_trim: i32, // samples cut off the start of the link (see start_trim())
+   discarded: i32, // samples of those at the start of the frame just decoded
+   first_frame: bool, // the frame just decoded is the first of its link
+   pending_frame: Option<(i32, i32)>, // (left, len) of a first frame pumped but not returned
 
   // push mode scanning
    page_crc_tests: i32, // only in push_mode: number of tests active, -1 if not searching
+   resync_long_block: Option<bool>, // blocksize of the packet before a resync point, if known
    scan: [CRCscan; STB_PUSHDATA_CRC_COUNT as usize],
 
   // sample-access
    channel_buffer_start: i32,
    channel_buffer_end: i32,
+
+  // channel mixing
+   channel_mix: ChannelMix,
+   mix_gains: Vec<f32>, // output major, empty when channels are copied as they are
+   mix_channels: Option<(usize, usize)>, // (decoded, output) channel counts mix_gains is for
+
+  // output rate conversion
+   resampler: Option<Resampler>,
+
+  // integer output dithering
+   dither: Option<Ditherer>,
+
+  // page CRC checks in pull mode
+   crc_check: CrcCheck,
+   corrupt_pages: u64, // pages skipped for their CRC
+   corrupt_page_callback: Option<CorruptPageCallback>,
+   drop_continued: bool, // a page was skipped: drop the end of the packet it broke off
+   page_lost: bool,      // a page was skipped while reading the current packet
+
+  // error recovery in pull mode
+   recover: bool,
+   concealed_samples: u64, // silence put out for damaged packets
+   concealment_callback: Option<ConcealmentCallback>,
+   silence_left: u64, // of the current concealment, still to put out
+
+  // header allocation caps
+   limits: SetupLimits,
+   setup_bytes: u64, // charged against limits.max_setup_bytes, this link
 }
 
+// the raw pointers in a Vorbis only ever point at
+//   - `stream_data`, or memory borrowed for the lifetime of a VorbisDecoder
+//   - the data passed to stb_vorbis_decode_frame_pushdata, during that call
+//   - its own channel buffers (`outputs`), whose heap blocks don't move
+// none of that is shared with another decoder, and the reader is Send, so
+// the decoder can be moved to another thread as a whole.
+unsafe impl Send for Vorbis {}
+
 impl Vorbis {
     pub fn new() -> Self {
         Vorbis {
             eof: false,
-            error: VorbisError::NoError,
+            error: VorbisErrorKind::NoError,
+            io_error: None,
+            packet_type: None,
             stream: std::ptr::null_mut(),
             codebooks: Vec::new(),
+            comments: VorbisComments::default(),
             page_crc_tests: -1,
+            resync_long_block: None,
             f: None,
             
             // zero
             sample_rate: 0,
             channels: 0,
+            bitrate_maximum: 0,
+            bitrate_nominal: 0,
+            bitrate_minimum: 0,
             f_start: 0,
             stream_start: std::ptr::null_mut(),
             stream_end: std::ptr::null_mut(),
             stream_len: 0,
+            stream_data: None,
             push_mode: false,
             first_audio_page_offset: 0,
             p_first: ProbedPage::default(), p_last: ProbedPage::default(),
+            links: Vec::new(),
+            link_page_start: 0,
+            link_sample_start: 0,
+            link_changed: false,
+            streams: Vec::new(),
+            wanted_serial: None,
             blocksize: [0; 2],
             blocksize_0: 0, blocksize_1: 0,
             floor_config: Vec::new(),
@@ -512,17 +932,19 @@ impl Vorbis {
             mapping: Vec::new(),
             mode_config: SmallVec::new(), // varies
             total_samples: 0,
-            channel_buffers: Vec::with_capacity(STB_VORBIS_MAX_CHANNELS as usize),
-            outputs        : AudioBufferSlice::new(STB_VORBIS_MAX_CHANNELS as usize),
-            previous_window: Vec::with_capacity(STB_VORBIS_MAX_CHANNELS as usize),
+            channel_buffers: Vec::new(),
+            outputs        : AudioBufferSlice::new(0),
+            previous_window: Vec::new(),
             previous_length: 0,
-            final_y: Vec::with_capacity(STB_VORBIS_MAX_CHANNELS as usize),
+            final_y: Vec::new(),
+            floor0_data: Vec::new(),
             current_loc: 0, // sample location of next frame to decode
             current_loc_valid: false,
             a: [Vec::new(), Vec::new()], b: [Vec::new(), Vec::new()], c: [Vec::new(), Vec::new()],
             window: [Vec::new(), Vec::new()],
             bit_reverse:  [Vec::new(), Vec::new()],
-            last_page: 0,
+            serial: 0,
+            last_page: None,
             segment_count: 0,
             segments: [0; 255],
             page_flag: 0,
@@ -538,33 +960,258 @@ impl Vorbis {
             known_loc_for_packet: 0,
             discard_samples_deferred: 0,
             samples_output: 0,
+            start_trim: 0,
+            discarded: 0,
+            first_frame: false,
+            pending_frame: None,
             scan: [CRCscan::default(); STB_PUSHDATA_CRC_COUNT as usize],
             channel_buffer_start: 0,
             channel_buffer_end: 0,
+            channel_mix: ChannelMix::Standard,
+            mix_gains: Vec::new(),
+            mix_channels: None,
+            resampler: None,
+            dither: None,
+            crc_check: CrcCheck::Off,
+            corrupt_pages: 0,
+            corrupt_page_callback: None,
+            drop_continued: false,
+            page_lost: false,
+            recover: false,
+            concealed_samples: 0,
+            concealment_callback: None,
+            silence_left: 0,
+            limits: SetupLimits::default(),
+            setup_bytes: 0,
         }
     }
 }
 
+impl Vorbis {
+    /// create an ogg vorbis decoder from any `Read + Seek + Send + 'static`
+    /// source (see `ReadSeek`), looking for a stream at the _current_ seek
+    /// point. see `stb_vorbis_open_reader()`
+    pub fn from_reader<R: Read + Seek + Send + 'static>(reader: R) -> Result<Vorbis, VorbisError> {
+        stb_vorbis_open_reader(reader)
+    }
+}
+
 impl Default for Vorbis {
     fn default() -> Vorbis {
         Vorbis::new()
     }
 }
 
+/// one logical stream of a multiplexed ogg file, as announced by its first
+/// page (see `stb_vorbis_get_streams()`)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OggStream
+{
+   pub serial: u32,
+   pub vorbis: bool, // whether it is a vorbis stream this decoder can play
+}
+
+/// how the functions that take a channel count map the decoded channels
+/// onto that many output channels (see `stb_vorbis_set_channel_mix()`)
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelMix
+{
+   /// mix by speaker position, for streams and outputs of 1 to 8 channels
+   /// in the Vorbis I channel order. the default
+   Standard,
+   /// the rules of the original stb_vorbis, for comparing against it:
+   /// the 16-bit functions sum left/center and right/center channels of
+   /// streams of up to 6 channels into mono or stereo, the float
+   /// functions never mix
+   Original,
+   /// `gains[out * inputs + in]` is the gain of decoded channel `in` in
+   /// output channel `out`. used for streams of `inputs` channels decoded
+   /// to `outputs` channels, anything else is mixed as `Standard`
+   Matrix { inputs: usize, outputs: usize, gains: Vec<f32> },
+}
+
+/// how the functions that take a channel count convert to another output
+/// rate (see `stb_vorbis_set_output_rate()`)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResampleQuality
+{
+   /// straight lines between neighbouring samples: cheap, but it dulls the
+   /// highs and lets some aliasing through
+   Linear,
+   /// a Blackman windowed sinc reaching 16 zero crossings each side, with
+   /// the cutoff a little below the lower of the two Nyquist frequencies
+   WindowedSinc,
+}
+
+/// how the integer output functions round i16, I24 and u8 samples (see
+/// `stb_vorbis_set_dither()`). i32 samples are never dithered, whatever
+/// the setting
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dither
+{
+   /// to the nearest step
+   Off,
+   /// with triangular noise of up to a step either way added first, so the
+   /// error no longer follows the signal. quiet fades stay smooth, at the
+   /// cost of a little hiss
+   Tpdf,
+   /// TPDF, with the rounding error fed back so that most of the noise
+   /// ends up at high frequencies. louder noise overall, but less of it
+   /// where the ear is sensitive, at 44.1 or 48 kHz
+   Shaped,
+}
+
+/// what pull and memory mode decoding does with a page whose CRC doesn't
+/// match (see `stb_vorbis_set_crc_check()`)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CrcCheck
+{
+   /// pages are taken as they are, as stb_vorbis always did. the default
+   Off,
+   /// corrupt pages are skipped, with the packets they carry parts of, and
+   /// decoding picks up at the next good page
+   Skip,
+   /// decoding stops at the corrupt page with an InvalidPageCrc error
+   Fail,
+}
+
+/// a page skipped for its CRC (see `stb_vorbis_set_corrupt_page_callback()`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorruptPage
+{
+   /// where the page starts in the stream
+   pub offset: u64,
+   pub serial: u32,
+   pub sequence: u32,
+}
+
+/// called with every page `CrcCheck::Skip` skips
+pub type CorruptPageCallback = Box<dyn FnMut(&CorruptPage) + Send>;
+
+/// a stretch of the stream error recovery put out as silence (see
+/// `stb_vorbis_set_concealment_callback()`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Concealment
+{
+   /// where in the stream the damaged packet was given up on
+   pub offset: u64,
+   /// the sample the silence starts at, at the stream's sample rate, or
+   /// None where the position wasn't known
+   pub sample: Option<u64>,
+   /// samples per channel of silence put in for what was dropped, 0 where
+   /// that can't be told
+   pub samples: u64,
+}
+
+/// called every time error recovery gets past a damaged packet
+pub type ConcealmentCallback = Box<dyn FnMut(&Concealment) + Send>;
+
+/// caps on what a stream's headers may make the decoder allocate. the setup
+/// header sizes its codebook and residue tables from counts in the stream,
+/// so a hostile one can ask for gigabytes; past the cap the stream fails to
+/// open with OutOfMem instead (see `stb_vorbis_open_memory_with_limits()`)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SetupLimits
+{
+   /// bytes of codebook and residue tables, per link
+   pub max_setup_bytes: usize,
+   /// bytes of vendor string and user comments kept; comments past it are
+   /// dropped, the stream still opens
+   pub max_comment_bytes: usize,
+   /// channels a link may have; a stream with more fails to open with
+   /// TooManyChannels. the ID header can't count past 255
+   pub max_channels: usize,
+}
+
+impl Default for SetupLimits {
+    /// 16 MiB of each and every channel count; real encoders stay well
+    /// under 1 MiB of tables
+    fn default() -> Self {
+        SetupLimits {
+            max_setup_bytes: 16 << 20,
+            max_comment_bytes: 16 << 20,
+            max_channels: 255,
+        }
+    }
+}
+
+/// what the ID header says about the stream. the comments aren't in here:
+/// this is `Copy` and handed out by value, which a `VorbisComments` would
+/// stop, so they're borrowed from the decoder with `stb_vorbis_get_comment()`
 #[derive(Copy, Clone)]
 pub struct VorbisInfo
 {
    pub sample_rate: u32,
    pub channels: i32,
+   pub layout: ChannelLayout, // which speaker each channel is for
+
+   // bits per second, as the encoder put them in the ID header. 0 or
+   // less where it didn't say
+   pub bitrate_maximum: i32,
+   pub bitrate_nominal: i32,
+   pub bitrate_minimum: i32,
 
    pub max_frame_size: usize,
 }
 
+/// the vorbis comment header: vendor string plus `KEY=value` user comments
+/// (TITLE, ARTIST, ALBUM, TRACKNUMBER...). keys are matched case-insensitively
+/// and may repeat.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VorbisComments
+{
+   vendor: String,
+   comments: Vec<(String, String)>,
+}
+
+impl VorbisComments {
+    /// the encoder that wrote the stream, e.g. "Xiph.Org libVorbis I 20030909"
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+
+    /// all comments as (key, value) pairs, in stream order
+    pub fn comments(&self) -> &[(String, String)] {
+        &self.comments
+    }
+
+    pub fn len(&self) -> usize {
+        self.comments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.comments.is_empty()
+    }
+
+    /// value of the first comment named 'key'
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.comments.iter()
+            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(key))
+            .map(|&(_, ref v)| v.as_str())
+    }
+
+    /// values of every comment named 'key' (e.g. several ARTIST entries)
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.comments.iter()
+            .filter(move |&&(ref k, _)| k.eq_ignore_ascii_case(key))
+            .map(|&(_, ref v)| v.as_str())
+    }
+
+    // a comment without '=' is malformed; keep it as a key with empty value
+    fn push(&mut self, comment: &str) {
+        let (key, value) = match comment.find('=') {
+            Some(i) => (&comment[.. i], &comment[i + 1 ..]),
+            None    => (comment, ""),
+        };
+        self.comments.push((key.to_string(), value.to_string()));
+    }
+}
+
 ////////   ERROR CODES
 
 #[repr(i32)]
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub enum VorbisError
+pub enum VorbisErrorKind
 {
    NoError,
 
@@ -572,10 +1219,11 @@ pub enum VorbisError
 
    InvalidApiMixing,           // can't mix API modes
    OutOfMem,                     // not enough memory
-   FeatureNotSupported,        // uses floor 0
-   TooManyChannels,            // STB_VORBIS_MAX_CHANNELS is too small
+   FeatureNotSupported,
+   TooManyChannels,            // more channels than SetupLimits or an output format allow
    FileOpenFailure,            // fopen() failed
    SeekWithoutLength,          // can't seek in unknown-length file
+   WriteFailure,               // writing the output failed
 
    UnexpectedEof=10,            // file is truncated?
    SeekInvalid,                 // seek past EOF
@@ -595,7 +1243,142 @@ pub enum VorbisError
    InvalidFirstPage,
    BadPacketType,
    CantFindLastPage,
-   SeekFailed
+   SeekFailed,
+   InvalidPageCrc,             // page CRC mismatch, with CrcCheck::Fail
+}
+
+impl VorbisErrorKind {
+    fn description(&self) -> &'static str {
+        use VorbisErrorKind::*;
+        match *self {
+            NoError => "no error",
+            NeedMoreData => "need more data",
+            InvalidApiMixing => "can't mix pull and push apis",
+            OutOfMem => "out of memory",
+            FeatureNotSupported => "feature not supported",
+            TooManyChannels => "too many channels",
+            FileOpenFailure => "failed to open file",
+            SeekWithoutLength => "can't seek in a stream of unknown length",
+            WriteFailure => "failed to write the output",
+            UnexpectedEof => "unexpected end of stream",
+            SeekInvalid => "seek past end of stream",
+            InvalidSetup => "invalid vorbis setup",
+            InvalidStream => "invalid vorbis stream",
+            MissingCapturePattern => "missing ogg capture pattern",
+            InvalidStreamStructureVersion => "invalid ogg stream structure version",
+            ContinuedPacketFlagInvalid => "invalid continued packet flag",
+            IncorrectStreamSerialNumber => "incorrect stream serial number",
+            InvalidFirstPage => "invalid first page",
+            BadPacketType => "bad packet type",
+            CantFindLastPage => "can't find last page",
+            SeekFailed => "seek failed",
+            InvalidPageCrc => "page CRC mismatch",
+        }
+    }
+}
+
+impl fmt::Display for VorbisErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+// error returned by the decoder api: the error code plus where in the
+// stream it happened and the i/o error behind it, if any
+#[derive(Debug)]
+pub struct VorbisError
+{
+   kind: VorbisErrorKind,
+   offset: Option<u64>,
+   page_sequence: Option<u32>,
+   packet_type: Option<u8>,
+   source: Option<io::Error>,
+}
+
+impl VorbisError {
+    pub fn new(kind: VorbisErrorKind) -> VorbisError {
+        VorbisError {
+            kind: kind,
+            offset: None,
+            page_sequence: None,
+            packet_type: None,
+            source: None,
+        }
+    }
+
+    fn with_source(kind: VorbisErrorKind, source: io::Error) -> VorbisError {
+        let mut e = VorbisError::new(kind);
+        e.source = Some(source);
+        e
+    }
+
+    pub fn kind(&self) -> VorbisErrorKind {
+        self.kind
+    }
+
+    /// byte offset in the stream where decoding stopped
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// sequence number of the last ogg page read
+    pub fn page_sequence(&self) -> Option<u32> {
+        self.page_sequence
+    }
+
+    /// type of the packet being decoded: 1, 3, 5 for the headers, 0 for audio
+    pub fn packet_type(&self) -> Option<u8> {
+        self.packet_type
+    }
+
+    pub fn io_error(&self) -> Option<&io::Error> {
+        self.source.as_ref()
+    }
+}
+
+impl fmt::Display for VorbisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.kind));
+        if let Some(offset) = self.offset {
+            try!(write!(f, " at byte {}", offset));
+        }
+        if let Some(page) = self.page_sequence {
+            try!(write!(f, ", page {}", page));
+        }
+        match self.packet_type {
+            Some(PACKET_ID) => try!(write!(f, ", identification header")),
+            Some(PACKET_COMMENT) => try!(write!(f, ", comment header")),
+            Some(PACKET_SETUP) => try!(write!(f, ", setup header")),
+            Some(0) => try!(write!(f, ", audio packet")),
+            Some(t) => try!(write!(f, ", packet type {}", t)),
+            None => {}
+        }
+        if let Some(ref source) = self.source {
+            try!(write!(f, ": {}", source));
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for VorbisError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self.source {
+            Some(ref e) => Some(e),
+            None => None,
+        }
+    }
+}
+
+impl From<VorbisErrorKind> for VorbisError {
+    fn from(kind: VorbisErrorKind) -> VorbisError {
+        VorbisError::new(kind)
+    }
+}
+
+impl PartialEq<VorbisErrorKind> for VorbisError {
+    fn eq(&self, other: &VorbisErrorKind) -> bool {
+        self.kind == *other
+    }
 }
 
 
@@ -625,21 +1408,49 @@ impl PartialOrd for Point {
 
 // Converted function is here
 
-fn error(f: &mut Vorbis, e: VorbisError) -> bool
+// build the public error for 'kind' from the decoder's current position
+fn vorbis_error(f: &mut Vorbis, kind: VorbisErrorKind) -> VorbisError
+{
+   let mut e = VorbisError::new(kind);
+   if f.push_mode == false && (!f.stream.is_null() || f.f.is_some()) {
+      e.offset = Some(stb_vorbis_get_file_offset(f));
+   }
+   e.page_sequence = f.last_page;
+   e.packet_type = f.packet_type;
+   e.source = f.io_error.take();
+   return e;
+}
+
+fn error(f: &mut Vorbis, e: VorbisErrorKind) -> bool
 {
-    // NOTE: e is VorbisError
+    // NOTE: e is VorbisErrorKind
     f.error = e;
-    if f.eof == false && e != VorbisError::NeedMoreData {
+    if f.eof == false && e != VorbisErrorKind::NeedMoreData {
         f.error = e; // breakpoint for debugging
     }
     
     return false;
 }
 
+// charge 'count' items of 'size' bytes against the setup allocation cap,
+// before allocating them; false (with OutOfMem) if the stream asks for more
+fn setup_malloc(f: &mut Vorbis, count: u64, size: usize) -> bool
+{
+    let total = count.checked_mul(size as u64).and_then(|bytes| bytes.checked_add(f.setup_bytes));
+    match total {
+        Some(total) if total <= f.limits.max_setup_bytes as u64 => {
+            f.setup_bytes = total;
+            return true;
+        }
+        _ => return error(f, VorbisErrorKind::OutOfMem),
+    }
+}
+
 fn include_in_sort(c: &Codebook, len: u8) -> bool
 {
    if c.sparse == true { 
-       assert!(len != NO_CODE); 
+       // the sparse tables only hold the entries add_entry() gave a code
+       debug_assert!(len != NO_CODE); 
        return true;
     }
    if len == NO_CODE {
@@ -653,16 +1464,21 @@ fn include_in_sort(c: &Codebook, len: u8) -> bool
 
 const  CRC32_POLY  : u32 =  0x04c11db7;   // from spec
 
-unsafe fn crc32_init()
+const fn crc32_table() -> [u32; 256]
 {
-   for i in 0 .. 256 {
-       let mut s : u32 = i << 24;
-       for _ in 0 .. 8 {
+   let mut table = [0u32; 256];
+   let mut i = 0;
+   while i < 256 {
+       let mut s : u32 = (i as u32) << 24;
+       let mut j = 0;
+       while j < 8 {
            s = (s << 1) ^ (if s >= (1u32<<31) {CRC32_POLY} else {0});
+           j += 1;
        }
-       CRC_TABLE[i as usize] = s;
+       table[i] = s;
+       i += 1;
    }
-   
+   return table;
 }
 
 
@@ -737,10 +1553,15 @@ fn compute_codewords(c: &mut Codebook, len: &mut [u8], values: &mut [u32]) -> bo
    }
    
    if k == n { 
-       assert!(c.sorted_entries == 0); 
-       return true;
+       // no entry has a code, so none can have been counted for the sort
+       return c.sorted_entries == 0;
    }
    
+   // a 32-bit codeword has no room in available[]
+   if len[k] >= 32 {
+       return false;
+   }
+
    // add to the list
    let mut m = 0;
    add_entry(c, 0, k, m, len[k], values);
@@ -764,6 +1585,11 @@ fn compute_codewords(c: &mut Codebook, len: &mut [u8], values: &mut [u32]) -> bo
       if z == NO_CODE {
           continue;
       }
+      // available[] has no room for a 32-bit codeword; z only goes down
+      // from here, so it indexes available[] below
+      if z >= 32 {
+          return false;
+      }
       // find lowest available leaf (should always be earliest,
       // which is what the specification calls for)
       // note that this property, and the fact we can never have
@@ -777,20 +1603,19 @@ fn compute_codewords(c: &mut Codebook, len: &mut [u8], values: &mut [u32]) -> bo
           return false;
       }
       res = available[z as usize];
-      assert!(z < 32); // NOTE(z is u8 so negative is impossible)
       available[z as usize] = 0;
       add_entry(c, bit_reverse(res), i, m, len[i], values);
       m += 1;
       
       // propogate availability up the tree
       if z != len[i] {
-        //  assert!(len[i as usize] >= 0 && len[i as usize] < 32);
-         assert!(len[i] < 32); // NOTE (len[x] is already unsigned)
-         
          let mut y = len[i];
          while y > z {
-            assert!(available[y as usize] == 0);
-            available[y as usize] = res + (1 << (32-y));
+            // only a broken length table gets here with the leaf taken
+            if available[y as usize] != 0 {
+                return false;
+            }
+            available[y as usize] = res.wrapping_add(1 << (32-y));
              
              y -= 1;
          }         
@@ -843,11 +1668,11 @@ fn ilog(n: i32) -> i32
        
 }
 
-fn get_window(f: &Vorbis, len: usize) -> &[f32]
+fn window_index(f: &Vorbis, len: usize) -> usize
 {
    let len = len << 1;
-   if len == f.blocksize_0 as usize { return &f.window[0]; }
-   if len == f.blocksize_1 as usize { return &f.window[1]; }
+   if len == f.blocksize_0 as usize { return 0; }
+   if len == f.blocksize_1 as usize { return 1; }
 
    unreachable!();
 }
@@ -879,8 +1704,13 @@ fn lookup1_values(entries: i32, dim: i32) -> i32
     if f64::floor(f64::powi( (r+1) as f64, dim)) as i32 <= entries {
        r += 1;
     }
-    assert!(f64::powi((r+1) as f64, dim) > entries as f64);
-    assert!(f64::powi(r as f64, dim) as i32 <= entries);
+    // floating point can be off for a silly 'dim'; the caller rejects -1
+    if f64::powi((r+1) as f64, dim) <= entries as f64 {
+       return -1;
+    }
+    if f64::powi(r as f64, dim) as i32 > entries {
+       return -1;
+    }
     return r;
 }
 
@@ -966,11 +1796,16 @@ fn get8(z: &mut Vorbis) -> u8
    }
 
    let mut buf = [0; 1];
-   let mut f = z.f.as_mut().unwrap();
-   match f.read(&mut buf){
+   let result = z.f.as_mut().unwrap().read(&mut buf);
+   match result {
        Ok(n) if n == 1 => return buf[0],
-       _ => {
+       Ok(_) => {
+           z.eof = true;
+           return 0;
+       }
+       Err(e) => {
            z.eof = true;
+           if z.io_error.is_none() {z.io_error = Some(e);}
            return 0;
        }
    }
@@ -1002,11 +1837,14 @@ fn getn(z: &mut Vorbis, data: &mut [u8]) -> bool
       }
    }
 
-   let mut f = z.f.as_mut().unwrap();
-   match f.read_exact(data) {
+   let result = z.f.as_mut().unwrap().read_exact(data);
+   match result {
        Ok(_) => return true,
-       Err(_) => {
+       Err(e) => {
            z.eof = true;
+           if e.kind() != io::ErrorKind::UnexpectedEof && z.io_error.is_none() {
+               z.io_error = Some(e);
+           }
            return false;
        }
    }
@@ -1025,7 +1863,9 @@ fn skip(z: &mut Vorbis, n: i32)
 
    // file must not None
    let mut f = z.f.as_mut().unwrap();
-   f.seek(SeekFrom::Current(n as i64)).unwrap();
+   if f.seek(SeekFrom::Current(n as i64)).is_err() {
+       z.eof = true;
+   }
 }
 
 fn capture_pattern(f: &mut Vorbis) -> bool
@@ -1037,22 +1877,83 @@ fn capture_pattern(f: &mut Vorbis) -> bool
    return true;
 }
 
+// skip junk in front of the first page of a stream, as far as the next
+// capture pattern and version 0 within STB_VORBIS_MAX_JUNK bytes (junk
+// can hold "OggS" like anything else). if there's none, stay
+// put and let start_page() fail
+fn skip_junk(f: &mut Vorbis)
+{
+   let stream = f.stream;
+   let offset = stb_vorbis_get_file_offset(f);
+   let eof = f.eof;
+   let mut header : [u8; 27] = [0; 27];
+   for _ in 0 .. STB_VORBIS_MAX_JUNK {
+      if peek_page_header(f, &mut header) == false {
+         break;
+      }
+      if header.starts_with(&OGG_PAGE_HEADER) && header[4] == 0 {
+         return;
+      }
+      skip(f, 1);
+   }
+   if !f.stream.is_null() {
+      f.stream = stream;
+   } else {
+      set_file_offset(f, offset);
+   }
+   f.eof = eof;
+}
 
-const EOP : i32 = -1;
-const INVALID_BITS : i32 = -1;
-
-fn get8_packet_raw(f: &mut Vorbis) -> i32
+// stream serial number out of a page header
+fn page_serial(header: &[u8]) -> u32
 {
-    if f.bytes_in_seg == 0 && (f.last_seg == true || next_segment(f) == 0){
-        return EOP;
-    }
-    
-    assert!(f.bytes_in_seg > 0);
-    
-    f.bytes_in_seg -= 1;
-    f.packet_bytes += 1;
-    
-    return get8(f) as i32;
+   return header[14] as u32 | (header[15] as u32) << 8 | (header[16] as u32) << 16 | (header[17] as u32) << 24;
+}
+
+// absolute granule position out of a page header, !0 if no packet ends on
+// the page
+fn page_granule(header: &[u8]) -> u64
+{
+   let mut x : u64 = 0;
+   for i in (6 .. 14).rev() {
+      x = (x << 8) | header[i] as u64;
+   }
+   return x;
+}
+
+// read the next 27 byte page header without consuming it
+fn peek_page_header(f: &mut Vorbis, header: &mut [u8; 27]) -> bool
+{
+   if !f.stream.is_null() {
+      let stream = f.stream;
+      let ok = getn(f, &mut header[..]);
+      f.stream = stream;
+      return ok;
+   }
+
+   let offset = stb_vorbis_get_file_offset(f);
+   let ok = getn(f, &mut header[..]);
+   set_file_offset(f, offset);
+   return ok;
+}
+
+
+const EOP : i32 = -1;
+const INVALID_BITS : i32 = -1;
+
+fn get8_packet_raw(f: &mut Vorbis) -> i32
+{
+    if f.bytes_in_seg == 0 && (f.last_seg == true || next_segment(f) == 0){
+        return EOP;
+    }
+    
+    // next_segment() returns the length it put in bytes_in_seg
+    debug_assert!(f.bytes_in_seg > 0);
+    
+    f.bytes_in_seg -= 1;
+    f.packet_bytes += 1;
+    
+    return get8(f) as i32;
 }
 
 
@@ -1069,6 +1970,46 @@ fn flush_packet(f: &mut Vorbis)
     while get8_packet_raw(f) != EOP {}
 }
 
+/// read a 32-bit little-endian value from the packet; None if the packet
+/// ends first
+fn get32_packet(f: &mut Vorbis) -> Option<u32>
+{
+    let mut x : u32 = 0;
+    for i in 0 .. 4 {
+        let c = get8_packet(f);
+        if c == EOP {
+            return None;
+        }
+        x |= (c as u32) << (8 * i);
+    }
+    return Some(x);
+}
+
+/// read a length-prefixed string from the packet, taking its length off
+/// 'left'; None if the packet ends first or it's longer than 'left'
+fn get_string_packet(f: &mut Vorbis, left: &mut usize) -> Option<String>
+{
+    let len = match get32_packet(f) {
+        Some(len) => len,
+        None      => return None,
+    };
+    if len as u64 > *left as u64 {
+        return None;
+    }
+    *left -= len as usize;
+    
+    // don't trust len for the allocation, the packet ends where it ends
+    let mut bytes = Vec::new();
+    for _ in 0 .. len {
+        let c = get8_packet(f);
+        if c == EOP {
+            return None;
+        }
+        bytes.push(c as u8);
+    }
+    return Some(String::from_utf8_lossy(&bytes).into_owned());
+}
+
 
 // @OPTIMIZE: this is the secondary bit decoder, so it's probably not as important
 // as the huffman decoder?
@@ -1076,13 +2017,14 @@ fn flush_packet(f: &mut Vorbis)
 fn get_bits(f: &mut Vorbis, n: i32) -> u32
 {
    if f.valid_bits < 0 {return 0;}
+   if n > 24 {
+      // the accumulator technique below would not work correctly in this
+      // case, and the mask can't be made for all 32 bits of it
+      let mut z = get_bits(f, 24);
+      z += get_bits(f, n-24) << 24;
+      return z;
+   }
    if f.valid_bits < n {
-      if n > 24 {
-         // the accumulator technique below would not work correctly in this case
-         let mut z = get_bits(f, 24);
-         z += get_bits(f, n-24) << 24;
-         return z;
-      }
       if f.valid_bits == 0 {f.acc = 0;}
       while f.valid_bits < n {
          let z = get8_packet_raw(f);
@@ -1109,7 +2051,7 @@ fn get_bits(f: &mut Vorbis, n: i32) -> u32
 fn start_page(f: &mut Vorbis) -> bool
 {
    if capture_pattern(f) == false {
-       return error(f, VorbisError::MissingCapturePattern);
+       return error(f, VorbisErrorKind::MissingCapturePattern);
    } 
    return start_page_no_capturepattern(f);
 }
@@ -1120,7 +2062,7 @@ fn start_packet(f: &mut Vorbis) -> bool
    while f.next_seg == -1 {
       if start_page(f) == false { return false; }
       if (f.page_flag & PAGEFLAG_CONTINUED_PACKET) != 0 {
-         return error(f, VorbisError::ContinuedPacketFlagInvalid);
+         return error(f, VorbisErrorKind::ContinuedPacketFlagInvalid);
       }
    }
    f.last_seg = false;
@@ -1133,9 +2075,10 @@ fn start_packet(f: &mut Vorbis) -> bool
 
 fn maybe_start_packet(f: &mut Vorbis) -> bool
 {
-    use VorbisError::{MissingCapturePattern, ContinuedPacketFlagInvalid};
+    use VorbisErrorKind::{MissingCapturePattern, ContinuedPacketFlagInvalid};
     
    if f.next_seg == -1 {
+      let page_start = stb_vorbis_get_file_offset(f);
       let x = get8(f) as i32;
       if f.eof == true { return false; } // EOF at page boundary is not an error!
       if 0x4f != x       { return error(f, MissingCapturePattern); }
@@ -1143,6 +2086,18 @@ fn maybe_start_packet(f: &mut Vorbis) -> bool
       if 0x67 != get8(f) { return error(f, MissingCapturePattern); }
       if 0x53 != get8(f) { return error(f, MissingCapturePattern); }
       if start_page_no_capturepattern(f) == false { return false; }
+      if (f.page_flag & PAGEFLAG_FIRST_PAGE) != 0 && f.push_mode == false {
+         // the next link of a chained stream; rewind to its first page
+         // and read its headers (push mode catches this earlier)
+         set_file_offset(f, page_start);
+         if unsafe { start_next_link(f) } == false {
+            // without its headers there's nothing to decode the link
+            // with, error recovery or not
+            f.eof = true;
+            return false;
+         }
+         return start_packet(f);
+      }
       if (f.page_flag & PAGEFLAG_CONTINUED_PACKET) != 0 {
          // set up enough state that we can read this packet if we want,
          // e.g. during recovery
@@ -1164,8 +2119,13 @@ fn next_segment(f: &mut Vorbis) -> i32
           f.last_seg = true; 
           return 0;
       }
+      if f.page_lost == true {
+          // the rest of the packet went with a corrupt page
+          f.last_seg = true;
+          return 0;
+      }
       if (f.page_flag & PAGEFLAG_CONTINUED_PACKET) == 0 {
-          error(f, VorbisError::ContinuedPacketFlagInvalid); 
+          error(f, VorbisErrorKind::ContinuedPacketFlagInvalid); 
           return 0;
       }
    }
@@ -1180,43 +2140,102 @@ fn next_segment(f: &mut Vorbis) -> i32
    if f.next_seg >= f.segment_count{
       f.next_seg = -1;
    }
-   assert!(f.bytes_in_seg == 0);
+   // only get8_packet_raw() calls this, once the segment is used up
+   debug_assert!(f.bytes_in_seg == 0);
    f.bytes_in_seg = len;
    return len as i32;
 }
 
-fn vorbis_decode_packet(f: &mut Vorbis) -> Result<(i32, i32, i32), VorbisError>
+fn vorbis_decode_packet(f: &mut Vorbis) -> Result<(i32, i32, i32), VorbisErrorKind>
 {
     let (p_left_start, _, p_right_start, p_right_end, mode_index) = try!( vorbis_decode_initial(f) );
-    unsafe {
-        let mode : &Mode = FORCE_BORROW!( &f.mode_config[mode_index as usize] );
-        let (len, p_left_start) = try!( vorbis_decode_packet_rest(f, mode, p_left_start, p_right_start, p_right_end) );
-        return Ok((len, p_left_start, p_right_start));
-    }
+    let mode = f.mode_config[mode_index as usize];
+    let codebooks = mem::replace(&mut f.codebooks, Vec::new());
+    let floors = mem::replace(&mut f.floor_config, Vec::new());
+    let residues = mem::replace(&mut f.residue_config, Vec::new());
+    let mappings = mem::replace(&mut f.mapping, Vec::new());
+    let result = {
+        let tables = SetupTables { codebooks: &codebooks, floors: &floors, residues: &residues, mappings: &mappings };
+        unsafe { vorbis_decode_packet_rest(f, &mode, &tables, p_left_start, p_right_start, p_right_end) }
+    };
+    f.codebooks = codebooks;
+    f.floor_config = floors;
+    f.residue_config = residues;
+    f.mapping = mappings;
+    let (len, p_left_start) = try!( result );
+    return Ok((len, p_left_start, p_right_start));
 }
 
 
-fn vorbis_pump_first_frame(f: &mut Vorbis)
+// the first page of another link of a chained stream is next: read its
+// headers (channels, sample rate and everything else may change) and get
+// ready to decode its first audio packet
+unsafe fn start_next_link(f: &mut Vorbis) -> bool
+{
+   // its samples follow on from wherever the old link ended
+   let link_sample_start = if f.current_loc_valid == true && f.link_sample_start != SAMPLE_UNKNOWN {
+      f.link_sample_start.wrapping_add(f.current_loc)
+   } else {
+      SAMPLE_UNKNOWN
+   };
+   let page_start = stb_vorbis_get_file_offset(f);
+
+   if start_decoder(f) == false {
+      return false;
+   }
+   f.link_page_start = page_start;
+   f.link_sample_start = link_sample_start;
+   f.link_changed = true;
+   f.page_lost = false;
+   f.silence_left = 0;
+   f.pending_frame = None;
+   // nothing of it decoded yet
+   f.current_loc = 0;
+   f.current_loc_valid = true;
+   f.channel_buffer_start = 0;
+   f.channel_buffer_end = 0;
+   return true;
+}
+
+fn vorbis_pump_first_frame(f: &mut Vorbis) -> bool
 {
+    f.pending_frame = None;
     match vorbis_decode_packet(f){
-        Ok((len, left, right)) => {vorbis_finish_frame(f, len, left, right);},
-        Err(why) => {error(f, why);}
+        Ok((len, left, right)) => {
+            let len = vorbis_finish_frame(f, len, left, right);
+            if f.first_frame == true && len > 0 {
+                // the first frame of a link has samples of its own to put
+                // out; the next stb_vorbis_get_frame_*() returns them
+                f.pending_frame = Some((left, len));
+                f.current_loc = f.current_loc.wrapping_sub(len as u64);
+            }
+            return true;
+        },
+        Err(why) => {return error(f, why);}
     }
 }
 
 
 
-// create an ogg vorbis decoder from an open FILE *, looking for a stream at
-// the _current_ seek point (ftell); the stream will be of length 'len' bytes.
-// on failure, returns NULL and sets *error. note that stb_vorbis must "own"
-// this stream; if you seek it in between calls to stb_vorbis, it will become
-// confused.
-pub fn stb_vorbis_open_file_section(mut file: File, length: u64) -> Result<Vorbis, VorbisError>
+// create an ogg vorbis decoder from any Read + Seek + Send source, looking for a
+// stream at the _current_ seek point; the stream will be of length 'len' bytes.
+// on failure, returns Err. note that stb_vorbis must "own" this reader; if you
+// seek it in between calls to stb_vorbis, it will become confused.
+pub fn stb_vorbis_open_reader_section<R: Read + Seek + Send + 'static>(reader: R, length: u64) -> Result<Vorbis, VorbisError>
+{
+   return open_reader_section(reader, length, SetupLimits::default());
+}
+
+fn open_reader_section<R: Read + Seek + Send + 'static>(mut reader: R, length: u64, limits: SetupLimits) -> Result<Vorbis, VorbisError>
 {
    let mut p = Vorbis::new();
-   p.f_start = file.seek(SeekFrom::Current(0)).unwrap() as u32; // NOTE(bungcip): change it to i64/u64?
-   p.f = Some(BufReader::new(file));
-   p.stream_len   = length as u32;
+   p.limits = limits;
+   p.f_start = match reader.seek(SeekFrom::Current(0)) {
+       Ok(start) => start,
+       Err(e)    => return Err(VorbisError::with_source(VorbisErrorKind::SeekFailed, e)),
+   };
+   p.f = Some(BufReader::new(Box::new(reader)));
+   p.stream_len   = length;
     
    unsafe {
     if start_decoder(&mut p) == true {
@@ -1225,7 +2244,44 @@ pub fn stb_vorbis_open_file_section(mut file: File, length: u64) -> Result<Vorbi
     }
    }
 
-   return Err(p.error);
+   let kind = p.error;
+   return Err(vorbis_error(&mut p, kind));
+}
+
+// create an ogg vorbis decoder from any Read + Seek + Send source, looking for a
+// stream at the _current_ seek point. the decoder assumes it owns the _entire_
+// rest of the reader after the start point; use stb_vorbis_open_reader_section()
+// to limit it.
+pub fn stb_vorbis_open_reader<R: Read + Seek + Send + 'static>(reader: R) -> Result<Vorbis, VorbisError>
+{
+    return stb_vorbis_open_reader_with_limits(reader, SetupLimits::default());
+}
+
+// same as above, with caps on what the stream's headers may make the
+// decoder allocate; a stream asking for more fails with OutOfMem
+pub fn stb_vorbis_open_reader_with_limits<R: Read + Seek + Send + 'static>(mut reader: R, limits: SetupLimits) -> Result<Vorbis, VorbisError>
+{
+    let (start, end) = match (reader.seek(SeekFrom::Current(0)), reader.seek(SeekFrom::End(0))) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => return Err(VorbisError::with_source(VorbisErrorKind::SeekFailed, e)),
+    };
+    
+    // seek to start position
+    if let Err(e) = reader.seek(SeekFrom::Start(start)) {
+        return Err(VorbisError::with_source(VorbisErrorKind::SeekFailed, e));
+    }
+    
+    return open_reader_section(reader, end - start, limits);
+}
+
+// create an ogg vorbis decoder from an open FILE *, looking for a stream at
+// the _current_ seek point (ftell); the stream will be of length 'len' bytes.
+// on failure, returns NULL and sets *error. note that stb_vorbis must "own"
+// this stream; if you seek it in between calls to stb_vorbis, it will become
+// confused.
+pub fn stb_vorbis_open_file_section(file: File, length: u64) -> Result<Vorbis, VorbisError>
+{
+   return stb_vorbis_open_reader_section(file, length);
 }
 
 // create an ogg vorbis decoder from an open file handle, looking for a stream at
@@ -1235,16 +2291,9 @@ pub fn stb_vorbis_open_file_section(mut file: File, length: u64) -> Result<Vorbi
 // perform stb_vorbis_seek_*() operations on this file, it will assume it
 // owns the _entire_ rest of the file after the start point. Use the next
 // function, stb_vorbis_open_file_section(), to limit it.
-pub fn stb_vorbis_open_file(mut file: File) -> Result<Vorbis, VorbisError>
+pub fn stb_vorbis_open_file(file: File) -> Result<Vorbis, VorbisError>
 {
-    let start = file.seek(SeekFrom::Current(0)).unwrap();
-    let end = file.seek(SeekFrom::End(0)).unwrap();
-    let len = end - start;
-    
-    // seek to start position
-    file.seek(SeekFrom::Start(start)).unwrap();
-    
-    return stb_vorbis_open_file_section(file, len);
+    return stb_vorbis_open_reader(file);
 }
 
 
@@ -1253,7 +2302,7 @@ pub fn stb_vorbis_open_file(mut file: File) -> Result<Vorbis, VorbisError>
 pub fn stb_vorbis_open_filename(filename: &Path)-> Result<Vorbis, VorbisError>
 {    
     let file = match File::open(filename){
-        Err(_)   => return Err(VorbisError::FileOpenFailure),
+        Err(e)   => return Err(VorbisError::with_source(VorbisErrorKind::FileOpenFailure, e)),
         Ok(file) => file
     };
     
@@ -1275,7 +2324,7 @@ pub fn stb_vorbis_open_filename(filename: &Path)-> Result<Vorbis, VorbisError>
 //        has to be the same as frame N+1's left_end-left_start (which they are by
 //        construction)
 
-fn vorbis_decode_initial(f: &mut Vorbis) -> Result<(i32, i32, i32, i32, i32), VorbisError>
+fn vorbis_decode_initial(f: &mut Vorbis) -> Result<(i32, i32, i32, i32, i32), VorbisErrorKind>
 {
    f.channel_buffer_start = 0;
    f.channel_buffer_end = 0;
@@ -1288,12 +2337,13 @@ fn vorbis_decode_initial(f: &mut Vorbis) -> Result<(i32, i32, i32, i32, i32), Vo
         // check packet type
         if get_bits(f,1) != 0 {
             if f.push_mode {
-                return Err(VorbisError::BadPacketType);
+                return Err(VorbisErrorKind::BadPacketType);
             }
             while EOP != get8_packet(f){}
             continue;
         }
         
+       f.packet_type = Some(0);
        break;
    }
 
@@ -1312,8 +2362,7 @@ fn vorbis_decode_initial(f: &mut Vorbis) -> Result<(i32, i32, i32, i32, i32), Vo
 
    mode = i;
 
-   // NOTE: hack to forget borrow
-   let &mut m : &mut Mode = unsafe { FORCE_BORROW_MUT!(&mut f.mode_config[i as usize])};
+   let m = f.mode_config[i as usize];
    
    let n;
    let prev;
@@ -1359,12 +2408,12 @@ fn vorbis_finish_frame(f: &mut Vorbis, len: i32, left: i32, right: i32) -> i32
    // indicates where our sin() ending-window starts, therefore that's where
    // we start saving, and where our returned-data ends.
 
-   // mixin from previous window
+   // mixin from previous window, which overlaps the samples discarded
+   // from the start of the frame too
    if f.previous_length != 0 {
       let n = f.previous_length as usize;
-      // NOTE(bungcip): need to force borrow because mut f is borrowed....
-      let w : &[f32] = unsafe { FORCE_BORROW!( get_window(f, n)) };
-      let left = left as usize;
+      let w = &f.window[window_index(f, n)];
+      let left = (left - f.discarded) as usize;
       for i in 0 .. f.channels as usize {
          for j in 0 .. n {
             f.channel_buffers[i][left + j] =
@@ -1393,7 +2442,7 @@ fn vorbis_finish_frame(f: &mut Vorbis, len: i32, left: i32, right: i32) -> i32
       }           
    }
 
-   if prev == 0 {
+   if prev == 0 && f.first_frame == false {
       // there was no previous packet, so this data isn't valid...
       // this isn't entirely true, only the would-have-overlapped data
       // isn't valid, but this seems to be what the spec requires
@@ -1404,7 +2453,7 @@ fn vorbis_finish_frame(f: &mut Vorbis, len: i32, left: i32, right: i32) -> i32
    let right = std::cmp::min(len, right);
 //    let right = if len < right { len } else { right };
 
-   f.samples_output += (right-left) as u32;
+   f.samples_output += (right-left) as u64;
 
    return right - left;
 }
@@ -1422,17 +2471,22 @@ fn vorbis_finish_frame(f: &mut Vorbis, len: i32, left: i32, right: i32) -> i32
 // per channel.
 
 // Channel coercion rules:
-//    Let M be the number of channels requested, and N the number of channels present,
-//    and Cn be the nth channel; let stereo L be the sum of all L and center channels,
-//    and stereo R be the sum of all R and center channels (channel assignment from the
-//    vorbis spec).
+//    Let M be the number of channels requested, and N the number of channels present.
+//    When both are from 1 to 8 and differ, the channels are taken as speakers in the
+//    Vorbis I channel order
+//        1: mono   2: L R   3: L C R   4: FL FR RL RR   5: FL C FR RL RR
+//        6: FL C FR RL RR LFE   7: FL C FR SL SR RC LFE   8: FL C FR SL SR RL RR LFE
+//    and a speaker the output doesn't have is folded into its neighbours at -3dB
+//    (center into left and right, left and right into center, side into rear or
+//    front, rear into side or front, rear center into rear left and right). The LFE
+//    is left out, as in ITU-R BS.775 downmixes, and the gains are scaled down so no
+//    output channel can clip. Otherwise:
 //        M    N       output
-//        1    k      sum(Ck) for all k
-//        2    *      stereo L, stereo R
 //        k    l      k > l, the first l channels, then 0s
 //        k    l      k <= l, the first k channels
-//    Note that this is not _good_ surround etc. mixing at all! It's just so
-//    you get something useful.
+//    stb_vorbis_set_channel_mix() selects the rules of the original stb_vorbis or a
+//    mix matrix of your own instead. The float functions that take a channel count
+//    follow the same rules.
 pub fn stb_vorbis_get_frame_short_interleaved(f: &mut Vorbis, 
     channel_count: u32, buffer: &mut [i16]) -> i32
 {
@@ -1451,7 +2505,39 @@ pub fn stb_vorbis_get_frame_short_interleaved(f: &mut Vorbis,
       if len * channel_count as usize > num_shorts {
         len = num_shorts / channel_count as usize;  
       } 
-      convert_channels_short_interleaved(channel_count, buffer, &output, len);
+      let (gains, dither) = output_mix(f, channel_count as usize);
+      convert_channels_short_interleaved(channel_count, buffer, &output, gains, dither, len);
+   }
+   return len as i32;
+}
+
+// decode the next frame into 'channels' channels of any Sample type (f32,
+// f64, i32, I24, i16, u8), converted from the float buffers as they're
+// stored. takes the coercion rules of the float functions; i16 samples are
+// the same as the short functions', but for ChannelMix::Original. returns
+// the number of samples per channel, cut short if the buffer is.
+pub fn stb_vorbis_get_frame<T: Sample>(f: &mut Vorbis, channels: i32, buffer: &mut AudioBufferSlice<T>) -> i32
+{
+   let mut output: AudioBufferSlice<f32> = AudioBufferSlice::new(f.channels as usize);
+   let len = stb_vorbis_get_frame_float(f, None, Some(&mut output)) as usize;
+   let len = std::cmp::min(len, buffer.len());
+   if len != 0 {
+      let (gains, dither) = output_mix(f, channels as usize);
+      convert_samples(channels as usize, &output, gains, dither, len, |c, i, sample| buffer[c][i] = sample);
+   }
+   return len as i32;
+}
+
+// stb_vorbis_get_frame(), interleaved
+pub fn stb_vorbis_get_frame_interleaved<T: Sample>(f: &mut Vorbis, channels: i32, buffer: &mut [T]) -> i32
+{
+   let mut output: AudioBufferSlice<f32> = AudioBufferSlice::new(f.channels as usize);
+   let len = stb_vorbis_get_frame_float(f, None, Some(&mut output)) as usize;
+   let len = std::cmp::min(len, buffer.len() / channels as usize);
+   if len != 0 {
+      let (gains, dither) = output_mix(f, channels as usize);
+      let stride = channels as usize;
+      convert_samples(stride, &output, gains, dither, len, |c, i, sample| buffer[i * stride + c] = sample);
    }
    return len as i32;
 }
@@ -1507,6 +2593,129 @@ pub fn stb_vorbis_decode_filename(filename: &Path,
 }
 
 
+// error recovery: the packet being decoded turned out to be damaged. the
+// rest of its page goes with it, and decoding picks up at the last packet
+// of the next good page of the stream that gives a position, the way a seek
+// does. the samples between the last one put out and the first one decoded
+// there are put out as silence (see silence_frame()), so everything after
+// stays where it belongs. false if there's nothing left to decode
+fn conceal_damage(f: &mut Vorbis) -> bool
+{
+   let offset = stb_vorbis_get_file_offset(f);
+   // where the silence starts, if known
+   let start = if f.current_loc_valid == true && f.previous_length != 0 {
+      Some(f.current_loc)
+   } else {
+      None
+   };
+
+   let mut search = offset;
+   loop {
+      f.error = VorbisErrorKind::NoError;
+      set_file_offset(f, search);
+      // the next page with a good CRC
+      let page_end = match vorbis_find_page(f) {
+         Some((end, _)) => end,
+         None => return false,
+      };
+      search = page_end;
+
+      let mut header = [0; 27];
+      if peek_page_header(f, &mut header) == false {
+         return false;
+      }
+      f.page_lost = false;
+      f.drop_continued = false;
+      if (header[5] & PAGEFLAG_FIRST_PAGE) != 0 {
+         // the next link of a chained stream, which starts over anyway;
+         // where its samples start is lost with the damaged ones
+         f.next_seg = -1;
+         f.previous_length = 0;
+         f.current_loc_valid = false;
+         report_concealment(f, offset, start, 0);
+         return true;
+      }
+      if page_serial(&header) != f.serial || page_granule(&header) == !0 {
+         continue;
+      }
+
+      // the last packet that ends on the page, as in seek_to_sample_coarse()
+      f.first_decode = false;
+      if start_page(f) == false || f.end_seg_with_known_loc < 0 {
+         continue;
+      }
+      let mut i = f.end_seg_with_known_loc;
+      while i > 0 && f.segments[(i-1) as usize] == 255 {
+         i -= 1;
+      }
+      if i == 0 && (f.page_flag & PAGEFLAG_CONTINUED_PACKET) != 0 {
+         // it began on a page that's gone; try the next
+         continue;
+      }
+
+      f.current_loc_valid = false;
+      f.last_seg = false;
+      f.valid_bits = 0;
+      f.packet_bytes = 0;
+      f.bytes_in_seg = 0;
+      f.previous_length = 0;
+      f.discard_samples_deferred = 0;
+      f.resync_long_block = None;
+      f.next_seg = i;
+      for k in 0 .. i as usize {
+         let seg = f.segments[k] as i32;
+         skip(f, seg);
+      }
+      if vorbis_pump_first_frame(f) == true && f.page_lost == false && f.current_loc_valid == true {
+         break;
+      }
+      // that packet is damaged too
+      set_file_offset(f, page_end);
+   }
+   f.error = VorbisErrorKind::NoError;
+
+   // the silence can't be longer than the packets skipped could have
+   // held, whatever the granule positions say
+   let resume = f.current_loc;
+   let skipped = stb_vorbis_get_file_offset(f).saturating_sub(offset);
+   let most = (skipped + 1).saturating_mul(f.blocksize_1 as u64 / 2);
+   let samples = match start {
+      Some(start) if resume > start && resume - start <= most => resume - start,
+      _ => 0,
+   };
+   f.silence_left = samples;
+   f.current_loc = resume - samples;
+   report_concealment(f, offset, start, samples);
+   return true;
+}
+
+fn report_concealment(f: &mut Vorbis, offset: u64, start: Option<u64>, samples: u64)
+{
+   let sample = match start {
+      Some(start) if f.link_sample_start != SAMPLE_UNKNOWN => Some(f.link_sample_start.wrapping_add(start)),
+      _ => None,
+   };
+   f.concealed_samples += samples;
+   if let Some(ref mut callback) = f.concealment_callback {
+      callback(&Concealment { offset: offset, sample: sample, samples: samples });
+   }
+}
+
+// the next block of the silence conceal_damage() put in, as a frame at the
+// start of the channel buffers. returns (len, left)
+fn silence_frame(f: &mut Vorbis) -> (i32, i32)
+{
+   let len = std::cmp::min(f.silence_left, f.channel_buffers[0].len() as u64) as usize;
+   for i in 0 .. f.channels as usize {
+      for sample in f.channel_buffers[i][.. len].iter_mut() {
+         *sample = 0.0;
+      }
+   }
+   f.silence_left -= len as u64;
+   f.current_loc = f.current_loc.wrapping_add(len as u64);
+   return (len as i32, 0);
+}
+
 // decode the next frame and return the number of samples. the number of
 // channels returned are stored in *channels (which can be NULL--it is always
 // the same as the number of channels reported by get_info). *output will
@@ -1519,21 +2728,96 @@ pub fn stb_vorbis_get_frame_float(f: &mut Vorbis,
     channel_count: Option<&mut i32>, output: Option<&mut AudioBufferSlice<f32>>) -> i32
 {
    if f.push_mode{
-       error(f, VorbisError::InvalidApiMixing);
+       error(f, VorbisErrorKind::InvalidApiMixing);
        return 0;
    } 
 
-    let (len, left, right) = match vorbis_decode_packet(f) {
-        Ok(result) => result,
-        Err(why) => {
+   let (len, left) = loop {
+      if f.silence_left > 0 {
+         break silence_frame(f);
+      }
+      if let Some((left, len)) = f.pending_frame.take() {
+         f.current_loc = f.current_loc.wrapping_add(len as u64);
+         break (len, left);
+      }
+      // where the frames put out so far end, for error recovery
+      let loc = f.current_loc;
+      let (len, left, right) = match vorbis_decode_packet(f) {
+         // a packet cut short by a page that failed its CRC check
+         Ok(_) if f.error == VorbisErrorKind::InvalidPageCrc => {
+            f.channel_buffer_start = 0;
+            f.channel_buffer_end = 0;
+            return 0;
+         }
+         // a packet cut short by a damaged page, with error recovery on
+         Ok(_) if f.recover == true && f.error != VorbisErrorKind::NoError && f.eof == false => {
+            let why = f.error;
+            f.current_loc = loc;
+            if conceal_damage(f) == false {
+               f.channel_buffer_start = 0;
+               f.channel_buffer_end = 0;
+               error(f, why);
+               return 0;
+            }
+            continue;
+         }
+         // a packet a page didn't go on with
+         Ok(_) if f.error == VorbisErrorKind::ContinuedPacketFlagInvalid => {
+            f.channel_buffer_start = 0;
+            f.channel_buffer_end = 0;
+            f.eof = true;
+            return 0;
+         }
+         Ok(result) => result,
+         Err(_) if f.page_lost == true && f.eof == false => (0, 0, 0),
+         Err(why) if f.recover == true && f.eof == false && why != VorbisErrorKind::InvalidPageCrc => {
+            // a damaged packet: go on from the next page that can be
+            // decoded, with silence for what was lost
+            f.current_loc = loc;
+            if conceal_damage(f) == false {
+               f.channel_buffer_start = 0;
+               f.channel_buffer_end = 0;
+               error(f, why);
+               return 0;
+            }
+            continue;
+         }
+         Err(why) => {
             f.channel_buffer_start = 0;
             f.channel_buffer_end = 0;
+            if why == VorbisErrorKind::ContinuedPacketFlagInvalid {
+               // the pages don't say where packets start any more, so
+               // whatever came next would be decoded from the wrong bytes
+               // (the same for a packet a page didn't go on with, above)
+               f.eof = true;
+            }
             error(f, why);
             return 0;
-        }
-    };
+         }
+      };
+      if f.page_lost == true {
+         // the packet was read across or right after a page skipped for
+         // its CRC: drop it, and start over from the next one as after a
+         // seek, the position unknown until a page gives it
+         f.page_lost = false;
+         f.error = VorbisErrorKind::NoError;
+         f.previous_length = 0;
+         f.current_loc_valid = false;
+         f.resync_long_block = None;
+         resample_forget(f);
+         dither_forget(f);
+         continue;
+      }
+      let len = vorbis_finish_frame(f, len, left, right);
+      // the first frame of a link usually has nothing to put out, and the
+      // first after a seek or a skipped page never has, so go on to the
+      // next one
+      if len == 0 {
+         continue;
+      }
+      break (len, left);
+   };
 
-   let len = vorbis_finish_frame(f, len, left, right);
    for i in 0 .. f.channels as usize {
     unsafe {
         f.outputs.set(i, &mut f.channel_buffers[i][left as usize ..]);
@@ -1548,7 +2832,7 @@ pub fn stb_vorbis_get_frame_float(f: &mut Vorbis,
    }
 
    if let Some(output) = output {
-       *output = f.outputs;
+       output.clone_from(&f.outputs);
    }
 
    return len;
@@ -1563,16 +2847,21 @@ pub fn stb_vorbis_get_frame_short(f: &mut Vorbis, num_c: i32, mut sample_buffer:
    let len = std::cmp::min(len, sample_buffer.len());
    
    if len != 0 {
-        convert_samples_short(num_c, &mut sample_buffer, &output, len);
+        let (gains, dither) = output_mix(f, num_c as usize);
+        convert_samples_short(num_c, &mut sample_buffer, &output, gains, dither, len);
    }
    return len as i32;
 }
 
 
-fn convert_samples_short(buf_c: i32, buffer: &mut AudioBufferSlice<i16>, data: &AudioBufferSlice<f32>, samples: usize)
+// without a mix matrix the mono/stereo coercion below is only reached by
+// ChannelMix::Original; Standard has a matrix for all of those cases
+fn convert_samples_short(buf_c: i32, buffer: &mut AudioBufferSlice<i16>, data: &AudioBufferSlice<f32>, gains: Option<&[f32]>, dither: &mut Option<Ditherer>, samples: usize)
 {
    let buf_c = buf_c as usize;
-   if buf_c != data.channel_count() && buf_c <= 2 && data.channel_count() <= 6 {
+   if let Some(gains) = gains {
+      mix_samples(gains, data, samples, |c, i, value| buffer[c][i] = dithered(dither, c, value));
+   } else if buf_c != data.channel_count() && buf_c <= 2 && data.channel_count() <= 6 {
       static CHANNEL_SELECTOR : [[i8;2]; 3] = [
           [0, 0],
           [PLAYBACK_MONO, PLAYBACK_MONO],
@@ -1581,7 +2870,7 @@ fn convert_samples_short(buf_c: i32, buffer: &mut AudioBufferSlice<i16>, data: &
       
       for i in 0 .. buf_c {
          compute_samples(CHANNEL_SELECTOR[buf_c][i] as i32, &mut buffer[i], 
-            data, samples);
+            data, samples, i, dither);
       }
    } else {
       let limit = std::cmp::min(buf_c, data.channel_count());
@@ -1590,7 +2879,7 @@ fn convert_samples_short(buf_c: i32, buffer: &mut AudioBufferSlice<i16>, data: &
       while i < limit {
          let mut buffer_slice = &mut buffer[i]; 
          let data_slice = &data[i];
-         copy_samples(&mut buffer_slice, data_slice, samples);
+         copy_samples(&mut buffer_slice, data_slice, samples, i, dither);
          i += 1;
       }
       
@@ -1606,12 +2895,16 @@ fn convert_samples_short(buf_c: i32, buffer: &mut AudioBufferSlice<i16>, data: &
 }
 
 
-fn convert_channels_short_interleaved(buf_c: u32, buffer: &mut [i16], data: &AudioBufferSlice<f32>, len: usize)
+fn convert_channels_short_interleaved(buf_c: u32, buffer: &mut [i16], data: &AudioBufferSlice<f32>, gains: Option<&[f32]>, dither: &mut Option<Ditherer>, len: usize)
 {
-   if buf_c != data.channel_count() as u32 && buf_c <= 2 && data.channel_count() <= 6 {
-       assert!(buf_c == 2);
-       for _ in 0 .. buf_c {
-         compute_stereo_samples(buffer, data, len);
+   if let Some(gains) = gains {
+       let buf_c = buf_c as usize;
+       mix_samples(gains, data, len, |c, i, value| buffer[i * buf_c + c] = dithered(dither, c, value));
+   } else if buf_c != data.channel_count() as u32 && buf_c <= 2 && data.channel_count() <= 6 {
+       if buf_c == 1 {
+         compute_samples(PLAYBACK_MONO as i32, buffer, data, len, 0, dither);
+       } else {
+         compute_stereo_samples(buffer, data, len, dither);
        }
    } else {
        let limit = std::cmp::min(buf_c as usize, data.channel_count()) as usize;
@@ -1619,7 +2912,7 @@ fn convert_channels_short_interleaved(buf_c: u32, buffer: &mut [i16], data: &Aud
        for j in 0 .. len as usize {
            let mut i = 0;
            while i < limit {
-               buffer[buffer_index] = convert_to_i16(data[i][ j as usize ]);
+               buffer[buffer_index] = dithered(dither, i, data[i][ j as usize ]);
                buffer_index += 1;
                
                i += 1;
@@ -1635,10 +2928,10 @@ fn convert_channels_short_interleaved(buf_c: u32, buffer: &mut [i16], data: &Aud
    }
 }
 
-fn copy_samples(dest: &mut [i16], src: &[f32], len: usize)
+fn copy_samples(dest: &mut [i16], src: &[f32], len: usize, channel: usize, dither: &mut Option<Ditherer>)
 {
    for i in 0 .. len  {
-      dest[i] = convert_to_i16(src[i]);
+      dest[i] = dithered(dither, channel, src[i]);
    }
 }
 
@@ -1647,19 +2940,50 @@ fn copy_samples(dest: &mut [i16], src: &[f32], len: usize)
 // the specified sample. after calling stb_vorbis_seek(), the next call to
 // stb_vorbis_get_samples_* will start with the specified sample. If you
 // do not need to seek to EXACTLY the target sample when using get_samples_*,
-// you can also use seek_frame().
-pub fn stb_vorbis_seek(f: &mut Vorbis, sample_number: u32) -> bool
+// you can also use seek_frame(). with an output rate set, 'sample_number'
+// counts samples at that rate.
+pub fn stb_vorbis_seek(f: &mut Vorbis, sample_number: u64) -> bool
+{
+   if f.resampler.is_none() || f.push_mode == true {
+      return seek_sample(f, sample_number);
+   }
+   let p = stream_position(f, sample_number);
+   let (rate, before) = match f.resampler {
+      Some(ref r) => (r.rate, r.reach(p.input_rate).0),
+      None => (0, 0),
+   };
+   if p.input_rate == rate {
+      return seek_sample(f, p.link_start + p.sample);
+   }
+
+   // far enough back for the filter to have all it needs
+   if seek_sample(f, p.link_start + p.sample.saturating_sub(before)) == false {
+      return false;
+   }
+   resample_input(f);
+   if let Some(ref mut r) = f.resampler {
+      r.next_out = p.offset;
+      r.link_start = p.output_link_start;
+   }
+   return true;
+}
+
+// stb_vorbis_seek() at the stream's rate
+fn seek_sample(f: &mut Vorbis, sample_number: u64) -> bool
 {
-   if stb_vorbis_seek_frame(f, sample_number) == false {
+   if seek_frame(f, sample_number) == false {
       return false;
    }
 
+   // where the sample is in the link seek_frame() picked
+   let sample_number = sample_number - f.link_sample_start;
    if sample_number != f.current_loc {
       let mut n = 0;
       let frame_start = f.current_loc;
       stb_vorbis_get_frame_float(f, Some(&mut n), None);
-      assert!(sample_number > frame_start);
-      assert!(f.channel_buffer_start + (sample_number-frame_start) as i32 <= f.channel_buffer_end);
+      if sample_number < frame_start || f.channel_buffer_start + (sample_number-frame_start) as i32 > f.channel_buffer_end {
+         return error(f, VorbisErrorKind::SeekFailed);
+      }
       f.channel_buffer_start += (sample_number - frame_start) as i32;
    }
 
@@ -1717,51 +3041,124 @@ fn compute_accelerated_huffman(c: &mut Codebook)
 
 // returns the current seek point within the file, or offset from the beginning
 // of the memory buffer. In pushdata mode it returns 0.
-pub fn stb_vorbis_get_file_offset(f: &mut Vorbis) -> u32
+pub fn stb_vorbis_get_file_offset(f: &mut Vorbis) -> u64
 {
    if f.push_mode == true {return 0;}
-   if !f.stream.is_null() {return (f.stream as usize - f.stream_start as usize) as u32;}
+   if !f.stream.is_null() {return (f.stream as usize - f.stream_start as usize) as u64;}
 
    let mut file = f.f.as_mut().unwrap();
-   let current = file.seek(SeekFrom::Current(0)).unwrap();
+   let current = match file.seek(SeekFrom::Current(0)) {
+       Ok(current) => current,
+       Err(_)      => return 0,
+   };
    
-   // NOTE(bungcip): change to u64/i64?
-   return (current as u32 - f.f_start) as u32;
+   return current - f.f_start;
 }
 
 fn start_page_no_capturepattern(f: &mut Vorbis) -> bool
 {
-    use VorbisError::*;
+    use VorbisErrorKind::*;
     
-   // stream structure version
-   if 0 != get8(f) {return error(f, InvalidStreamStructureVersion);}
-   // header flag
-   f.page_flag = get8(f);
-   // absolute granule position
-   let loc0 = get32(f); 
-   let loc1 = get32(f);
-   // @TODO: validate loc0,loc1 as valid positions?
-   // stream serial number -- vorbis doesn't interleave, so discard
-   get32(f);
-   // page sequence number
-   let n = get32(f);
-   f.last_page = n as i32;
-   // CRC32
-   get32(f);
-   // page_segments
-   f.segment_count = get8(f) as i32;
-   unsafe {
-        let segments_slice = {
-            let sc = f.segment_count as usize;
-            FORCE_BORROW_MUT!(&mut f.segments[0 .. sc])
-        };
-        if getn(f, segments_slice) == false {
-            return error(f, UnexpectedEof);
-        }
-   }
+   let checked = f.crc_check != CrcCheck::Off && f.push_mode == false;
+   let (loc, page_start, first_seg) = loop {
+      // where the page starts, capture pattern included; only needed for
+      // the first audio page, for seeking, and for CRC checks
+      let page_start = if (f.first_decode == true || checked) && f.push_mode == false { stb_vorbis_get_file_offset(f) - 4 } else { 0 };
+      // stream structure version
+      if 0 != get8(f) {return error(f, InvalidStreamStructureVersion);}
+      // header flag
+      f.page_flag = get8(f);
+      // absolute granule position
+      let loc = get32(f) as u64 | (get32(f) as u64) << 32;
+      // @TODO: validate loc as a valid position?
+      // stream serial number
+      let serial = get32(f);
+      // page sequence number
+      let n = get32(f);
+      // CRC32
+      let crc = get32(f);
+      // page_segments
+      f.segment_count = get8(f) as i32;
+      let mut segments = [0; 255];
+      if getn(f, &mut segments[0 .. f.segment_count as usize]) == false {
+         return error(f, UnexpectedEof);
+      }
+      f.segments = segments;
+
+      if checked {
+         let mut header = [0u8; 27];
+         header[0 .. 4].copy_from_slice(&OGG_PAGE_HEADER);
+         header[5] = f.page_flag;
+         header[6 .. 14].copy_from_slice(&loc.to_le_bytes());
+         header[14 .. 18].copy_from_slice(&serial.to_le_bytes());
+         header[18 .. 22].copy_from_slice(&n.to_le_bytes());
+         header[26] = f.segment_count as u8;
+         match page_crc(f, &header) {
+            Some(computed) if computed == crc => {}
+            Some(_) => {
+               f.last_page = Some(n);
+               if f.crc_check == CrcCheck::Fail {
+                  // left at the page, so the error says where it is
+                  set_file_offset(f, page_start);
+                  return error(f, InvalidPageCrc);
+               }
+               if skip_corrupt_page(f, page_start, serial, n) == false {
+                  return false;
+               }
+               continue;
+            }
+            None => return error(f, UnexpectedEof),
+         }
+      }
+
+      // a multiplexed file interleaves the pages of other logical streams
+      // (video, skeleton, other audio) with ours; skip them. the first
+      // pages of a link are left to start_decoder(), which picks the serial
+      if serial == f.serial || (f.page_flag & PAGEFLAG_FIRST_PAGE) != 0 || f.streams.is_empty() {
+         f.last_page = Some(n);
+         if f.drop_continued == false || (f.page_flag & PAGEFLAG_CONTINUED_PACKET) == 0 {
+            f.drop_continued = false;
+            break (loc, page_start, 0);
+         }
+         // the packet the page goes on with lost its start to a corrupt
+         // page: pass over what's left of it, and treat the page as if it
+         // started with a new packet
+         let mut len = 0;
+         let mut i = 0;
+         while i < f.segment_count as usize {
+            len += f.segments[i] as i32;
+            i += 1;
+            if f.segments[i - 1] < 255 {
+               break;
+            }
+         }
+         skip(f, len);
+         if i < f.segment_count as usize {
+            f.drop_continued = false;
+            f.page_flag &= !PAGEFLAG_CONTINUED_PACKET;
+            break (loc, page_start, i as i32);
+         }
+         // all of the page was the lost packet
+         if capture_pattern(f) == false {
+            if f.eof == true {return false;}
+            return error(f, MissingCapturePattern);
+         }
+         continue;
+      }
+      let mut len = 0;
+      for i in 0 .. f.segment_count as usize {
+         len += f.segments[i] as i32;
+      }
+      skip(f, len);
+      if capture_pattern(f) == false {
+         // running out of data among foreign pages is just the end
+         if f.eof == true {return false;}
+         return error(f, MissingCapturePattern);
+      }
+   };
    // assume we _don't_ know any the sample position of any segments
    f.end_seg_with_known_loc = -2;
-   if loc0 != !0 || loc1 != !0 {
+   if loc != !0 {
       let mut i;
       // determine which packet is the last one that will complete
       i = f.segment_count - 1;
@@ -1775,7 +3172,7 @@ fn start_page_no_capturepattern(f: &mut Vorbis) -> bool
       // 'i' is now the index of the _last_ segment of a packet that ends
       if i >= 0 {
          f.end_seg_with_known_loc = i;
-         f.known_loc_for_packet   = loc0;
+         f.known_loc_for_packet   = loc;
       }
    }
 
@@ -1786,18 +3183,73 @@ fn start_page_no_capturepattern(f: &mut Vorbis) -> bool
       }
       len += 27 + f.segment_count as i32;
       
+      // in a multiplexed file, pages of other streams can come before it
       let p = ProbedPage {
-          page_start: f.first_audio_page_offset,
-          page_end: f.first_audio_page_offset + len as u32,
-          last_decoded_sample: loc0
+          page_start: page_start,
+          page_end: page_start + len as u64,
+          last_decoded_sample: loc
       };
       
       f.p_first = p;
    }
-   f.next_seg = 0;
+   f.next_seg = first_seg;
    return true;
 }
 
+// the CRC of the page whose 'header' (CRC field zeroed) and segment table
+// were just read, over its body too, leaving the stream where the body
+// starts. None if the page is cut off
+fn page_crc(f: &mut Vorbis, header: &[u8; 27]) -> Option<u32>
+{
+   let body_start = stb_vorbis_get_file_offset(f);
+   let mut crc = 0;
+   for &byte in header.iter().chain(f.segments[.. f.segment_count as usize].iter()) {
+      crc = crc32_update(crc, byte);
+   }
+   let mut segment = [0u8; 255];
+   for i in 0 .. f.segment_count as usize {
+      let len = f.segments[i] as usize;
+      if getn(f, &mut segment[.. len]) == false {
+         return None;
+      }
+      for &byte in &segment[.. len] {
+         crc = crc32_update(crc, byte);
+      }
+   }
+   set_file_offset(f, body_start);
+   return Some(crc);
+}
+
+// counts the corrupt page at 'page_start', tells the callback, and moves
+// on to the next page, leaving its capture pattern read. anything read
+// across the page is lost
+fn skip_corrupt_page(f: &mut Vorbis, page_start: u64, serial: u32, sequence: u32) -> bool
+{
+   f.corrupt_pages += 1;
+   if let Some(ref mut callback) = f.corrupt_page_callback {
+      callback(&CorruptPage { offset: page_start, serial: serial, sequence: sequence });
+   }
+   f.page_lost = true;
+   f.drop_continued = true;
+   // where the segment table says the page ends, if another page starts
+   // there, so that one gets checked in turn
+   let mut len = 27 + f.segment_count as u64;
+   for i in 0 .. f.segment_count as usize {
+      len += f.segments[i] as u64;
+   }
+   if set_file_offset(f, page_start + len) == true && capture_pattern(f) == true {
+      return true;
+   }
+   // otherwise the segment table may be what's wrong, so look for the next
+   // page with a good CRC from just after this one's capture pattern
+   set_file_offset(f, page_start + 1);
+   if vorbis_find_page(f).is_none() {
+      f.eof = true;
+      return false;
+   }
+   return capture_pattern(f);
+}
+
 fn predict_point(x: i32, x0: i32 , x1: i32 , y0: i32 , y1: i32 ) -> i32
 {
    let dy = y1 - y0;
@@ -1809,8 +3261,26 @@ fn predict_point(x: i32, x0: i32 , x1: i32 , y0: i32 , y1: i32 ) -> i32
    return if dy < 0  {y0 - off} else {y0 + off};
 }
 
+fn bark(x: f64) -> f64
+{
+   return 13.1 * (0.00074 * x).atan() + 2.24 * (0.0000000185 * x * x).atan() + 0.0001 * x;
+}
+
+// floor 0 maps each of the n output bins to a bark scale band
+fn floor0_bark_map(n: usize, rate: u16, bark_map_size: u16) -> Vec<i32>
+{
+   let rate = rate as f64;
+   let scale = bark_map_size as f64 / bark(0.5 * rate);
+   let mut map = Vec::with_capacity(n);
+   for i in 0 .. n {
+      let k = (bark(rate * i as f64 / (2.0 * n as f64)) * scale).floor() as i32;
+      map.push(std::cmp::min(k, bark_map_size as i32 - 1));
+   }
+   return map;
+}
+
 // NOTE(bungcip): reduce parameter count
-fn do_floor(floor_config: &[Floor], map: &Mapping, i: usize, n: usize, target: &mut [f32], final_y: &[YTYPE]) -> Result<(), VorbisError>
+fn do_floor(floor_config: &[Floor], map: &Mapping, i: usize, n: usize, target: &mut [f32], final_y: &[YTYPE], floor0: Option<&Floor0Data>) -> Result<(), VorbisErrorKind>
 {
    let n2 = n >> 1;
 
@@ -1820,8 +3290,57 @@ fn do_floor(floor_config: &[Floor], map: &Mapping, i: usize, n: usize, target: &
    };
    
    match floor_config[floor] {
-       Floor::Type0(_) => return Err(VorbisError::InvalidStream),
-       Floor::Type1(g) => {
+       Floor::Type0(ref g) => {
+            let data = match floor0 {
+                Some(data) => data,
+                None => return Err(VorbisErrorKind::InvalidStream),
+            };
+            let bark_map = if g.bark_map[0].len() == n2 { &g.bark_map[0] } else { &g.bark_map[1] };
+            let omega_step = std::f32::consts::PI / g.bark_map_size as f32;
+            let coefficients = &data.coefficients[.. g.order as usize];
+
+            let mut j = 0;
+            while j < n2 {
+                let k = bark_map[j];
+                let cos_omega = (omega_step * k as f32).cos();
+                let two_cos_omega = 2.0 * cos_omega;
+
+                let mut p : f32 = 1.0;
+                let mut q : f32 = 1.0;
+                let mut pairs = coefficients.chunks(2);
+                let mut odd = false;
+                for pair in &mut pairs {
+                    q *= pair[0] - two_cos_omega;
+                    if pair.len() == 2 {
+                        p *= pair[1] - two_cos_omega;
+                    } else {
+                        odd = true;
+                    }
+                }
+                if odd {
+                    p = p * p * (1.0 - cos_omega * cos_omega);
+                    q = q * q * 0.25;
+                } else {
+                    p = p * p * ((1.0 - cos_omega) / 2.0);
+                    q = q * q * ((1.0 + cos_omega) / 2.0);
+                }
+                if p + q == 0.0 {
+                    return Err(VorbisErrorKind::InvalidStream);
+                }
+
+                let amplitude_offset = g.amplitude_offset as f32;
+                let a = data.amplitude.wrapping_mul(g.amplitude_offset as u64) as f32;
+                let b = (p + q).sqrt() * ((1u64 << g.amplitude_bits) - 1) as f32;
+                let value = (0.11512925 * (a / b - amplitude_offset)).exp();
+
+                // runs of equal bark map entries share one value
+                while j < n2 && bark_map[j] == k {
+                    target[j] *= value;
+                    j += 1;
+                }
+            }
+       }
+       Floor::Type1(ref g) => {
             let mut lx = 0;
             let mut ly = final_y[0] as i32 * g.floor1_multiplier as i32;
             for q in 1 .. g.values as usize {
@@ -1843,7 +3362,7 @@ fn do_floor(floor_config: &[Floor], map: &Mapping, i: usize, n: usize, target: &
             if lx < n2 {
                 // optimization of: draw_line(target, lx,ly, n,ly, n2);
                 for j in lx .. n2 {
-                    target[j] *= INVERSE_DB_TABLE[ly as usize];
+                    target[j] *= INVERSE_DB_TABLE[(ly & 255) as usize];
                 }
                 CHECK!(f);
             }
@@ -1876,7 +3395,8 @@ fn draw_line(output: &mut [f32], x0: i32, y0: i32, mut x1: i32, y1: i32, n: i32)
    
    x1 = std::cmp::min(x1, n);
    if x < x1 {
-      output[x as usize] *= INVERSE_DB_TABLE[y as usize];
+      // a broken floor can climb off the table; wrap like the C does
+      output[x as usize] *= INVERSE_DB_TABLE[(y & 255) as usize];
 
       x += 1;
       while x < x1 {
@@ -1888,7 +3408,7 @@ fn draw_line(output: &mut [f32], x0: i32, y0: i32, mut x1: i32, y1: i32, n: i32)
          } else{
             y += base;
          }
-         output[x as usize] *= INVERSE_DB_TABLE[y as usize];
+         output[x as usize] *= INVERSE_DB_TABLE[(y & 255) as usize];
          
          x += 1;
       }      
@@ -1902,9 +3422,8 @@ fn residue_decode(f: &mut Vorbis, book: &Codebook, target: &mut [f32], mut offse
    if rtype == 0 {
       let step = n / book.dimensions;
       for k in 0 .. step {
-            // NOTE(bungcip): simplify this!
-            let mut target_slice = &mut target[ (offset+k) as usize .. ( (offset+k) + (n-offset-k) ) as usize ];
-            if codebook_decode_step(f, book, &mut target_slice, n-offset-k, step) == false {
+            let mut target_slice = &mut target[ (offset+k) as usize .. (offset+n) as usize ];
+            if codebook_decode_step(f, book, &mut target_slice, n-k, step) == false {
                 return false;
             }
       }
@@ -1955,7 +3474,7 @@ fn codebook_decode(f: &mut Vorbis, c: &Codebook, output: &mut [f32], len: i32 )
 #[inline(always)]
 fn decode_raw(f: &mut Vorbis, codebook: &Codebook) -> i32 {
     let mut value = codebook_decode_scalar(f, codebook);
-    if codebook.sparse == true {
+    if codebook.sparse == true && value >= 0 {
         value = codebook.sorted_values[value as usize];
     }
     value
@@ -1968,15 +3487,17 @@ fn codebook_decode_start(f: &mut Vorbis, c: &Codebook) -> i32
 
    // type 0 is only legal in a scalar context
    if c.lookup_type == 0 {
-      error(f, VorbisError::InvalidStream);
+      error(f, VorbisErrorKind::InvalidStream);
    } else {
       z = codebook_decode_scalar(f, c);
-      if c.sparse == true {assert!(z < c.sorted_entries);}
+      // the fast table and the binary search only give indexes into a sparse
+      // codebook's sorted_entries entries
+      if c.sparse == true {debug_assert!(z < c.sorted_entries);}
       if z < 0 && f.bytes_in_seg == 0 { // check for EOP
          if f.last_seg == true {
             return z;
          }
-         error(f,  VorbisError::InvalidStream);
+         error(f,  VorbisErrorKind::InvalidStream);
       }
    }
    return z;
@@ -2074,8 +3595,9 @@ fn codebook_decode_scalar_raw(f: &mut Vorbis, c: &Codebook) -> i32
       return -1;
    }
 
-   // if small, linear search
-   assert!(c.sparse == false);
+   // if small, linear search. sparse codebooks have no codewords[], so
+   // they always take the binary search above
+   debug_assert!(c.sparse == false);
    for i in 0 .. c.entries as usize  {
       if c.codeword_lengths[i] == NO_CODE {
           continue;
@@ -2091,7 +3613,7 @@ fn codebook_decode_scalar_raw(f: &mut Vorbis, c: &Codebook) -> i32
       }
    }
 
-   error(f, VorbisError::InvalidStream);
+   error(f, VorbisErrorKind::InvalidStream);
    f.valid_bits = 0;
    return -1;
 }
@@ -2128,17 +3650,18 @@ unsafe fn codebook_decode_deinterleave_repeat(f: &mut Vorbis, c: &Codebook, outp
 
    // type 0 is only legal in a scalar context
    if c.lookup_type == 0 {
-     return error(f, VorbisError::InvalidStream);
+     return error(f, VorbisErrorKind::InvalidStream);
    } 
    while total_decode > 0 {
       let mut last : f32 = 0.0;
       z = codebook_decode_scalar(f, c);
-      assert!(c.sparse == false || z < c.sorted_entries);
+      // as in codebook_decode_start()
+      debug_assert!(c.sparse == false || z < c.sorted_entries);
       if z < 0 {
          if f.bytes_in_seg == 0 && f.last_seg == true {
             return false;
          }
-         return error(f, VorbisError::InvalidStream);
+         return error(f, VorbisErrorKind::InvalidStream);
       }
 
       // if this will take us off the end of the buffers, stop short!
@@ -2146,7 +3669,11 @@ unsafe fn codebook_decode_deinterleave_repeat(f: &mut Vorbis, c: &Codebook, outp
       // buffer (len*ch), our current offset within it (p_inter*ch)+(c_inter),
       // and the length we'll be using (effective)
       if c_inter + p_inter*ch + effective > len * ch {
-         effective = len*ch - (p_inter*ch - c_inter);
+         effective = len*ch - (p_inter*ch + c_inter);
+      }
+      // an earlier partition already ran up to the end
+      if effective <= 0 {
+         break;
       }
 
       {
@@ -2202,13 +3729,369 @@ fn compute_window(n: i32, window: &mut [f32])
    }
 }
 
-fn compute_samples(mask: i32, output: &mut [i16], data: &AudioBufferSlice<f32>, len: usize)
+// adds 'gain' of a channel at 'speaker' to the gains of input 'input' for
+// the speakers of layout 'to', folding it into the nearest ones the layout has
+fn route_speaker(speaker: Speaker, gain: f32, to: &[Speaker], gains: &mut [f32], from: usize, input: usize)
 {
-   const BUFFER_SIZE : usize = 32;
-   let mut buffer: [f32; BUFFER_SIZE];
-   let mut n = BUFFER_SIZE;
-   let mut o : usize = 0;
-   
+   use Speaker::*;
+   if let Some(o) = to.iter().position(|&s| s == speaker) {
+      gains[o * from + input] += gain;
+      return;
+   }
+   let half = gain * MIX_3DB;
+   match speaker {
+      FrontCenter => {
+         route_speaker(FrontLeft, half, to, gains, from, input);
+         route_speaker(FrontRight, half, to, gains, from, input);
+      }
+      FrontLeft | FrontRight => route_speaker(FrontCenter, half, to, gains, from, input),
+      SideLeft  => route_speaker(if to.contains(&RearLeft)  {RearLeft}  else {FrontLeft},  half, to, gains, from, input),
+      SideRight => route_speaker(if to.contains(&RearRight) {RearRight} else {FrontRight}, half, to, gains, from, input),
+      RearLeft  => route_speaker(if to.contains(&SideLeft)  {SideLeft}  else {FrontLeft},  half, to, gains, from, input),
+      RearRight => route_speaker(if to.contains(&SideRight) {SideRight} else {FrontRight}, half, to, gains, from, input),
+      RearCenter => {
+         route_speaker(RearLeft, half, to, gains, from, input);
+         route_speaker(RearRight, half, to, gains, from, input);
+      }
+      Lfe => {}
+   }
+}
+
+// the ChannelMix::Standard gains from 'from' to 'to' channels; leaves 'gains'
+// empty if either count has no standard layout
+fn standard_channel_mix(from: usize, to: usize, gains: &mut Vec<f32>)
+{
+   if from == 0 || from > 8 || to == 0 || to > 8 {
+      return;
+   }
+   gains.resize(from * to, 0.0);
+   for (input, &speaker) in VORBIS_CHANNEL_ORDER[from].iter().enumerate() {
+      route_speaker(speaker, 1.0, VORBIS_CHANNEL_ORDER[to], gains, from, input);
+   }
+
+   // scale down so the loudest output can't clip
+   let mut loudest: f32 = 1.0;
+   for row in gains.chunks(from) {
+      loudest = loudest.max(row.iter().sum());
+   }
+   for gain in gains.iter_mut() {
+      *gain /= loudest;
+   }
+}
+
+// works out f.mix_gains for 'channels' output channels. false if the
+// decoded channels are copied (or coerced the original way) instead
+fn prepare_channel_mix(f: &mut Vorbis, channels: usize) -> bool
+{
+   let from = f.channels as usize;
+   if f.mix_channels != Some((from, channels)) {
+      f.mix_gains.clear();
+      match f.channel_mix {
+         ChannelMix::Matrix { inputs, outputs, ref gains } if inputs == from && outputs == channels => {
+            f.mix_gains.extend_from_slice(gains);
+         }
+         ChannelMix::Original => {}
+         _ => if from != channels {
+            standard_channel_mix(from, channels, &mut f.mix_gains);
+         }
+      }
+      f.mix_channels = Some((from, channels));
+   }
+   return f.mix_gains.is_empty() == false;
+}
+
+// what the output conversions for 'channels' channels need: the mix gains,
+// if there's a mix, and the ditherer, if there's one
+fn output_mix(f: &mut Vorbis, channels: usize) -> (Option<&[f32]>, &mut Option<Ditherer>)
+{
+   if prepare_channel_mix(f, channels) == false {
+      return (None, &mut f.dither);
+   }
+   return (Some(&f.mix_gains), &mut f.dither);
+}
+
+// mixes 'len' samples of 'data' through 'gains', one row of gains per output
+// channel, and hands every output sample to 'store(channel, index, value)'
+fn mix_samples<F: FnMut(usize, usize, f32)>(gains: &[f32], data: &AudioBufferSlice<f32>, len: usize, mut store: F)
+{
+   let from = data.channel_count();
+   for i in 0 .. len {
+      for (c, row) in gains.chunks(from).enumerate() {
+         let mut value = 0.0;
+         for j in 0 .. from {
+            if row[j] != 0.0 {
+               value += row[j] * data[(j, i)];
+            }
+         }
+         store(c, i, value);
+      }
+   }
+}
+
+// converts 'len' samples of 'data' to 'channels' channels of T by the
+// coercion rules: through 'gains' if there's a mix, else the decoded
+// channels as they are, then silence. hands every output sample to
+// 'store(channel, index, sample)'
+fn convert_samples<T: Sample, F: FnMut(usize, usize, T)>(channels: usize, data: &AudioBufferSlice<f32>, gains: Option<&[f32]>, dither: &mut Option<Ditherer>, len: usize, mut store: F)
+{
+   if let Some(gains) = gains {
+      mix_samples(gains, data, len, |c, i, value| store(c, i, dithered(dither, c, value)));
+      return;
+   }
+   let silence = T::from_f32(0.0);
+   for c in 0 .. channels {
+      if c < data.channel_count() {
+         for (i, &value) in data[c][.. len].iter().enumerate() {
+            store(c, i, dithered(dither, c, value));
+         }
+      } else {
+         for i in 0 .. len {
+            store(c, i, silence);
+         }
+      }
+   }
+}
+
+// 'value' as a T, through the ditherer if there is one and T has steps
+fn dithered<T: Sample>(dither: &mut Option<Ditherer>, channel: usize, value: f32) -> T
+{
+   match *dither {
+      Some(ref mut d) if T::STEP != 0.0 => T::from_f32(d.apply(channel, value, T::STEP)),
+      _ => T::from_f32(value),
+   }
+}
+
+// 'n' samples at 'from' samples per second, counted at 'to', rounded up
+fn rescale(n: u64, to: u32, from: u32) -> u64
+{
+   if from == 0 {
+      return n;
+   }
+   return (n as u128 * to as u128).div_ceil(from as u128) as u64;
+}
+
+// whether the get_samples_* functions go through the resampler, which
+// they don't at the output rate
+fn resampling(f: &Vorbis) -> bool
+{
+   match f.resampler {
+      Some(ref r) => r.rate != f.sample_rate,
+      None => false,
+   }
+}
+
+// decoding carries on somewhere else than where the resampler got to
+fn resample_forget(f: &mut Vorbis)
+{
+   if let Some(ref mut r) = f.resampler {
+      r.link = None;
+   }
+}
+
+// decoding carries on somewhere else, so the dither noise starts over from
+// its seed
+fn dither_forget(f: &mut Vorbis)
+{
+   if let Some(ref mut d) = f.dither {
+      d.channels.clear();
+   }
+}
+
+// in-link positions from here on, at either rate, only come of a damaged
+// granule; the resampler waits for a page with a sane one
+const RESAMPLE_POSITION_LIMIT : u64 = 1 << 56;
+
+// moves the decoded samples in the channel buffers into the resampler,
+// which starts over unless they follow on from what it has
+fn resample_input(f: &mut Vorbis)
+{
+   let start = f.channel_buffer_start as usize;
+   let end = f.channel_buffer_end as usize;
+   let link_start = output_position(f, f.link_sample_start);
+   let (link, input_rate, channels) = (f.link_page_start, f.sample_rate, f.channels as usize);
+   let rate = match f.resampler {
+      Some(ref r) => r.rate,
+      None => return,
+   };
+   // where the samples start in the link. after a page lost to damage or
+   // skipped for its CRC that's unknown until a page gives it again, and
+   // the samples until then are dropped, as a seek drops what it skips
+   let position = match f.current_loc.checked_sub((end - start) as u64) {
+      Some(position) if f.current_loc_valid == true && position < RESAMPLE_POSITION_LIMIT
+         && rescale(position, rate, input_rate) < RESAMPLE_POSITION_LIMIT => position,
+      _ => {
+         resample_forget(f);
+         f.channel_buffer_start = f.channel_buffer_end;
+         return;
+      }
+   };
+   let r = match f.resampler {
+      Some(ref mut r) => r,
+      None => return,
+   };
+   let history_end = r.history_start.checked_add(r.history.first().map_or(0, |history| history.len() as u64));
+   if r.link != Some(link) || r.input_rate != input_rate || r.history.len() != channels || history_end != Some(position) {
+      r.start(link, input_rate, channels, position, link_start);
+   }
+   for (history, buffer) in r.history.iter_mut().zip(f.channel_buffers.iter()) {
+      history.extend_from_slice(&buffer[start .. end]);
+   }
+   f.channel_buffer_start = 0;
+   f.channel_buffer_end = 0;
+}
+
+// resamples what the resampler has input for into the channel buffers, up
+// to their size. with 'flush', the link has ended: the output runs on to
+// its end, with silence after the last decoded sample. false if there was
+// nothing to put out
+fn resample_output(f: &mut Vorbis, flush: bool) -> bool
+{
+   if resampling(f) == false {
+      return false;
+   }
+   let (link, input_rate) = (f.link_page_start, f.sample_rate);
+   let limit = f.channel_buffers.first().map_or(0, |buffer| buffer.len());
+   let r = match f.resampler {
+      Some(ref mut r) if r.link == Some(link) => r,
+      _ => return false,
+   };
+   let (before, after) = r.reach(input_rate);
+   let history_end = match r.history_start.checked_add(r.history[0].len() as u64) {
+      Some(history_end) => history_end,
+      None => {
+         r.link = None;
+         return false;
+      }
+   };
+   let mut m = 0;
+   while m < limit {
+      let position = r.next_out as u128 * input_rate as u128;
+      let sample = (position / r.rate as u128) as u64;
+      if flush == true && position >= history_end as u128 * r.rate as u128 {
+         break;
+      }
+      if flush == false && sample.saturating_add(after) >= history_end {
+         break;
+      }
+
+      r.set_weights((position % r.rate as u128) as f64 / r.rate as f64, input_rate);
+      let first = sample as i128 - before as i128 - r.history_start as i128;
+      for (history, buffer) in r.history.iter().zip(f.channel_buffers.iter_mut()) {
+         let mut value = 0.0;
+         for (k, &weight) in r.weights.iter().enumerate() {
+            let i = first + k as i128;
+            if i >= 0 && (i as usize) < history.len() {
+               value += weight * history[i as usize];
+            }
+         }
+         buffer[m] = value;
+      }
+      m += 1;
+      r.next_out += 1;
+   }
+
+   // drop what no output sample needs any more
+   let needed = ((r.next_out as u128 * input_rate as u128 / r.rate as u128) as u64).saturating_sub(before);
+   if needed > r.history_start {
+      let drop = std::cmp::min(needed - r.history_start, r.history[0].len() as u64) as usize;
+      for history in r.history.iter_mut() {
+         history.drain(.. drop);
+      }
+      r.history_start += drop as u64;
+   }
+
+   f.channel_buffer_start = 0;
+   f.channel_buffer_end = m as i32;
+   return m > 0;
+}
+
+// the output-rate position of the next sample the resampler puts out, if
+// it's where get_samples_*() carry on from
+fn resampled_position(f: &Vorbis) -> Option<u64>
+{
+   match f.resampler {
+      Some(ref r) if r.link == Some(f.link_page_start) && resampling(f) == true => Some(r.link_start.saturating_add(r.next_out)),
+      _ => None,
+   }
+}
+
+// the output-rate position of stream-rate sample 'sample_number': all the
+// output of the links before its own, and its position in its link
+fn output_position(f: &Vorbis, sample_number: u64) -> u64
+{
+   let rate = match f.resampler {
+      Some(ref r) => r.rate,
+      None => return sample_number,
+   };
+   if sample_number == SAMPLE_UNKNOWN {
+      return sample_number;
+   }
+   let mut output_start = 0;
+   for (k, link) in f.links.iter().enumerate() {
+      match f.links.get(k + 1) {
+         Some(next) if next.sample_start <= sample_number => {
+            output_start += rescale(next.sample_start - link.sample_start, rate, link.sample_rate);
+         }
+         _ => return output_start + rescale(sample_number - link.sample_start, rate, link.sample_rate),
+      }
+   }
+   return rescale(sample_number, rate, f.sample_rate);
+}
+
+// where an output-rate sample is in the stream
+struct StreamPosition
+{
+   link_start: u64, // stream-rate position of the start of its link
+   output_link_start: u64, // and output-rate
+   input_rate: u32, // of the link
+   offset: u64, // output-rate position in the link
+   sample: u64, // stream-rate position in the link, rounded down
+}
+
+fn stream_position(f: &mut Vorbis, sample_number: u64) -> StreamPosition
+{
+   let mut p = StreamPosition {
+      link_start: 0,
+      output_link_start: 0,
+      input_rate: f.sample_rate,
+      offset: sample_number,
+      sample: sample_number,
+   };
+   let rate = match f.resampler {
+      Some(ref r) if f.push_mode == false => r.rate,
+      _ => return p,
+   };
+   // finds the links of a chained stream
+   stream_length(f);
+   for (k, link) in f.links.iter().enumerate() {
+      p.link_start = link.sample_start;
+      p.input_rate = link.sample_rate;
+      let next = match f.links.get(k + 1) {
+         Some(next) => next,
+         None => break,
+      };
+      let output_length = rescale(next.sample_start - link.sample_start, rate, link.sample_rate);
+      if sample_number < p.output_link_start + output_length {
+         break;
+      }
+      p.output_link_start += output_length;
+   }
+   p.offset = sample_number - p.output_link_start;
+   p.sample = if p.input_rate == 0 {
+      p.offset
+   } else {
+      (p.offset as u128 * p.input_rate as u128 / rate as u128) as u64
+   };
+   return p;
+}
+
+fn compute_samples(mask: i32, output: &mut [i16], data: &AudioBufferSlice<f32>, len: usize, channel: usize, dither: &mut Option<Ditherer>)
+{
+   const BUFFER_SIZE : usize = 32;
+   let mut buffer: [f32; BUFFER_SIZE];
+   let mut n = BUFFER_SIZE;
+   let mut o : usize = 0;
+   
    while o < len {
       buffer = [0.0; BUFFER_SIZE];
       
@@ -2223,14 +4106,14 @@ fn compute_samples(mask: i32, output: &mut [i16], data: &AudioBufferSlice<f32>,
          }
       }
       for i in 0 .. n  {
-         output[ (o+i) as usize] = convert_to_i16(buffer[i]);
+         output[ (o+i) as usize] = dithered(dither, channel, buffer[i]);
       }
        
        o += BUFFER_SIZE;
    }
 }
 
-fn compute_stereo_samples(output: &mut [i16], data: &AudioBufferSlice<f32>, len: usize)
+fn compute_stereo_samples(output: &mut [i16], data: &AudioBufferSlice<f32>, len: usize, dither: &mut Option<Ditherer>)
 {
    const BUFFER_SIZE : usize = 32;
    
@@ -2266,7 +4149,7 @@ fn compute_stereo_samples(output: &mut [i16], data: &AudioBufferSlice<f32>, len:
       
       
       for i in 0 .. n << 1 {
-         output[ (o2+i) as usize ] = convert_to_i16(buffer[i]);
+         output[ (o2+i) as usize ] = dithered(dither, i & 1, buffer[i]);
       }
        
        o += BUFFER_SIZE >> 1;
@@ -2279,12 +4162,28 @@ fn compute_stereo_samples(output: &mut [i16], data: &AudioBufferSlice<f32>, len:
 // the specified sample. after calling stb_vorbis_seek(), the next call to
 // stb_vorbis_get_samples_* will start with the specified sample. If you
 // do not need to seek to EXACTLY the target sample when using get_samples_*,
-// you can also use seek_frame().
-pub fn stb_vorbis_seek_frame(f: &mut Vorbis, sample_number: u32) -> bool
+// you can also use seek_frame(). with an output rate set, 'sample_number'
+// counts samples at that rate.
+pub fn stb_vorbis_seek_frame(f: &mut Vorbis, sample_number: u64) -> bool
+{
+   let p = stream_position(f, sample_number);
+   return seek_frame(f, p.link_start + p.sample);
+}
+
+// stb_vorbis_seek_frame() at the stream's rate
+fn seek_frame(f: &mut Vorbis, sample_number: u64) -> bool
 {
    if f.push_mode { 
-       return error(f, VorbisError::InvalidApiMixing);
+       return error(f, VorbisErrorKind::InvalidApiMixing);
    }
+   resample_forget(f);
+   dither_forget(f);
+
+   // pick the link of a chained stream, from here on it's all within that
+   let sample_number = match seek_to_link(f, sample_number) {
+       Ok(sample_number) => sample_number,
+       Err(why)          => return error(f, why),
+   };
 
    // fast page-level search
    let result = seek_to_sample_coarse(f, sample_number);
@@ -2293,12 +4192,18 @@ pub fn stb_vorbis_seek_frame(f: &mut Vorbis, sample_number: u32) -> bool
        return false;
    }
 
-   assert!(f.current_loc_valid == true);
-   assert!(f.current_loc <= sample_number);
-
    // linear search for the relevant packet
-   let max_frame_samples = ((f.blocksize_1*3 - f.blocksize_0) >> 2) as u32;
+   let max_frame_samples = ((f.blocksize_1*3 - f.blocksize_0) >> 2) as i64;
    while f.current_loc < sample_number {
+      if let Some((_, len)) = f.pending_frame {
+         // the first frame of the link, already decoded
+         if f.current_loc.saturating_add(len as u64) > sample_number {
+            return true;
+         }
+         f.current_loc += len as u64;
+         f.pending_frame = None;
+         continue;
+      }
       let mut left_start = 0; 
       let mut left_end = 0;
       let mut right_start = 0;
@@ -2306,82 +4211,336 @@ pub fn stb_vorbis_seek_frame(f: &mut Vorbis, sample_number: u32) -> bool
       let mut mode = 0;
       let frame_samples: i32;
       if peek_decode_initial(f, &mut left_start, &mut left_end, &mut right_start, &mut right_end, &mut mode) == false{
-         return error(f, VorbisError::SeekFailed);
+         return error(f, VorbisErrorKind::SeekFailed);
       }
       // calculate the number of samples returned by the next frame
       frame_samples = right_start - left_start;
-      if f.current_loc as i32 + frame_samples > sample_number as i32 {
+      let frame_end = f.current_loc.saturating_add(frame_samples as u64);
+      if frame_end > sample_number {
          return true; // the next frame will contain the sample
-      } else if f.current_loc as i32 + frame_samples + max_frame_samples as i32 > sample_number as i32 {
+      } else if frame_end.saturating_add(max_frame_samples as u64) > sample_number {
          // there's a chance the frame after this could contain the sample
          vorbis_pump_first_frame(f);
       } else {
          // this frame is too early to be relevant
-         f.current_loc += frame_samples as u32;
+         f.current_loc += frame_samples as u64;
          f.previous_length = 0;
          maybe_start_packet(f);
          flush_packet(f);
       }
    }
-   // the next frame will start with the sample
-   assert!(f.current_loc == sample_number);
+   // the next frame should start with the sample
+   if f.current_loc != sample_number {
+      return error(f, VorbisErrorKind::SeekFailed);
+   }
    return true;
 }
 
 // get the last error detected (clears it, too)
-pub fn stb_vorbis_get_error(f: &mut Vorbis) -> VorbisError
+pub fn stb_vorbis_get_error(f: &mut Vorbis) -> VorbisErrorKind
 {
    let e = f.error;
-   f.error = VorbisError::NoError;
+   f.error = VorbisErrorKind::NoError;
    return e;
 }
 
+// stb_vorbis_get_error(), with where in the stream the decoder is: for an
+// InvalidPageCrc error, the start of the corrupt page. None if there's no
+// error
+pub fn stb_vorbis_get_last_error(f: &mut Vorbis) -> Option<VorbisError>
+{
+   let e = stb_vorbis_get_error(f);
+   if e == VorbisErrorKind::NoError {
+      return None;
+   }
+   return Some(vorbis_error(f, e));
+}
+
 // this function is equivalent to stb_vorbis_seek(f,0)
 pub fn stb_vorbis_seek_start(f: &mut Vorbis)
 {
    if f.push_mode { 
-       error(f, VorbisError::InvalidApiMixing); 
+       error(f, VorbisErrorKind::InvalidApiMixing); 
        return;
    }
-   
+
+   if f.link_page_start != 0 {
+      // back to the first link of a chained stream
+      set_file_offset(f, 0);
+      if unsafe { start_next_link(f) } == false {
+         return;
+      }
+      f.link_sample_start = 0;
+   }
+   seek_link_start(f);
+}
+
+// go back to the start of the current link
+fn seek_link_start(f: &mut Vorbis)
+{
    let offset = f.first_audio_page_offset;
    set_file_offset(f, offset);
    f.previous_length = 0;
    f.first_decode = true;
    f.next_seg = -1;
+   f.page_lost = false;
+   f.drop_continued = false;
+   f.silence_left = 0;
+   resample_forget(f);
+   dither_forget(f);
    vorbis_pump_first_frame(f);
 }
 
-// these functions return the total length of the vorbis stream
-#[allow(unreachable_code, unused_variables)]
+// these functions return the total length of the vorbis stream. the links
+// of a chained stream are timed each at its own rate
 pub fn stb_vorbis_stream_length_in_seconds(f: &mut Vorbis) -> f32
 {
-   panic!("EXPECTED PANIC: need ogg sample that will trigger this panic");
-   return stb_vorbis_stream_length_in_samples(f) as f32 / f.sample_rate as f32;
+   let length = stream_length(f);
+   if f.links.is_empty() {
+      return (length as f64 / f.sample_rate as f64) as f32;
+   }
+   let mut seconds = 0.0;
+   for (k, link) in f.links.iter().enumerate() {
+      let end = match f.links.get(k + 1) {
+         Some(next) => next.sample_start,
+         None => length,
+      };
+      if link.sample_rate != 0 {
+         seconds += end.saturating_sub(link.sample_start) as f64 / link.sample_rate as f64;
+      }
+   }
+   return seconds as f32;
 }
 
 // this function returns the offset (in samples) from the beginning of the
 // file that will be returned by the next decode, if it is known, or -1
 // otherwise. after a flush_pushdata() call, this may take a while before
-// it becomes valid again.
-// NOT WORKING YET after a seek with PULLDATA API
-#[allow(unreachable_code, unused_variables)]
-pub fn stb_vorbis_get_sample_offset(f: &mut Vorbis) -> i32
+// it becomes valid again. in pull mode, samples already decoded but not yet
+// returned by get_samples_*() count as not returned, so after a
+// stb_vorbis_seek() this is exactly the sample that was asked for. in a
+// chained stream the samples of all earlier links count too; in push mode
+// they're unknown if a new link starts after a flush. with an output rate
+// set, this counts samples at that rate.
+pub fn stb_vorbis_get_sample_offset(f: &mut Vorbis) -> i64
 {
-   panic!("EXPECTED PANIC: need ogg sample that will trigger this panic");
-   if f.current_loc_valid == true {
-      return f.current_loc as i32;
+   if f.current_loc_valid == true && f.link_sample_start != SAMPLE_UNKNOWN {
+      let buffered = if f.push_mode { 0 } else { f.channel_buffer_end - f.channel_buffer_start };
+      if let Some(position) = resampled_position(f) {
+         // what's buffered is resampled already
+         return position as i64 - buffered as i64;
+      }
+      let position = f.link_sample_start.wrapping_add(f.current_loc) as i64 - buffered as i64;
+      return if position < 0 { position } else { output_position(f, position as u64) as i64 };
    } else {
       return -1;
    }
 }
 
+// returns true (once) if the decoder has moved on to a new link of a chained
+// stream since the last call: a new logical stream with headers of its own.
+// the channel count and sample rate may have changed, so check
+// stb_vorbis_get_info() and stb_vorbis_get_comment() again. after
+// get_frame_*() the frame just returned is the first of the new link.
+// get_samples_*() stop short at the end of a link and move on to the next
+// before returning, so the change shows up before any of its samples do;
+// the same goes for the next frame decoded in push mode.
+pub fn stb_vorbis_link_changed(f: &mut Vorbis) -> bool
+{
+   let changed = f.link_changed;
+   f.link_changed = false;
+   return changed;
+}
+
+// the logical streams announced by the first pages of the current link, in
+// file order. a plain vorbis file has just one; a multiplexed one also lists
+// the video, skeleton etc. streams whose pages are skipped while decoding
+pub fn stb_vorbis_get_streams(f: &Vorbis) -> &[OggStream]
+{
+   &f.streams
+}
+
+// serial number of the vorbis stream being decoded
+pub fn stb_vorbis_get_serial(f: &Vorbis) -> u32
+{
+   return f.serial;
+}
+
+// switch to another vorbis stream of a multiplexed file, one of those
+// stb_vorbis_get_streams() lists, and start decoding it from the start of
+// the current link. the choice sticks for later links that have a stream
+// with the same serial number. not available in push mode; open the stream
+// with stb_vorbis_open_pushdata_stream() instead.
+pub fn stb_vorbis_select_stream(f: &mut Vorbis, serial: u32) -> bool
+{
+   if f.push_mode { return error(f, VorbisErrorKind::InvalidApiMixing); }
+   if f.streams.iter().any(|s| s.serial == serial && s.vorbis == true) == false {
+      return error(f, VorbisErrorKind::IncorrectStreamSerialNumber);
+   }
+
+   f.wanted_serial = Some(serial);
+   // the links have to be measured again with the new stream
+   f.total_samples = 0;
+   f.links.clear();
+
+   let link_page_start = f.link_page_start;
+   let link_sample_start = f.link_sample_start;
+   set_file_offset(f, link_page_start);
+   if unsafe { start_next_link(f) } == false {
+      return false;
+   }
+   f.link_sample_start = link_sample_start;
+   f.link_changed = false;
+   resample_forget(f);
+   dither_forget(f);
+   return vorbis_pump_first_frame(f);
+}
+
+// choose how the functions that take a channel count mix the decoded
+// channels into that many (see the channel coercion rules). returns false,
+// leaving the mix as it was, if a matrix doesn't hold inputs*outputs gains
+pub fn stb_vorbis_set_channel_mix(f: &mut Vorbis, mix: ChannelMix) -> bool
+{
+   if let ChannelMix::Matrix { inputs, outputs, ref gains } = mix {
+      if inputs == 0 || outputs == 0 || gains.len() != inputs * outputs {
+         return false;
+      }
+   }
+   f.channel_mix = mix;
+   f.mix_channels = None;
+   return true;
+}
+
+pub fn stb_vorbis_get_channel_mix(f: &Vorbis) -> &ChannelMix
+{
+   &f.channel_mix
+}
+
+// have the get_samples_* functions put out 'rate' samples per second,
+// whatever the rate of the stream (or of each link of a chained one), or
+// go back to the stream's own rate with 0. seeking, the sample offset and
+// the length in samples count at that rate from then on. get_frame_*()
+// still return frames as they are decoded, so don't mix them with
+// get_samples_*(). set it before decoding, or seek after: what's buffered
+// at the old rate is dropped.
+pub fn stb_vorbis_set_output_rate(f: &mut Vorbis, rate: u32, quality: ResampleQuality)
+{
+   if resampling(f) == true {
+      f.channel_buffer_start = f.channel_buffer_end;
+   }
+   f.resampler = if rate == 0 { None } else { Some(Resampler::new(rate, quality)) };
+   if resampling(f) == true && f.push_mode == false && f.channel_buffer_start < f.channel_buffer_end {
+      resample_input(f);
+   }
+}
+
+// the rate the get_samples_* functions put out
+pub fn stb_vorbis_get_output_rate(f: &Vorbis) -> u32
+{
+   match f.resampler {
+      Some(ref r) => r.rate,
+      None => f.sample_rate,
+   }
+}
+
+// dither the integer output (the short functions, and get_samples_* and
+// get_frame_* with i16, I24 or u8 samples) with 'dither', the noise starting
+// from 'seed': the same seed gives the same samples, decode after decode.
+// the noise starts over from the seed on every seek and after every page
+// skipped for its CRC, so the samples after either don't depend on what was
+// decoded before. each output channel is dithered on its own, so how the
+// samples are asked for makes no difference. Dither::Off goes back to
+// rounding to the nearest step. i32 samples are never dithered, whatever
+// the setting: their steps are well below the decoder's precision, so they
+// are always rounded as they are.
+pub fn stb_vorbis_set_dither(f: &mut Vorbis, dither: Dither, seed: u32)
+{
+   f.dither = if dither == Dither::Off { None } else { Some(Ditherer::new(dither, seed)) };
+}
+
+pub fn stb_vorbis_get_dither(f: &Vorbis) -> Dither
+{
+   match f.dither {
+      Some(ref d) => d.dither,
+      None => Dither::Off,
+   }
+}
+
+// check the CRC of every page read while decoding in pull and memory
+// modes, something stb_vorbis otherwise only does in push mode when it
+// resynchronizes. the headers are read when the file is opened, so this
+// applies from the audio pages on. with CrcCheck::Skip a corrupt page is
+// passed over, counted and handed to the callback, and the frames that
+// needed it are dropped, so a damaged file has gaps instead of noise; with
+// CrcCheck::Fail decoding stops at it, and stb_vorbis_get_last_error()
+// says where it is. checking reads every page twice.
+pub fn stb_vorbis_set_crc_check(f: &mut Vorbis, check: CrcCheck)
+{
+   f.crc_check = check;
+}
+
+pub fn stb_vorbis_get_crc_check(f: &Vorbis) -> CrcCheck
+{
+   return f.crc_check;
+}
+
+// the number of pages CrcCheck::Skip has skipped
+pub fn stb_vorbis_get_corrupt_pages(f: &Vorbis) -> u64
+{
+   return f.corrupt_pages;
+}
+
+// call 'callback' with every page CrcCheck::Skip skips, as it does; None
+// for no callback
+pub fn stb_vorbis_set_corrupt_page_callback(f: &mut Vorbis, callback: Option<CorruptPageCallback>)
+{
+   f.corrupt_page_callback = callback;
+}
+
+// error recovery in pull and memory modes: when on, a packet that can't be
+// decoded no longer ends the stream. decoding picks up at the next good page
+// instead, and the samples lost with the damage are put out as silence, as
+// far as the pages' granule positions tell how many there were. off by
+// default. a stream that ends, or a page failed by CrcCheck::Fail, still
+// stops decoding
+pub fn stb_vorbis_set_error_recovery(f: &mut Vorbis, recover: bool)
+{
+   f.recover = recover;
+}
+
+pub fn stb_vorbis_get_error_recovery(f: &Vorbis) -> bool
+{
+   return f.recover;
+}
+
+// the samples per channel of silence error recovery has put in so far
+pub fn stb_vorbis_get_concealed_samples(f: &Vorbis) -> u64
+{
+   return f.concealed_samples;
+}
+
+// call 'callback' every time error recovery gets past a damaged packet,
+// before the silence is put out; None for no callback
+pub fn stb_vorbis_set_concealment_callback(f: &mut Vorbis, callback: Option<ConcealmentCallback>)
+{
+   f.concealment_callback = callback;
+}
+
+// get ogg comments
+pub fn stb_vorbis_get_comment(f: &Vorbis) -> &VorbisComments
+{
+   &f.comments
+}
+
 // get general information about the file
 pub fn stb_vorbis_get_info(f: &Vorbis) -> VorbisInfo
 {
    VorbisInfo {
        channels: f.channels,
        sample_rate: f.sample_rate,
+       layout: ChannelLayout::from_channels(f.channels as usize),
+       bitrate_maximum: f.bitrate_maximum,
+       bitrate_nominal: f.bitrate_nominal,
+       bitrate_minimum: f.bitrate_minimum,
        max_frame_size: f.blocksize_1 >> 1
    }
 }
@@ -2396,10 +4555,8 @@ pub fn stb_vorbis_get_info(f: &Vorbis) -> VorbisInfo
 // call stb_vorbis_flush_pushdata(), then start calling decoding, then once
 // decoding is returning you data, call stb_vorbis_get_sample_offset, and
 // if you don't like the result, seek your file again and repeat.
-#[allow(unreachable_code, unused_variables)]
 pub fn stb_vorbis_flush_pushdata(f: &mut Vorbis)
 {
-   panic!("EXPECTED PANIC: need ogg sample that will trigger this panic");
    f.previous_length = 0;
    f.page_crc_tests  = 0;
    f.discard_samples_deferred = 0;
@@ -2408,31 +4565,316 @@ pub fn stb_vorbis_flush_pushdata(f: &mut Vorbis)
    f.samples_output = 0;
    f.channel_buffer_start = 0;
    f.channel_buffer_end = 0;
+   resample_forget(f);
+   dither_forget(f);
 }
 
-// create an ogg vorbis decoder from an ogg vorbis stream in memory (note
-// this must be the entire stream!). on failure, returns NULL and sets *error
-pub unsafe fn stb_vorbis_open_memory(data: &[u8], error: &mut VorbisError) -> Option<Vorbis>
+// point the decoder at a memory block and parse the headers. the caller must
+// keep 'data' alive (and unmoved) for as long as the returned Vorbis is used;
+// VorbisDecoder is the safe way to guarantee that.
+unsafe fn open_memory_raw(mut p: Vorbis, data: *const u8, len: usize) -> Result<Vorbis, VorbisError>
 {
-   if data.len() == 0 {
-     return None;       
+   if len == 0 {
+     return Err(VorbisError::new(VorbisErrorKind::UnexpectedEof));
    } 
    
-   let mut p = Vorbis::new();
-   
-   p.stream_len = data.len() as u32;
-   p.stream = data.as_ptr();
-   p.stream_end = data.as_ptr().offset(data.len() as isize);
+   p.stream_len = len as u64;
+   p.stream = data;
+   p.stream_end = data.offset(len as isize);
    p.stream_start = p.stream;
    p.push_mode = false;
    
    if start_decoder(&mut p) == true {
         vorbis_pump_first_frame(&mut p);
-        return Some(p);
+        return Ok(p);
    }
    
-    *error = p.error;
-    return None;
+   let kind = p.error;
+   return Err(vorbis_error(&mut p, kind));
+}
+
+// create an ogg vorbis decoder from an ogg vorbis stream in memory (note
+// this must be the entire stream!). on failure, returns Err.
+pub fn stb_vorbis_open_memory(data: &[u8]) -> Result<VorbisDecoder<'_>, VorbisError>
+{
+   VorbisDecoder::new(data)
+}
+
+// same as above, with caps on what the stream's headers may make the
+// decoder allocate; a stream asking for more fails with OutOfMem
+pub fn stb_vorbis_open_memory_with_limits(data: &[u8], limits: SetupLimits) -> Result<VorbisDecoder<'_>, VorbisError>
+{
+   VorbisDecoder::with_limits(data, limits)
+}
+
+/// storage owned by a memory decoder. the decoder reads straight out of the
+/// heap block, which stays put when the owner (and the Vorbis) is moved.
+enum StreamData {
+    Vec(Vec<u8>),
+    Boxed(Box<[u8]>),
+    Shared(Arc<[u8]>),
+}
+
+impl StreamData {
+    fn as_slice(&self) -> &[u8] {
+        match *self {
+            StreamData::Vec(ref data)    => data,
+            StreamData::Boxed(ref data)  => data,
+            StreamData::Shared(ref data) => data,
+        }
+    }
+}
+
+/// safe decoder for an ogg vorbis stream held in memory.
+///
+/// `VorbisDecoder<'a>` borrows the data for `'a`, so the buffer can't be
+/// dropped while the decoder still reads from it. the owning variant
+/// (`OwnedVorbisDecoder`, created by `from_vec`, `from_boxed_slice` or
+/// `from_arc`) keeps the data alive itself.
+///
+/// the decoder derefs to `Vorbis` for read access; the methods below mirror
+/// the `stb_vorbis_*` functions. a `VorbisDecoder<'static>` can also be passed
+/// as `&mut Vorbis` directly.
+pub struct VorbisDecoder<'a> {
+    vorbis: Vorbis,
+    _data: PhantomData<&'a [u8]>,
+}
+
+/// memory decoder that owns its data
+pub type OwnedVorbisDecoder = VorbisDecoder<'static>;
+
+impl<'a> VorbisDecoder<'a> {
+    /// create a decoder reading from 'data' (the entire stream)
+    pub fn new(data: &'a [u8]) -> Result<VorbisDecoder<'a>, VorbisError> {
+        Self::with_limits(data, SetupLimits::default())
+    }
+
+    /// the same, with caps on what the headers may make it allocate
+    pub fn with_limits(data: &'a [u8], limits: SetupLimits) -> Result<VorbisDecoder<'a>, VorbisError> {
+        let mut p = Vorbis::new();
+        p.limits = limits;
+        let vorbis = unsafe { try!(open_memory_raw(p, data.as_ptr(), data.len())) };
+        Ok(VorbisDecoder { vorbis: vorbis, _data: PhantomData })
+    }
+
+    pub fn get_info(&self) -> VorbisInfo {
+        stb_vorbis_get_info(&self.vorbis)
+    }
+
+    pub fn get_comment(&self) -> &VorbisComments {
+        stb_vorbis_get_comment(&self.vorbis)
+    }
+
+    pub fn get_error(&mut self) -> VorbisErrorKind {
+        stb_vorbis_get_error(&mut self.vorbis)
+    }
+
+    pub fn get_last_error(&mut self) -> Option<VorbisError> {
+        stb_vorbis_get_last_error(&mut self.vorbis)
+    }
+
+    pub fn link_changed(&mut self) -> bool {
+        stb_vorbis_link_changed(&mut self.vorbis)
+    }
+
+    pub fn get_streams(&self) -> &[OggStream] {
+        stb_vorbis_get_streams(&self.vorbis)
+    }
+
+    pub fn set_channel_mix(&mut self, mix: ChannelMix) -> bool {
+        stb_vorbis_set_channel_mix(&mut self.vorbis, mix)
+    }
+
+    pub fn get_channel_mix(&self) -> &ChannelMix {
+        stb_vorbis_get_channel_mix(&self.vorbis)
+    }
+
+    pub fn set_output_rate(&mut self, rate: u32, quality: ResampleQuality) {
+        stb_vorbis_set_output_rate(&mut self.vorbis, rate, quality)
+    }
+
+    pub fn get_output_rate(&self) -> u32 {
+        stb_vorbis_get_output_rate(&self.vorbis)
+    }
+
+    pub fn set_dither(&mut self, dither: Dither, seed: u32) {
+        stb_vorbis_set_dither(&mut self.vorbis, dither, seed)
+    }
+
+    pub fn get_dither(&self) -> Dither {
+        stb_vorbis_get_dither(&self.vorbis)
+    }
+
+    pub fn set_crc_check(&mut self, check: CrcCheck) {
+        stb_vorbis_set_crc_check(&mut self.vorbis, check)
+    }
+
+    pub fn get_crc_check(&self) -> CrcCheck {
+        stb_vorbis_get_crc_check(&self.vorbis)
+    }
+
+    pub fn get_corrupt_pages(&self) -> u64 {
+        stb_vorbis_get_corrupt_pages(&self.vorbis)
+    }
+
+    pub fn set_corrupt_page_callback(&mut self, callback: Option<CorruptPageCallback>) {
+        stb_vorbis_set_corrupt_page_callback(&mut self.vorbis, callback)
+    }
+
+    pub fn set_error_recovery(&mut self, recover: bool) {
+        stb_vorbis_set_error_recovery(&mut self.vorbis, recover)
+    }
+
+    pub fn get_error_recovery(&self) -> bool {
+        stb_vorbis_get_error_recovery(&self.vorbis)
+    }
+
+    pub fn get_concealed_samples(&self) -> u64 {
+        stb_vorbis_get_concealed_samples(&self.vorbis)
+    }
+
+    pub fn set_concealment_callback(&mut self, callback: Option<ConcealmentCallback>) {
+        stb_vorbis_set_concealment_callback(&mut self.vorbis, callback)
+    }
+
+    pub fn get_serial(&self) -> u32 {
+        stb_vorbis_get_serial(&self.vorbis)
+    }
+
+    pub fn select_stream(&mut self, serial: u32) -> bool {
+        stb_vorbis_select_stream(&mut self.vorbis, serial)
+    }
+
+    pub fn get_file_offset(&mut self) -> u64 {
+        stb_vorbis_get_file_offset(&mut self.vorbis)
+    }
+
+    pub fn get_sample_offset(&mut self) -> i64 {
+        stb_vorbis_get_sample_offset(&mut self.vorbis)
+    }
+
+    pub fn stream_length_in_samples(&mut self) -> u64 {
+        stb_vorbis_stream_length_in_samples(&mut self.vorbis)
+    }
+
+    pub fn stream_length_in_seconds(&mut self) -> f32 {
+        stb_vorbis_stream_length_in_seconds(&mut self.vorbis)
+    }
+
+    pub fn seek_frame(&mut self, sample_number: u64) -> bool {
+        stb_vorbis_seek_frame(&mut self.vorbis, sample_number)
+    }
+
+    pub fn seek(&mut self, sample_number: u64) -> bool {
+        stb_vorbis_seek(&mut self.vorbis, sample_number)
+    }
+
+    pub fn seek_start(&mut self) {
+        stb_vorbis_seek_start(&mut self.vorbis)
+    }
+
+    pub fn get_frame_float(&mut self, channel_count: Option<&mut i32>, output: Option<&mut AudioBufferSlice<f32>>) -> i32 {
+        stb_vorbis_get_frame_float(&mut self.vorbis, channel_count, output)
+    }
+
+    pub fn get_frame_short(&mut self, num_c: i32, sample_buffer: &mut AudioBufferSlice<i16>) -> i32 {
+        stb_vorbis_get_frame_short(&mut self.vorbis, num_c, sample_buffer)
+    }
+
+    pub fn get_frame_short_interleaved(&mut self, channel_count: u32, buffer: &mut [i16]) -> i32 {
+        stb_vorbis_get_frame_short_interleaved(&mut self.vorbis, channel_count, buffer)
+    }
+
+    pub fn get_frame<T: Sample>(&mut self, channels: i32, buffer: &mut AudioBufferSlice<T>) -> i32 {
+        stb_vorbis_get_frame(&mut self.vorbis, channels, buffer)
+    }
+
+    pub fn get_frame_interleaved<T: Sample>(&mut self, channels: i32, buffer: &mut [T]) -> i32 {
+        stb_vorbis_get_frame_interleaved(&mut self.vorbis, channels, buffer)
+    }
+
+    pub fn get_samples_float(&mut self, channels: i32, buffer: &mut AudioBufferSlice<f32>) -> i32 {
+        stb_vorbis_get_samples_float(&mut self.vorbis, channels, buffer)
+    }
+
+    pub fn get_samples_float_interleaved(&mut self, channels: i32, buffer: &mut [f32]) -> i32 {
+        stb_vorbis_get_samples_float_interleaved(&mut self.vorbis, channels, buffer)
+    }
+
+    pub fn get_samples<T: Sample>(&mut self, channels: i32, buffer: &mut AudioBufferSlice<T>) -> i32 {
+        stb_vorbis_get_samples(&mut self.vorbis, channels, buffer)
+    }
+
+    pub fn get_samples_interleaved<T: Sample>(&mut self, channels: i32, buffer: &mut [T]) -> i32 {
+        stb_vorbis_get_samples_interleaved(&mut self.vorbis, channels, buffer)
+    }
+
+    pub fn get_samples_short_interleaved(&mut self, channel_count: u32, buffer: &mut [i16]) -> i32 {
+        stb_vorbis_get_samples_short_interleaved(&mut self.vorbis, channel_count, buffer)
+    }
+
+    pub fn write_wav<W: Write + Seek>(&mut self, out: W, format: WavFormat) -> Result<u64, VorbisError> {
+        stb_vorbis_write_wav(&mut self.vorbis, out, format)
+    }
+
+    pub fn write_wav_channels<W: Write + Seek>(&mut self, out: W, format: WavFormat, channels: i32) -> Result<u64, VorbisError> {
+        stb_vorbis_write_wav_channels(&mut self.vorbis, out, format, channels)
+    }
+}
+
+impl VorbisDecoder<'static> {
+    fn from_stream_data(data: StreamData, limits: SetupLimits) -> Result<OwnedVorbisDecoder, VorbisError> {
+        let mut p = Vorbis::new();
+        p.limits = limits;
+        let (ptr, len) = {
+            let slice = data.as_slice();
+            (slice.as_ptr(), slice.len())
+        };
+        p.stream_data = Some(data);
+        let vorbis = unsafe { try!(open_memory_raw(p, ptr, len)) };
+        Ok(VorbisDecoder { vorbis: vorbis, _data: PhantomData })
+    }
+
+    /// create a decoder that takes ownership of 'data'
+    pub fn from_vec(data: Vec<u8>) -> Result<OwnedVorbisDecoder, VorbisError> {
+        Self::from_stream_data(StreamData::Vec(data), SetupLimits::default())
+    }
+
+    /// the same, with caps on what the headers may make it allocate
+    pub fn from_vec_with_limits(data: Vec<u8>, limits: SetupLimits) -> Result<OwnedVorbisDecoder, VorbisError> {
+        Self::from_stream_data(StreamData::Vec(data), limits)
+    }
+
+    /// create a decoder that takes ownership of 'data'
+    pub fn from_boxed_slice(data: Box<[u8]>) -> Result<OwnedVorbisDecoder, VorbisError> {
+        Self::from_stream_data(StreamData::Boxed(data), SetupLimits::default())
+    }
+
+    /// create a decoder that shares 'data' with other owners
+    pub fn from_arc(data: Arc<[u8]>) -> Result<OwnedVorbisDecoder, VorbisError> {
+        Self::from_stream_data(StreamData::Shared(data), SetupLimits::default())
+    }
+
+    /// unwrap the decoder; the Vorbis keeps the data alive (or it's 'static)
+    pub fn into_vorbis(self) -> Vorbis {
+        self.vorbis
+    }
+}
+
+impl<'a> Deref for VorbisDecoder<'a> {
+    type Target = Vorbis;
+
+    fn deref(&self) -> &Vorbis {
+        &self.vorbis
+    }
+}
+
+// only for 'static: a Vorbis swapped out of a shorter-lived decoder would
+// outlive the data it points into
+impl DerefMut for VorbisDecoder<'static> {
+    fn deref_mut(&mut self) -> &mut Vorbis {
+        &mut self.vorbis
+    }
 }
 
 // decode an entire file and output the data interleaved into a malloc()ed
@@ -2442,11 +4884,10 @@ pub unsafe fn stb_vorbis_open_memory(data: &[u8], error: &mut VorbisError) -> Op
 pub fn stb_vorbis_decode_memory(mem: &[u8],
      channels: &mut u32, sample_rate: &mut u32, output: &mut Vec<i16>) -> i32
 {
-   let mut error = VorbisError::NoError;
-   let mut v : Vorbis = unsafe { match stb_vorbis_open_memory(mem, &mut error){
-       None    => return -1,
-       Some(v) => v
-   }};
+   let mut v = match VorbisDecoder::new(mem) {
+       Err(_) => return -1,
+       Ok(v)  => v
+   };
    
    *channels = v.channels as u32;
    *sample_rate = v.sample_rate;
@@ -2460,13 +4901,7 @@ pub fn stb_vorbis_decode_memory(mem: &[u8],
    
    loop {
        let ch = v.channels as u32;
-       let n = {
-           let mut output_slice = output.as_mut_slice();
-           stb_vorbis_get_frame_short_interleaved(
-               &mut v, ch, 
-               &mut output_slice[ offset .. total ]
-            )
-       };
+       let n = v.get_frame_short_interleaved(ch, &mut output[ offset .. total ]);
 
       if n == 0 {
         break;  
@@ -2487,94 +4922,141 @@ pub fn stb_vorbis_decode_memory(mem: &[u8],
 
 }
 
+// for the get_samples_* functions: once every buffered sample is returned and
+// the current link of a chained stream is used up, start the next link right
+// away, so its format is known before any of its samples are returned.
+// returns true if it did.
+fn get_samples_end_of_link(f: &mut Vorbis) -> bool
+{
+   if f.next_seg != -1 || (f.page_flag & PAGEFLAG_LAST_PAGE) == 0 {
+      return false;
+   }
+   // the resampler hands out the rest of the link before it's left
+   if f.channel_buffer_start == f.channel_buffer_end {
+      resample_output(f, true);
+   }
+   if f.channel_buffer_start < f.channel_buffer_end {
+      return false;
+   }
+   let link_page_start = f.link_page_start;
+   maybe_start_packet(f);
+   if f.link_page_start == link_page_start {
+      return false;
+   }
+   if f.resampler.is_some() && f.links.is_empty() {
+      // output-rate positions in the new link depend on the rates of
+      // those before it
+      stream_length(f);
+   }
+   return true;
+}
+
+// decode the next frame for the get_samples_* functions; false at the end of
+// the stream, or at the start of a new link once 'n' samples are in--one call
+// never returns samples of two links.
+fn get_samples_next_frame(f: &mut Vorbis, n: usize) -> bool
+{
+   loop {
+      // with an output rate set, frames go through the resampler until
+      // it has some output
+      if resample_output(f, false) == true {
+         return true;
+      }
+      if get_samples_end_of_link(f) == true && n > 0 {
+         return false;
+      }
+      if f.channel_buffer_start < f.channel_buffer_end {
+         return true; // the resampled end of the link
+      }
+      if stb_vorbis_get_frame_float(f, None, None) == 0 {
+         return resample_output(f, true);
+      }
+      if resampling(f) == false {
+         return true;
+      }
+      resample_input(f);
+   }
+}
+
 // gets num_samples samples, not necessarily on a frame boundary--this requires
-// buffering so you have to supply the buffers. DOES NOT APPLY THE COERCION RULES.
-// Returns the number of samples stored per channel; it may be less than requested
-// at the end of the file. If there are no more samples in the file, returns 0.
+// buffering so you have to supply the buffers. Applies the coercion rules above
+// to produce 'channels' channels. Returns the number of samples stored per channel;
+// it may be less than requested at the end of the file. If there are no more
+// samples in the file, returns 0.
 pub fn stb_vorbis_get_samples_float(f: &mut Vorbis, channels: i32 , buffer: &mut AudioBufferSlice<f32>) -> i32
 {
-   let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
+   return stb_vorbis_get_samples(f, channels, buffer);
+}
+
+// gets num_samples samples, not necessarily on a frame boundary--this requires
+// buffering so you have to supply the buffers. Applies the coercion rules above
+// to produce 'channels' channels. Returns the number of samples stored per channel;
+// it may be less than requested at the end of the file. If there are no more
+// samples in the file, returns 0.
+pub fn stb_vorbis_get_samples_float_interleaved(f: &mut Vorbis, channels: i32 , buffer: &mut [f32]) -> i32 
+{
+   return stb_vorbis_get_samples_interleaved(f, channels, buffer);
+}
+
+// gets num_samples samples of any Sample type (f32, f64, i32, I24, i16, u8),
+// converted from the float buffers as they're stored. takes the coercion
+// rules of the float functions; i16 samples are the same as the short
+// functions', but for ChannelMix::Original. Returns the number of samples
+// stored per channel; it may be less than requested at the end of the file.
+// If there are no more samples in the file, returns 0.
+pub fn stb_vorbis_get_samples<T: Sample>(f: &mut Vorbis, channels: i32, buffer: &mut AudioBufferSlice<T>) -> i32
+{
    let mut n = 0;
-   let num_samples = buffer.len() as i32;
-   let z = std::cmp::min(f.channels, channels);
+   let num_samples = buffer.len();
    while n < num_samples {
-      let mut k = f.channel_buffer_end - f.channel_buffer_start;
+      let mut k = (f.channel_buffer_end - f.channel_buffer_start) as usize;
       if n + k >= num_samples { k = num_samples - n; }
-      if k != 0 {
-          let mut i = 0;
-          while i < z {
-              unsafe {
-                std::ptr::copy_nonoverlapping(
-                    f.channel_buffers[i as usize].as_ptr().offset(f.channel_buffer_start as isize),
-                    buffer[i as usize].as_mut_ptr().offset(n as isize),
-                    k as usize
-                );
-              }
-            i += 1;
-          }
-          
-          while i < channels {
-              unsafe{
-                std::ptr::write_bytes(
-                    buffer[i as usize].as_mut_ptr().offset(n as isize),
-                    0,
-                    k as usize
-                );        
-              }
-            i += 1;
-          }          
+      if k != 0 {
+         let data = unsafe { AudioBufferSlice::from(&mut f.channel_buffers) };
+         let data = data.range_from(f.channel_buffer_start as usize);
+         let (gains, dither) = output_mix(f, channels as usize);
+         convert_samples(channels as usize, &data, gains, dither, k, |c, i, sample| buffer[c][n + i] = sample);
       }
       n += k;
-      f.channel_buffer_start += k;
-      if n == num_samples{
+      f.channel_buffer_start += k as i32;
+      if n == num_samples {
          break;
       }
-      if stb_vorbis_get_frame_float(f, None, Some(&mut outputs)) == 0 {
+      if get_samples_next_frame(f, n) == false {
          break;
       }
    }
-   return n;
+   get_samples_end_of_link(f);
+   return n as i32;
 }
 
-// gets num_samples samples, not necessarily on a frame boundary--this requires
-// buffering so you have to supply the buffers. DOES NOT APPLY THE COERCION RULES.
-// Returns the number of samples stored per channel; it may be less than requested
-// at the end of the file. If there are no more samples in the file, returns 0.
-pub fn stb_vorbis_get_samples_float_interleaved(f: &mut Vorbis, channels: i32 , mut buffer: &mut [f32]) -> i32 
-{
-   let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
-   let len : i32 = buffer.len() as i32 / channels;
-   let mut n=0;
-   let z = std::cmp::min(f.channels, channels);
-   
-   let mut buffer_index = 0;
+// stb_vorbis_get_samples(), interleaved
+pub fn stb_vorbis_get_samples_interleaved<T: Sample>(f: &mut Vorbis, channels: i32, buffer: &mut [T]) -> i32
+{
+   let stride = channels as usize;
+   let len = buffer.len() / stride;
+   let mut n = 0;
    while n < len {
-      let mut k = f.channel_buffer_end - f.channel_buffer_start;
-      if n+k >= len {k = len - n;}
-      for j in 0 .. k  {
-          let mut i = 0;
-          while i < z {
-            buffer[buffer_index] = f.channel_buffers[i as usize][ (f.channel_buffer_start+j) as usize];
-            buffer_index += 1;
-            i += 1;
-          }
-          
-          while i < channels {
-            buffer[buffer_index] = 0.0;
-            buffer_index += 1;
-              i += 1;
-          }
+      let mut k = (f.channel_buffer_end - f.channel_buffer_start) as usize;
+      if n + k >= len { k = len - n; }
+      if k != 0 {
+         let data = unsafe { AudioBufferSlice::from(&mut f.channel_buffers) };
+         let data = data.range_from(f.channel_buffer_start as usize);
+         let (gains, dither) = output_mix(f, stride);
+         let output = &mut buffer[n * stride ..];
+         convert_samples(stride, &data, gains, dither, k, |c, i, sample| output[i * stride + c] = sample);
       }
       n += k;
-      f.channel_buffer_start += k;
-      if n == len{
+      f.channel_buffer_start += k as i32;
+      if n == len {
          break;
       }
-      if stb_vorbis_get_frame_float(f, None, Some(&mut outputs)) == 0{
+      if get_samples_next_frame(f, n) == false {
          break;
       }
    }
-   return n;
+   get_samples_end_of_link(f);
+   return n as i32;
 }
 
 // gets num_samples samples, not necessarily on a frame boundary--this requires
@@ -2584,7 +5066,6 @@ pub fn stb_vorbis_get_samples_float_interleaved(f: &mut Vorbis, channels: i32 ,
 // samples in the file, returns 0.
 pub unsafe fn stb_vorbis_get_samples_short(f: &mut Vorbis, channels: i32, buffer: &mut AudioBufferSlice<i16>) -> u32
 {
-   let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(channels as usize);
    let mut n = 0;
    let len = buffer.len();
 
@@ -2595,13 +5076,15 @@ pub unsafe fn stb_vorbis_get_samples_short(f: &mut Vorbis, channels: i32, buffer
          let channel_buffers_slice = AudioBufferSlice::from(&mut f.channel_buffers);
          let channel_buffers_slice = channel_buffers_slice.range_from(f.channel_buffer_start as usize);
          let mut buffer = buffer.range_from(n as usize);
-         convert_samples_short(channels, &mut buffer, &channel_buffers_slice, k); 
+         let (gains, dither) = output_mix(f, channels as usize);
+         convert_samples_short(channels, &mut buffer, &channel_buffers_slice, gains, dither, k); 
       }
       n += k;
       f.channel_buffer_start += k as i32;
       if n == len{ break;}
-      if stb_vorbis_get_frame_float(f, None, Some(&mut outputs)) == 0 {break;}
+      if get_samples_next_frame(f, n) == false {break;}
    }
+   get_samples_end_of_link(f);
    return n as u32;
 }
 
@@ -2612,7 +5095,6 @@ pub unsafe fn stb_vorbis_get_samples_short(f: &mut Vorbis, channels: i32, buffer
 // samples in the file, returns 0.
 pub fn stb_vorbis_get_samples_short_interleaved(f: &mut Vorbis, channel_count: u32, mut buffer: &mut [i16]) -> i32
 {
-   let mut outputs: AudioBufferSlice<f32> = AudioBufferSlice::new(0);
    let len_per_channel = buffer.len() / channel_count as usize;
    let mut n = 0;
    let mut buffer_offset = 0;
@@ -2629,10 +5111,13 @@ pub fn stb_vorbis_get_samples_short_interleaved(f: &mut Vorbis, channel_count: u
           // NOTE(bungcip): create type AudioBuffer too
          let audio_buffer_slice = unsafe { AudioBufferSlice::from(&mut f.channel_buffers) };
          let audio_buffer_slice = audio_buffer_slice.range_from(f.channel_buffer_start as usize);
+         let (gains, dither) = output_mix(f, channel_count as usize);
           
          convert_channels_short_interleaved(
              channel_count, &mut buffer[buffer_offset ..], 
              &audio_buffer_slice, 
+             gains,
+             dither,
              k);
       }
       buffer_offset += k * channel_count as usize;
@@ -2642,32 +5127,36 @@ pub fn stb_vorbis_get_samples_short_interleaved(f: &mut Vorbis, channel_count: u
         break;
       }
 
-      // NOTE(bungcip): outputs not used? maybe can be changed to None
-      if stb_vorbis_get_frame_float(f, None, Some(&mut outputs)) == 0 {
+      if get_samples_next_frame(f, n) == false {
           break;
       }
    }
+   get_samples_end_of_link(f);
    
    return n as i32;
 }
 
 // the same as vorbis_decode_initial, but without advancing
-#[allow(unreachable_code, unused_variables)]
 fn peek_decode_initial(f: &mut Vorbis, p_left_start: &mut i32, p_left_end: &mut i32, p_right_start: &mut i32, p_right_end: &mut i32, mode: &mut i32) -> bool
 {
-  panic!("EXPECTED PANIC: need ogg sample that will trigger this panic");
 
-   let (p_left_start, p_left_end, p_right_start, p_right_end, mode) = match vorbis_decode_initial(f) {
-       Ok(result) => result,
+   match vorbis_decode_initial(f) {
+       Ok(result) => {
+           *p_left_start = result.0;
+           *p_left_end = result.1;
+           *p_right_start = result.2;
+           *p_right_end = result.3;
+           *mode = result.4;
+       },
        Err(why) => {
            error(f, why);
            return false;
        }
-   };
+   }
 
    // either 1 or 2 bytes were read, figure out which so we can rewind
    let mut bits_read = 1 + ilog(f.mode_config.len() as i32 - 1);
-   if f.mode_config[mode as usize].blockflag != 0 {
+   if f.mode_config[*mode as usize].blockflag != 0 {
       bits_read += 2;
    }
    let bytes_read = (bits_read + 7) / 8;
@@ -2685,13 +5174,14 @@ fn peek_decode_initial(f: &mut Vorbis, p_left_start: &mut i32, p_left_end: &mut
    return true;
 }
 
-fn set_file_offset(f: &mut Vorbis, mut loc: u32) -> bool
+fn set_file_offset(f: &mut Vorbis, mut loc: u64) -> bool
 {
    if f.push_mode == true {return false;}
    f.eof = false;
    if !f.stream.is_null() {
       unsafe {
-        if f.stream_start.offset(loc as isize)  >= f.stream_end || f.stream_start.offset(loc as isize) < f.stream_start {
+        let len = f.stream_end as usize - f.stream_start as usize;
+        if loc >= len as u64 {
             f.stream = f.stream_end;
             f.eof = true;
             return false;
@@ -2701,19 +5191,20 @@ fn set_file_offset(f: &mut Vorbis, mut loc: u32) -> bool
         }
       }
    }
-   if loc + f.f_start < loc || loc >= 0x80000000 {
-      loc = 0x7fffffff;
-      f.eof = true;
-   } else {
-      loc += f.f_start;
+   match loc.checked_add(f.f_start) {
+      Some(x) if x <= i64::MAX as u64 => loc = x,
+      _ => {
+         loc = i64::MAX as u64;
+         f.eof = true;
+      }
    }
 
    let mut file = f.f.as_mut().unwrap();
-   match file.seek(SeekFrom::Start(loc as u64)) {
+   match file.seek(SeekFrom::Start(loc)) {
        Ok(_)  => return true,
        Err(_) => {
            f.eof = true;
-           file.seek(SeekFrom::End(f.f_start as i64)).unwrap();
+           let _ = file.seek(SeekFrom::End(f.f_start as i64));
            return false;
        }
    }
@@ -2721,32 +5212,47 @@ fn set_file_offset(f: &mut Vorbis, mut loc: u32) -> bool
 
 // rarely used function to seek back to the preceeding page while finding the
 // start of a packet
-#[allow(unreachable_code, unused_variables)]
-fn go_to_page_before(f: &mut Vorbis, limit_offset: u32) -> bool
+fn go_to_page_before(f: &mut Vorbis, limit_offset: u64) -> bool
 {
-  panic!("EXPECTED PANIC: need ogg sample that will trigger this panic");
+   // in a multiplexed file the pages of the other streams can push our
+   // previous page further back, so keep widening the window until it shows
+   let mut window : u64 = 65536;
+   loop {
+      // now we want to seek back 64K from the limit
+      let previous_safe : u64 = if limit_offset >= window && limit_offset-window >= f.first_audio_page_offset {
+         limit_offset - window
+      } else {
+         f.first_audio_page_offset
+      };
 
-   // now we want to seek back 64K from the limit
-   let previous_safe : u32 = if limit_offset >= 65536 && limit_offset-65536 >= f.first_audio_page_offset {
-      limit_offset - 65536
-   } else {
-      f.first_audio_page_offset
-   };
+      set_file_offset(f, previous_safe);
 
-   set_file_offset(f, previous_safe);
+      let mut found = None;
+      while let Some((end, _)) = vorbis_find_page(f) {
+         let start = stb_vorbis_get_file_offset(f);
+         if start >= limit_offset {
+            break;
+         }
+         let mut header : [u8; 27] = [0; 27];
+         if peek_page_header(f, &mut header) && page_serial(&header) == f.serial {
+            found = Some(start);
+         }
+         set_file_offset(f, end);
+      }
 
-   while let Some((end, _)) = vorbis_find_page(f) {
-      if end >= limit_offset && stb_vorbis_get_file_offset(f) < limit_offset {
-         return true;          
+      if let Some(start) = found {
+         set_file_offset(f, start);
+         return true;
       }
-      set_file_offset(f, end);
+      if previous_safe == f.first_audio_page_offset {
+         return false;
+      }
+      window = window.saturating_mul(2);
    }
-
-   return false;
 }
 
 /// return end & last if page exist
-fn vorbis_find_page(f: &mut Vorbis) -> Option<(u32, u32)>
+fn vorbis_find_page(f: &mut Vorbis) -> Option<(u64, u32)>
 {
    loop {
       if f.eof == true {
@@ -2761,7 +5267,7 @@ fn vorbis_find_page(f: &mut Vorbis) -> Option<(u32, u32)>
          let retry_loc = stb_vorbis_get_file_offset(f);
          
          // check if we're off the end of a file_section stream
-         if retry_loc - 25 > f.stream_len {
+         if retry_loc.wrapping_sub(25) > f.stream_len {
             return None;
          }
          // check the rest of the header
@@ -2812,20 +5318,20 @@ fn vorbis_find_page(f: &mut Vorbis) -> Option<(u32, u32)>
                 let mut crc = 0;
 
                 for &item in header.iter() {
-                    crc = unsafe { crc32_update(crc, item) };
+                    crc = crc32_update(crc, item);
                 }
 
                 let mut len = 0;
                 for _ in 0 .. header[26] {
                     let s = get8(f) as i32;
-                    crc = unsafe { crc32_update(crc, s as u8) };
+                    crc = crc32_update(crc, s as u8);
                     len += s;
                 }
                 if len != 0 && f.eof == true {
                     return None;
                 }
                 for _ in 0 .. len {
-                    crc = unsafe { crc32_update(crc, get8(f)) };
+                    crc = crc32_update(crc, get8(f));
                 }
 
                 crc
@@ -2842,7 +5348,7 @@ fn vorbis_find_page(f: &mut Vorbis) -> Option<(u32, u32)>
                // another 2^32, not worth it since it would hose those
                // invalid-but-useful files?
 
-               let end : u32 = stb_vorbis_get_file_offset(f);
+               let end : u64 = stb_vorbis_get_file_offset(f);
                let last : u32 = if (header[5] & 0x04) != 0 {
                   1
                } else {
@@ -2856,7 +5362,7 @@ fn vorbis_find_page(f: &mut Vorbis) -> Option<(u32, u32)>
 }
 
 #[inline(always)]
-unsafe fn crc32_update(crc: u32, byte: u8) -> u32
+fn crc32_update(crc: u32, byte: u8) -> u32
 {
    return (crc << 8) ^ CRC_TABLE[ (byte as u32 ^ (crc >> 24)) as usize];
 }
@@ -2871,10 +5377,8 @@ unsafe fn crc32_update(crc: u32, byte: u8) -> u32
 // to try to bound either side of the binary search sensibly, while still
 // working in O(log n) time if they fail.
 
-#[allow(unreachable_code, unused_variables)]
 fn get_seek_page_info(f: &mut Vorbis, z: &mut ProbedPage) -> bool
 {
-  panic!("EXPECTED PANIC: need ogg sample that will trigger this panic");
 
    // record where the page starts
    z.page_start = stb_vorbis_get_file_offset(f);
@@ -2888,22 +5392,24 @@ fn get_seek_page_info(f: &mut Vorbis, z: &mut ProbedPage) -> bool
    }
 
    let mut lacing: [u8; 255] = [0; 255];
-   getn(f, &mut lacing[..]);
+   getn(f, &mut lacing[.. header[26] as usize]);
 
    // determine the length of the payload
-   let mut len = 0;
+   let mut len : u32 = 0;
    for i in 0 .. header[26] as usize {
-      len += lacing[i];
+      len += lacing[i] as u32;
    }
 
    // this implies where the page ends
-   z.page_end = z.page_start + 27 + header[26] as u32 + len as u32;
+   z.page_end = z.page_start + 27 + header[26] as u64 + len as u64;
 
    // read the last-decoded sample out of the data
-   z.last_decoded_sample = header[6] as u32 + 
-    ( (header[7] as u32) << 8) + 
-    ( (header[8] as u32) << 16) + 
-    ( (header[9] as u32) << 24);
+   z.last_decoded_sample = page_granule(&header);
+
+   // a page of another stream in a multiplexed file says nothing about ours
+   if page_serial(&header) != f.serial {
+      z.last_decoded_sample = !0;
+   }
 
    // restore file state to where we were
    set_file_offset(f, z.page_start);
@@ -2924,21 +5430,57 @@ pub fn stb_vorbis_open_pushdata(
          )
          -> Result<Vorbis, VorbisError>
 {
+   return open_pushdata(data, None, SetupLimits::default(), data_used);
+}
+
+// same as above, with caps on what the stream's headers may make the
+// decoder allocate; a stream asking for more fails with OutOfMem (which,
+// unlike NeedMoreData, more data won't fix)
+pub fn stb_vorbis_open_pushdata_with_limits(
+         data: &[u8],
+         limits: SetupLimits,
+         data_used: &mut i32
+         )
+         -> Result<Vorbis, VorbisError>
+{
+   return open_pushdata(data, None, limits, data_used);
+}
+
+// same as above, but for a multiplexed stream with more than one vorbis
+// stream in it: decode the one with the given serial number instead of the
+// first (fails with IncorrectStreamSerialNumber if there's no such stream)
+pub fn stb_vorbis_open_pushdata_stream(
+         data: &[u8],
+         serial: u32,
+         data_used: &mut i32
+         )
+         -> Result<Vorbis, VorbisError>
+{
+   return open_pushdata(data, Some(serial), SetupLimits::default(), data_used);
+}
 
+fn open_pushdata(data: &[u8], wanted_serial: Option<u32>, limits: SetupLimits, data_used: &mut i32) -> Result<Vorbis, VorbisError>
+{
    let mut p = Vorbis::new();
+   p.limits = limits;
    let start_position = data.as_ptr() as usize;
+   p.wanted_serial = wanted_serial;
    unsafe {
         p.stream     = data.as_ptr();
         p.stream_end = p.stream.offset(data.len() as isize);
         p.push_mode  = true;
         if start_decoder(&mut p) == false {
             if p.eof == true {
-                return Err(VorbisError::NeedMoreData);
+                return Err(VorbisError::new(VorbisErrorKind::NeedMoreData));
             } else {
-                return Err(p.error);
+                let kind = p.error;
+                return Err(vorbis_error(&mut p, kind));
             }
         }
    }
+   if wanted_serial.is_some() && wanted_serial != Some(p.serial) {
+      return Err(vorbis_error(&mut p, VorbisErrorKind::IncorrectStreamSerialNumber));
+   }
    
     *data_used = (p.stream as usize - start_position) as i32;
     return Ok(p);
@@ -2955,6 +5497,10 @@ pub fn stb_vorbis_open_pushdata(
 // note that after opening a file, you will ALWAYS get one N-bytes,0-sample
 // frame, because Vorbis always "discards" the first frame.
 //
+// If the data stops lining up with the stream (e.g. a chunk went missing),
+// the decoder notices at the next page boundary and resynchs by itself, as
+// if stb_vorbis_flush_pushdata() had been called.
+//
 // Note that on resynch, stb_vorbis will rarely consume all of the buffer,
 // instead only datablock_length_in_bytes-3 or less. This is because it wants
 // to avoid missing parts of a page header if they cross a datablock boundary,
@@ -2978,12 +5524,39 @@ pub unsafe fn stb_vorbis_decode_frame_pushdata(
 {
 
    if f.push_mode == false { 
-       error(f, VorbisError::InvalidApiMixing);
+       error(f, VorbisErrorKind::InvalidApiMixing);
        return 0;
     };
     
    let data_len = data.len() as i32;
 
+   // the first page of the next link of a chained stream (the page scan
+   // stops in front of one, too)
+   let at_page = f.page_crc_tests >= 0 || f.next_seg == -1;
+   if at_page && data.len() > 5 && data.starts_with(&OGG_PAGE_HEADER) && (data[5] & PAGEFLAG_FIRST_PAGE) != 0 {
+      *samples = 0;
+      f.stream     = data.as_ptr();
+      f.stream_end = f.stream.offset(data_len as isize);
+      f.error      = VorbisErrorKind::NoError;
+      if start_next_link(f) == false {
+         if f.eof == true || f.error == VorbisErrorKind::NeedMoreData {
+            // the headers aren't all there yet; start over on this
+            // page with more data
+            f.eof = false;
+            f.next_seg = -1;
+            f.error = VorbisErrorKind::NeedMoreData;
+            return 0;
+         }
+         // not a link we can decode, look for pages past it
+         let error = f.error;
+         stb_vorbis_flush_pushdata(f);
+         f.error = error;
+         return 1;
+      }
+      f.page_crc_tests = -1;
+      return (f.stream as usize - data.as_ptr() as usize) as i32;
+   }
+
    if f.page_crc_tests >= 0 {
       *samples = 0;
       return vorbis_search_for_page_pushdata(f, data);
@@ -2991,29 +5564,37 @@ pub unsafe fn stb_vorbis_decode_frame_pushdata(
 
    f.stream     = data.as_ptr();
    f.stream_end = f.stream.offset(data_len as isize) as *mut u8;
-   f.error      = VorbisError::NoError;
+   f.error      = VorbisErrorKind::NoError;
 
    // check that we have the entire packet in memory
    if is_whole_packet_present(f, false) == false {
       *samples = 0;
+      if f.error != VorbisErrorKind::NeedMoreData {
+         // the data doesn't continue the stream we were decoding (a chunk
+         // went missing?), so scan for the next page like after a flush
+         let error = f.error;
+         stb_vorbis_flush_pushdata(f);
+         f.error = error;
+         return 1;
+      }
       return 0;
    }
 
     let (len, left, right) = match vorbis_decode_packet(f) {
         Ok(result) => result,
-        Err(VorbisError::BadPacketType) => {
+        Err(VorbisErrorKind::BadPacketType) => {
             // flush and resynch
-            f.error = VorbisError::NoError;
+            f.error = VorbisErrorKind::NoError;
             while get8_packet(f) != EOP{
                 if f.eof == true {break;}
             }
             *samples = 0;
             return (f.stream as usize - data.as_ptr() as usize) as i32;
         },
-        Err(VorbisError::ContinuedPacketFlagInvalid) if f.previous_length == 0 => {
+        Err(VorbisErrorKind::ContinuedPacketFlagInvalid) if f.previous_length == 0 => {
             // we may be resynching, in which case it's ok to hit one
             // of these; just discard the packet
-            f.error = VorbisError::NoError;
+            f.error = VorbisErrorKind::NoError;
             while get8_packet(f) != EOP{
                 if f.eof == true {break;}
             }
@@ -3040,7 +5621,7 @@ pub unsafe fn stb_vorbis_decode_frame_pushdata(
    *channels = f.channels;
    *samples = len;
    
-   *output = f.outputs;
+   output.clone_from(&f.outputs);
     return (f.stream as usize - data.as_ptr() as usize) as i32;
 }
 
@@ -3069,40 +5650,52 @@ unsafe fn is_whole_packet_present(f: &mut Vorbis, end_page: bool) -> bool
       }
       // either this continues, or it ends it...
       if end_page && s < f.segment_count-1 {
-         return error(f, VorbisError::InvalidStream);
+         return error(f, VorbisErrorKind::InvalidStream);
       }
       if s == f.segment_count {
          s = -1; // set 'crosses page' flag
       }
       if p > f.stream_end {
-        return error(f, VorbisError::NeedMoreData);
+        return error(f, VorbisErrorKind::NeedMoreData);
       }
       first = false;
    }
    while s == -1 {
       // check that we have the page header ready
-      if p.offset(26) >= f.stream_end               {return error(f, VorbisError::NeedMoreData);}
+      if p.offset(26) >= f.stream_end               {return error(f, VorbisErrorKind::NeedMoreData);}
       
       // validate the page
       {
           let p_slice = std::slice::from_raw_parts(p, 4);
-          if p_slice != OGG_PAGE_HEADER {return error(f, VorbisError::InvalidStream);}
+          if p_slice != OGG_PAGE_HEADER {return error(f, VorbisErrorKind::InvalidStream);}
+      }
+      if *p.offset(4) != 0                             {return error(f, VorbisErrorKind::InvalidStream);}
+      // pages of the other streams of a multiplexed file are skipped
+      let serial = page_serial(std::slice::from_raw_parts(p, 27));
+      if serial != f.serial && (*p.offset(5) & PAGEFLAG_FIRST_PAGE) == 0 && f.streams.is_empty() == false {
+         let n = *p.offset(26) as isize;
+         let q = p.offset(27);
+         p = q.offset(n);
+         if p > f.stream_end                     {return error(f, VorbisErrorKind::NeedMoreData);}
+         for i in 0 .. n {
+            p = p.offset(*q.offset(i) as isize);
+         }
+         continue;
       }
-      if *p.offset(4) != 0                             {return error(f, VorbisError::InvalidStream);}
       if first  { // the first segment must NOT have 'continued_packet', later ones MUST
          if f.previous_length != 0 && ( (*p.offset(5) & PAGEFLAG_CONTINUED_PACKET) != 0 ){
-            return error(f, VorbisError::InvalidStream);
+            return error(f, VorbisErrorKind::InvalidStream);
          }
          // if no previous length, we're resynching, so we can come in on a continued-packet,
          // which we'll just drop
       } else if (*p.offset(5) & PAGEFLAG_CONTINUED_PACKET) == 0 {
-         return error(f, VorbisError::InvalidStream);
+         return error(f, VorbisErrorKind::InvalidStream);
       }
       let n = *p.offset(26) as i32; // segment counts
       let q = p.offset(27);  // q points to segment table
       p = q.offset(n as isize); // advance past header
       // make sure we've read the segment table
-      if p > f.stream_end                     {return error(f, VorbisError::NeedMoreData);}
+      if p > f.stream_end                     {return error(f, VorbisErrorKind::NeedMoreData);}
       
       s = 0;
       while s < n {
@@ -3114,7 +5707,7 @@ unsafe fn is_whole_packet_present(f: &mut Vorbis, end_page: bool) -> bool
       }
       
       if end_page && s < n-1 {
-          return error(f, VorbisError::InvalidStream);
+          return error(f, VorbisErrorKind::InvalidStream);
       }
       
       if s == n {
@@ -3122,7 +5715,7 @@ unsafe fn is_whole_packet_present(f: &mut Vorbis, end_page: bool) -> bool
       }
       
       if p > f.stream_end {
-          return error(f, VorbisError::NeedMoreData);
+          return error(f, VorbisErrorKind::NeedMoreData);
       }
       
       first = false;
@@ -3131,23 +5724,32 @@ unsafe fn is_whole_packet_present(f: &mut Vorbis, end_page: bool) -> bool
    return true;    
 }
 
-const SAMPLE_UNKNOWN : u32 = 0xffffffff;
+const SAMPLE_UNKNOWN : u64 = !0;
+
+// these functions return the total length of the vorbis stream. with an
+// output rate set, in samples at that rate
+pub fn stb_vorbis_stream_length_in_samples(f: &mut Vorbis) -> u64
+{
+   let length = stream_length(f);
+   if length == 0 {
+      return 0;
+   }
+   return output_position(f, length);
+}
 
-// these functions return the total length of the vorbis stream
-pub fn stb_vorbis_stream_length_in_samples(f: &mut Vorbis) -> u32
+// stb_vorbis_stream_length_in_samples() at the stream's rate
+fn stream_length(f: &mut Vorbis) -> u64
 {
-    use VorbisError::*;
+    use VorbisErrorKind::*;
     
-    let restore_offset : u32;
-    let previous_safe :u32;
-    let mut end: u32;
-    let mut last_page_loc :u32;
+    let restore_offset : u64;
+    let previous_safe :u64;
+    let mut end: u64;
+    let mut last_page_loc :u64;
 
-   if f.push_mode { return error(f, InvalidApiMixing) as u32; }
+   if f.push_mode { return error(f, InvalidApiMixing) as u64; }
    if f.total_samples == 0 {
-      let mut lo : u32;
-      let hi : u32;
-      let mut header: [u8; 6] = [0; 6];
+      let mut header: [u8; 27] = [0; 27];
     
       'done: loop {
       // first, store the current decode position so we can restore it
@@ -3174,7 +5776,7 @@ pub fn stb_vorbis_stream_length_in_samples(f: &mut Vorbis) -> u32
           None => {
             // if we can't find a page, we're hosed!
             f.error = CantFindLastPage;
-            f.total_samples = 0xffffffff;
+            f.total_samples = SAMPLE_UNKNOWN;
             //  goto done;
             break 'done;
           }
@@ -3205,27 +5807,38 @@ pub fn stb_vorbis_stream_length_in_samples(f: &mut Vorbis) -> u32
          last_page_loc = stb_vorbis_get_file_offset(f);
       }
 
+      // a page after the last one means more links follow
+      set_file_offset(f, end);
+      let more_links = vorbis_find_page(f).is_some();
+
       set_file_offset(f, last_page_loc);
 
       // parse the header
       getn(f, &mut header[..]);
       // extract the absolute granule position
-      lo = get32(f);
-      hi = get32(f);
-      if lo == 0xffffffff && hi == 0xffffffff {
+      let granule = page_granule(&header);
+      let serial = page_serial(&header);
+
+      // a chained or multiplexed stream; every page needs to be looked at
+      if more_links || serial != f.serial || f.link_page_start != 0 {
+         if scan_links(f) == false {
+            f.error = CantFindLastPage;
+            f.total_samples = SAMPLE_UNKNOWN;
+         }
+         break 'done;
+      }
+
+      if granule == !0 {
          f.error = CantFindLastPage;
          f.total_samples = SAMPLE_UNKNOWN;
         //  goto done;
         break 'done;
       }
-      if hi != 0{
-         lo = 0xfffffffe; // saturate
-      }
-      f.total_samples = lo;
+      f.total_samples = granule;
 
       f.p_last.page_start = last_page_loc;
       f.p_last.page_end   = end;
-      f.p_last.last_decoded_sample = lo;
+      f.p_last.last_decoded_sample = granule;
 
       break 'done;
      }
@@ -3236,59 +5849,201 @@ pub fn stb_vorbis_stream_length_in_samples(f: &mut Vorbis) -> u32
    return if f.total_samples == SAMPLE_UNKNOWN {0} else {f.total_samples};
 }
 
+// walk the pages of a chained stream, noting where each link starts, how
+// many samples it holds and where its last page is. the vorbis stream of
+// each link is picked the same way start_decoder() does
+fn scan_links(f: &mut Vorbis) -> bool
+{
+   let mut links: Vec<Link> = Vec::new();
+   let mut offset = 0;
+   let mut first_pages = false;
+   let mut chosen = false;
+   while offset < f.stream_len {
+      set_file_offset(f, offset);
+      let mut header: [u8; 27] = [0; 27];
+      if getn(f, &mut header[..]) == false {
+         break;
+      }
+      if header.starts_with(&OGG_PAGE_HEADER) == false || header[4] != 0 {
+         // junk between pages; find the next real one
+         set_file_offset(f, offset);
+         if vorbis_find_page(f).is_none() {
+            break;
+         }
+         offset = stb_vorbis_get_file_offset(f);
+         continue;
+      }
+
+      let mut lacing: [u8; 255] = [0; 255];
+      if getn(f, &mut lacing[.. header[26] as usize]) == false {
+         break;
+      }
+      let len : u64 = lacing[.. header[26] as usize].iter().map(|&x| x as u64).sum();
+      let page_end = offset + 27 + header[26] as u64 + len;
+      if page_end > f.stream_len {
+         break;
+      }
+
+      let (granule, serial) = (page_granule(&header), page_serial(&header));
+      if (header[5] & PAGEFLAG_FIRST_PAGE) != 0 {
+         // the first pages of every stream in a link come together
+         if first_pages == false {
+            links.push(Link {
+               serial: serial,
+               page_start: offset,
+               sample_start: 0,
+               p_last: ProbedPage { page_start: 0, page_end: 0, last_decoded_sample: SAMPLE_UNKNOWN },
+               sample_rate: 0,
+            });
+            first_pages = true;
+            chosen = false;
+         }
+         let mut id : [u8; 16] = [0; 16];
+         let vorbis = header[26] == 1 && lacing[0] == 30 && getn(f, &mut id[..]) 
+            && id[0] == PACKET_ID && vorbis_validate(&id[1 .. 7]);
+         if vorbis == true && (chosen == false || f.wanted_serial == Some(serial)) {
+            if let Some(link) = links.last_mut() {
+               link.serial = serial;
+               link.sample_rate = id[12] as u32 | (id[13] as u32) << 8 | (id[14] as u32) << 16 | (id[15] as u32) << 24;
+            }
+            chosen = true;
+         }
+      } else {
+         // no vorbis in this link, nothing to play
+         if first_pages == true && chosen == false {
+            links.pop();
+         }
+         first_pages = false;
+         if granule != !0 {
+            if let Some(link) = links.last_mut() {
+               if link.serial == serial {
+                  link.p_last = ProbedPage {
+                     page_start: offset,
+                     page_end: page_end,
+                     last_decoded_sample: granule,
+                  };
+               }
+            }
+         }
+      }
+      offset = page_end;
+   }
+
+   if links.is_empty() {
+      return false;
+   }
+
+   let mut total : u64 = 0;
+   for link in links.iter_mut() {
+      link.sample_start = total;
+      if link.p_last.last_decoded_sample != SAMPLE_UNKNOWN {
+         total = total.saturating_add(link.p_last.last_decoded_sample);
+      }
+      if link.page_start == f.link_page_start {
+         f.p_last = link.p_last;
+      }
+   }
+   f.total_samples = total;
+   f.links = links;
+   return true;
+}
+
+// find the link of a chained stream that holds 'sample_number', switch over
+// to it if it isn't the current one, and return where in the link the sample is
+fn seek_to_link(f: &mut Vorbis, sample_number: u64) -> Result<u64, VorbisErrorKind>
+{
+   let stream_length = stream_length(f);
+   if f.links.is_empty() {
+      return Ok(sample_number);
+   }
+   if stream_length == 0 {
+      return Err(VorbisErrorKind::SeekWithoutLength);
+   }
+   if sample_number > stream_length {
+      return Err(VorbisErrorKind::SeekInvalid);
+   }
+
+   let link = match f.links.iter().rposition(|link| link.sample_start <= sample_number) {
+      Some(k) => f.links[k],
+      None    => return Err(VorbisErrorKind::SeekInvalid),
+   };
+   if link.page_start != f.link_page_start {
+      set_file_offset(f, link.page_start);
+      if unsafe { start_next_link(f) } == false {
+         return Err(f.error);
+      }
+      // finds the first audio page for seek_to_sample_coarse()
+      if vorbis_pump_first_frame(f) == false {
+         return Err(f.error);
+      }
+   }
+   f.link_sample_start = link.sample_start;
+   f.p_last = link.p_last;
+   return Ok(sample_number - link.sample_start);
+}
+
 // implements the search logic for finding a page and starting decoding. if
 // the function succeeds, current_loc_valid will be true and current_loc will
 // be less than or equal to the provided sample number (the closer the better).
-fn seek_to_sample_coarse(f: &mut Vorbis, mut sample_number: u32) -> Result<(), VorbisError>
+fn seek_to_sample_coarse(f: &mut Vorbis, sample_number: u64) -> Result<(), VorbisErrorKind>
 {
    let mut end_pos : i32;
-   let mut page_start : i32;
-   let mut delta: u32;
+   let mut page_start : u64;
+   let mut delta: u64;
    let mut offset: f64 = 0.0;
    let mut bytes_per_sample : f64 = 0.0;
    let mut probe = 0; 
    
-   // find the last page and validate the target sample
-   let stream_length = stb_vorbis_stream_length_in_samples(f);
+   // find the last page and validate the target sample (within the
+   // current link, for a chained stream)
+   let stream_length = stream_length(f);
    if stream_length == 0 {
-       return Err(VorbisError::SeekWithoutLength);
+       return Err(VorbisErrorKind::SeekWithoutLength);
    }
-   if sample_number > stream_length {
-       return Err(VorbisError::SeekInvalid);
+   if sample_number > f.p_last.last_decoded_sample {
+       return Err(VorbisErrorKind::SeekInvalid);
    }
 
    // this is the maximum difference between the window-center (which is the
    // actual granule position value), and the right-start (which the spec
    // indicates should be the granule position (give or take one)).
-   let padding = ((f.blocksize_1 - f.blocksize_0) >> 2) as u32;
-   if sample_number < padding {
-      sample_number = 0;
-   }else{
-      sample_number -= padding;
-   }
+   let padding = ((f.blocksize_1 - f.blocksize_0) >> 2) as u64;
+   let last_sample_limit = if sample_number < padding {
+      0
+   } else {
+      sample_number - padding
+   };
    
    let mut left = f.p_first;
    while left.last_decoded_sample == !0 {
       // (untested) the first page does not have a 'last_decoded_sample'
       set_file_offset(f, left.page_end);
       if get_seek_page_info(f, &mut left) == false {
-        stb_vorbis_seek_start(f);
-        return Err(VorbisError::SeekFailed);
+        seek_link_start(f);
+        return Err(VorbisErrorKind::SeekFailed);
       }
    }
 
    let mut right = f.p_last;
-   assert!(right.last_decoded_sample != !0 );
+   if right.last_decoded_sample == !0 {
+      return Err(VorbisErrorKind::SeekFailed);
+   }
 
    // starting from the start is handled differently
-   if sample_number <= left.last_decoded_sample {
-      stb_vorbis_seek_start(f);
+   if last_sample_limit <= left.last_decoded_sample {
+      seek_link_start(f);
+      if f.current_loc_valid == false || f.current_loc > sample_number {
+         return Err(VorbisErrorKind::SeekFailed);
+      }
       return Ok(());
    }
 
    let mut mid: ProbedPage = ProbedPage::default();
    while left.page_end != right.page_start {
-      assert!(left.page_end < right.page_start);
+      if left.page_end > right.page_start {
+         seek_link_start(f);
+         return Err(VorbisErrorKind::SeekFailed);
+      }
       // search range in bytes
       delta = right.page_start - left.page_end;
       if delta <= 65536 {
@@ -3300,10 +6055,10 @@ fn seek_to_sample_coarse(f: &mut Vorbis, mut sample_number: u32) -> Result<(), V
                // first probe (interpolate)
                let data_bytes : f64 = (right.page_end - left.page_start) as f64;
                bytes_per_sample = data_bytes / right.last_decoded_sample as f64;
-               offset = left.page_start as f64 + bytes_per_sample * (sample_number - left.last_decoded_sample) as f64;
+               offset = left.page_start as f64 + bytes_per_sample * (last_sample_limit - left.last_decoded_sample) as f64;
             } else {
                // second probe (try to bound the other side)
-               let mut error: f64 = (sample_number as f64 - mid.last_decoded_sample as f64) * bytes_per_sample;
+               let mut error: f64 = (last_sample_limit as f64 - mid.last_decoded_sample as f64) * bytes_per_sample;
                if error >= 0.0 && error <  8000.0 {error =  8000.0;}
                if error <  0.0 && error > -8000.0 {error = -8000.0;}
                offset += error * 2.0;
@@ -3317,7 +6072,7 @@ fn seek_to_sample_coarse(f: &mut Vorbis, mut sample_number: u32) -> Result<(), V
                offset = (right.page_start - 65536) as f64;
             }
             
-            set_file_offset(f, offset as u32);
+            set_file_offset(f, offset as u64);
          } else {
             // binary search for large ranges (offset by 32K to ensure
             // we don't hit the right page)
@@ -3325,33 +6080,36 @@ fn seek_to_sample_coarse(f: &mut Vorbis, mut sample_number: u32) -> Result<(), V
          }
 
          if vorbis_find_page(f) == None {
-            stb_vorbis_seek_start(f);
-            return Err(VorbisError::SeekFailed);
+            seek_link_start(f);
+            return Err(VorbisErrorKind::SeekFailed);
          }
       }
 
       loop {
          if get_seek_page_info(f, &mut mid) == false {
-            stb_vorbis_seek_start(f);
-            return Err(VorbisError::SeekFailed);
+            seek_link_start(f);
+            return Err(VorbisErrorKind::SeekFailed);
          }
          if mid.last_decoded_sample != !0 {
              break;
          }
          // (untested) no frames end on this page
          set_file_offset(f, mid.page_end);
-         assert!(mid.page_start < right.page_start);
+         if mid.page_start >= right.page_start {
+            seek_link_start(f);
+            return Err(VorbisErrorKind::SeekFailed);
+         }
       }
 
       // if we've just found the last page again then we're in a tricky file,
-      // and we're close enough.
-      if mid.page_start == right.page_start{
-         break;
-      }
-      
-      if sample_number < mid.last_decoded_sample{
+      // and we're close enough (if it wasn't an interpolation probe).
+      if mid.page_start == right.page_start {
+         if probe >= 2 || delta <= 65536 {
+            break;
+         }
+      } else if last_sample_limit < mid.last_decoded_sample {
          right = mid;
-      }else{
+      } else {
          left = mid;
       }
       
@@ -3359,13 +6117,16 @@ fn seek_to_sample_coarse(f: &mut Vorbis, mut sample_number: u32) -> Result<(), V
    }
 
    // seek back to start of the last packet
-   page_start = left.page_start as i32;
-   set_file_offset(f, page_start as u32);
+   page_start = left.page_start;
+   set_file_offset(f, page_start);
    if start_page(f) == false {
-       return Err(VorbisError::SeekFailed);
+       return Err(VorbisErrorKind::SeekFailed);
    }
    end_pos = f.end_seg_with_known_loc;
-   assert!(end_pos >= 0);
+   if end_pos < 0 {
+      seek_link_start(f);
+      return Err(VorbisErrorKind::SeekFailed);
+   }
 
    let mut start_seg_with_known_loc : i32;
    loop {
@@ -3384,15 +6145,15 @@ fn seek_to_sample_coarse(f: &mut Vorbis, mut sample_number: u32) -> Result<(), V
       }
 
       // (untested) the final packet begins on an earlier page
-      if go_to_page_before(f, page_start as u32) == false {
-        stb_vorbis_seek_start(f);
-        return Err(VorbisError::SeekFailed);
+      if go_to_page_before(f, page_start) == false {
+        seek_link_start(f);
+        return Err(VorbisErrorKind::SeekFailed);
       }
 
-      page_start = stb_vorbis_get_file_offset(f) as i32;
+      page_start = stb_vorbis_get_file_offset(f);
       if start_page(f) == false {
-        stb_vorbis_seek_start(f);
-        return Err(VorbisError::SeekFailed);
+        seek_link_start(f);
+        return Err(VorbisErrorKind::SeekFailed);
       }
         
       end_pos = f.segment_count - 1;
@@ -3406,6 +6167,9 @@ fn seek_to_sample_coarse(f: &mut Vorbis, mut sample_number: u32) -> Result<(), V
    f.bytes_in_seg = 0;
    f.previous_length = 0;
    f.next_seg = start_seg_with_known_loc;
+   f.page_lost = false;
+   f.drop_continued = false;
+   f.silence_left = 0;
 
    for i in 0 .. start_seg_with_known_loc as usize {
        let seg = f.segments[i] as i32;
@@ -3413,15 +6177,18 @@ fn seek_to_sample_coarse(f: &mut Vorbis, mut sample_number: u32) -> Result<(), V
    }
 
    // start decoding (optimizable - this frame is generally discarded)
-   vorbis_pump_first_frame(f);
+   if vorbis_pump_first_frame(f) == false {
+      return Err(f.error);
+   }
+   if f.current_loc_valid == false || f.current_loc > sample_number {
+      return Err(VorbisErrorKind::SeekFailed);
+   }
    return Ok(());
   
 }
 
-#[allow(unreachable_code, unused_variables)]
 unsafe fn vorbis_search_for_page_pushdata(f: &mut Vorbis, data: &[u8]) -> i32
 {
-   panic!("EXPECTED PANIC: need ogg sample that will trigger this panic");
 
    // NOTE(bungcip): change to return usize/u32?
 
@@ -3450,6 +6217,20 @@ unsafe fn vorbis_search_for_page_pushdata(f: &mut Vorbis, data: &[u8]) -> i32
                   data_len = i;
                   break;
                }
+               // a new link of a chained stream; stop in front of it so
+               // its headers get read
+               if (data[i + 5] & PAGEFLAG_FIRST_PAGE) != 0 {
+                  data_len = i;
+                  break;
+               }
+               // pages of another link are no use without its headers
+               let serial = data[i + 14] as u32
+                    + ((data[i + 15] as u32) <<  8)
+                    + ((data[i + 16] as u32) << 16)
+                    + ((data[i + 17] as u32) << 24);
+               if serial != f.serial {
+                  continue;
+               }
                // ok, we have it all; compute the length of the page
                let mut len : i32 = 27 + data[i + 26] as i32;
                for j in 0 .. data[i + 26] as usize {
@@ -3479,12 +6260,25 @@ unsafe fn vorbis_search_for_page_pushdata(f: &mut Vorbis, data: &[u8]) -> i32
                if data[i + 27 + data[i + 26] as usize - 1] == 255 {
                   f.scan[n].sample_loc = !0;
                }else{
-                  f.scan[n].sample_loc = data[i + 6] as u32
-                    + ((data[i + 7] as u32) <<  8)
-                    + ((data[i + 8] as u32) << 16)
-                    + ((data[i + 9] as u32) << 24);
+                  f.scan[n].sample_loc = page_granule(&data[i ..]);
                }
                f.scan[n].bytes_done = (i+j as usize) as i32;
+               // remember where the last packet ending on this page starts, so
+               // we can tell its blocksize once the page checks out
+               f.scan[n].last_packet_left = 0;
+               f.scan[n].last_packet_byte = -1;
+               let lacing = &data[i + 27 .. i + 27 + data[i + 26] as usize];
+               if let Some(end) = lacing.iter().rposition(|&x| x < 255) {
+                  let start = match lacing[.. end].iter().rposition(|&x| x < 255) {
+                      Some(k) => k + 1,
+                      None    => 0,
+                  };
+                  let continued = (data[i + 5] & PAGEFLAG_CONTINUED_PACKET) != 0;
+                  if (start > 0 || !continued) && lacing[start ..= end].iter().any(|&x| x != 0) {
+                     let offset = 27 + lacing.len() as i32 + lacing[.. start].iter().map(|&x| x as i32).sum::<i32>();
+                     f.scan[n].last_packet_left = len - offset;
+                  }
+               }
                if f.page_crc_tests == STB_PUSHDATA_CRC_COUNT {
                   break;
                }
@@ -3502,6 +6296,9 @@ unsafe fn vorbis_search_for_page_pushdata(f: &mut Vorbis, data: &[u8]) -> i32
       // m is the bytes to scan in the current chunk
       crc = f.scan[i].crc_so_far;
       for j in 0 .. m {
+         if f.scan[i].bytes_left - j as i32 == f.scan[i].last_packet_left {
+            f.scan[i].last_packet_byte = data[n + j] as i32;
+         }
          crc = crc32_update(crc, data[n + j]);
       }
       f.scan[i].bytes_left -= m as i32;
@@ -3516,8 +6313,15 @@ unsafe fn vorbis_search_for_page_pushdata(f: &mut Vorbis, data: &[u8]) -> i32
             f.next_seg = -1;       // start a new page
             f.current_loc = f.scan[i].sample_loc; // set the current sample location
                                     // to the amount we'd have decoded had we decoded this page
-            f.current_loc_valid = true; 
-            f.current_loc = !0;
+            f.current_loc_valid = f.current_loc != !0;
+            f.resync_long_block = None;
+            let byte = f.scan[i].last_packet_byte;
+            if byte >= 0 && (byte & 1) == 0 {
+               let mode = (byte >> 1) & ((1 << ilog(f.mode_config.len() as i32 - 1)) - 1);
+               if let Some(m) = f.mode_config.get(mode as usize) {
+                  f.resync_long_block = Some(m.blockflag != 0);
+               }
+            }
             return data_len as i32;
          }
          // delete entry
@@ -3534,7 +6338,7 @@ unsafe fn vorbis_search_for_page_pushdata(f: &mut Vorbis, data: &[u8]) -> i32
 // if the fast table above doesn't work, we want to binary
 // search them... need to reverse the bits
 
-unsafe fn compute_sorted_huffman(c: &mut Codebook, lengths: &mut [u8], values: &[u32])
+fn compute_sorted_huffman(c: &mut Codebook, lengths: &[u8], values: &[u32]) -> bool
 {
    // build a list of all the entries
    // OPTIMIZATION: don't include the short ones, since they'll be caught by FAST_HUFFMAN.
@@ -3549,7 +6353,9 @@ unsafe fn compute_sorted_huffman(c: &mut Codebook, lengths: &mut [u8], values: &
             k += 1;
          }
       }
-      assert!(k == c.sorted_entries);
+      if k != c.sorted_entries {
+         return false;
+      }
    } else {
       for i in 0 .. c.sorted_entries {
          c.sorted_codewords[i as usize] = bit_reverse(
@@ -3589,7 +6395,10 @@ unsafe fn compute_sorted_huffman(c: &mut Codebook, lengths: &mut [u8], values: &
                n >>= 1;
             }
          }
-         assert!(c.sorted_codewords[x as usize] == code);
+         // only a broken length table gives two entries the same code
+         if c.sorted_codewords[x as usize] != code {
+            return false;
+         }
          if c.sparse == true {
             c.sorted_values[x as usize] = values[i as usize] as i32;
             c.codeword_lengths[x as usize] = huff_len;
@@ -3599,10 +6408,11 @@ unsafe fn compute_sorted_huffman(c: &mut Codebook, lengths: &mut [u8], values: &
       }
 
    }
+   return true;
 }
 
-unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode, 
-    left_start: i32, right_start: i32, right_end: i32) -> Result<(i32, i32), VorbisError>
+unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode, tables: &SetupTables,
+    left_start: i32, right_start: i32, right_end: i32) -> Result<(i32, i32), VorbisErrorKind>
 {
     let mut left_start = left_start;
 
@@ -3613,24 +6423,74 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
 // WINDOWING
 
     let n = f.blocksize[m.blockflag as usize] as i32;
-    let map: &Mapping = FORCE_BORROW!( &f.mapping[ m.mapping as usize ] );
+    let map: &Mapping = &tables.mappings[ m.mapping as usize ];
 
 // FLOORS
    let n2 : i32 = n >> 1;
 
    CHECK!(f);
 
-    let mut zero_channel = [false; STB_VORBIS_MAX_CHANNELS as usize];
-    let mut really_zero_channel  = [false; STB_VORBIS_MAX_CHANNELS as usize];
+    let mut zero_channel: SmallVec<[bool; 16]> = std::iter::repeat(false).take(f.channels as usize).collect();
+    let mut really_zero_channel: SmallVec<[bool; 16]> = std::iter::repeat(false).take(f.channels as usize).collect();
 
    for i in 0 .. f.channels as usize {
       let s = map.chan[i].mux as i32;
       zero_channel[i] = false;
       let floor = map.submap_floor[s as usize] as i32;
 
-      match f.floor_config[floor as usize] {
-          Floor::Type0(_) => return Err(VorbisError::InvalidStream),
-          Floor::Type1(g) => {
+      let floor_config: &Floor = &tables.floors[floor as usize];
+      match *floor_config {
+          Floor::Type0(ref g) => {
+            let amplitude = if g.amplitude_bits > 32 {
+                let low = get_bits(f, 32) as u64;
+                low | (get_bits(f, g.amplitude_bits as i32 - 32) as u64) << 32
+            } else {
+                get_bits(f, g.amplitude_bits as i32) as u64
+            };
+            if amplitude == 0 || f.valid_bits == INVALID_BITS {
+                zero_channel[i] = true;
+                continue;
+            }
+
+            let booknumber = get_bits(f, ilog(g.number_of_books as i32)) as usize;
+            if booknumber >= g.number_of_books as usize {
+                return Err(VorbisErrorKind::InvalidStream);
+            }
+            let c: &Codebook = &tables.codebooks[g.book_list[booknumber] as usize];
+            if c.dimensions <= 0 {
+                return Err(VorbisErrorKind::InvalidStream);
+            }
+            let order = g.order as usize;
+            let mut coefficients = mem::replace(&mut f.floor0_data[i].coefficients, Vec::new());
+            coefficients.clear();
+
+            // coefficients come in whole vectors, each one offset by
+            // the last scalar of the vector before it
+            let mut temp = vec![0.0; c.dimensions as usize];
+            let mut last = 0.0;
+            while coefficients.len() < order {
+                for x in temp.iter_mut() { *x = 0.0; }
+                if !codebook_decode(f, c, &mut temp, c.dimensions) {
+                    break;
+                }
+                let count = std::cmp::min(order - coefficients.len(), temp.len());
+                for &x in &temp[.. count] {
+                    coefficients.push(x + last);
+                }
+                last = coefficients[coefficients.len() - 1];
+            }
+            for x in coefficients.iter_mut() {
+                *x = 2.0 * x.cos();
+            }
+
+            let complete = coefficients.len() == order;
+            f.floor0_data[i] = Floor0Data { amplitude: amplitude, coefficients: coefficients };
+            if !complete || f.valid_bits == INVALID_BITS {
+                zero_channel[i] = true;
+                continue;
+            } // behavior according to spec
+          }
+          Floor::Type1(ref g) => {
             if get_bits(f, 1) == 0 {
                 zero_channel[i as usize] = true;
                 continue;
@@ -3639,7 +6499,7 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
             static RANGE_LIST: [i32; 4] = [ 256, 128, 86, 64 ];
             let range = RANGE_LIST[ (g.floor1_multiplier-1) as usize];
             let mut offset = 2;
-            let mut final_y : &mut [YTYPE] = FORCE_BORROW_MUT!( f.final_y[i].as_mut_slice());
+            let mut final_y = mem::replace(&mut f.final_y[i], Vec::new());
             final_y[0] = get_bits(f, ilog(range)-1) as i16;
             final_y[1] = get_bits(f, ilog(range)-1) as i16;
             for j in 0 .. g.partitions as usize {
@@ -3648,8 +6508,7 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
                 let cbits = g.class_subclasses[pclass];
                 let csub = (1 << cbits)-1;
                 let mut cval = if cbits != 0 {
-                    let c: &mut Codebook = FORCE_BORROW_MUT!( &mut f.codebooks[ g.class_masterbooks[pclass] as usize]);
-                    decode_raw(f, c)
+                    decode_raw(f, &tables.codebooks[ g.class_masterbooks[pclass] as usize])
                 }else{
                     0
                 };
@@ -3658,8 +6517,7 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
                     let book = g.subclass_books[pclass][ (cval & csub) as usize];
                     cval >>= cbits;
                     if book >= 0 {
-                        let c: &mut Codebook = FORCE_BORROW_MUT!( &mut f.codebooks[book as usize] );
-                        let temp : i32 = decode_raw(f, c);
+                        let temp : i32 = decode_raw(f, &tables.codebooks[book as usize]);
                         final_y[offset] = temp as i16;
                     } else {
                         final_y[offset] = 0;
@@ -3669,6 +6527,7 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
             }
 
             if f.valid_bits == INVALID_BITS {
+                f.final_y[i] = final_y;
                 zero_channel[i as usize] = true;
                 continue;
             } // behavior according to spec
@@ -3687,7 +6546,8 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
                     final_y[low] as i32, 
                     final_y[high] as i32
                 );
-                let val = final_y[j];
+                // in i32, a broken stream can push these past i16
+                let val = final_y[j] as i32;
                 let highroom = range - pred;
                 let lowroom = pred;
                 let room = if highroom < lowroom {
@@ -3701,16 +6561,16 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
                     step2_flag[high] = 1;
                     step2_flag[j] = 1;
                     
-                    if val >= room as i16 {
+                    if val >= room {
                         if highroom > lowroom {
-                            final_y[j] = (val - lowroom as i16 + pred as i16) as i16;
+                            final_y[j] = (val - lowroom + pred) as i16;
                         } else {
-                            final_y[j] = (pred as i16 - val + highroom as i16 - 1) as i16;
+                            final_y[j] = (pred - val + highroom - 1) as i16;
                         }
                     } else if (val & 1) != 0 {
-                        final_y[j] = pred as i16 - ((val+1)>>1);
+                        final_y[j] = (pred - ((val+1)>>1)) as i16;
                     } else {
-                        final_y[j] = pred as i16+ (val>>1);
+                        final_y[j] = (pred + (val>>1)) as i16;
                     }
                 } else {
                     step2_flag[j] = 0;
@@ -3724,6 +6584,7 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
                     final_y[j] = -1;
                 }
             }
+            f.final_y[i] = final_y;
           }
       }
    }
@@ -3761,7 +6622,7 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
          }
       }
       let r = map.submap_residue[i];
-      decode_residue(f, &mut residue_buffers, n2, r as i32, &do_not_decode);
+      decode_residue(f, tables, &mut residue_buffers, n2, r as i32, &do_not_decode);
    }
 
    CHECK!(f);
@@ -3770,8 +6631,15 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
    for &map_chan in map.chan[.. map.coupling_steps as usize].iter().rev() {
       let n2 = n >> 1;
       let MappingChannel{magnitude, angle, ..} = map_chan;
-      let mut m : &mut Vec<f32> = FORCE_BORROW_MUT!( &mut f.channel_buffers[magnitude as usize] );
-      let mut a : &mut Vec<f32> = FORCE_BORROW_MUT!( &mut f.channel_buffers[angle  as usize] );
+      // the setup never couples a channel with itself
+      let (magnitude, angle) = (magnitude as usize, angle as usize);
+      let (m, a) = if magnitude < angle {
+         let (low, high) = f.channel_buffers.split_at_mut(angle);
+         (&mut low[magnitude], &mut high[0])
+      } else {
+         let (low, high) = f.channel_buffers.split_at_mut(magnitude);
+         (&mut high[0], &mut low[angle])
+      };
       for j in 0 .. n2 as usize {
          let a2 : f32;
          let m2 : f32;
@@ -3803,15 +6671,16 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
           std::ptr::write_bytes(f.channel_buffers[i].as_mut_ptr(), 0, n2 as usize);
       } else {
           let n = n as usize;
-          try!( do_floor(&f.floor_config, map, i, n, &mut f.channel_buffers[i], &f.final_y[i]));
+          try!( do_floor(tables.floors, map, i, n, &mut f.channel_buffers[i], &f.final_y[i], f.floor0_data.get(i)));
       }
    }
 
 // INVERSE MDCT
    CHECK!(f);
    for i in 0 .. f.channels as usize {
-      let cb : &mut Vec<f32> = FORCE_BORROW_MUT!(&mut f.channel_buffers[i]);
-      inverse_mdct(cb, n, f, m.blockflag as i32);
+      let mut cb = mem::replace(&mut f.channel_buffers[i], Vec::new());
+      inverse_mdct(&mut cb, n, f, m.blockflag as i32);
+      f.channel_buffers[i] = cb;
    }
    CHECK!(f);
 
@@ -3819,44 +6688,59 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
    // and want to flush to get to the next packet
    flush_packet(f);
 
-   if f.first_decode == true {
-      // assume we start so first non-discarded sample is sample 0
-      // this isn't to spec, but spec would require us to read ahead
-      // and decode the size of all current frames--could be done,
-      // but presumably it's not a commonly used feature
-      // NOTE(bungcip): maybe this is bug?
-      f.current_loc = -n2 as u32; // start of first frame is positioned for discard
-      // we might have to discard samples "from" the next frame too,
-      // if we're lapping a large block then a small at the start?
-      f.discard_samples_deferred = n - right_end;
+   let first_frame = f.first_decode;
+   f.first_frame = first_frame;
+   if first_frame == true {
+      // nothing comes before the first frame to overlap it, so the stream
+      // starts at its center, as in libvorbis: it puts out the samples
+      // from there to where the next frame overlaps it, which are none
+      // unless a long block is followed by a short one. the samples a
+      // stream cut at its start is to lose go from there on (see
+      // start_trim())
+      left_start = n2;
+      p_left = left_start;
+      f.current_loc = 0;
+      f.discard_samples_deferred = f.start_trim;
       f.current_loc_valid = true;
       f.first_decode = false; 
-   } else if f.discard_samples_deferred != 0 {
-      if f.discard_samples_deferred >= right_start - left_start {
-         f.discard_samples_deferred -= right_start - left_start;
-         left_start = right_start;
-         p_left = left_start;
-      } else {
-         left_start += f.discard_samples_deferred;
-         f.discard_samples_deferred = 0;
-         p_left = left_start;
-      }
-   } else if f.previous_length == 0 && f.current_loc_valid == true {
+   }
+   f.discarded = 0;
+   if f.discard_samples_deferred != 0 {
+      let discarded = std::cmp::min(f.discard_samples_deferred, right_start - left_start);
+      f.discard_samples_deferred -= discarded;
+      f.discarded = discarded;
+      left_start += discarded;
+      p_left = left_start;
+   } else if first_frame == false && f.previous_length == 0 && f.current_loc_valid == true {
       // we're recovering from a seek... that means we're going to discard
       // the samples from this packet even though we know our position from
       // the last page header, so we need to update the position based on
       // the discarded samples here
       // but wait, the code below is going to add this in itself even
       // on a discard, so we don't need to do it here...
+      //
+      // ...except the page header gives the center of the packet before
+      // this one. a long packet's left_start lines up with that, but a
+      // short packet after a long one starts (n1-n0)/4 later, and if we
+      // don't know what came before we'll pick it up from the next page
+      if m.blockflag == 0 {
+         match f.resync_long_block {
+            Some(true)  => f.current_loc = f.current_loc.wrapping_add(((f.blocksize_1 - f.blocksize_0) >> 2) as u64),
+            Some(false) => {},
+            None        => f.current_loc_valid = false,
+         }
+      }
+      f.resync_long_block = None;
    }
    
    // check if we have ogg information about the sample # for this packet
    if f.last_seg_which == f.end_seg_with_known_loc {
       // if we have a valid current loc, and this is final:
       if f.current_loc_valid == true && (f.page_flag & PAGEFLAG_LAST_PAGE) != 0 {
-         let current_end : u32 = f.known_loc_for_packet - (n-right_end) as u32;
+         // a broken granule can be anything; wrap like the C does
+         let current_end : u64 = f.known_loc_for_packet.wrapping_sub((n-right_end) as u64);
          // then let's infer the size of the (probably) short final frame
-         if current_end < f.current_loc + (right_end-left_start) as u32 {
+         if current_end < f.current_loc.wrapping_add((right_end-left_start) as u64) {
             if current_end < f.current_loc {
                // negative truncation, that's impossible!
                len = 0;
@@ -3867,7 +6751,8 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
             if len > right_end {
                 len = right_end; // this should never happen
             }
-            f.current_loc += len as u32;
+            // only the samples from left_start on are returned
+            f.current_loc = f.current_loc.wrapping_add((len - left_start) as u64);
 
             return Ok((len, p_left));
             // return true;
@@ -3876,13 +6761,14 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
       // otherwise, just set our sample loc
       // guess that the ogg granule pos refers to the _middle_ of the
       // last frame?
-      // set f.current_loc to the position of left_start
-      f.current_loc = f.known_loc_for_packet - (n2-left_start) as u32;
+      // set f.current_loc to the position of left_start, which wraps
+      // around for the first frame when the page ends right after it
+      f.current_loc = f.known_loc_for_packet.wrapping_sub((n2-left_start) as u64);
       f.current_loc_valid = true;
    }
 
    if f.current_loc_valid == true {
-       let temp_1 = (right_start - left_start) as u32;
+       let temp_1 = (right_start - left_start) as u64;
       // NOTE(bungcip): maybe this is bug?
       f.current_loc = f.current_loc.wrapping_add(temp_1);
    }
@@ -3894,21 +6780,30 @@ unsafe fn vorbis_decode_packet_rest(f: &mut Vorbis, m: &Mode,
 }
 
 
-unsafe fn decode_residue(f: &mut Vorbis, residue_buffers: &mut AudioBufferSlice<f32>, n: i32, rn: i32, do_not_decode: &[bool])
+unsafe fn decode_residue(f: &mut Vorbis, tables: &SetupTables, residue_buffers: &mut AudioBufferSlice<f32>, n: i32, rn: i32, do_not_decode: &[bool])
 {
    let ch = residue_buffers.channel_count() as i32;
-   let r: &Residue = FORCE_BORROW!( &f.residue_config[rn as usize] );
+   let r: &Residue = &tables.residues[rn as usize];
    let rtype = f.residue_types[rn as usize] as i32;
    let c = r.classbook as i32;
-   let classwords = f.codebooks[c as usize].dimensions as usize;
-   let n_read = (r.end - r.begin) as i32;
+   let classwords = tables.codebooks[c as usize].dimensions as usize;
+   // a setup can ask for more than the block holds; clamp to what's there
+   let actual_size = if rtype == 2 { n * ch } else { n };
+   let limit_r_begin = std::cmp::min(r.begin as i32, actual_size);
+   let limit_r_end = std::cmp::min(r.end as i32, actual_size);
+   let n_read = limit_r_end - limit_r_begin;
    let part_read = n_read / r.part_size as i32;
    
    // NOTE(bungcip): optimize?
-   let mut part_classdata = Vec::with_capacity(f.channels as usize);
-   for _ in 0 .. f.channels {
-       let mut temp: Vec<Vec<u8>> = Vec::with_capacity(part_read as usize);
-       temp.resize(part_read as usize, Vec::new());
+   // one classification per 'classwords' partitions, and type 2 only
+   // ever fills the first channel's; a hostile setup with a part size of
+   // 1 over many channels would otherwise allocate gigabytes per packet
+   let class_sets = (part_read as usize + classwords) / std::cmp::max(classwords, 1);
+   let class_channels = if rtype == 2 { 1 } else { f.channels };
+   let mut part_classdata = Vec::with_capacity(class_channels as usize);
+   for _ in 0 .. class_channels {
+       let mut temp: Vec<Vec<u8>> = Vec::with_capacity(class_sets);
+       temp.resize(class_sets, Vec::new());
        part_classdata.push(temp);
    }
 
@@ -3942,7 +6837,7 @@ unsafe fn decode_residue(f: &mut Vorbis, residue_buffers: &mut AudioBufferSlice<
                let mut c_inter = z & 1;
                let mut p_inter = z >> 1;
                if pass == 0 {
-                  let c: &Codebook = FORCE_BORROW!(&f.codebooks[r.classbook as usize]);
+                  let c : &Codebook = &tables.codebooks[r.classbook as usize];
                   let q = decode_raw(f,c);
                   if q == EOP {
                     return;
@@ -3958,7 +6853,7 @@ unsafe fn decode_residue(f: &mut Vorbis, residue_buffers: &mut AudioBufferSlice<
                   let c = part_classdata[0][class_set][i] as i32;
                   let b = r.residue_books[c as usize][pass as usize] as i32;
                   if b >= 0 {
-                    let book : &Codebook = FORCE_BORROW!( &f.codebooks[b as usize] );
+                    let book : &Codebook = &tables.codebooks[b as usize];
                      // saves 1%
                      if codebook_decode_deinterleave_repeat(f, book, residue_buffers, &mut c_inter, &mut p_inter, n, r.part_size as i32) == false {
                          return;
@@ -3978,7 +6873,7 @@ unsafe fn decode_residue(f: &mut Vorbis, residue_buffers: &mut AudioBufferSlice<
                let mut c_inter = 0;
                let mut p_inter = z as i32;
                if pass == 0 {
-                  let c : &Codebook = FORCE_BORROW!( &f.codebooks[r.classbook as usize] );
+                  let c : &Codebook = &tables.codebooks[r.classbook as usize];
                   let q = decode_raw(f,c);
                   if q == EOP{
                       return;
@@ -3992,7 +6887,7 @@ unsafe fn decode_residue(f: &mut Vorbis, residue_buffers: &mut AudioBufferSlice<
                   let c = part_classdata[0][class_set as usize][i as usize] as i32;
                   let b = r.residue_books[c as usize][pass as usize] as i32;
                   if b >= 0 {
-                     let book : &Codebook = FORCE_BORROW!( &f.codebooks[b as usize] );
+                     let book : &Codebook = &tables.codebooks[b as usize];
                      if codebook_decode_deinterleave_repeat(f, book, residue_buffers, &mut c_inter, &mut p_inter, n, r.part_size as i32) == false {
                          return;
                      }
@@ -4011,7 +6906,7 @@ unsafe fn decode_residue(f: &mut Vorbis, residue_buffers: &mut AudioBufferSlice<
                let mut c_inter = z % ch;
                let mut p_inter = z / ch;
                if pass == 0 {
-                  let c : &Codebook = FORCE_BORROW!( &f.codebooks[r.classbook as usize] );
+                  let c : &Codebook = &tables.codebooks[r.classbook as usize];
                   let q = decode_raw(f,c);
                   if q == EOP{
                       return;
@@ -4026,7 +6921,7 @@ unsafe fn decode_residue(f: &mut Vorbis, residue_buffers: &mut AudioBufferSlice<
                   let c = part_classdata[0][class_set as usize][i as usize] as i32;
                   let b = r.residue_books[c as usize][pass as usize] as i32;
                   if b >= 0 {
-                     let book : &Codebook = FORCE_BORROW!( &f.codebooks[b as usize] );
+                     let book : &Codebook = &tables.codebooks[b as usize];
                      if codebook_decode_deinterleave_repeat(f, book, residue_buffers, &mut c_inter, &mut p_inter, n, r.part_size as i32) == false {
                          return;
                      }
@@ -4053,7 +6948,7 @@ unsafe fn decode_residue(f: &mut Vorbis, residue_buffers: &mut AudioBufferSlice<
          if pass == 0 {
             for j in 0 .. residue_buffers.channel_count() as usize {
                if do_not_decode[j] == false {
-                  let c : &Codebook = FORCE_BORROW!( &f.codebooks[r.classbook as usize]);
+                  let c : &Codebook = &tables.codebooks[r.classbook as usize];
                   let temp = decode_raw(f,c);
                   if temp == EOP {
                       return;
@@ -4075,7 +6970,7 @@ unsafe fn decode_residue(f: &mut Vorbis, residue_buffers: &mut AudioBufferSlice<
                       let mut target = &mut residue_buffers[j];
                       let offset =  r.begin as i32 + pcount*r.part_size as i32;
                       let n = r.part_size as i32;
-                      let book : &Codebook = FORCE_BORROW!( &f.codebooks[b as usize] );
+                      let book : &Codebook = &tables.codebooks[b as usize];
                       if residue_decode(f, book, &mut target, offset, n, rtype) == false {
                           return;
                       }
@@ -4102,7 +6997,8 @@ unsafe fn imdct_step3_iter0_loop(n: i32, e: *mut f32, i_off: i32, k_off: i32 , m
    let mut ee2 = ee0.offset(k_off as isize);
    let mut i : i32;
 
-   assert!((n & 3) == 0);
+   // n is a blocksize over 16, and blocksizes are powers of two from 64 up
+   debug_assert!((n & 3) == 0);
    i = n>>2;
    while i > 0 {
       let mut k00_20: f32;
@@ -4531,8 +7427,8 @@ unsafe fn inverse_mdct(buffer: &mut [f32], n: i32, f: &mut Vorbis, blocktype: i3
    // (paper output is u, now v)
 
 
-   // data must be in buf2
-   assert!(v == buf2.as_mut_ptr());
+   // data must be in buf2; v never points anywhere else
+   debug_assert!(v == buf2.as_mut_ptr());
 
    // step 7   (paper output is v, now v)
    // this is now in place
@@ -4637,64 +7533,206 @@ unsafe fn inverse_mdct(buffer: &mut [f32], n: i32, f: &mut Vorbis, blocktype: i3
 
 }
 
+// how many samples the start of the link loses. a stream cut out of a
+// longer one starts with a granule position smaller than the samples its
+// packets decode to (each but the first puts out a quarter of its block
+// and a quarter of the one before); as in libvorbis the difference comes
+// off the start. reads ahead from the first audio page and comes back,
+// so pull and memory mode only: in push mode nothing is cut
+fn start_trim(f: &mut Vorbis) -> i32
+{
+   let offset = stb_vorbis_get_file_offset(f);
+   let eof = f.eof;
+   let mode_count = f.mode_config.len() as i32;
+   let mode_mask = (1 << ilog(mode_count - 1)) - 1;
+
+   let mut samples: u64 = 0;
+   let mut previous = 0; // block size of the last packet, 0 before the first
+   let mut current = 0;  // block size of the packet being read, 0 if none
+   let mut packet_start = true;
+   let mut trim: u64 = 0;
+   // the first page with a granule position comes early, or the stream
+   // isn't worth guessing about
+   for _ in 0 .. 32 {
+      let mut header: [u8; 27] = [0; 27];
+      if getn(f, &mut header[..]) == false || header.starts_with(&OGG_PAGE_HEADER) == false {
+         break;
+      }
+      let mut lacing: [u8; 255] = [0; 255];
+      let segments = header[26] as usize;
+      if getn(f, &mut lacing[.. segments]) == false {
+         break;
+      }
+      if page_serial(&header) != f.serial {
+         let len: i32 = lacing[.. segments].iter().map(|&l| l as i32).sum();
+         skip(f, len);
+         continue;
+      }
+      for &l in lacing[.. segments].iter() {
+         let mut len = l as i32;
+         if packet_start == true && len > 0 {
+            // audio packets start with a 0 bit, then the mode number
+            let byte = get8(f) as i32;
+            len -= 1;
+            let mode = (byte >> 1) & mode_mask;
+            current = 0;
+            if byte & 1 == 0 && mode < mode_count {
+               current = f.blocksize[f.mode_config[mode as usize].blockflag as usize];
+            }
+         }
+         skip(f, len);
+         packet_start = l < 255;
+         if packet_start == true && current != 0 {
+            if previous != 0 {
+               samples += (previous / 4 + current / 4) as u64;
+            }
+            previous = current;
+            current = 0;
+         }
+      }
+      if f.eof == true {
+         break;
+      }
+      let granule = page_granule(&header);
+      if granule != !0 {
+         // a short last page is cut at the end instead
+         if (header[5] & PAGEFLAG_LAST_PAGE) == 0 && granule < samples {
+            trim = samples - granule;
+         }
+         break;
+      }
+   }
+
+   set_file_offset(f, offset);
+   f.eof = eof;
+   return std::cmp::min(trim, f.blocksize_1 as u64 * 32) as i32;
+}
+
 unsafe fn start_decoder(f: &mut Vorbis) -> bool
 {
    let mut header : [u8; 6] = [0; 6];
    let mut longest_floorlist = 0;
-   use VorbisError::*;
+   let mut longest_floor0_order = 0;
+   use VorbisErrorKind::*;
+
+   // we're called again for every link of a chained stream
+   f.codebooks.clear();
+   f.floor_config.clear();
+   f.residue_types.clear();
+   f.residue_config.clear();
+   f.mapping.clear();
+   f.mode_config.clear();
+
+   // first page(s), first packet. a multiplexed file starts with the first
+   // page of every logical stream in it; note them all and pick the vorbis
+   // one (or the one asked for)
+   f.streams.clear();
+   let mut chosen = false;
+   loop {
+      if f.streams.is_empty() == true {
+         skip_junk(f);
+      }
+      let mut page : [u8; 27] = [0; 27];
+      let peeked = peek_page_header(f, &mut page) && page[0 .. 4] == OGG_PAGE_HEADER;
+      if f.streams.is_empty() == false && (peeked == false || (page[5] & PAGEFLAG_FIRST_PAGE) == 0) {
+         break;
+      }
+      let serial = page_serial(&page);
+
+      if start_page(f) == false                              {return false;} 
+      // validate page flag
+      if (f.page_flag & PAGEFLAG_FIRST_PAGE) == 0       {return error(f, InvalidFirstPage)}
+      let mut len = 0;
+      for i in 0 .. f.segment_count as usize {
+         len += f.segments[i] as i32;
+      }
 
-   // first page, first packet
+      // the id header is a single 30 byte packet on a page of its own
+      let mut used = 0;
+      let mut vorbis = false;
+      if (f.page_flag & (PAGEFLAG_LAST_PAGE | PAGEFLAG_CONTINUED_PACKET)) == 0 && f.segment_count == 1 && f.segments[0] == 30 {
+         // check packet header
+         let mut id : [u8; 7] = [0; 7];
+         if getn(f, &mut id[..]) == false                         {return error(f, UnexpectedEof);}
+         used = 7;
+         vorbis = id[0] == PACKET_ID && vorbis_validate(&id[1 ..]);
+      }
+      f.streams.push(OggStream { serial: serial, vorbis: vorbis });
+
+      if vorbis == true && (chosen == false || f.wanted_serial == Some(serial)) {
+         chosen = true;
+         f.serial = serial;
+         // read packet
+         f.packet_type = Some(PACKET_ID);
+         // vorbis_version
+         if get32(f) != 0                               {return error(f, InvalidFirstPage);}
+         f.channels = get8(f) as i32; if f.channels == 0        { return error(f, InvalidFirstPage);}
+         if f.channels as usize > f.limits.max_channels { return error(f, TooManyChannels);}
+         f.sample_rate = get32(f); if f.sample_rate == 0  {return error(f, InvalidFirstPage);}
+         f.bitrate_maximum = get32(f) as i32;
+         f.bitrate_nominal = get32(f) as i32;
+         f.bitrate_minimum = get32(f) as i32;
 
-   if start_page(f) == false                              {return false;} 
-   // validate page flag
-   if (f.page_flag & PAGEFLAG_FIRST_PAGE) == 0       {return error(f, InvalidFirstPage)}
-   if (f.page_flag & PAGEFLAG_LAST_PAGE) != 0           {return error(f, InvalidFirstPage);}
-   if (f.page_flag & PAGEFLAG_CONTINUED_PACKET) != 0   {return error(f, InvalidFirstPage);}
-   // check for expected packet length
-   if f.segment_count != 1                       {return error(f, InvalidFirstPage);}
-   if f.segments[0] != 30                        {return error(f, InvalidFirstPage);}
-   // read packet
-   // check packet header
-   if get8(f) != PACKET_ID                 {return error(f, InvalidFirstPage);}
-   if getn(f, &mut header[..]) == false                         {return error(f, UnexpectedEof);}
-   if vorbis_validate(&header) == false                    {return error(f, InvalidFirstPage);}
-   // vorbis_version
-   if get32(f) != 0                               {return error(f, InvalidFirstPage);}
-   f.channels = get8(f) as i32; if f.channels == 0        { return error(f, InvalidFirstPage);}
-   if f.channels > STB_VORBIS_MAX_CHANNELS       {return error(f, TooManyChannels);}
-   f.sample_rate = get32(f); if f.sample_rate == 0  {return error(f, InvalidFirstPage);}
-   get32(f); // bitrate_maximum
-   get32(f); // bitrate_nominal
-   get32(f); // bitrate_minimum
+         {
+            let x = get8(f);
+            let log0 = (x & 15) as i32;
+            let log1 = (x >> 4) as i32;
+            f.blocksize_0 = 1 << log0;
+            f.blocksize_1 = 1 << log1;
+            if log0 < 6 || log0 > 13                       {return error(f, InvalidSetup);}
+            if log1 < 6 || log1 > 13                       {return error(f, InvalidSetup);}
+            if log0 > log1                                 {return error(f, InvalidSetup);}
+         }
 
-   {
-      let x = get8(f);
-      let log0 = (x & 15) as i32;
-      let log1 = (x >> 4) as i32;
-      f.blocksize_0 = 1 << log0;
-      f.blocksize_1 = 1 << log1;
-      if log0 < 6 || log0 > 13                       {return error(f, InvalidSetup);}
-      if log1 < 6 || log1 > 13                       {return error(f, InvalidSetup);}
-      if log0 > log1                                 {return error(f, InvalidSetup);}
-   }
+         // framing_flag
+         {
+              let x = get8(f);
+              if (x & 1) == 0                                    {return error(f, InvalidFirstPage);}
+         }
+         used = 30;
+      }
 
-   // framing_flag
-   {
-        let x = get8(f);
-        if (x & 1) == 0                                    {return error(f, InvalidFirstPage);}
+      // some other stream's first page
+      if len > used {
+         skip(f, len - used);
+      }
    }
+   if chosen == false                                     {return error(f, InvalidFirstPage);}
 
    // second packet!
    if start_page(f) == false                              {return false;} 
    if start_packet(f) == false                            {return false;} 
+   f.packet_type = Some(PACKET_COMMENT);
+   if get8_packet(f) != PACKET_COMMENT as i32             {return error(f, InvalidSetup);}
+   for item in header.iter_mut().take(6){
+       *item = get8_packet(f) as u8;
+   }
+   if vorbis_validate(&header) == false                    {return error(f, InvalidSetup);}
+   
+   // a truncated comment list isn't worth rejecting the stream over,
+   // keep whatever was readable
+   f.comments = VorbisComments::default();
+   let mut left = f.limits.max_comment_bytes;
+   if let Some(vendor) = get_string_packet(f, &mut left) {
+      f.comments.vendor = vendor;
+      let comment_count = get32_packet(f).unwrap_or(0);
+      for _ in 0 .. comment_count {
+         // each comment costs its entry as well as its text
+         let entry = mem::size_of::<(String, String)>();
+         if left < entry {
+            break;
+         }
+         left -= entry;
+         match get_string_packet(f, &mut left) {
+             Some(comment) => f.comments.push(&comment),
+             None          => break,
+         }
+      }
+   }
+   flush_packet(f);
+   if f.eof == true                                       {return error(f, UnexpectedEof);}
    
    let mut len;
-   while {
-      len = next_segment(f);
-      skip(f, len);
-      f.bytes_in_seg = 0;
-      len != 0
-   } {/* do nothing */}
 
    // third packet!
    if start_packet(f) == false                            {return false;} 
@@ -4707,8 +7745,8 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
         return false;
    }
 
-   crc32_init(); // always init it, to avoid multithread race conditions
 
+   f.packet_type = Some(PACKET_SETUP);
    if get8_packet(f) != PACKET_SETUP as i32       {return error(f, InvalidSetup);}
    for item in header.iter_mut().take(6){
        *item = get8_packet(f) as u8;
@@ -4716,9 +7754,10 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
    if vorbis_validate(&header) == false                    {return error(f, InvalidSetup);}
 
    // codebooks
+   f.setup_bytes = 0;
    let codebook_count = (get_bits(f,8) + 1) as i32;
    f.codebooks.reserve(codebook_count as usize);
-   'codebook: for _ in 0 .. codebook_count {
+   for _ in 0 .. codebook_count {
       let mut c : Codebook = Codebook::default();
 
       CHECK!(f);
@@ -4735,34 +7774,32 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
 
       if c.dimensions == 0 && c.entries != 0    {return error(f, InvalidSetup);}
 
-      let mut _lengths: Vec<u8> = Vec::new(); // NOTE(bungcip): just temporary
-      let mut lengths: &mut [u8] = if c.sparse == true {
-          _lengths.resize(c.entries as usize, 0);
-          FORCE_BORROW_MUT!( &mut _lengths[..] )
-      }else{
-          c.codeword_lengths.resize(c.entries as usize, 0);
-          FORCE_BORROW_MUT!( &mut c.codeword_lengths[..] )
-      };
+      if setup_malloc(f, c.entries as u64, 1) == false     {return false;}
+      let mut lengths: Vec<u8> = vec![0; c.entries as usize];
 
       let mut total = 0;
       if is_ordered  {
          let mut current_entry = 0;
          let mut current_length = (get_bits(f,5) + 1) as i32;
          while current_entry < c.entries {
+            if current_length >= 32 {
+                return error(f, InvalidSetup);
+            }
             let limit = c.entries - current_entry;
             let n     = get_bits(f, ilog(limit)) as i32;
             if current_entry + n > c.entries as i32 { 
                 return error(f, InvalidSetup);
             }
 
-            let mut lengths_slice = &mut lengths[current_entry as usize .. (current_entry + n) as usize];
-            std::ptr::write_bytes(lengths_slice.as_mut_ptr(), current_length as u8, n as usize);
+            for item in &mut lengths[current_entry as usize .. (current_entry + n) as usize] {
+                *item = current_length as u8;
+            }
 
             current_entry += n;
             current_length += 1;
          }
       } else {
-         for item in lengths.iter_mut().take(c.entries as usize){
+         for item in lengths.iter_mut() {
             let present = if c.sparse == true { get_bits(f,1) } else { 1 };
             
             if present != 0 {
@@ -4779,8 +7816,6 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
 
       if c.sparse == true && total >= c.entries >> 2 {
          // convert sparse items to non-sparse!
-         c.codeword_lengths = _lengths.clone();
-         lengths = FORCE_BORROW_MUT!( &mut c.codeword_lengths[..] );
          c.sparse = false;
       }
 
@@ -4789,7 +7824,6 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
          total
       } else {
          lengths.iter()
-            .take(c.entries as usize)
             .filter(|&item| *item > STB_FAST_HUFFMAN_LENGTH as u8 && *item != NO_CODE)
             .count() as i32
       };
@@ -4797,28 +7831,35 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
 
       CHECK!(f);
       if c.sparse == false {
+         if setup_malloc(f, c.entries as u64, 4) == false        {return false;}
          c.codewords.resize(c.entries as usize, 0);
       } else if c.sorted_entries != 0 {
+         if setup_malloc(f, c.sorted_entries as u64, 1 + 4 + 4) == false {return false;}
          c.codeword_lengths.resize(c.sorted_entries as usize, 0);
          c.codewords.resize(c.sorted_entries as usize, 0);
          values.resize(c.sorted_entries as usize, 0);
       }
 
-      if compute_codewords(&mut c, lengths, &mut values) == false {
+      if compute_codewords(&mut c, &mut lengths, &mut values) == false {
         return error(f, InvalidSetup);
       }
 
       if c.sorted_entries != 0 {
          // allocate an extra slot for sentinels
+         if setup_malloc(f, c.sorted_entries as u64 * 2 + 1, 4) == false {return false;}
          c.sorted_codewords.resize( (c.sorted_entries+1) as usize, 0);
          c.sorted_values.resize(c.sorted_entries as usize, 0);
          
-         compute_sorted_huffman(&mut c, &mut lengths, &values);
+         if compute_sorted_huffman(&mut c, &lengths, &values) == false {
+            return error(f, InvalidSetup);
+         }
       }
 
       if c.sparse == true {
          values.clear();
          c.codewords.clear();
+      } else {
+         c.codeword_lengths = lengths;
       }
 
       compute_accelerated_huffman(&mut c);
@@ -4833,16 +7874,18 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
          c.delta_value = float32_unpack(get_bits(f, 32));
          c.value_bits = ( get_bits(f, 4)+1 ) as u8;
          c.sequence_p = ( get_bits(f,1) ) as u8;
-         c.lookup_values =  if c.lookup_type == 1 {
-            lookup1_values(c.entries, c.dimensions) as u32
+         let lookup_values = if c.lookup_type == 1 {
+            lookup1_values(c.entries, c.dimensions) as i64
          } else {
-            c.entries as u32 * c.dimensions as u32
+            c.entries as i64 * c.dimensions as i64
          };
 
-         if c.lookup_values == 0 {
+         if lookup_values <= 0 || lookup_values > std::u32::MAX as i64 {
              return error(f, InvalidSetup);
          }
+         c.lookup_values = lookup_values as u32;
          
+         if setup_malloc(f, c.lookup_values as u64, 2) == false   {return false;}
          let mut mults : Vec<u16> = Vec::with_capacity(c.lookup_values as usize);
          for _ in 0 .. c.lookup_values {
             let q = get_bits(f, c.value_bits as i32);
@@ -4855,45 +7898,39 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
          if c.lookup_type == 1 {
             let sparse = c.sparse;
             // pre-expand the lookup1-style multiplicands, to avoid a divide in the inner loop
-            let multiplicands_count = if sparse {
-                if c.sorted_entries == 0 { 
-                    continue 'codebook;
-                }
-                c.sorted_entries * c.dimensions
-            }else{
-                c.entries * c.dimensions
-            } as usize;
-
-            c.multiplicands.resize(multiplicands_count, 0.0);
-
-            
+            // (a sparse book with no codes at all has nothing to expand)
             len = if sparse  { c.sorted_entries } else {c.entries};
-            let mut last : f32 = 0.0;
-            for j in 0 .. len {
-               let z : u32 = if sparse  { c.sorted_values[j as usize] } else {j} as u32;
-               let mut div: u32 = 1;
-               for k in 0 .. c.dimensions {
-                  let off: i32 = (z / div) as i32 % c.lookup_values as i32;
-                //   let mut val: f32 = *mults.offset(off as isize) as f32; // NOTE(bungcip) : maybe bugs?
-                  let val = mults[off as usize] as f32 * c.delta_value + c.minimum_value + last;
-                  c.multiplicands[ (j * c.dimensions + k) as usize] = val;
-                  if c.sequence_p != 0 {
-                     last = val;
-                  }
-                  if k + 1 < c.dimensions {
-                     use std::u32;
-                     if div > u32::MAX / c.lookup_values as u32 {
-                        return error(f, InvalidSetup);
+            if len > 0 {
+               let multiplicands_count = len as u64 * c.dimensions as u64;
+               if setup_malloc(f, multiplicands_count, 4) == false {return false;}
+               c.multiplicands.resize(multiplicands_count as usize, 0.0);
+
+               let mut last : f32 = 0.0;
+               for j in 0 .. len {
+                  let z : u32 = if sparse  { c.sorted_values[j as usize] } else {j} as u32;
+                  let mut div: u32 = 1;
+                  for k in 0 .. c.dimensions {
+                     let off: i32 = (z / div) as i32 % c.lookup_values as i32;
+                     let val = mults[off as usize] as f32 * c.delta_value + c.minimum_value + last;
+                     c.multiplicands[ (j * c.dimensions + k) as usize] = val;
+                     if c.sequence_p != 0 {
+                        last = val;
+                     }
+                     if k + 1 < c.dimensions {
+                        use std::u32;
+                        if div > u32::MAX / c.lookup_values as u32 {
+                           return error(f, InvalidSetup);
+                        }
+                        div *= c.lookup_values;
                      }
-                     div *= c.lookup_values;
                   }
                }
+               c.lookup_type = 2;
             }
-            c.lookup_type = 2;
-
          } else {
             let mut last = 0.0;
             CHECK!(f);
+            if setup_malloc(f, c.lookup_values as u64, 4) == false {return false;}
             c.multiplicands.reserve(c.lookup_values as usize);
             for j in mults {
                let val : f32 = j as f32 * c.delta_value + c.minimum_value + last;
@@ -4926,19 +7963,38 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
    for _ in 0 .. floor_count {
       match get_bits(f, 16){
           0 => {
-                let mut g: Floor0 = mem::zeroed();
-                g.order = get_bits(f,8) as u8;
-                g.rate = get_bits(f,16) as u16;
-                g.bark_map_size = get_bits(f,16) as u16;
-                g.amplitude_bits = get_bits(f,6) as u8;
-                g.amplitude_offset = get_bits(f,8) as u8;
-                g.number_of_books = (get_bits(f,4) + 1) as u8;
-                for j in 0 .. g.number_of_books as usize {
-                    g.book_list[j] = get_bits(f,8) as u8;
+                let order = get_bits(f,8) as u8;
+                let rate = get_bits(f,16) as u16;
+                let bark_map_size = get_bits(f,16) as u16;
+                let amplitude_bits = get_bits(f,6) as u8;
+                let amplitude_offset = get_bits(f,8) as u8;
+                let number_of_books = (get_bits(f,4) + 1) as u8;
+                let mut book_list = [0; 16];
+                for j in 0 .. number_of_books as usize {
+                    book_list[j] = get_bits(f,8) as u8;
+                    if book_list[j] as i32 >= codebook_count {
+                        return error(f, InvalidSetup);
+                    }
+                }
+                if rate == 0 || bark_map_size == 0 {
+                    return error(f, InvalidSetup);
                 }
 
-                f.floor_config.push(Floor::Type0(g));
-                return error(f, FeatureNotSupported);
+                let bark_map = [
+                    floor0_bark_map(f.blocksize_0 >> 1, rate, bark_map_size),
+                    floor0_bark_map(f.blocksize_1 >> 1, rate, bark_map_size),
+                ];
+                longest_floor0_order = std::cmp::max(order as usize, longest_floor0_order);
+
+                f.floor_config.push(Floor::Type0(Floor0 {
+                    order: order,
+                    bark_map_size: bark_map_size,
+                    amplitude_bits: amplitude_bits,
+                    amplitude_offset: amplitude_offset,
+                    number_of_books: number_of_books,
+                    book_list: book_list,
+                    bark_map: bark_map,
+                }));
           },
           1 => {
                 let mut g : Floor1 = mem::zeroed();
@@ -4953,7 +8009,7 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
                     g.class_subclasses[j] = get_bits(f, 2) as u8;
                     if g.class_subclasses[j] != 0 {
                         g.class_masterbooks[j] = get_bits(f, 8) as u8;
-                        if g.class_masterbooks[j] >= codebook_count as u8 {
+                        if g.class_masterbooks[j] as i32 >= codebook_count {
                             return error(f, InvalidSetup);
                         }
                     }
@@ -4986,6 +8042,13 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
                     
                     points.sort();
                     
+                    // two points at the same x would make a zero-width segment
+                    for pair in points.windows(2) {
+                        if pair[0].x == pair[1].x {
+                            return error(f, InvalidSetup);
+                        }
+                    }
+
                     for (j, item) in points.iter().enumerate() {
                         g.sorted_order[j] = item.y as u8;
                     }
@@ -5102,6 +8165,11 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
       
       if get_bits(f,1) != 0 {
          m.coupling_steps = get_bits(f,8) as u16 + 1;
+         // the same pair can be coupled more than once, so there can be
+         // more steps than channels
+         if m.coupling_steps as usize > m.chan.len() {
+            m.chan.resize(m.coupling_steps as usize, MappingChannel::default());
+         }
          for k in 0 .. m.coupling_steps as usize {
             // satify borrow checker
             let ilog_result = ilog(f.channels-1);
@@ -5169,6 +8237,7 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
    f.channel_buffers.resize(f.channels as usize, Vec::new());
    f.previous_window.resize(f.channels as usize, Vec::new());
    f.final_y.resize(f.channels as usize, Vec::new());
+   f.outputs = AudioBufferSlice::new(f.channels as usize);
    
    for i in 0 .. f.channels as usize {
       let block_size_1 = f.blocksize_1;
@@ -5176,6 +8245,10 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
       f.previous_window[i].resize( (block_size_1/2) as usize, 0.0);
       f.final_y[i].resize(longest_floorlist as usize, 0);
    }
+   f.floor0_data.resize(f.channels as usize, Floor0Data::default());
+   for data in &mut f.floor0_data {
+      data.coefficients.reserve(longest_floor0_order);
+   }
 
    {  
        let blocksize_0 = f.blocksize_0;
@@ -5189,5 +8262,6 @@ unsafe fn start_decoder(f: &mut Vorbis) -> bool
 
    f.first_decode = true;
    f.first_audio_page_offset = stb_vorbis_get_file_offset(f);
+   f.start_trim = if f.push_mode == false { start_trim(f) } else { 0 };
    return true;
 }
@@ -0,0 +1,198 @@
+// Ogg page inspection: OggPageReader reads the pages of an Ogg file from
+// any reader, without a decoder, for looking into files that won't decode
+
+use std::io;
+use std::io::prelude::*;
+use std::ops::Range;
+
+use ::{OGG_PAGE_HEADER, PAGEFLAG_CONTINUED_PACKET, PAGEFLAG_FIRST_PAGE, PAGEFLAG_LAST_PAGE};
+use ::{crc32_update, page_serial, page_granule};
+
+/// one Ogg page, as stored
+#[derive(Clone, Debug, PartialEq)]
+pub struct OggPage
+{
+    /// byte offset of the page in the input
+    pub offset: u64,
+    /// bytes right before the page that aren't part of any page
+    pub skipped: u64,
+    /// stream structure version, 0 for every valid page
+    pub version: u8,
+    /// header type flags: 1 continued packet, 2 first page, 4 last page
+    pub flags: u8,
+    /// granule position of the last packet that ends on the page, !0 if
+    /// none does
+    pub granule_position: u64,
+    pub serial: u32,
+    pub sequence: u32,
+    /// the CRC in the header
+    pub crc: u32,
+    /// whether the CRC matches the page
+    pub crc_ok: bool,
+    /// the segment table
+    pub lacing: Vec<u8>,
+    /// the page body
+    pub data: Vec<u8>,
+}
+
+/// a piece of a packet on a page
+#[derive(Clone, Debug, PartialEq)]
+pub struct OggPacketSpan
+{
+    /// where it is in the page body
+    pub range: Range<usize>,
+    /// whether it goes on from the packet the previous page left unfinished
+    pub continued: bool,
+    /// whether the packet ends on this page
+    pub complete: bool,
+}
+
+impl OggPage {
+    pub fn is_continued(&self) -> bool {
+        (self.flags & PAGEFLAG_CONTINUED_PACKET) != 0
+    }
+
+    pub fn is_first(&self) -> bool {
+        (self.flags & PAGEFLAG_FIRST_PAGE) != 0
+    }
+
+    pub fn is_last(&self) -> bool {
+        (self.flags & PAGEFLAG_LAST_PAGE) != 0
+    }
+
+    /// header, segment table and body, in bytes
+    pub fn size(&self) -> usize {
+        27 + self.lacing.len() + self.data.len()
+    }
+
+    /// the packets on the page, split where the segment table says: a
+    /// lacing value under 255 ends a packet
+    pub fn packets(&self) -> Vec<OggPacketSpan> {
+        let mut spans = Vec::new();
+        let mut start = 0;
+        let mut end = 0;
+        for (i, &l) in self.lacing.iter().enumerate() {
+            end += l as usize;
+            let last = i + 1 == self.lacing.len();
+            if l < 255 || last {
+                spans.push(OggPacketSpan {
+                    range: start .. end,
+                    continued: spans.is_empty() && self.is_continued(),
+                    complete: l < 255,
+                });
+                start = end;
+            }
+        }
+        return spans;
+    }
+}
+
+/// reads the Ogg pages of 'reader' one after the other, skipping anything
+/// that isn't a page (see `OggPage::skipped`). pages are returned whatever
+/// their CRC or version; a page cut off by the end of the input is an
+/// `UnexpectedEof` error.
+pub struct OggPageReader<R>
+{
+    reader: R,
+    offset: u64,
+}
+
+impl<R: Read> OggPageReader<R> {
+    pub fn new(reader: R) -> OggPageReader<R> {
+        OggPageReader {
+            reader: reader,
+            offset: 0,
+        }
+    }
+
+    /// bytes read so far: past the end of the last page returned, or of
+    /// everything once `next_page()` has returned None
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    // reads all of 'buffer', or fails saying which page was cut off
+    fn read_page_part(&mut self, buffer: &mut [u8], start: u64) -> io::Result<()> {
+        match self.reader.read_exact(buffer) {
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("page at byte {} is cut off", start)))
+            }
+            result => result,
+        }
+    }
+
+    /// the next page, None at the end of the input
+    pub fn next_page(&mut self) -> io::Result<Option<OggPage>> {
+        let mut header = [0; 27];
+        let mut have = 0;
+        let mut skipped = 0;
+        while have < 4 {
+            let n = match self.reader.read(&mut header[have .. 4]) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                self.offset += skipped + have as u64;
+                return Ok(None);
+            }
+            have += n;
+            if have == 4 && header[.. 4] != OGG_PAGE_HEADER {
+                header.copy_within(1 .. 4, 0);
+                have = 3;
+                skipped += 1;
+            }
+        }
+        let start = self.offset + skipped;
+        // counted as read whatever happens from here
+        self.offset = start + 4;
+
+        self.read_page_part(&mut header[4 ..], start)?;
+        let mut lacing = vec![0; header[26] as usize];
+        self.read_page_part(&mut lacing, start)?;
+        let mut data = vec![0; lacing.iter().map(|&l| l as usize).sum()];
+        self.read_page_part(&mut data, start)?;
+
+        let crc = u32::from_le_bytes([header[22], header[23], header[24], header[25]]);
+        let mut computed = 0;
+        for (i, &byte) in header.iter().enumerate() {
+            // the CRC is computed with its own field zeroed
+            computed = crc32_update(computed, if (22 .. 26).contains(&i) { 0 } else { byte });
+        }
+        for &byte in lacing.iter().chain(data.iter()) {
+            computed = crc32_update(computed, byte);
+        }
+
+        let page = OggPage {
+            offset: start,
+            skipped: skipped,
+            version: header[4],
+            flags: header[5],
+            granule_position: page_granule(&header),
+            serial: page_serial(&header),
+            sequence: u32::from_le_bytes([header[18], header[19], header[20], header[21]]),
+            crc: crc,
+            crc_ok: computed == crc,
+            lacing: lacing,
+            data: data,
+        };
+        self.offset = start + page.size() as u64;
+        return Ok(Some(page));
+    }
+}
+
+impl<R: Read> Iterator for OggPageReader<R> {
+    type Item = io::Result<OggPage>;
+
+    fn next(&mut self) -> Option<io::Result<OggPage>> {
+        match self.next_page() {
+            Ok(Some(page)) => Some(Ok(page)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
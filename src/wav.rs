@@ -0,0 +1,216 @@
+// RIFF WAVE output: stb_vorbis_write_wav() decodes a stream into a WAV file
+// a block at a time, so memory use doesn't grow with the length of the
+// stream
+
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use ::{Vorbis, VorbisError, VorbisErrorKind, ChannelLayout, Sample, I24};
+use ::{stb_vorbis_get_samples_interleaved, stb_vorbis_get_output_rate, stb_vorbis_get_error};
+
+/// the sample format of the WAV files `stb_vorbis_write_wav()` writes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WavFormat
+{
+    /// 16-bit integer PCM, dithered if the decoder is set to
+    Pcm16,
+    /// 24-bit integer PCM, dithered if the decoder is set to
+    Pcm24,
+    /// 32-bit IEEE float, as decoded
+    Float32,
+}
+
+impl WavFormat {
+    pub fn bits_per_sample(self) -> usize {
+        match self {
+            WavFormat::Pcm16 => 16,
+            WavFormat::Pcm24 => 24,
+            WavFormat::Float32 => 32,
+        }
+    }
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+// the KSDATAFORMAT_SUBTYPE GUIDs of WAVE_FORMAT_EXTENSIBLE are the format
+// tag followed by these bytes
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+// samples per channel decoded and written at a time
+const WAV_BLOCK: usize = 4096;
+
+// the order WAV files keep channels in, that of their speakers' bits in the
+// channel mask: each entry is the Vorbis channel that goes there
+fn wav_channel_order(layout: ChannelLayout) -> Vec<usize> {
+    let speakers = layout.speakers();
+    let mut order: Vec<usize> = (0 .. layout.channels()).collect();
+    if speakers.is_empty() == false {
+        order.sort_by_key(|&c| speakers[c].mask());
+    }
+    return order;
+}
+
+// everything before the sample data. WAVE_FORMAT_EXTENSIBLE for more than
+// two channels or more than 16 bits, as Windows expects, and a fact chunk
+// for anything but plain PCM. FeatureNotSupported if the bytes per second
+// or per sample frame don't fit their fields
+fn wav_header(format: WavFormat, layout: ChannelLayout, rate: u32, samples: u64, data_size: u64) -> Result<Vec<u8>, VorbisError> {
+    let channels = layout.channels();
+    let bits = format.bits_per_sample();
+    let block_align = channels * bits / 8;
+    let byte_rate = rate as u64 * block_align as u64;
+    if byte_rate > u32::MAX as u64 || block_align > u16::MAX as usize {
+        return Err(VorbisError::new(VorbisErrorKind::FeatureNotSupported));
+    }
+    let tag = if format == WavFormat::Float32 { WAVE_FORMAT_IEEE_FLOAT } else { WAVE_FORMAT_PCM };
+    let extensible = channels > 2 || bits > 16;
+
+    let mut fmt = Vec::with_capacity(40);
+    fmt.extend_from_slice(&(if extensible { WAVE_FORMAT_EXTENSIBLE } else { tag }).to_le_bytes());
+    fmt.extend_from_slice(&(channels as u16).to_le_bytes());
+    fmt.extend_from_slice(&rate.to_le_bytes());
+    fmt.extend_from_slice(&(byte_rate as u32).to_le_bytes());
+    fmt.extend_from_slice(&(block_align as u16).to_le_bytes());
+    fmt.extend_from_slice(&(bits as u16).to_le_bytes());
+    if extensible {
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&(bits as u16).to_le_bytes());
+        fmt.extend_from_slice(&layout.channel_mask().to_le_bytes());
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+    } else if tag != WAVE_FORMAT_PCM {
+        fmt.extend_from_slice(&0u16.to_le_bytes());
+    }
+
+    let mut header = Vec::with_capacity(80);
+    let fact = extensible || tag != WAVE_FORMAT_PCM;
+    let riff_size = 4 + 8 + fmt.len() as u64 + if fact { 12 } else { 0 } + 8 + data_size + (data_size & 1);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(riff_size as u32).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+    header.extend_from_slice(&fmt);
+    if fact {
+        header.extend_from_slice(b"fact");
+        header.extend_from_slice(&4u32.to_le_bytes());
+        header.extend_from_slice(&(samples as u32).to_le_bytes());
+    }
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&(data_size as u32).to_le_bytes());
+    return Ok(header);
+}
+
+fn write_failure(e: io::Error) -> VorbisError {
+    VorbisError::with_source(VorbisErrorKind::WriteFailure, e)
+}
+
+// decodes the rest of the stream as T and writes it in WAV channel order,
+// 'put' turning each block into bytes. returns the samples per channel and
+// the bytes written
+fn write_samples<T, W, F>(f: &mut Vorbis, out: &mut W, order: &[usize], limit: u64, mut put: F) -> Result<(u64, u64), VorbisError>
+    where T: Sample + Default, W: Write, F: FnMut(&[T], &mut Vec<u8>)
+{
+    let channels = order.len();
+    let mut samples = vec![T::default(); WAV_BLOCK * channels];
+    let mut reordered = Vec::with_capacity(WAV_BLOCK * channels);
+    let mut bytes = Vec::new();
+    let mut written = (0, 0);
+    loop {
+        let n = stb_vorbis_get_samples_interleaved(f, channels as i32, &mut samples) as usize;
+        if n == 0 {
+            return Ok(written);
+        }
+        reordered.clear();
+        for frame in samples[.. n * channels].chunks(channels) {
+            reordered.extend(order.iter().map(|&c| frame[c]));
+        }
+        bytes.clear();
+        put(&reordered, &mut bytes);
+        if written.1 + bytes.len() as u64 > limit {
+            return Err(write_failure(io::Error::other("too long for a WAV file")));
+        }
+        out.write_all(&bytes).map_err(write_failure)?;
+        written.0 += n as u64;
+        written.1 += bytes.len() as u64;
+    }
+}
+
+// decode the rest of 'f' into 'out' as a WAV file in 'format', at the
+// output rate, with the channel count and speakers of the ID header; links
+// of a chained stream with other channel counts are mixed to that. the file
+// starts where 'out' is and the header is filled in once the data is
+// written, so 'out' has to be seekable. returns the number of samples per
+// channel written. on a decode error, the header is still filled in for
+// what was written before the error is returned. a WriteFailure, from 'out'
+// or from the data outgrowing the 4 GB a WAV file can hold, is returned
+// straight away, with the sizes in the header left at 0. an output rate too
+// high for the header to give the bytes per second in 32 bits is
+// FeatureNotSupported, with nothing written.
+pub fn stb_vorbis_write_wav<W: Write + Seek>(f: &mut Vorbis, out: W, format: WavFormat) -> Result<u64, VorbisError>
+{
+    let channels = f.channels;
+    return stb_vorbis_write_wav_channels(f, out, format, channels);
+}
+
+// stb_vorbis_write_wav(), mixed to 'channels' (1 to 255) channels by the
+// channel mix (see stb_vorbis_set_channel_mix()), with the speakers of the
+// Vorbis I layout of that many channels
+pub fn stb_vorbis_write_wav_channels<W: Write + Seek>(f: &mut Vorbis, mut out: W, format: WavFormat, channels: i32) -> Result<u64, VorbisError>
+{
+    if f.push_mode == true {
+        return Err(::vorbis_error(f, VorbisErrorKind::InvalidApiMixing));
+    }
+    if channels < 1 {
+        return Err(VorbisError::new(VorbisErrorKind::FeatureNotSupported));
+    }
+    if channels > 255 {
+        return Err(VorbisError::new(VorbisErrorKind::TooManyChannels));
+    }
+    let layout = ChannelLayout::from_channels(channels as usize);
+    let rate = stb_vorbis_get_output_rate(f);
+    let order = wav_channel_order(layout);
+
+    let header = wav_header(format, layout, rate, 0, 0)?;
+    let start = out.seek(SeekFrom::Current(0)).map_err(write_failure)?;
+    out.write_all(&header).map_err(write_failure)?;
+
+    // the RIFF size has to fit in 32 bits
+    let limit = (u32::MAX as u64 - header.len() as u64) & !1;
+    let (samples, data_size) = match format {
+        WavFormat::Pcm16 => write_samples(f, &mut out, &order, limit, |samples: &[i16], bytes| {
+            for sample in samples {
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+        })?,
+        WavFormat::Pcm24 => write_samples(f, &mut out, &order, limit, |samples: &[I24], bytes| {
+            bytes.extend_from_slice(I24::as_bytes(samples));
+        })?,
+        WavFormat::Float32 => write_samples(f, &mut out, &order, limit, |samples: &[f32], bytes| {
+            for sample in samples {
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+        })?,
+    };
+
+    // chunks are padded to an even size
+    if data_size & 1 != 0 {
+        out.write_all(&[0]).map_err(write_failure)?;
+    }
+    let end = out.seek(SeekFrom::Current(0)).map_err(write_failure)?;
+    out.seek(SeekFrom::Start(start)).map_err(write_failure)?;
+    out.write_all(&wav_header(format, layout, rate, samples, data_size)?).map_err(write_failure)?;
+    out.seek(SeekFrom::Start(end)).map_err(write_failure)?;
+    out.flush().map_err(write_failure)?;
+
+    let e = stb_vorbis_get_error(f);
+    if e != VorbisErrorKind::NoError {
+        return Err(::vorbis_error(f, e));
+    }
+    return Ok(samples);
+}